@@ -0,0 +1,218 @@
+use crate::disk_operator::{Directory, DiskOperator, FileType};
+use crate::error::{FsError, FsResult};
+use crate::session::Session;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Component;
+use tar::{Archive, Builder, EntryType, Header};
+
+// 将虚拟磁盘中path目录下的全部内容导出为tar文件，条目路径相对于该目录
+pub fn export_tar(vd: &DiskOperator, session: &Session, path: &str, tar_name: &str) -> FsResult<usize> {
+    let dir = vd.get_directory_by_path(session, path)?;
+    let file = File::create(tar_name).map_err(io_error)?;
+    let mut builder = Builder::new(file);
+
+    let count = append_directory(vd, &mut builder, &dir, "")?;
+    builder.finish().map_err(io_error)?;
+    Ok(count)
+}
+
+// 递归写入目录，返回写入的条目数
+fn append_directory(
+    vd: &DiskOperator,
+    builder: &mut Builder<File>,
+    dir: &Directory,
+    prefix: &str,
+) -> FsResult<usize> {
+    let mut count = 0;
+
+    for fcb in dir.entries() {
+        let name = format!("{}{}", prefix, fcb.name());
+        let mut header = Header::new_ustar();
+        header.set_mtime(fcb.modified().max(0) as u64);
+
+        match fcb.file_type() {
+            FileType::Directory => {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_size(0);
+                builder
+                    .append_data(&mut header, format!("{}/", name), std::io::empty())
+                    .map_err(io_error)?;
+
+                let sub_dir = vd.get_directory_by_fcb(fcb)?;
+                count += 1 + append_directory(vd, builder, &sub_dir, &format!("{}/", name))?;
+            }
+            FileType::File => {
+                let data = vd.get_file_by_fcb(fcb)?;
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(0o644);
+                header.set_size(data.len() as u64);
+                builder
+                    .append_data(&mut header, &name, data.as_slice())
+                    .map_err(io_error)?;
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+// 将tar文件导入虚拟磁盘的path目录，保留修改时间，已存在的同名文件会被覆盖，返回导入的条目数
+pub fn import_tar(vd: &mut DiskOperator, session: &Session, tar_name: &str, path: &str) -> FsResult<usize> {
+    let target = vd.get_directory_by_path(session, path)?.cluster();
+    let file = File::open(tar_name).map_err(io_error)?;
+    let mut archive = Archive::new(file);
+    let mut count = 0;
+
+    for entry in archive.entries().map_err(io_error)? {
+        let mut entry = entry.map_err(io_error)?;
+        let entry_path = entry.path().map_err(io_error)?.into_owned();
+
+        // 只接受普通的相对路径，拒绝..与绝对路径
+        let mut names: Vec<String> = Vec::new();
+        for component in entry_path.components() {
+            match component {
+                Component::Normal(name) => names.push(name.to_string_lossy().into_owned()),
+                Component::CurDir => continue,
+                _ => return Err(FsError::InvalidName(entry_path.display().to_string())),
+            }
+        }
+        let Some(file_name) = names.pop() else {
+            continue;
+        };
+
+        let parent = make_directories(vd, session, target, &names)?;
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                make_directories(vd, session, parent, std::slice::from_ref(&file_name))?;
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).map_err(io_error)?;
+                // 先写入新数据再替换，写入失败时已有的同名文件保持不变
+                vd.replace_file_at(session, parent, &file_name, data.as_slice()).map_err(|err| match err {
+                    FsError::IsADirectory(_) => FsError::IsADirectory(entry_path.display().to_string()),
+                    err => err,
+                })?;
+            }
+            // 链接、设备文件等无法在虚拟磁盘中表示，直接跳过
+            _ => continue,
+        }
+        // 保留修改时间
+        let mtime = entry.header().mtime().map_err(io_error)?;
+        vd.set_modified_at(session, parent, &file_name, i64::try_from(mtime).unwrap_or(i64::MAX))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// 从start目录开始逐级查找或创建目录，返回最后一级目录的首块号
+pub fn make_directories(vd: &mut DiskOperator, session: &Session, start: usize, names: &[String]) -> FsResult<usize> {
+    let mut cluster = start;
+    for name in names {
+        let dir = vd.get_directory_by_cluster(cluster)?;
+        cluster = match dir.get_entry(name) {
+            Some(fcb) if fcb.file_type() == FileType::Directory => fcb.first_cluster(),
+            Some(_) => return Err(FsError::NotADirectory(name.clone())),
+            None => vd.new_directory_at(session, cluster, name)?,
+        };
+    }
+    Ok(cluster)
+}
+
+fn io_error(err: std::io::Error) -> FsError {
+    FsError::Io(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("file-system-{}-{}.tar", name, std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn export_then_import_keeps_the_tree() {
+        let session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        vd.new_directory(&session, "src").unwrap();
+        vd.write_file_by_path(&session, "/src/main.rs", b"fn main() {}").unwrap();
+        vd.write_file_by_path(&session, "/src/empty", b"").unwrap();
+        vd.new_directory_at(&session, vd.get_directory_by_path(&session, "/src").unwrap().cluster(), "nested").unwrap();
+        let big: Vec<u8> = (0..3 * crate::disk::BLOCK_SIZE + 7).map(|i| i as u8).collect();
+        vd.write_file_by_path(&session, "/src/nested/big", &big).unwrap();
+        // 修改时间早于导入的时间，导入后应保持不变
+        let src = vd.get_directory_by_path(&session, "/src").unwrap().cluster();
+        vd.set_modified_at(&session, src, "main.rs", 1_000_000_000).unwrap();
+        vd.set_modified_at(&session, src, "nested", 1_200_000_000).unwrap();
+
+        let name = tar_path("round-trip");
+        assert_eq!(export_tar(&vd, &session, "/src", &name).unwrap(), 4);
+
+        // 导出的修改时间来自文件控制块
+        let mut archive = Archive::new(File::open(&name).unwrap());
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().trim_end_matches('/').to_string();
+            let fcb = vd.get_fcb_by_path(&session, &format!("/src/{}", path)).unwrap();
+            assert_eq!(entry.header().mtime().unwrap(), fcb.modified() as u64);
+        }
+
+        vd.new_directory(&session, "copy").unwrap();
+        assert_eq!(import_tar(&mut vd, &session, &name, "/copy").unwrap(), 4);
+        std::fs::remove_file(&name).unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "/copy/main.rs").unwrap(), b"fn main() {}");
+        assert_eq!(vd.read_file_by_path(&session, "/copy/empty").unwrap(), b"");
+        assert_eq!(vd.read_file_by_path(&session, "/copy/nested/big").unwrap(), big);
+        for path in ["main.rs", "empty", "nested", "nested/big"] {
+            let original = vd.get_fcb_by_path(&session, &format!("/src/{}", path)).unwrap();
+            let copy = vd.get_fcb_by_path(&session, &format!("/copy/{}", path)).unwrap();
+            assert_eq!(copy.modified(), original.modified(), "{}", path);
+        }
+        assert_eq!(vd.get_fcb_by_path(&session, "/copy/main.rs").unwrap().modified(), 1_000_000_000);
+
+        // 覆盖已有的文件，写入失败时原文件不变
+        let name = tar_path("overwrite");
+        export_tar(&vd, &session, "/src", &name).unwrap();
+        vd.new_directory(&session, "small").unwrap();
+        vd.new_directory_at(&session, vd.get_directory_by_path(&session, "/small").unwrap().cluster(), "nested").unwrap();
+        vd.write_file_by_path(&session, "/small/nested/big", b"old").unwrap();
+        let small = vd.get_directory_by_path(&session, "/small").unwrap().cluster();
+        vd.quotas().set_dir(small, crate::quota::Limit { blocks: 6, inodes: 0 });
+        assert!(matches!(import_tar(&mut vd, &session, &name, "/small"), Err(FsError::QuotaExceeded(_))));
+        std::fs::remove_file(&name).unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "/small/nested/big").unwrap(), b"old");
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+    }
+
+    #[test]
+    fn import_rejects_paths_outside_the_target() {
+        let session = Session::default();
+        for (index, path) in ["../escape", "a/../../escape", "/etc/passwd"].into_iter().enumerate() {
+            // Builder会拒绝这些路径，直接写入头部的名字字段
+            let name = tar_path(&format!("unsafe-{}", index));
+            let mut builder = Builder::new(File::create(&name).unwrap());
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(1);
+            header.set_cksum();
+            builder.append(&header, &b"x"[..]).unwrap();
+            builder.finish().unwrap();
+            drop(builder);
+
+            let mut vd = DiskOperator::new(None);
+            vd.new_directory(&session, "a").unwrap();
+            let result = import_tar(&mut vd, &session, &name, "/a");
+            std::fs::remove_file(&name).unwrap();
+            assert_eq!(result, Err(FsError::InvalidName(path.to_string())));
+            assert!(vd.get_directory_by_path(&session, "/a").unwrap().entries().count() == 0);
+            assert!(vd.get_fcb_by_path(&session, "/escape").is_err());
+        }
+    }
+}
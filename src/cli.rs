@@ -0,0 +1,410 @@
+use crate::archive;
+use crate::disk_operator::*;
+use crate::error::FsError;
+use crate::fat_image;
+use crate::filesystem::FileSystem;
+use crate::fsck;
+use crate::session::Session;
+use crate::ninep;
+#[cfg(target_os = "linux")]
+use crate::fuse;
+use crate::listing::{self, ListOptions};
+use crate::webdav::WebServer;
+use crate::crypto::Encryption;
+use crate::ui::{
+    change_passphrase, interact_with_user, load_disk, parse_exportfat_args, print_disk_info, read_new_passphrase, resize_disk,
+    run_command,
+};
+
+use std::fs;
+use std::io::{stdout, Write};
+use std::path::Path;
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+const USAGE: &str = "\
+Usage:
+    file-system                                   Start the interactive shell.
+    file-system --image <disk.vd>                 Open an image in the interactive shell.
+    file-system --image <disk.vd> --script <file> Run shell commands from a file.
+    file-system --image <disk.vd> <command> [args]
+
+Commands:
+    mkfs [-f] [--encrypt]      Create a new empty image, -f overwrites an existing one,
+                               --encrypt asks for a passphrase to encrypt it with.
+    passphrase [--remove]      Change the passphrase of an image or encrypt it,
+                               --remove decrypts it.
+    resize <new_block_count>   Grow or shrink the image, refusing when the data does not fit.
+    ls [options] [path...]     List dirs, options as in the shell, --json for scripts.
+    tree [options] [path]      Show a dir as a tree, -L <depth> limits the depth.
+    cat <filename>             Write a file to stdout.
+    cp <src> <dst>             Copy a file inside the image.
+    import <tarfile> [dirname] Import a tar archive into a dir.
+    export <dirname> <tarfile> Export a dir to a tar archive.
+    exportfat [-F 12|16] <dirname> <image>
+                               Export a dir to a FAT12 (default) or FAT16 image.
+    importfat <image> [dirname]
+                               Import a FAT12/16 image into a dir.
+    fsck                       Check the consistency of the image.
+    serve [--listen <addr>]    Serve the image over HTTP/WebDAV, 127.0.0.1:8080 by default.
+    serve9p [--listen <addr>]  Serve the image over 9P2000.L, 127.0.0.1:5640 by default,
+                               unix:<path> listens on a Unix socket.
+    mount <mountpoint>         Mount the image with FUSE (Linux, root), Ctrl-C to unmount.
+    info                       Show some info about disk.";
+
+// 解析命令行参数并执行，返回进程退出码
+pub fn run(args: &[String]) -> i32 {
+    let mut image: Option<String> = None;
+    let mut script: Option<String> = None;
+    let mut index = 0;
+
+    while index < args.len() {
+        match args[index].as_str() {
+            "--image" | "-i" => {
+                index += 1;
+                match args.get(index) {
+                    Some(name) => image = Some(name.clone()),
+                    None => return usage_error("--image requires a file name."),
+                }
+            }
+            "--script" | "-s" => {
+                index += 1;
+                match args.get(index) {
+                    Some(name) => script = Some(name.clone()),
+                    None => return usage_error("--script requires a file name."),
+                }
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return EXIT_SUCCESS;
+            }
+            _ => break,
+        }
+        index += 1;
+    }
+
+    let Some(image) = image else {
+        return usage_error("--image is required.");
+    };
+    let command = &args[index..];
+
+    if script.is_some() && !command.is_empty() {
+        return usage_error("--script can not be used with a command.");
+    }
+
+    let result = match (script, command.first()) {
+        (Some(script), _) => run_script(&image, &script),
+        (None, Some(name)) => run_subcommand(&image, name, &command[1..]),
+        (None, None) => open_interactive(&image),
+    };
+
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(CliError::Usage(err)) => usage_error(&err),
+        Err(CliError::Failed(err)) => {
+            eprintln!("file-system: {}", err);
+            EXIT_FAILURE
+        }
+    }
+}
+
+enum CliError {
+    Usage(String),
+    Failed(String),
+}
+
+impl From<FsError> for CliError {
+    fn from(err: FsError) -> Self {
+        CliError::Failed(err.to_string())
+    }
+}
+
+fn usage_error(err: &str) -> i32 {
+    eprintln!("file-system: {}", err);
+    eprintln!("{}", USAGE);
+    EXIT_USAGE
+}
+
+// 镜像不存在时新建，以便直接在交互界面中使用
+fn open_interactive(image: &str) -> Result<(), CliError> {
+    let mut vd = if Path::new(image).exists() {
+        load_disk(image)?
+    } else {
+        DiskOperator::new(None)
+    };
+    vd.image = Some(image.to_string());
+    interact_with_user(vd);
+    Ok(())
+}
+
+// 逐行执行脚本中的命令，遇到错误立即停止
+fn run_script(image: &str, script: &str) -> Result<(), CliError> {
+    let content = fs::read_to_string(script)
+        .map_err(|err| CliError::Failed(format!("{}: {}", script, err)))?;
+    let mut vd = load_disk(image)?;
+    let mut session = Session::default();
+
+    for (line_number, line) in content.lines().enumerate() {
+        match run_command(&mut vd, &mut session, line.trim()) {
+            Ok(true) => continue,
+            Ok(false) => break,
+            Err(err) => return Err(CliError::Failed(format!("{}:{}: {}", script, line_number + 1, err))),
+        }
+    }
+    Ok(())
+}
+
+fn check_arity(name: &str, args: &[String], min: usize, max: usize) -> Result<(), CliError> {
+    if args.len() < min || args.len() > max {
+        return Err(CliError::Usage(format!("wrong number of arguments for {}.", name)));
+    }
+    Ok(())
+}
+
+fn run_subcommand(image: &str, name: &str, args: &[String]) -> Result<(), CliError> {
+    // 新会话的当前目录为根目录，相对路径从根目录开始解析
+    let mut session = Session::default();
+    match name {
+        "mkfs" => {
+            let (mut force, mut encrypt) = (false, false);
+            for arg in args {
+                match arg.as_str() {
+                    "-f" => force = true,
+                    "--encrypt" => encrypt = true,
+                    _ => return Err(CliError::Usage(format!("unknown option {}.", arg))),
+                }
+            }
+            if Path::new(image).exists() && !force {
+                return Err(CliError::Failed(format!("{} already exists, use -f to overwrite.", image)));
+            }
+            let mut vd = DiskOperator::new(None);
+            if encrypt {
+                vd.encryption = Some(Encryption::new(&read_new_passphrase()?)?);
+            }
+            vd.save(image)?;
+        }
+        "passphrase" => {
+            let remove = match args {
+                [] => false,
+                [flag] if flag == "--remove" => true,
+                _ => return Err(CliError::Usage(format!("wrong arguments for {}.", name))),
+            };
+            let mut vd = load_disk(image)?;
+            println!("{}", change_passphrase(&mut vd, remove)?);
+            vd.save(image)?;
+        }
+        "resize" => {
+            check_arity(name, args, 1, 1)?;
+            let mut vd = load_disk(image)?;
+            println!("{}", resize_disk(&mut vd, &mut session, &args[0])?);
+            vd.save(image)?;
+        }
+        "ls" | "tree" => {
+            let (options, paths) = ListOptions::parse(args).map_err(|err| CliError::Usage(err.to_string()))?;
+            let vd = load_disk(image)?;
+            let text = match (name, paths.as_slice()) {
+                ("ls", paths) => listing::list(&vd, &session, paths, &options)?,
+                (_, []) => listing::tree(&vd, &session, "/", &options)?,
+                (_, [path]) => listing::tree(&vd, &session, path, &options)?,
+                _ => return Err(CliError::Usage(format!("wrong number of arguments for {}.", name))),
+            };
+            print!("{}", text);
+        }
+        "cat" => {
+            check_arity(name, args, 1, 1)?;
+            let vd = load_disk(image)?;
+            let data = vd.read_file_by_path(&session, &args[0])?;
+            stdout()
+                .write_all(data.as_slice())
+                .map_err(|err| CliError::Failed(err.to_string()))?;
+        }
+        "cp" => {
+            check_arity(name, args, 2, 2)?;
+            let vd = load_disk(image)?;
+            vd.copy_file_by_path(&session, &args[0], &args[1])?;
+            vd.save(image)?;
+        }
+        "import" => {
+            check_arity(name, args, 1, 2)?;
+            let mut vd = load_disk(image)?;
+            let path = args.get(1).map(|arg| arg.as_str()).unwrap_or("/");
+            let count = archive::import_tar(&mut vd, &session, &args[0], path)?;
+            vd.save(image)?;
+            println!("{} entries imported from {}.", count, args[0]);
+        }
+        "export" => {
+            check_arity(name, args, 2, 2)?;
+            let vd = load_disk(image)?;
+            let count = archive::export_tar(&vd, &session, &args[0], &args[1])?;
+            println!("{} entries exported to {}.", count, args[1]);
+        }
+        "exportfat" => {
+            let (fat_type, dir, fat_file) = parse_exportfat_args(args).map_err(CliError::Usage)?;
+            let vd = load_disk(image)?;
+            let count = fat_image::export_fat(&vd, &session, dir, fat_file, fat_type)?;
+            println!("{} entries exported to {}.", count, fat_file);
+        }
+        "importfat" => {
+            check_arity(name, args, 1, 2)?;
+            let mut vd = load_disk(image)?;
+            let path = args.get(1).map(|arg| arg.as_str()).unwrap_or("/");
+            let count = fat_image::import_fat(&mut vd, &session, &args[0], path)?;
+            vd.save(image)?;
+            println!("{} entries imported from {}.", count, args[0]);
+        }
+        "fsck" => {
+            check_arity(name, args, 0, 0)?;
+            let vd = load_disk(image)?;
+            let errors = fsck::check(&vd);
+            for err in &errors {
+                println!("{}", err);
+            }
+            if !errors.is_empty() {
+                return Err(CliError::Failed(format!("{} problems found in {}.", errors.len(), image)));
+            }
+            println!("{}: clean.", image);
+        }
+        "serve" => {
+            let listen = match args {
+                [] => "127.0.0.1:8080",
+                [option, listen] if option == "--listen" => listen.as_str(),
+                _ => return Err(CliError::Usage(format!("wrong arguments for {}.", name))),
+            };
+            let fs = FileSystem::from_operator(load_disk(image)?);
+            let server = WebServer::bind(listen)?;
+            let addr = server.local_addr().map_or(listen.to_string(), |addr| addr.to_string());
+            println!("Serving {} on http://{}/, press Ctrl-C to stop.", image, addr);
+            server.serve(&fs, session.user(), |fs| fs.save(image))?;
+        }
+        "serve9p" => {
+            let listen = match args {
+                [] => "127.0.0.1:5640",
+                [option, listen] if option == "--listen" => listen.as_str(),
+                _ => return Err(CliError::Usage(format!("wrong arguments for {}.", name))),
+            };
+            let mut vd = load_disk(image)?;
+            println!("Serving {} over 9P on {}, press Ctrl-C to stop.", image, listen);
+            ninep::serve(&mut vd, listen, |vd| vd.save(image))?;
+        }
+        #[cfg(target_os = "linux")]
+        "mount" => {
+            check_arity(name, args, 1, 1)?;
+            let mut vd = load_disk(image)?;
+            let mount = fuse::FuseMount::new(&args[0])?;
+            mount.unmount_on_interrupt();
+            println!("{} mounted on {}, press Ctrl-C to unmount.", image, args[0]);
+            fuse::serve(&mut vd, &mount, |vd| vd.save(image))?;
+            println!("{} unmounted.", args[0]);
+        }
+        "info" => {
+            check_arity(name, args, 0, 0)?;
+            let vd = load_disk(image)?;
+            print_disk_info(&vd);
+        }
+        _ => return Err(CliError::Usage(format!("unknown command {}.", name))),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("file-system-cli-{}-{}", name, std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn run_args(args: &[&str]) -> i32 {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        run(&args)
+    }
+
+    #[test]
+    fn bad_arguments_are_usage_errors() {
+        let image = temp_path("usage.vd");
+        assert_eq!(run_args(&["--help"]), EXIT_SUCCESS);
+        assert_eq!(run_args(&[]), EXIT_USAGE);
+        assert_eq!(run_args(&["ls"]), EXIT_USAGE);
+        assert_eq!(run_args(&["--image"]), EXIT_USAGE);
+        assert_eq!(run_args(&["--image", &image, "--script"]), EXIT_USAGE);
+        assert_eq!(run_args(&["--image", &image, "--script", "x", "ls"]), EXIT_USAGE);
+        // 参数错误在读取镜像之前报告，镜像不存在也返回EXIT_USAGE
+        assert_eq!(run_args(&["--image", &image, "bogus"]), EXIT_USAGE);
+        assert_eq!(run_args(&["--image", &image, "mkfs", "-x"]), EXIT_USAGE);
+        assert_eq!(run_args(&["--image", &image, "cp", "a"]), EXIT_USAGE);
+        assert_eq!(run_args(&["--image", &image, "fsck", "extra"]), EXIT_USAGE);
+        assert_eq!(run_args(&["--image", &image, "ls", "--bogus"]), EXIT_USAGE);
+        assert!(!Path::new(&image).exists());
+    }
+
+    #[test]
+    fn subcommands_work_on_the_image() {
+        let session = Session::default();
+        let image = temp_path("subcommands.vd");
+        let tar = temp_path("subcommands.tar");
+        let _ = fs::remove_file(&image);
+
+        // 镜像不存在属于执行失败
+        assert_eq!(run_args(&["--image", &image, "info"]), EXIT_FAILURE);
+        assert_eq!(run_args(&["--image", &image, "mkfs"]), EXIT_SUCCESS);
+        assert_eq!(run_args(&["--image", &image, "mkfs"]), EXIT_FAILURE);
+        assert_eq!(run_args(&["--image", &image, "mkfs", "-f"]), EXIT_SUCCESS);
+
+        let vd = DiskOperator::load(&image).unwrap();
+        vd.new_directory(&session, "d").unwrap();
+        vd.write_file_by_path(&session, "/d/a", b"hello").unwrap();
+        vd.save(&image).unwrap();
+
+        assert_eq!(run_args(&["--image", &image, "cp", "/d/a", "/b"]), EXIT_SUCCESS);
+        assert_eq!(run_args(&["--image", &image, "cp", "/missing", "/c"]), EXIT_FAILURE);
+        assert_eq!(run_args(&["--image", &image, "ls", "-l", "/d"]), EXIT_SUCCESS);
+        assert_eq!(run_args(&["--image", &image, "tree", "/"]), EXIT_SUCCESS);
+        assert_eq!(run_args(&["--image", &image, "export", "/d", &tar]), EXIT_SUCCESS);
+        assert_eq!(run_args(&["--image", &image, "import", &tar, "/e"]), EXIT_FAILURE);
+        assert_eq!(run_args(&["--image", &image, "import", &tar]), EXIT_SUCCESS);
+        assert_eq!(run_args(&["--image", &image, "resize", "x"]), EXIT_FAILURE);
+        assert_eq!(run_args(&["--image", &image, "fsck"]), EXIT_SUCCESS);
+        assert_eq!(run_args(&["--image", &image, "info"]), EXIT_SUCCESS);
+
+        let vd = DiskOperator::load(&image).unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "/b").unwrap(), b"hello");
+        assert_eq!(vd.read_file_by_path(&session, "/a").unwrap(), b"hello");
+
+        // fsck发现问题时返回EXIT_FAILURE
+        let lost = vd.disk.fat().iter().position(|item| matches!(item, crate::disk::FATItem::UnUsed)).unwrap();
+        vd.disk.set_fat(lost, crate::disk::FATItem::EOF);
+        vd.save(&image).unwrap();
+        assert_eq!(run_args(&["--image", &image, "fsck"]), EXIT_FAILURE);
+
+        fs::remove_file(&image).unwrap();
+        fs::remove_file(&tar).unwrap();
+    }
+
+    #[test]
+    fn scripts_stop_at_the_first_error() {
+        let session = Session::default();
+        let image = temp_path("script.vd");
+        let script = temp_path("script.txt");
+        assert_eq!(run_args(&["--image", &image, "mkfs", "-f"]), EXIT_SUCCESS);
+        assert_eq!(run_args(&["--image", &image, "--script", &script]), EXIT_FAILURE);
+
+        fs::write(&script, "# comment\nmkdir a\nsave\nrm missing\nmkdir b\nsave\n").unwrap();
+        assert_eq!(run_args(&["--image", &image, "--script", &script]), EXIT_FAILURE);
+        let vd = DiskOperator::load(&image).unwrap();
+        assert!(vd.get_directory_by_path(&session, "/a").is_ok());
+        assert!(vd.get_directory_by_path(&session, "/b").is_err());
+
+        // exit之后的命令不再执行
+        fs::write(&script, "mkdir c\nsave\nexit\nmkdir d\nsave\n").unwrap();
+        assert_eq!(run_args(&["--image", &image, "--script", &script]), EXIT_SUCCESS);
+        let vd = DiskOperator::load(&image).unwrap();
+        assert!(vd.get_directory_by_path(&session, "/c").is_ok());
+        assert!(vd.get_directory_by_path(&session, "/d").is_err());
+
+        fs::remove_file(&image).unwrap();
+        fs::remove_file(&script).unwrap();
+    }
+}
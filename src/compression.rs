@@ -0,0 +1,130 @@
+use crate::error::{FsError, FsResult};
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Write};
+
+// 存储数据前的长度字段的字节数
+const HEADER_SIZE: usize = 8;
+
+// 文件的压缩算法，创建文件时选定，记录在FCB中
+// 目录的压缩算法是其中新建的文件默认使用的算法
+// 压缩后的数据以8字节的小端序长度开头，之后是压缩算法输出的数据
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+    Deflate,
+}
+
+impl Compression {
+    pub fn parse(name: &str) -> FsResult<Compression> {
+        match name {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            "deflate" => Ok(Compression::Deflate),
+            _ => Err(FsError::InvalidArgument(format!("unknown compression {}, expected lz4, zstd, deflate or none", name))),
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+
+    // 压缩data，得到写入磁盘的数据
+    pub fn compress(&self, data: &[u8]) -> FsResult<Vec<u8>> {
+        let payload = match self {
+            Compression::None => return Ok(data.to_vec()),
+            Compression::Lz4 => lz4_flex::compress(data),
+            Compression::Zstd => zstd::encode_all(data, 0).map_err(codec_error)?,
+            Compression::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).map_err(codec_error)?;
+                encoder.finish().map_err(codec_error)?
+            }
+        };
+        let mut stored = Vec::with_capacity(HEADER_SIZE + payload.len());
+        stored.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        stored.extend_from_slice(&payload);
+        Ok(stored)
+    }
+
+    // 还原长度为length的原始数据，stored可以带有块末尾的填充
+    pub fn decompress(&self, stored: &[u8], length: usize) -> FsResult<Vec<u8>> {
+        if self.is_none() {
+            return Ok(stored[..length.min(stored.len())].to_vec());
+        }
+        let payload = &stored[HEADER_SIZE..stored_size(stored)?];
+        let data = match self {
+            Compression::None => unreachable!(),
+            Compression::Lz4 => lz4_flex::decompress(payload, length).map_err(codec_error)?,
+            Compression::Zstd => zstd::decode_all(payload).map_err(codec_error)?,
+            Compression::Deflate => {
+                let mut data = Vec::with_capacity(length);
+                flate2::read::DeflateDecoder::new(payload).read_to_end(&mut data).map_err(codec_error)?;
+                data
+            }
+        };
+        if data.len() != length {
+            return Err(FsError::Corrupted(format!("decompressed {} bytes, expected {}", data.len(), length)));
+        }
+        Ok(data)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+            Compression::Deflate => "deflate",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// 压缩数据连同长度字段的字节数
+pub fn stored_size(stored: &[u8]) -> FsResult<usize> {
+    let Some(header) = stored.get(..HEADER_SIZE) else {
+        return Err(FsError::Corrupted("compressed data is too short".to_string()));
+    };
+    let size = HEADER_SIZE + u64::from_le_bytes(header.try_into().unwrap_or_default()) as usize;
+    if size > stored.len() {
+        return Err(FsError::Corrupted(format!("compressed data needs {} bytes, found {}", size, stored.len())));
+    }
+    Ok(size)
+}
+
+fn codec_error<E: fmt::Display>(err: E) -> FsError {
+    FsError::Corrupted(format!("compressed data: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_codec_round_trips() {
+        let text = "hello compression ".repeat(500).into_bytes();
+        for name in ["none", "lz4", "zstd", "deflate"] {
+            let codec = Compression::parse(name).unwrap();
+            assert_eq!(codec.to_string(), name);
+            let mut stored = codec.compress(&text).unwrap();
+            if !codec.is_none() {
+                assert!(stored.len() < text.len() / 10, "{} stored {} bytes", name, stored.len());
+            }
+            // 块末尾的填充被忽略
+            stored.extend_from_slice(&[0, 255, 0]);
+            assert_eq!(codec.decompress(&stored, text.len()).unwrap(), text);
+            assert_eq!(codec.decompress(&codec.compress(b"").unwrap(), 0).unwrap(), b"");
+        }
+        assert!(Compression::parse("gzip").is_err());
+        assert!(Compression::Zstd.decompress(&[1, 0, 0, 0, 0, 0, 0, 0, 7], 3).is_err());
+        assert!(Compression::Lz4.decompress(&[9, 0], 3).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use crate::error::{FsError, FsResult};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+
+// 加密镜像的开头
+const MAGIC: &[u8; 8] = b"FSVDENC1";
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const KEY_SIZE: usize = 32;
+const TAG_SIZE: usize = 16;
+// 头部：MAGIC、KDF参数（内存KiB与迭代次数）、盐、加密数据密钥的nonce与密文
+const HEADER_SIZE: usize = MAGIC.len() + 8 + SALT_SIZE + NONCE_SIZE + KEY_SIZE + TAG_SIZE;
+
+// Argon2id的默认参数：19MiB内存，迭代2次
+const MEMORY_COST: u32 = 19 * 1024;
+const TIME_COST: u32 = 2;
+
+// 镜像加密：序列化后的整个镜像（FAT、数据区与目录）以AES-256-GCM加密保存
+// 数据密钥随机生成，由口令经Argon2id派生的密钥加密后保存在头部；修改口令只需重新加密数据密钥
+#[derive(Clone)]
+pub struct Encryption {
+    key: [u8; KEY_SIZE], // 数据密钥
+    header: Vec<u8>,
+}
+
+impl Encryption {
+    // 以新的随机数据密钥加密
+    pub fn new(passphrase: &str) -> FsResult<Encryption> {
+        Encryption::with_cost(passphrase, MEMORY_COST, TIME_COST)
+    }
+
+    // 指定KDF的内存（KiB）与迭代次数
+    pub fn with_cost(passphrase: &str, memory: u32, time: u32) -> FsResult<Encryption> {
+        let mut key = [0; KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut key);
+        let mut encryption = Encryption { key, header: Vec::new() };
+        encryption.wrap_key(passphrase, memory, time)?;
+        Ok(encryption)
+    }
+
+    // 修改口令，数据密钥不变，下次保存时生效
+    pub fn change_passphrase(&mut self, passphrase: &str) -> FsResult<()> {
+        let (memory, time) = kdf_params(&self.header);
+        self.wrap_key(passphrase, memory, time)
+    }
+
+    // 口令是否正确
+    pub fn verify(&self, passphrase: &str) -> bool {
+        unwrap_key(&self.header, passphrase).is_ok()
+    }
+
+    fn wrap_key(&mut self, passphrase: &str, memory: u32, time: u32) -> FsResult<()> {
+        if passphrase.is_empty() {
+            return Err(FsError::InvalidArgument("empty passphrase".to_string()));
+        }
+        let mut salt = [0; SALT_SIZE];
+        let mut nonce = [0; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&memory.to_le_bytes());
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce);
+        let wrapping = derive_key(passphrase, &salt, memory, time)?;
+        // 头部的前半部分作为附加数据，篡改KDF参数或盐都会导致解密失败
+        let wrapped = cipher(&wrapping)
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &self.key, aad: &header })
+            .map_err(|_| FsError::Corrupted("failed to encrypt the data key".to_string()))?;
+        header.extend_from_slice(&wrapped);
+        self.header = header;
+        Ok(())
+    }
+
+    // 加密序列化后的镜像
+    pub fn seal(&self, plain: &[u8]) -> FsResult<Vec<u8>> {
+        let mut nonce = [0; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let sealed = cipher(&self.key)
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain, aad: &self.header })
+            .map_err(|_| FsError::Corrupted("failed to encrypt the image".to_string()))?;
+
+        let mut data = Vec::with_capacity(HEADER_SIZE + NONCE_SIZE + sealed.len());
+        data.extend_from_slice(&self.header);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&sealed);
+        Ok(data)
+    }
+
+    // 以口令解密镜像，返回加密参数与序列化的镜像；口令错误时返回WrongPassphrase
+    pub fn open(data: &[u8], passphrase: &str) -> FsResult<(Encryption, Vec<u8>)> {
+        if !is_encrypted(data) || data.len() < HEADER_SIZE + NONCE_SIZE {
+            return Err(FsError::Corrupted("truncated encrypted image".to_string()));
+        }
+        let header = &data[..HEADER_SIZE];
+        let key = unwrap_key(header, passphrase)?;
+        let nonce = &data[HEADER_SIZE..HEADER_SIZE + NONCE_SIZE];
+        let plain = cipher(&key)
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: &data[HEADER_SIZE + NONCE_SIZE..], aad: header })
+            .map_err(|_| FsError::Corrupted("encrypted image failed authentication".to_string()))?;
+        Ok((Encryption { key, header: header.to_vec() }, plain))
+    }
+}
+
+// 调试输出中不显示密钥
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (memory, time) = kdf_params(&self.header);
+        write!(f, "Encryption {{ cipher: AES-256-GCM, kdf: Argon2id m={} t={} }}", memory, time)
+    }
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn kdf_params(header: &[u8]) -> (u32, u32) {
+    let field = |start: usize| u32::from_le_bytes(header[start..start + 4].try_into().unwrap_or_default());
+    (field(MAGIC.len()), field(MAGIC.len() + 4))
+}
+
+// 用口令解开头部中的数据密钥，认证失败说明口令错误
+fn unwrap_key(header: &[u8], passphrase: &str) -> FsResult<[u8; KEY_SIZE]> {
+    let (memory, time) = kdf_params(header);
+    let salt_start = MAGIC.len() + 8;
+    let nonce_start = salt_start + SALT_SIZE;
+    let wrapped_start = nonce_start + NONCE_SIZE;
+    let wrapping = derive_key(passphrase, &header[salt_start..nonce_start], memory, time)?;
+    let key = cipher(&wrapping)
+        .decrypt(
+            Nonce::from_slice(&header[nonce_start..wrapped_start]),
+            Payload { msg: &header[wrapped_start..], aad: &header[..wrapped_start] },
+        )
+        .map_err(|_| FsError::WrongPassphrase)?;
+    key.try_into().map_err(|_| FsError::Corrupted("invalid data key".to_string()))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], memory: u32, time: u32) -> FsResult<[u8; KEY_SIZE]> {
+    let params = Params::new(memory, time, 1, Some(KEY_SIZE))
+        .map_err(|err| FsError::Corrupted(format!("invalid KDF parameters: {}", err)))?;
+    let mut key = [0; KEY_SIZE];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| FsError::Corrupted(format!("key derivation failed: {}", err)))?;
+    Ok(key)
+}
+
+fn cipher(key: &[u8; KEY_SIZE]) -> Aes256Gcm {
+    Aes256Gcm::new(key.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrong_passphrases_are_detected() {
+        // 测试中使用较小的KDF参数
+        let mut encryption = Encryption::with_cost("secret", 64, 1).unwrap();
+        let sealed = encryption.seal(b"image data").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.windows(10).any(|window| window == b"image data"));
+        assert_eq!(Encryption::open(&sealed, "guess").err(), Some(FsError::WrongPassphrase));
+        assert_eq!(Encryption::open(&sealed, "secret").unwrap().1, b"image data");
+
+        // 修改口令后旧口令失效，数据密钥不变
+        encryption.change_passphrase("changed").unwrap();
+        assert!(encryption.verify("changed") && !encryption.verify("secret"));
+        let sealed = encryption.seal(b"image data").unwrap();
+        assert_eq!(Encryption::open(&sealed, "secret").err(), Some(FsError::WrongPassphrase));
+        assert_eq!(Encryption::open(&sealed, "changed").unwrap().1, b"image data");
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(Encryption::open(&tampered, "changed"), Err(FsError::Corrupted(_))));
+        assert!(Encryption::new("").is_err());
+    }
+}
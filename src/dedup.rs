@@ -0,0 +1,97 @@
+use crate::disk::{BLOCK_SIZE, EOF_BYTE};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// 块去重的索引：由块中有效的数据与后继块号的哈希查找内容相同的块
+// FAT中每块只有一个后继，因此只有后继也相同的块才能共享，相同的块链尾部被共享；
+// 索引只用于查找候选块，共享前总是比较块的实际内容，过时的索引项不影响正确性
+#[derive(Default)]
+pub struct DedupIndex {
+    buckets: HashMap<u64, Vec<usize>>,
+    keys: HashMap<usize, u64>, // 块号到其哈希，释放块时移除
+}
+
+impl DedupIndex {
+    pub fn insert(&mut self, cluster: usize, key: u64) {
+        self.forget(cluster);
+        self.buckets.entry(key).or_default().push(cluster);
+        self.keys.insert(cluster, key);
+    }
+
+    pub fn candidates(&self, key: u64) -> &[usize] {
+        self.buckets.get(&key).map_or(&[], Vec::as_slice)
+    }
+
+    // 块被释放后不再作为候选
+    pub fn forget(&mut self, cluster: usize) {
+        let Some(key) = self.keys.remove(&cluster) else {
+            return;
+        };
+        if let Some(clusters) = self.buckets.get_mut(&key) {
+            clusters.retain(|&other| other != cluster);
+            if clusters.is_empty() {
+                self.buckets.remove(&key);
+            }
+        }
+    }
+}
+
+// 块的哈希，next为后继块号，None表示链尾
+pub fn key(block: &[u8], next: Option<usize>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    block.hash(&mut hasher);
+    next.hash(&mut hasher);
+    hasher.finish()
+}
+
+// 数据按块切分后每块中有效的字节，与write_in_clusters写入的内容相同：
+// 长度不是块大小整数倍时最后一块在数据之后写入EOF，空数据只有一个EOF
+pub fn blocks(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut blocks: Vec<Vec<u8>> = data.chunks(BLOCK_SIZE).map(<[u8]>::to_vec).collect();
+    if !data.len().is_multiple_of(BLOCK_SIZE) || data.is_empty() {
+        match blocks.last_mut() {
+            Some(last) => last.push(EOF_BYTE),
+            None => blocks.push(vec![EOF_BYTE]),
+        }
+    }
+    blocks
+}
+
+// 长度为length的数据在块链中第index块的有效字节数
+pub fn block_len(length: usize, index: usize) -> usize {
+    let rest = length - (index * BLOCK_SIZE).min(length);
+    if rest >= BLOCK_SIZE {
+        BLOCK_SIZE
+    } else {
+        rest + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_match_the_stored_layout() {
+        assert_eq!(blocks(b""), [vec![EOF_BYTE]]);
+        let data = vec![7; BLOCK_SIZE * 2 + 3];
+        let split = blocks(&data);
+        assert_eq!(split.iter().map(Vec::len).collect::<Vec<_>>(), [BLOCK_SIZE, BLOCK_SIZE, 4]);
+        assert_eq!(blocks(&data[..BLOCK_SIZE]).len(), 1);
+        for (index, block) in split.iter().enumerate() {
+            assert_eq!(block_len(data.len(), index), block.len());
+        }
+        assert_eq!(block_len(BLOCK_SIZE, 0), BLOCK_SIZE);
+        assert_eq!(block_len(0, 0), 1);
+
+        let mut index = DedupIndex::default();
+        index.insert(3, key(&split[0], Some(4)));
+        index.insert(5, key(&split[0], Some(4)));
+        assert_eq!(index.candidates(key(&split[0], Some(4))), [3, 5]);
+        assert!(index.candidates(key(&split[0], None)).is_empty());
+        index.forget(3);
+        assert_eq!(index.candidates(key(&split[0], Some(4))), [5]);
+    }
+}
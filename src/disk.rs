@@ -1,5 +1,12 @@
-use serde::{Serialize, Deserialize};
+use crate::error::FsResult;
+use crate::sparse;
+
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde::{Serialize, Deserialize, Serializer};
+use std::collections::BTreeSet;
 use std::mem::size_of;
+use std::ops::Range;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 pub const BLOCK_SIZE: usize = 4096; // 4KB
 pub const BLOCK_COUNT: usize = 1024;
@@ -10,40 +17,232 @@ pub enum FATItem {
     UnUsed, // 未使用
     Cluster(usize), // 指向下一块
     BadCluster, // 损坏块
-    EOF //  文件结束
+    EOF, //  文件结束
+    Hole(u32, u32), // 之后有若干未分配的空洞块，再指向下一块；下一块为HOLE_END时文件结束
 }
 
-#[derive(Serialize, Deserialize)]
+// 空洞之后没有下一块
+pub const HOLE_END: u32 = u32::MAX;
+
+// FAT、空闲块集合与数据区的每一块分别加锁，多个线程可以同时分配、释放与读写不同的块
+// 镜像中的格式不变：FAT之后是连续的数据区，空闲块集合由FAT得出
+#[derive(Deserialize)]
+#[serde(from = "DiskImage")]
 pub struct Disk {
-    pub fat: Vec<FATItem>,
-    pub data: Vec<u8>
+    fat: Mutex<Vec<FATItem>>,
+    free: Mutex<BTreeSet<usize>>, // 数据区中未使用的块号
+    blocks: Vec<RwLock<Box<[u8]>>>,
+}
+
+// 镜像中的磁盘
+#[derive(Deserialize)]
+struct DiskImage {
+    fat: Vec<FATItem>,
+    data: Vec<u8>,
+}
+
+impl From<DiskImage> for Disk {
+    fn from(image: DiskImage) -> Disk {
+        let blocks = image.data
+            .chunks(BLOCK_SIZE)
+            .map(|chunk| {
+                let mut block = empty_block();
+                block[..chunk.len()].copy_from_slice(chunk);
+                RwLock::new(block)
+            })
+            .collect();
+        Disk::from_parts(image.fat, blocks)
+    }
+}
+
+impl Serialize for Disk {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut image = serializer.serialize_struct("Disk", 2)?;
+        image.serialize_field("fat", &*lock(&self.fat))?;
+        image.serialize_field("data", &Data(&self.blocks))?;
+        image.end()
+    }
+}
+
+// 数据区按字节序列写入，与Vec<u8>的格式相同
+struct Data<'a>(&'a [RwLock<Box<[u8]>>]);
+
+impl Serialize for Data<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut data = serializer.serialize_seq(Some(self.0.len() * BLOCK_SIZE))?;
+        for block in self.0 {
+            for byte in read(block).iter() {
+                data.serialize_element(byte)?;
+            }
+        }
+        data.end()
+    }
+}
+
+// 逐块复制，复制期间不能有其他线程修改磁盘
+impl Clone for Disk {
+    fn clone(&self) -> Disk {
+        let blocks = self.blocks.iter().map(|block| RwLock::new(read(block).clone())).collect();
+        Disk::from_parts(lock(&self.fat).clone(), blocks)
+    }
+}
+
+impl Default for Disk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn lock<T>(lock: &Mutex<T>) -> MutexGuard<'_, T> {
+    lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn empty_block() -> Box<[u8]> {
+    vec![0u8; BLOCK_SIZE].into_boxed_slice()
+}
+
+// 数据区的前count块中未使用的块
+fn free_clusters(fat: &[FATItem], count: usize) -> BTreeSet<usize> {
+    (0..count.min(fat.len())).filter(|&i| matches!(fat[i], FATItem::UnUsed)).collect()
 }
 
 impl Disk {
     pub fn new() -> Disk {
+        Disk::with_block_count(BLOCK_COUNT)
+    }
+
+    // 总块数为block_count的磁盘
+    pub fn with_block_count(block_count: usize) -> Disk {
+        Disk::from_parts(
+            vec![FATItem::UnUsed; block_count], // 创建FAT文件分配表
+            (0..Disk::data_clusters(block_count)).map(|_| RwLock::new(empty_block())).collect(),
+            // 创建数据区，每块一把读写锁
+        )
+    }
+
+    fn from_parts(fat: Vec<FATItem>, blocks: Vec<RwLock<Box<[u8]>>>) -> Disk {
         Disk {
-            fat: vec![FATItem::UnUsed; BLOCK_COUNT], // 创建FAT文件分配表
-            data: vec![0u8; (BLOCK_COUNT - size_of::<FATItem>() * BLOCK_COUNT / BLOCK_SIZE - 1) * BLOCK_SIZE],
-            // 创建数据区，单位为字节，计算方式为总块数减去FAT块数乘上块大小
+            free: Mutex::new(free_clusters(&fat, blocks.len())),
+            fat: Mutex::new(fat),
+            blocks,
         }
     }
 
-    pub fn insert_data_in_offset(&mut self, data:  &[u8], offset: usize) {
-        self.data.splice(offset..(offset+data.len()), data.iter().cloned());
+    // 总块数为block_count时数据区的块数，计算方式为总块数减去FAT块数再减1
+    pub fn data_clusters(block_count: usize) -> usize {
+        block_count.saturating_sub(size_of::<FATItem>() * block_count / BLOCK_SIZE + 1)
+    }
+
+    // 改变总块数，FAT与数据区随之增长或截断；缩小前需要先移走新末尾之后的块
+    pub fn resize(&mut self, block_count: usize) {
+        let fat = self.fat.get_mut().unwrap_or_else(PoisonError::into_inner);
+        fat.resize(block_count, FATItem::UnUsed);
+        self.blocks.resize_with(Disk::data_clusters(block_count), || RwLock::new(empty_block()));
+        *self.free.get_mut().unwrap_or_else(PoisonError::into_inner) = free_clusters(fat, self.blocks.len());
+    }
+
+    // 数据区可用的块数，FAT中超出数据区的表项不参与分配
+    pub fn cluster_count(&self) -> usize {
+        self.blocks.len().min(lock(&self.fat).len())
+    }
+
+    // FAT的副本，之后的修改不影响副本
+    pub fn fat(&self) -> Vec<FATItem> {
+        lock(&self.fat).clone()
+    }
+
+    // FAT的表项数，可能多于数据区的块数
+    pub fn fat_len(&self) -> usize {
+        lock(&self.fat).len()
     }
 
-    pub fn insert_data_in_cluster(&mut self, data:&[u8], cluster: usize) {
-        return self.insert_data_in_offset(data, cluster * BLOCK_SIZE);
+    pub fn fat_item(&self, cluster: usize) -> FATItem {
+        lock(&self.fat)[cluster].clone()
+    }
+
+    // 设置FAT表项，空闲块集合随之更新
+    pub fn set_fat(&self, cluster: usize, item: FATItem) {
+        let unused = matches!(item, FATItem::UnUsed);
+        lock(&self.fat)[cluster] = item;
+        let mut free = lock(&self.free);
+        if unused && cluster < self.blocks.len() {
+            free.insert(cluster);
+        } else {
+            free.remove(&cluster);
+        }
+    }
+
+    // 从空闲块中取出块号最小的count块，在FAT中链接成一条链；空闲块不足时不分配
+    pub fn allocate(&self, count: usize) -> Option<Vec<usize>> {
+        let clusters: Vec<usize> = {
+            let mut free = lock(&self.free);
+            if free.len() < count {
+                return None;
+            }
+            (0..count).filter_map(|_| free.pop_first()).collect()
+        };
+        let mut fat = lock(&self.fat);
+        for pair in clusters.windows(2) {
+            fat[pair[0]] = FATItem::Cluster(pair[1]);
+        }
+        if let Some(&last) = clusters.last() {
+            fat[last] = FATItem::EOF;
+        }
+        Some(clusters)
+    }
+
+    // 释放块，之后可以再次分配
+    pub fn free(&self, cluster: usize) {
+        self.set_fat(cluster, FATItem::UnUsed);
+    }
+
+    // 沿FAT读取从start开始的块布局，空洞为None
+    pub fn layout(&self, start: usize) -> FsResult<Vec<Option<usize>>> {
+        let fat = lock(&self.fat);
+        sparse::layout(&fat, start, self.blocks.len().min(fat.len()))
+    }
+
+    // 按块布局重写其中各块的FAT表项
+    pub fn link(&self, layout: &[Option<usize>]) {
+        sparse::link(&mut lock(&self.fat), layout);
+    }
+
+    // 从块的offset处写入bytes
+    pub fn write_in_cluster(&self, cluster: usize, offset: usize, bytes: &[u8]) {
+        write(&self.blocks[cluster])[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // 将块中range范围的字节以0填充
+    pub fn zero_in_cluster(&self, cluster: usize, range: Range<usize>) {
+        write(&self.blocks[cluster])[range].fill(0);
+    }
+
+    // 将块from的内容复制到块to
+    pub fn copy_cluster(&self, from: usize, to: usize) {
+        let data = read(&self.blocks[from]).clone();
+        *write(&self.blocks[to]) = data;
+    }
+
+    pub fn insert_data_in_cluster(&self, data: &[u8], cluster: usize) {
+        self.write_in_cluster(cluster, 0, data)
     }
     // 传入数据，块号，是否插入EOF
-    pub fn write_in_clusters(&mut self, data: &[u8], clusters: &[usize], insert_eof: bool) {
+    pub fn write_in_clusters(&self, data: &[u8], clusters: &[usize], insert_eof: bool) {
         for i in 0..clusters.len() {
             if i != clusters.len() - 1 {
                 self.insert_data_in_cluster(&data[i * BLOCK_SIZE..(i+1) * BLOCK_SIZE], clusters[i]);
             }
             else {
                 let mut buffer: Vec<u8> = Vec::with_capacity(BLOCK_SIZE); // 初始长度0，容量为BLOCK_SIZE
-                buffer.extend((&data[i * BLOCK_SIZE..data.len()]).iter()); // extend从迭代器添加多个元素
+                buffer.extend(data[i * BLOCK_SIZE..data.len()].iter()); // extend从迭代器添加多个元素
 
                 if insert_eof {
                     buffer.push(EOF_BYTE);
@@ -57,18 +256,22 @@ impl Disk {
     }
 
     pub fn read_in_cluster(&self, cluster: usize) -> Vec<u8> {
-        (&self.data[cluster * BLOCK_SIZE..(cluster + 1) * BLOCK_SIZE]).to_vec()
+        read(&self.blocks[cluster]).to_vec()
+    }
+
+    // 将块中range范围的字节追加到buffer
+    pub fn read_range(&self, cluster: usize, range: Range<usize>, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&read(&self.blocks[cluster])[range]);
     }
 
     pub fn read_in_clusters(&self, clusters: &[usize]) -> Vec<u8> {
         let mut data: Vec<u8> = Vec::with_capacity(BLOCK_SIZE * clusters.len());
-        
+
         for cluster in clusters {
-            let mut buffer = self.read_in_cluster(*cluster);
-            data.append(&mut buffer);
+            self.read_range(*cluster, 0..BLOCK_SIZE, &mut data);
         }
         // 从后向前找EOF
-        for i in 1..BLOCK_SIZE {
+        for i in 1..=BLOCK_SIZE.min(data.len()) {
             let index = data.len() - i;
             if data[index] == EOF_BYTE {
                 data.truncate(index);
@@ -77,4 +280,4 @@ impl Disk {
         }
         data
     }
-}
\ No newline at end of file
+}
@@ -1,9 +1,20 @@
-use crate::disk::{Disk, FATItem, BLOCK_SIZE};
+use crate::compression::{self, Compression};
+use crate::crypto::{self, Encryption};
+use crate::dedup::{self, DedupIndex};
+use crate::disk::{Disk, FATItem, BLOCK_SIZE, EOF_BYTE};
+use crate::error::{FsError, FsResult};
+use crate::handle::FileHandle;
+use crate::lock::LockTable;
+use crate::quota::{self, Limit, Quotas};
+use crate::session::Session;
+use crate::snapshot::Snapshots;
+use crate::sparse;
 
 use ansi_rgb::Foreground;
-use core::panic;
 use serde::{Deserialize, Serialize};
-use std::{fmt, string::String, usize, vec::Vec};
+use std::cell::Cell;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::{fmt, fs, string::String, vec::Vec};
 
 pub fn print_info() {
     print!("{}", "[INFO]\t".fg(ansi_rgb::cyan_blue()));
@@ -33,7 +44,81 @@ pub struct Fcb {
     name: String,
     file_type: FileType,
     first_cluster: usize, // 起始块号
-    length: usize,
+    length: usize, // 文件的原始长度，压缩文件为解压后的长度
+    modified: i64, // 修改时间，Unix时间戳（秒）
+    compression: Compression, // 压缩算法，目录的为其中新建的文件默认使用的算法
+}
+
+impl Fcb {
+    // 新文件或目录的FCB，修改时间为当前时间
+    pub fn new(name: &str, file_type: FileType, first_cluster: usize, length: usize) -> Fcb {
+        Fcb {
+            name: String::from(name),
+            file_type,
+            first_cluster,
+            length,
+            modified: now(),
+            compression: Compression::None,
+        }
+    }
+
+    // 使用指定压缩算法的FCB
+    pub fn with_compression(&self, compression: Compression) -> Fcb {
+        Fcb {
+            compression,
+            ..self.clone()
+        }
+    }
+
+    // 改名后的FCB
+    pub fn renamed(&self, name: &str) -> Fcb {
+        Fcb {
+            name: String::from(name),
+            ..self.clone()
+        }
+    }
+
+    // 指向另一份相同内容的FCB，修改时间不变
+    pub fn with_cluster(&self, first_cluster: usize) -> Fcb {
+        Fcb {
+            first_cluster,
+            ..self.clone()
+        }
+    }
+
+    // 内容改写后的FCB，更新修改时间
+    pub fn rewritten(&self, first_cluster: usize, length: usize) -> Fcb {
+        Fcb {
+            first_cluster,
+            length,
+            modified: now(),
+            ..self.clone()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type.clone()
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn first_cluster(&self) -> usize {
+        self.first_cluster
+    }
+
+    pub fn modified(&self) -> i64 {
+        self.modified
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -61,12 +146,98 @@ impl Directory {
         result
     }
 
+    // 首块号为cluster、位于parent下的空目录
+    pub fn empty(name: &str, cluster: usize, parent: usize) -> Directory {
+        Directory {
+            name: String::from(name),
+            files: vec![
+                Fcb::new(".", FileType::Directory, cluster, 0),
+                Fcb::new("..", FileType::Directory, parent, 0),
+            ],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // 所在目录的首块号，即..的起始块
+    pub fn parent(&self) -> usize {
+        self.files[1].first_cluster
+    }
+
+    // 目录自身的FCB，即以目录名命名的.项
+    pub fn own_fcb(&self) -> Fcb {
+        self.files[0].renamed(&self.name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.len() <= 2
+    }
+
+    // 其中新建的文件默认使用的压缩算法
+    pub fn compression(&self) -> Compression {
+        self.files[0].compression
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.files[0].compression = compression;
+    }
+
+    // 添加目录项，目录大小随之增加
+    pub fn insert(&mut self, fcb: Fcb) {
+        self.files[0].length += fcb.length;
+        self.files.push(fcb);
+    }
+
+    // 移除目录项，目录大小随之减少
+    pub fn remove(&mut self, name: &str) -> Option<Fcb> {
+        let (index, _) = self.get_fcb(name)?;
+        let fcb = self.files.remove(index);
+        self.files[0].length = self.files[0].length.saturating_sub(fcb.length);
+        Some(fcb)
+    }
+
+    // 以同名的FCB替换目录项
+    pub fn replace(&mut self, fcb: Fcb) {
+        if let Some((index, old)) = self.get_fcb(&fcb.name) {
+            self.files[0].length = self.files[0].length.saturating_sub(old.length) + fcb.length;
+            self.files[index] = fcb;
+        }
+    }
+
+    // 目录移动或改名后更新名称与..
+    pub fn relocate(&mut self, name: &str, parent: usize) {
+        self.name = String::from(name);
+        self.files[1].first_cluster = parent;
+    }
+
+    pub fn get_entry(&self, name: &str) -> Option<&Fcb> {
+        self.get_fcb(name).map(|(_, fcb)| fcb)
+    }
+
     pub fn get_file_type(&self, name: &str) -> Option<FileType> {
-        match self.get_fcb(name) {
-            Some((_, fcb)) => Some(fcb.file_type.clone()),
-            None => None,
+        self.get_fcb(name).map(|(_, fcb)| fcb.file_type.clone())
+    }
+
+    // 目录自身的首块号，即.的起始块
+    pub fn cluster(&self) -> usize {
+        self.files[0].first_cluster
+    }
+
+    // 磁盘缩小时块被移动，按映射更新全部目录项（含.与..）的首块号
+    pub fn relocate_clusters<F: Fn(usize) -> usize>(&mut self, map: F) {
+        for fcb in &mut self.files {
+            fcb.first_cluster = map(fcb.first_cluster);
         }
     }
+
+    // 除.和..以外的目录项
+    pub fn entries(&self) -> impl Iterator<Item = &Fcb> {
+        self.files
+            .iter()
+            .filter(|fcb| fcb.name != "." && fcb.name != "..")
+    }
 }
 
 impl fmt::Display for Directory {
@@ -101,10 +272,83 @@ impl fmt::Display for Directory {
     }
 }
 
+// 镜像的可选功能，随镜像保存
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct DiskOptions {
+    pub dedup: bool, // 写入文件时共享内容相同的块
+}
+
+// 快照、选项与配额随镜像保存；锁表不写入镜像，设置了encryption时镜像加密保存
+// 会话由调用者持有并传入：相对路径从会话的当前目录开始查找，写入检查其他会话的强制锁并计入会话用户的配额
+// 方法只需要&self，FAT、空闲块、数据块、快照、配额、去重索引与目录数据各自加锁，可以在多个线程中同时调用；
+// 同一目录中的修改由调用者互斥（见FileSystem）
 #[derive(Serialize, Deserialize)]
 pub struct DiskOperator {
     pub disk: Disk,
-    pub cur_dir: Directory,
+    snapshots: Mutex<Snapshots>,
+    pub options: DiskOptions,
+    quotas: Mutex<Quotas>,
+    #[serde(skip)]
+    pub locks: Arc<LockTable>,
+    #[serde(skip)]
+    pub encryption: Option<Encryption>,
+    #[serde(skip)]
+    pub image: Option<String>, // 镜像的文件名，读取时记录，save命令保存到这里
+    #[serde(skip)]
+    dedup: Mutex<Option<DedupIndex>>, // 第一次去重写入时建立
+    #[serde(skip)]
+    allocating: Mutex<()>, // 配额检查与分配块一起进行，同时分配的线程不会一起超出配额
+    #[serde(skip)]
+    dir_locks: [RwLock<()>; DIR_LOCK_COUNT],
+}
+
+// 目录数据的读写锁按目录首块号分组：读取目录时共享，写回目录时独占，不会读到写入一半的目录
+const DIR_LOCK_COUNT: usize = 32;
+
+thread_local! {
+    // 当前线程正在写入的目录，分配的块计入其各级目录的配额
+    static CHARGED_DIR: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn lock<T>(lock: &Mutex<T>) -> MutexGuard<'_, T> {
+    lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+// 没有配额的旧镜像
+#[derive(Deserialize)]
+struct OptionsImage {
+    disk: Disk,
+    snapshots: Snapshots,
+    options: DiskOptions,
+}
+
+// 没有选项的旧镜像
+#[derive(Deserialize)]
+struct LegacyImage {
+    disk: Disk,
+    snapshots: Snapshots,
+}
+
+// 复制整个磁盘，副本与原来共用锁表；复制期间不能有其他线程修改磁盘
+impl Clone for DiskOperator {
+    fn clone(&self) -> DiskOperator {
+        let mut copy = DiskOperator::from_disk(self.disk.clone());
+        copy.snapshots = Mutex::new(self.snapshots().clone());
+        copy.options = self.options;
+        copy.quotas = Mutex::new(self.quotas().clone());
+        copy.locks = Arc::clone(&self.locks);
+        copy.encryption = self.encryption.clone();
+        copy.image = self.image.clone();
+        copy
+    }
 }
 
 impl DiskOperator {
@@ -113,362 +357,1074 @@ impl DiskOperator {
         // print_info();
         // println!("Creating a new disk...");
 
-        let mut disk = Disk::new();
+        let disk = Disk::new();
+
+        let root_dir = match root_dir {
+            Some(directory) => directory,
+            None => Directory {
+                name: String::from("root"),
+                files: vec![
+                    Fcb::new(".", FileType::Directory, 0, 0),
+                    Fcb::new("..", FileType::Directory, 0, 0),
+                ],
+            },
+        };
 
         let dir_data = bincode::serialize(&root_dir).unwrap();
-        disk.insert_data_in_offset(dir_data.as_slice(), 0); // 将根目录序列化后写入磁盘
-        disk.fat[0] = FATItem::EOF; // 根目录的FAT表项为EOF
+        let (eof, _) = DiskOperator::calculate_blocks_with_eof(dir_data.len());
+        disk.write_in_clusters(dir_data.as_slice(), &[0], eof); // 将根目录序列化后写入磁盘
+        disk.set_fat(0, FATItem::EOF); // 根目录的FAT表项为EOF
+
+        DiskOperator::from_disk(disk)
+    }
 
+    // 以已有的磁盘数据构造，没有快照
+    pub fn from_disk(disk: Disk) -> DiskOperator {
         DiskOperator {
             disk,
-            cur_dir: match root_dir {
-                Some(directory) => directory,
-                None => Directory {
-                    name: String::from("root"),
-                    files: vec![
-                        Fcb {
-                            name: String::from("."),
-                            file_type: FileType::Directory,
-                            first_cluster: 0,
-                            length: 0,
-                        },
-                        Fcb {
-                            name: String::from(".."),
-                            file_type: FileType::Directory,
-                            first_cluster: 0,
-                            length: 0,
-                        },
-                    ],
-                },
-            },
+            snapshots: Mutex::default(),
+            options: DiskOptions::default(),
+            quotas: Mutex::default(),
+            locks: Arc::default(),
+            encryption: None,
+            image: None,
+            dedup: Mutex::default(),
+            allocating: Mutex::default(),
+            dir_locks: Default::default(),
         }
     }
 
-    // 找到第一个unused
-    pub fn find_empty_block(&self) -> Option<usize> {
-        for i in 0..self.disk.fat.len() {
-            if let FATItem::UnUsed = self.disk.fat[i] {
-                return Some(i);
-            }
-        }
-        None
+    pub fn snapshots(&self) -> MutexGuard<'_, Snapshots> {
+        lock(&self.snapshots)
     }
 
-    // 分配指定数量的块，返回块号数组
-    pub fn allocate_block(&mut self, cnumber: usize) -> Result<Vec<usize>, &'static str> {
-        // print_info();
-        // println!("Allocating {} clusters...", cnumber);
+    pub fn quotas(&self) -> MutexGuard<'_, Quotas> {
+        lock(&self.quotas)
+    }
 
-        let mut clusters: Vec<usize> = Vec::with_capacity(cnumber);
-        for i in 0..cnumber {
-            clusters.push(match self.find_empty_block() {
-                Some(cluster) => cluster,
-                _ => return Err("No enough space!"),
-            });
+    fn dedup(&self) -> MutexGuard<'_, Option<DedupIndex>> {
+        lock(&self.dedup)
+    }
 
-            // print_debug();
-            // println!("Allocated cluster: {}", clusters[i]);
+    // 首块号为cluster的目录的数据所在组的读写锁
+    fn dir_lock(&self, cluster: usize) -> &RwLock<()> {
+        &self.dir_locks[cluster % DIR_LOCK_COUNT]
+    }
 
-            let cur_cluster = clusters[i];
-            if i > 0 {
-                self.disk.fat[clusters[i - 1]] = FATItem::Cluster(cur_cluster);
-            }
-            self.disk.fat[cur_cluster] = FATItem::EOF;
+    // 从本地文件读取虚拟磁盘，加密的镜像返回Encrypted，需要用load_encrypted读取
+    pub fn load(name: &str) -> FsResult<DiskOperator> {
+        let data = fs::read(name).map_err(|err| FsError::Io(format!("{}: {}", name, err)))?;
+        if crypto::is_encrypted(&data) {
+            return Err(FsError::Encrypted(name.to_string()));
         }
-        Ok(clusters)
+        let mut vd = DiskOperator::decode(name, &data)?;
+        vd.image = Some(name.to_string());
+        Ok(vd)
     }
 
-    // 查找某块开始的后面的块
-    fn get_series(&self, start: usize) -> Result<Vec<usize>, String> {
-        // print_info();
-        // println!("Getting series from cluster {}...", start);
-
-        let mut clusters: Vec<usize> = Vec::new();
-        let mut cur_cluster = start;
+    // 以口令读取加密的镜像，未加密的镜像忽略口令
+    pub fn load_encrypted(name: &str, passphrase: &str) -> FsResult<DiskOperator> {
+        let data = fs::read(name).map_err(|err| FsError::Io(format!("{}: {}", name, err)))?;
+        if !crypto::is_encrypted(&data) {
+            let mut vd = DiskOperator::decode(name, &data)?;
+            vd.image = Some(name.to_string());
+            return Ok(vd);
+        }
+        let (encryption, data) = Encryption::open(&data, passphrase)?;
+        let mut vd = DiskOperator::decode(name, &data)?;
+        vd.encryption = Some(encryption);
+        vd.image = Some(name.to_string());
+        Ok(vd)
+    }
 
-        clusters.push(cur_cluster);
-        loop {
-            match self.disk.fat[cur_cluster] {
-                FATItem::Cluster(cluster) => {
-                    clusters.push(cluster);
-                    cur_cluster = cluster;
-                }
-                FATItem::EOF => {
-                    // print_debug();
-                    // println!("Series end with {}.", cur_cluster);
-                    break Ok(clusters);
-                }
-                FATItem::BadCluster => {
-                    cur_cluster += 1;
-                    continue;
-                }
-                _ => {
-                    break Err(format!("Unexpected FATItem: {}", cur_cluster));
+    // 没有配额的旧镜像没有配额，没有选项的旧镜像使用默认选项，没有快照表的旧镜像只包含磁盘数据
+    fn decode(name: &str, data: &[u8]) -> FsResult<DiskOperator> {
+        bincode::deserialize(data).or_else(|err| {
+            if let Ok(image) = bincode::deserialize::<OptionsImage>(data) {
+                let mut vd = DiskOperator::from_disk(image.disk);
+                vd.snapshots = Mutex::new(image.snapshots);
+                vd.options = image.options;
+                return Ok(vd);
+            }
+            match bincode::deserialize::<LegacyImage>(data) {
+                Ok(image) => {
+                    let mut vd = DiskOperator::from_disk(image.disk);
+                    vd.snapshots = Mutex::new(image.snapshots);
+                    Ok(vd)
                 }
+                Err(_) => bincode::deserialize(data).map(DiskOperator::from_disk),
             }
+            .map_err(|_| FsError::Corrupted(format!("{}: {}", name, err)))
+        })
+    }
+
+    // 将虚拟磁盘保存至本地文件
+    pub fn save(&self, name: &str) -> FsResult<()> {
+        let mut data = bincode::serialize(self).map_err(|err| FsError::Io(err.to_string()))?;
+        if let Some(encryption) = &self.encryption {
+            data = encryption.seal(&data)?;
         }
+        fs::write(name, data.as_slice()).map_err(|err| FsError::Io(format!("{}: {}", name, err)))
+    }
+
+    // 分配指定数量的块，返回块号数组；空间不足或超出配额时不分配任何块
+    // 分配的块记入当前用户名下，并计入正在写入的目录的配额
+    pub fn allocate_block(&self, session: &Session, cnumber: usize) -> FsResult<Vec<usize>> {
+        // print_info();
+        // println!("Allocating {} clusters...", cnumber);
+
+        let _allocating = lock(&self.allocating);
+        quota::check(self, session.user(), CHARGED_DIR.get(), cnumber, 0)?;
+        let clusters = self.disk.allocate(cnumber).ok_or(FsError::NoSpace)?;
+        self.quotas().charge(&clusters, session.user());
+        Ok(clusters)
+    }
+
+    // 执行op，期间当前线程分配的块计入首块号为dir的目录及其各级祖先的配额
+    fn charged<T, F: FnOnce(&DiskOperator) -> FsResult<T>>(&self, dir: usize, op: F) -> FsResult<T> {
+        let previous = CHARGED_DIR.replace(Some(dir));
+        let result = op(self);
+        CHARGED_DIR.set(previous);
+        result
+    }
+
+    // 查找某块开始的后面的块，稀疏文件只包含已分配的块
+    pub fn get_series(&self, start: usize) -> FsResult<Vec<usize>> {
+        Ok(sparse::clusters(&self.get_layout(start)?))
     }
 
-    // 从start删除指定块序列, 返回series
-    fn delete_series(&mut self, start: usize) -> Result<Vec<usize>, String> {
+    // 某块开始的块布局，空洞为None
+    pub fn get_layout(&self, start: usize) -> FsResult<Vec<Option<usize>>> {
+        self.disk.layout(start)
+    }
+
+    // 从start删除指定块序列, 返回series；被快照或去重共享的块只减少引用计数
+    pub fn delete_series(&self, start: usize) -> FsResult<Vec<usize>> {
         // print_info();
         // println!("Deleting series from cluster {}...", start);
 
+        self.release_series(&mut self.dedup(), start)
+    }
+
+    // 释放期间持有去重索引的锁，同时进行的去重写入不会共享正在释放的块
+    fn release_series(&self, dedup: &mut Option<DedupIndex>, start: usize) -> FsResult<Vec<usize>> {
         let series = self.get_series(start)?;
-        let clusters = series.clone();
-        for cluster in clusters {
-            self.disk.fat[cluster] = FATItem::UnUsed;
+        for &cluster in &series {
+            if self.snapshots().release(cluster) {
+                if let Some(index) = dedup {
+                    index.forget(cluster);
+                }
+                self.free_cluster(cluster);
+            }
         }
         Ok(series)
     }
 
-    fn calculate_blocks_with_eof(len: usize) -> (bool, usize) {
+    pub fn calculate_blocks_with_eof(len: usize) -> (bool, usize) {
+        // 空数据也占用一块，只写入EOF
+        if len == 0 {
+            return (true, 1);
+        }
         let mut number: f32 = len as f32 / BLOCK_SIZE as f32;
         let mut eof = false;
         if number.fract() != 0.00000 {
-            number = number + 1.0;
+            number += 1.0;
             eof = true;
         }
         (eof, number as usize)
     }
 
     // 写入数据，返回数据开始块号
-    pub fn write_to_disk(&mut self, data: &[u8]) -> usize {
+    pub fn write_to_disk(&self, session: &Session, data: &[u8]) -> FsResult<usize> {
         // print_info();
         // println!("Writing data to disk...");
 
         let (eof, blocks_number) = DiskOperator::calculate_blocks_with_eof(data.len());
-        let clusters = self.allocate_block(blocks_number).unwrap();
+        let clusters = self.allocate_block(session, blocks_number)?;
         self.disk.write_in_clusters(data, clusters.as_slice(), eof);
 
         // print_debug();
         // println!("Data written to disk, start cluster: {}", clusters[0]);
-        clusters[0]
+        Ok(clusters[0])
+    }
+
+    // 写入文件数据，返回开始块号；开启去重时与已有块链尾部相同的部分被共享
+    pub fn write_file_to_disk(&self, session: &Session, data: &[u8]) -> FsResult<usize> {
+        if !self.options.dedup {
+            return self.write_to_disk(session, data);
+        }
+        // 去重写入期间持有索引的锁，候选块不会在共享之前被释放或原地改写
+        let mut dedup = self.dedup();
+        if dedup.is_none() {
+            *dedup = Some(self.build_dedup_index()?);
+        }
+        self.write_dedup(session, &mut dedup, data)
+    }
+
+    // 从最后一块向前写入，后继相同且内容相同的块直接共享；
+    // 一块没有共享之后，其前面的块的后继都是新块，不再查找
+    fn write_dedup(&self, session: &Session, dedup: &mut Option<DedupIndex>, data: &[u8]) -> FsResult<usize> {
+        let mut next: Option<usize> = None;
+        let mut sharing = true;
+        for block in dedup::blocks(data).iter().rev() {
+            let key = dedup::key(block, next);
+            let found = match (sharing, dedup.as_ref()) {
+                (true, Some(index)) => index.candidates(key).iter().copied().find(|&cluster| self.same_block(cluster, block, next)),
+                _ => None,
+            };
+            let cluster = match found {
+                Some(cluster) => {
+                    self.snapshots().share(cluster);
+                    cluster
+                }
+                None => {
+                    sharing = false;
+                    let cluster = match self.allocate_block(session, 1) {
+                        Ok(clusters) => clusters[0],
+                        Err(err) => {
+                            // 释放已写入的部分
+                            if let Some(first) = next {
+                                self.release_series(dedup, first)?;
+                            }
+                            return Err(err);
+                        }
+                    };
+                    let mut buffer = block.clone();
+                    buffer.resize(BLOCK_SIZE, 0);
+                    self.disk.insert_data_in_cluster(&buffer, cluster);
+                    self.disk.set_fat(cluster, next.map_or(FATItem::EOF, FATItem::Cluster));
+                    if let Some(index) = dedup {
+                        index.insert(cluster, key);
+                    }
+                    cluster
+                }
+            };
+            next = Some(cluster);
+        }
+        Ok(next.unwrap_or_default())
+    }
+
+    // 块的后继与有效内容是否与给出的相同
+    fn same_block(&self, cluster: usize, block: &[u8], next: Option<usize>) -> bool {
+        let same_next = match (self.disk.fat_item(cluster), next) {
+            (FATItem::EOF, None) => true,
+            (FATItem::Cluster(cluster), Some(next)) => cluster == next,
+            _ => false,
+        };
+        same_next && self.disk.read_in_cluster(cluster)[..block.len()] == *block
+    }
+
+    // 以当前目录树与各个快照中的文件建立去重索引
+    fn build_dedup_index(&self) -> FsResult<DedupIndex> {
+        let mut index = DedupIndex::default();
+        let roots: Vec<usize> = self.snapshots().list().iter().map(|snapshot| snapshot.root()).collect();
+        for root in std::iter::once(0).chain(roots) {
+            self.index_directory(root, &mut index)?;
+        }
+        Ok(index)
+    }
+
+    fn index_directory(&self, cluster: usize, index: &mut DedupIndex) -> FsResult<()> {
+        for fcb in self.get_directory_by_cluster(cluster)?.entries() {
+            if fcb.file_type == FileType::Directory {
+                self.index_directory(fcb.first_cluster, index)?;
+                continue;
+            }
+            // 稀疏文件的块与数据的位置不对应，不参与去重
+            let layout = self.get_layout(fcb.first_cluster)?;
+            if layout.contains(&None) {
+                continue;
+            }
+            let length = self.stored_size(fcb)?;
+            let series = sparse::clusters(&layout);
+            for (position, &cluster) in series.iter().enumerate() {
+                let block = self.disk.read_in_cluster(cluster);
+                let block = &block[..dedup::block_len(length, position)];
+                index.insert(cluster, dedup::key(block, series.get(position + 1).copied()));
+            }
+        }
+        Ok(())
+    }
+
+    // 块号改变后去重索引失效，下次去重写入时重建
+    pub fn clear_dedup_index(&self) {
+        *self.dedup() = None;
+    }
+
+    // 释放块，同时将其移出配额记录；文件的块在原地改写或释放前已移出去重索引
+    fn free_cluster(&self, cluster: usize) {
+        self.quotas().forget(cluster);
+        self.disk.free(cluster);
+    }
+
+    // 在start开始的块序列上覆盖写入数据，按需追加或释放尾部块，首块号不变
+    // 追加的块先分配好，改写块链与数据时持有该目录数据的锁，读取目录的线程看到的是改写前或改写后的内容
+    fn rewrite_series(&self, session: &Session, start: usize, data: &[u8]) -> FsResult<()> {
+        let mut series = self.get_series(start)?;
+        let (eof, blocks_number) = DiskOperator::calculate_blocks_with_eof(data.len());
+
+        if blocks_number > series.len() {
+            series.extend(self.allocate_block(session, blocks_number - series.len())?);
+        }
+        let freed = series.split_off(blocks_number);
+        {
+            let _dir = write(self.dir_lock(start));
+            self.disk.link(&series.iter().copied().map(Some).collect::<Vec<_>>());
+            self.disk.write_in_clusters(data, series.as_slice(), eof);
+        }
+        for cluster in freed {
+            self.free_cluster(cluster);
+        }
+        Ok(())
     }
 
     // 在当前文件夹新建文件夹
-    pub fn new_directory(&mut self, name: &str) -> Result<(), &'static str> {
+    pub fn new_directory(&self, session: &Session, name: &str) -> FsResult<()> {
+        let cluster = session.cwd();
+        self.new_directory_at(session, cluster, name).map(|_| ())
+    }
+
+    // 在首块号为parent的文件夹下新建文件夹，返回新文件夹首块号
+    pub fn new_directory_at(&self, session: &Session, parent: usize, name: &str) -> FsResult<usize> {
+        self.new_directory_with(session, parent, name, None)
+    }
+
+    // 新建文件夹并指定其中新建的文件默认使用的压缩算法，为None时沿用所在文件夹的算法
+    pub fn new_directory_with(
+        &self,
+        session: &Session,
+        parent: usize,
+        name: &str,
+        compression: Option<Compression>,
+    ) -> FsResult<usize> {
+        self.charged(parent, |vd| vd.create_directory(session, parent, name, compression))
+    }
+
+    fn create_directory(
+        &self,
+        session: &Session,
+        parent: usize,
+        name: &str,
+        compression: Option<Compression>,
+    ) -> FsResult<usize> {
         // print_info();
         // println!("Creating new directory: {}", name);
         // print_debug();
         // println!("Trying to write to disk");
 
-        if let Some((_, _)) = self.cur_dir.get_fcb(name) {
-            return Err("Directory already exists!");
+        check_name(name)?;
+        let mut parent_dir = self.get_directory_by_cluster(parent)?;
+        if let Some((_, _)) = parent_dir.get_fcb(name) {
+            return Err(FsError::AlreadyExists(name.to_string()));
         }
+        quota::check(self, session.user(), Some(parent), 0, 1)?;
+        let compression = compression.unwrap_or(parent_dir.files[0].compression);
+        // 先分配首块，.指向分配到的块
+        let first_cluster = self.allocate_block(session, 1)?[0];
 
         // 创新新目录，添加.和..
         let mut new_dir = Directory::new(name);
-        // ?
         new_dir.files.push(Fcb {
             name: String::from("."),
             file_type: FileType::Directory,
-            first_cluster: self.find_empty_block().unwrap(),
+            first_cluster,
             length: 0,
+            modified: now(),
+            compression,
         });
 
         new_dir.files.push(Fcb {
             name: String::from(".."),
             file_type: FileType::Directory,
-            first_cluster: self.cur_dir.files[0].first_cluster,
+            first_cluster: parent,
             length: 0,
+            modified: now(),
+            compression: Compression::None,
         });
 
         // 将新目录序列化后写入磁盘
-        let bin_dir = bincode::serialize(&new_dir).unwrap();
-
-        let first_cluster = self.write_to_disk(bin_dir.as_slice());
+        if let Err(err) = self.save_dir_to_disk(session, &new_dir) {
+            self.delete_series(first_cluster)?;
+            return Err(err);
+        }
         // print_debug();
         // println!("adding FCB to current directory...");
 
-        self.cur_dir.files.push(Fcb {
+        parent_dir.files.push(Fcb {
             name: String::from(name),
             file_type: FileType::Directory,
             first_cluster,
             length: 0,
+            modified: now(),
+            compression,
         });
-        // 当前文件夹未更新数据写入磁盘，只增加了fcb，写入磁盘的操作在set_current_dir中
+        if let Err(err) = self.save_dir_to_disk(session, &parent_dir) {
+            self.delete_series(first_cluster)?;
+            return Err(err);
+        }
         // print_debug();
         // println!("Directory {} created successfully!", name);
 
-        Ok(())
+        Ok(first_cluster)
     }
 
     // 根据首块获得数据
-    fn get_data_by_first_cluster(&self, first_cluster: usize) -> Vec<u8> {
+    fn get_data_by_first_cluster(&self, first_cluster: usize) -> FsResult<Vec<u8>> {
         // print_debug();
         // println!("Getting data by clusters...");
 
-        let clusters = self.get_series(first_cluster).unwrap();
+        let clusters = self.get_series(first_cluster)?;
         let data = self.disk.read_in_clusters(clusters.as_slice());
 
         // print_debug();
         // println!("Data read successfully!");
 
-        data
+        Ok(data)
+    }
+
+    // 通过首块号获取目录
+    pub fn get_directory_by_cluster(&self, cluster: usize) -> FsResult<Directory> {
+        let data = {
+            let _dir = read(self.dir_lock(cluster));
+            self.get_data_by_first_cluster(cluster)?
+        };
+        // 反序列化
+        bincode::deserialize(data.as_slice())
+            .map_err(|err| FsError::Corrupted(format!("Directory at cluster {}: {}", cluster, err)))
     }
 
     // 通过FCB获取目录
-    fn get_directory_by_fcb(&self, fcb: &Fcb) -> Directory {
+    pub fn get_directory_by_fcb(&self, fcb: &Fcb) -> FsResult<Directory> {
         // print_debug();
         // println!("Getting directory by FCB {:?}...", fcb);
 
         match fcb.file_type {
-            FileType::Directory => {
-                let data = self.get_data_by_first_cluster(fcb.first_cluster);
-                // 反序列化
-                let dir = bincode::deserialize(data.as_slice()).unwrap();
-                dir
+            FileType::Directory => self.get_directory_by_cluster(fcb.first_cluster),
+            _ => Err(FsError::NotADirectory(fcb.name.clone())),
+        }
+    }
+
+    // 按路径获取目录，以/开头时从根目录开始查找，否则从当前目录开始
+    pub fn get_directory_by_path(&self, session: &Session, path: &str) -> FsResult<Directory> {
+        let mut dir = if path.starts_with('/') {
+            self.get_directory_by_cluster(0)?
+        } else {
+            self.current_dir(session)?
+        };
+
+        for name in path.split('/') {
+            if name.is_empty() || name == "." {
+                continue;
             }
-            _ => panic!("Not a directory!"),
+            dir = match dir.get_fcb(name) {
+                Some((_, fcb)) => self.get_directory_by_fcb(fcb)?,
+                None => return Err(FsError::NotFound(name.to_string())),
+            };
         }
+        Ok(dir)
     }
 
     // 通过FCB获取文件
-    fn get_file_by_fcb(&self, fcb: &Fcb) -> Vec<u8> {
+    pub fn get_file_by_fcb(&self, fcb: &Fcb) -> FsResult<Vec<u8>> {
         // print_info();
         // println!("Getting file by FCB {:?}...", fcb);
 
-        match fcb.file_type {
-            FileType::File => self.get_data_by_first_cluster(fcb.first_cluster),
-            _ => panic!("Not a file!"),
-        }
+        self.read_at(fcb, 0, fcb.length)
     }
 
-    // 通过FCB删除文件,先删除占用的磁盘块，再从当前文件夹删除FCB
-    fn delete_file_by_fcb(&mut self, fcb: &Fcb) -> Result<(), String> {
-        // print_info();
-        // println!("Deleting file by FCB {:?}...", fcb);
+    // 从文件的offset处读取至多len字节，只读取涉及的块；文件长度以FCB为准
+    pub fn read_at(&self, fcb: &Fcb, offset: usize, len: usize) -> FsResult<Vec<u8>> {
+        if fcb.file_type == FileType::Directory {
+            return Err(FsError::IsADirectory(fcb.name.clone()));
+        }
+        let end = fcb.length.min(offset.saturating_add(len));
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        // 压缩文件整体解压后截取
+        if !fcb.compression.is_none() {
+            return Ok(self.read_content(fcb)?[offset..end].to_vec());
+        }
 
-        if let FileType::Directory = fcb.file_type {
-            let dir = self.get_directory_by_fcb(fcb);
-            if dir.files.len() > 2 {
-                return Err("Directory is not empty!".to_string());
+        let layout = self.get_layout(fcb.first_cluster)?;
+        if (end - 1) / BLOCK_SIZE >= layout.len() {
+            return Err(FsError::Corrupted(format!("{}: cluster chain shorter than file", fcb.name)));
+        }
+        // 空洞读取为0
+        let mut data = Vec::with_capacity(end - offset);
+        for (index, range) in sparse::block_ranges(offset, end) {
+            match layout[index] {
+                Some(cluster) => self.disk.read_range(cluster, range, &mut data),
+                None => data.resize(data.len() + range.len(), 0),
             }
         }
+        Ok(data)
+    }
 
-        if let Err(err) = self.delete_series(fcb.first_cluster) {
-            return Err(err);
+    // 块链中全部块的原始数据，含末尾的EOF与填充
+    fn read_raw(&self, start: usize) -> FsResult<Vec<u8>> {
+        let series = self.get_series(start)?;
+        let mut data = Vec::with_capacity(series.len() * BLOCK_SIZE);
+        for cluster in series {
+            self.disk.read_range(cluster, 0..BLOCK_SIZE, &mut data);
+        }
+        Ok(data)
+    }
+
+    // 文件的完整内容，压缩文件返回解压后的数据
+    pub fn read_content(&self, fcb: &Fcb) -> FsResult<Vec<u8>> {
+        if fcb.compression.is_none() {
+            return self.get_file_by_fcb(fcb);
+        }
+        fcb.compression
+            .decompress(&self.read_raw(fcb.first_cluster)?, fcb.length)
+            .map_err(|err| FsError::Corrupted(format!("{}: {}", fcb.name, err)))
+    }
+
+    // 文件在块链中实际存储的字节数，压缩文件为压缩后的大小
+    pub fn stored_size(&self, fcb: &Fcb) -> FsResult<usize> {
+        if fcb.compression.is_none() {
+            return Ok(fcb.length);
+        }
+        compression::stored_size(&self.read_raw(fcb.first_cluster)?)
+    }
+
+    // 文件或目录占用的块的字节数，不含空洞
+    pub fn allocated_size(&self, fcb: &Fcb) -> usize {
+        self.get_series(fcb.first_cluster).map_or(0, |series| series.len() * BLOCK_SIZE)
+    }
+
+    // 压缩文件的改写：解压后修改，重新压缩写回原来的块链
+    fn update_compressed<F>(
+        &self,
+        session: &Session,
+        parent_dir: Directory,
+        index: usize,
+        fcb: &Fcb,
+        update: F,
+    ) -> FsResult<()>
+    where
+        F: FnOnce(&mut Vec<u8>) -> FsResult<()>,
+    {
+        let mut content = self.read_content(fcb)?;
+        update(&mut content)?;
+        self.rewrite_series(session, fcb.first_cluster, &fcb.compression.compress(&content)?)?;
+        self.set_file_length(session, parent_dir, index, content.len())
+    }
+
+    // 在块布局中从offset处写入data，offset超过原长度时中间补0，返回新的长度；
+    // 只改写涉及的块，写入的部分落在空洞中时分配新块，文件末尾之后没有写入数据的块留作空洞
+    fn write_series_at(&self, session: &Session, start: usize, length: usize, offset: usize, data: &[u8]) -> FsResult<usize> {
+        let end = sparse::check_range(offset, data.len())?;
+        let new_length = length.max(end);
+        let mut layout = self.get_layout(start)?;
+        let (eof, blocks_number) = DiskOperator::calculate_blocks_with_eof(new_length);
+        if blocks_number > layout.len() {
+            layout.resize(blocks_number, None);
+        }
+        let holes = sparse::holes(&layout, offset, end);
+        let clusters = self.allocate_block(session, holes.len())?;
+        for (index, cluster) in holes.into_iter().zip(clusters) {
+            self.disk.zero_in_cluster(cluster, 0..BLOCK_SIZE);
+            layout[index] = Some(cluster);
         }
+        self.disk.link(&layout);
 
-        match self.cur_dir.get_fcb(fcb.name.as_str()) {
-            Some(fcb_data) => {
-                let index = fcb_data.0;
-                self.cur_dir.files.remove(index);
+        // 原文件末尾到offset之间补0，空洞本身读取为0
+        if length < offset {
+            self.zero_range(&layout, length, offset);
+        }
+        self.write_range(&layout, offset, data);
+        if eof {
+            self.write_range(&layout, new_length, &[EOF_BYTE]);
+        }
+        Ok(new_length)
+    }
+
+    // 将块布局截断为length字节，先截断块链再释放多余的块，沿FAT读取的线程不会走到已释放的块
+    fn truncate_series(&self, start: usize, length: usize) -> FsResult<()> {
+        let mut layout = self.get_layout(start)?;
+        let (eof, blocks_number) = DiskOperator::calculate_blocks_with_eof(length);
+        if blocks_number < layout.len() {
+            let freed = layout.split_off(blocks_number);
+            self.disk.link(&layout);
+            for cluster in sparse::clusters(&freed) {
+                self.free_cluster(cluster);
+            }
+        }
+        if eof {
+            self.write_range(&layout, length, &[EOF_BYTE]);
+        }
+        Ok(())
+    }
+
+    // 从块布局的position处写入bytes，落在空洞中的部分被跳过
+    fn write_range(&self, layout: &[Option<usize>], position: usize, bytes: &[u8]) {
+        for (index, range) in sparse::block_ranges(position, position + bytes.len()) {
+            if let Some(cluster) = layout[index] {
+                let from = index * BLOCK_SIZE + range.start - position;
+                self.disk.write_in_cluster(cluster, range.start, &bytes[from..from + range.len()]);
             }
-            None => {
-                return Err("FCB not found!!".to_string());
+        }
+    }
+
+    // 将块布局中[from, to)范围内已分配的部分以0填充
+    fn zero_range(&self, layout: &[Option<usize>], from: usize, to: usize) {
+        for (index, range) in sparse::block_ranges(from, to) {
+            if let Some(cluster) = layout[index] {
+                self.disk.zero_in_cluster(cluster, range);
             }
         }
+    }
 
+    // 找到路径对应的文件，返回所在目录、目录项下标与FCB
+    fn lookup_file(&self, session: &Session, path: &str) -> FsResult<(Directory, usize, Fcb)> {
+        let (dir_path, name) = split_path(path);
+        let parent = self.get_directory_by_path(session, dir_path)?.cluster();
+        self.lookup_file_at(parent, name).map_err(|err| match err {
+            FsError::NotFound(_) => FsError::NotFound(path.to_string()),
+            FsError::IsADirectory(_) => FsError::IsADirectory(path.to_string()),
+            err => err,
+        })
+    }
+
+    fn lookup_file_at(&self, parent: usize, name: &str) -> FsResult<(Directory, usize, Fcb)> {
+        let parent_dir = self.get_directory_by_cluster(parent)?;
+        let (index, fcb) = match parent_dir.get_fcb(name) {
+            Some((index, fcb)) => (index, fcb.clone()),
+            None => return Err(FsError::NotFound(name.to_string())),
+        };
+        if fcb.file_type == FileType::Directory {
+            return Err(FsError::IsADirectory(name.to_string()));
+        }
+        Ok((parent_dir, index, fcb))
+    }
+
+    // 更新目录中文件的长度与修改时间
+    fn set_file_length(&self, session: &Session, mut parent_dir: Directory, index: usize, length: usize) -> FsResult<()> {
+        let old_length = parent_dir.files[index].length;
+        parent_dir.files[index].length = length;
+        parent_dir.files[index].modified = now();
+        parent_dir.files[0].length = parent_dir.files[0].length.saturating_sub(old_length) + length;
+        self.save_dir_to_disk(session, &parent_dir)?;
         Ok(())
     }
 
+    // 写入被快照共享的文件前先将其块链复制一份并立即更新目录，返回更新后的FCB
+    // 没有被共享的块将被原地改写，先移出去重索引，之后不会再被共享
+    fn unshare_file(&self, session: &Session, parent_dir: &mut Directory, index: usize) -> FsResult<Fcb> {
+        let fcb = parent_dir.files[index].clone();
+        let layout = self.get_layout(fcb.first_cluster)?;
+        let series = sparse::clusters(&layout);
+        {
+            let mut dedup = self.dedup();
+            let snapshots = self.snapshots();
+            if !series.iter().any(|&cluster| snapshots.is_shared(cluster)) {
+                if let Some(index) = dedup.as_mut() {
+                    series.iter().for_each(|&cluster| index.forget(cluster));
+                }
+                return Ok(fcb);
+            }
+        }
+        let clusters = self.allocate_block(session, series.len())?;
+        for (&from, &to) in series.iter().zip(&clusters) {
+            self.disk.copy_cluster(from, to);
+        }
+        // 副本保持原来的空洞
+        let mut copies = clusters.iter().copied();
+        let copied: Vec<Option<usize>> = layout.iter().map(|block| block.and_then(|_| copies.next())).collect();
+        self.disk.link(&copied);
+        parent_dir.files[index].first_cluster = clusters[0];
+        if let Err(err) = self.save_dir_to_disk(session, parent_dir) {
+            self.delete_series(clusters[0])?;
+            return Err(err);
+        }
+        self.delete_series(fcb.first_cluster)?;
+        Ok(parent_dir.files[index].clone())
+    }
+
+    // 其他会话在文件[start, end)上的强制锁拒绝写入
+    fn check_write(&self, session: &Session, parent_dir: &Directory, fcb: &Fcb, start: usize, end: usize) -> FsResult<()> {
+        let file = FileHandle::new(parent_dir.cluster(), &fcb.name);
+        self.locks.check_write(&file, session.id(), start as u64, end as u64)
+    }
+
+    // 从文件的offset处写入数据，只改写涉及的块
+    pub fn write_at(&self, session: &Session, path: &str, offset: usize, data: &[u8]) -> FsResult<()> {
+        let (parent_dir, index, fcb) = self.lookup_file(session, path)?;
+        self.charged(parent_dir.cluster(), |vd| vd.write_entry(session, parent_dir, index, &fcb, offset, data))
+    }
+
+    // 对首块号为parent的文件夹下的文件name从offset处写入数据
+    pub fn write_file_at(&self, session: &Session, parent: usize, name: &str, offset: usize, data: &[u8]) -> FsResult<()> {
+        let (parent_dir, index, fcb) = self.lookup_file_at(parent, name)?;
+        self.charged(parent, |vd| vd.write_entry(session, parent_dir, index, &fcb, offset, data))
+    }
+
+    fn write_entry(
+        &self,
+        session: &Session,
+        mut parent_dir: Directory,
+        index: usize,
+        fcb: &Fcb,
+        offset: usize,
+        data: &[u8],
+    ) -> FsResult<()> {
+        // 偏移来自客户端，检查溢出后再检查锁与分配块
+        let end = sparse::check_range(offset, data.len())?;
+        self.check_write(session, &parent_dir, fcb, offset, end)?;
+        let fcb = self.unshare_file(session, &mut parent_dir, index)?;
+        if !fcb.compression.is_none() {
+            return self.update_compressed(session, parent_dir, index, &fcb, |content| write_into(content, offset, data));
+        }
+        let length = self.write_series_at(session, fcb.first_cluster, fcb.length, offset, data)?;
+        self.set_file_length(session, parent_dir, index, length)
+    }
+
+    // 将文件截断或以0扩展为length字节
+    pub fn truncate(&self, session: &Session, path: &str, length: usize) -> FsResult<()> {
+        let (parent_dir, index, fcb) = self.lookup_file(session, path)?;
+        self.charged(parent_dir.cluster(), |vd| vd.resize_file(session, parent_dir, index, &fcb, length))
+    }
+
+    // 将首块号为parent的文件夹下的文件name截断或扩展为length字节
+    pub fn truncate_at(&self, session: &Session, parent: usize, name: &str, length: usize) -> FsResult<()> {
+        let (parent_dir, index, fcb) = self.lookup_file_at(parent, name)?;
+        self.charged(parent, |vd| vd.resize_file(session, parent_dir, index, &fcb, length))
+    }
+
+    // 扩展的部分是空洞，不分配块
+    fn resize_file(
+        &self,
+        session: &Session,
+        mut parent_dir: Directory,
+        index: usize,
+        fcb: &Fcb,
+        length: usize,
+    ) -> FsResult<()> {
+        sparse::check_length(length)?;
+        // 截断或扩展改变的是length与原长度之间的部分
+        self.check_write(session, &parent_dir, fcb, length.min(fcb.length), length.max(fcb.length))?;
+        let fcb = &self.unshare_file(session, &mut parent_dir, index)?;
+        if !fcb.compression.is_none() {
+            return self.update_compressed(session, parent_dir, index, fcb, |content| {
+                content.resize(length, 0);
+                Ok(())
+            });
+        }
+        if length > fcb.length {
+            self.write_series_at(session, fcb.first_cluster, fcb.length, length, &[])?;
+        } else {
+            self.truncate_series(fcb.first_cluster, length)?;
+        }
+        self.set_file_length(session, parent_dir, index, length)
+    }
+
+    // 在文件的[offset, offset + length)范围内打洞，文件长度不变：范围完全覆盖的块被释放，
+    // 部分覆盖的块以0填充，文件的第一块总是保留；压缩文件没有空洞，只将范围内的数据改为0
+    pub fn punch_hole(&self, session: &Session, path: &str, offset: usize, length: usize) -> FsResult<()> {
+        let (parent_dir, index, fcb) = self.lookup_file(session, path)?;
+        self.charged(parent_dir.cluster(), |vd| vd.punch_entry(session, parent_dir, index, &fcb, offset, length))
+    }
+
+    fn punch_entry(
+        &self,
+        session: &Session,
+        mut parent_dir: Directory,
+        index: usize,
+        fcb: &Fcb,
+        offset: usize,
+        length: usize,
+    ) -> FsResult<()> {
+        let end = fcb.length.min(offset.saturating_add(length));
+        if offset >= end {
+            return Ok(());
+        }
+        self.check_write(session, &parent_dir, fcb, offset, end)?;
+        let fcb = self.unshare_file(session, &mut parent_dir, index)?;
+        if !fcb.compression.is_none() {
+            return self.update_compressed(session, parent_dir, index, &fcb, |content| {
+                content[offset..end].fill(0);
+                Ok(())
+            });
+        }
+
+        let mut layout = self.get_layout(fcb.first_cluster)?;
+        // 范围到达文件末尾时最后一块中剩余的数据也被覆盖
+        let last = if end == fcb.length { end.div_ceil(BLOCK_SIZE) } else { end / BLOCK_SIZE };
+        let freed: Vec<usize> = (offset.div_ceil(BLOCK_SIZE).max(1)..last.min(layout.len()))
+            .filter_map(|block| layout[block].take())
+            .collect();
+        self.disk.link(&layout);
+        for cluster in freed {
+            self.free_cluster(cluster);
+        }
+        self.zero_range(&layout, offset, end);
+        self.set_file_length(session, parent_dir, index, fcb.length)
+    }
+
     // 当前文件夹创建文件
-    pub fn new_file(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+    pub fn new_file(&self, session: &Session, name: &str, data: &[u8]) -> FsResult<()> {
+        let cluster = session.cwd();
+        self.new_file_at(session, cluster, name, data)
+    }
+
+    // 在首块号为parent的文件夹下创建文件，使用文件夹默认的压缩算法
+    pub fn new_file_at(&self, session: &Session, parent: usize, name: &str, data: &[u8]) -> FsResult<()> {
+        self.new_file_with(session, parent, name, data, None)
+    }
+
+    // 以指定的压缩算法创建文件，为None时使用文件夹默认的算法
+    pub fn new_file_with(
+        &self,
+        session: &Session,
+        parent: usize,
+        name: &str,
+        data: &[u8],
+        compression: Option<Compression>,
+    ) -> FsResult<()> {
+        self.charged(parent, |vd| vd.create_file(session, parent, name, data, compression))
+    }
+
+    fn create_file(
+        &self,
+        session: &Session,
+        parent: usize,
+        name: &str,
+        data: &[u8],
+        compression: Option<Compression>,
+    ) -> FsResult<()> {
         // print_info();
         // println!("Creating new file: {}", name);
 
-        if self.cur_dir.get_fcb(name).is_some() {
-            return Err("File already exists!".to_string());
+        check_name(name)?;
+        let mut parent_dir = self.get_directory_by_cluster(parent)?;
+        if parent_dir.get_fcb(name).is_some() {
+            return Err(FsError::AlreadyExists(name.to_string()));
         }
+        quota::check(self, session.user(), Some(parent), 0, 1)?;
 
         // 写入数据
-        let first_cluster = self.write_to_disk(data);
+        let compression = compression.unwrap_or(parent_dir.files[0].compression);
+        let first_cluster = self.write_file_to_disk(session, &compression.compress(data)?)?;
         let new_file_fcb = Fcb {
             name: String::from(name),
             file_type: FileType::File,
             first_cluster,
             length: data.len(),
+            modified: now(),
+            compression,
         };
-        self.cur_dir.files.push(new_file_fcb);
+        parent_dir.files.push(new_file_fcb);
         
         // 更新文件夹大小，将写入新数据的文件夹重新写入磁盘
         let add_length = data.len();
-        self.cur_dir.files[0].length += add_length;
+        parent_dir.files[0].length += add_length;
+        if let Err(err) = self.save_dir_to_disk(session, &parent_dir) {
+            self.delete_series(first_cluster)?;
+            return Err(err);
+        }
 
         Ok(())
     }
 
-    // 以文件名读取文件
-    pub fn read_file_by_name(&self, name: &str) -> Result<Vec<u8>, String> {
-        match self.cur_dir.get_fcb(name) {
-            Some((_, fcb)) => Ok(self.get_file_by_fcb(fcb)),
-            None => Err("File not found!".to_string()),
+    // 删除首块号为parent的文件夹下的文件，文件夹需为空
+    pub fn delete_file_at(&self, session: &Session, parent: usize, name: &str) -> FsResult<()> {
+        if name == "." || name == ".." {
+            return Err(FsError::InvalidName(name.to_string()));
+        }
+        let mut parent_dir = self.get_directory_by_cluster(parent)?;
+        let (index, fcb) = match parent_dir.get_fcb(name) {
+            Some((index, fcb)) => (index, fcb.clone()),
+            None => return Err(FsError::NotFound(name.to_string())),
+        };
+
+        if let FileType::Directory = fcb.file_type {
+            let dir = self.get_directory_by_cluster(fcb.first_cluster)?;
+            if dir.files.len() > 2 {
+                return Err(FsError::DirectoryNotEmpty(name.to_string()));
+            }
+            // 目录的块被快照共享时不会释放，配额需单独删除
+            self.quotas().set_dir(fcb.first_cluster, Limit::default());
+        } else {
+            // 删除改变整个文件，其他会话的强制锁拒绝删除
+            self.check_write(session, &parent_dir, &fcb, 0, usize::MAX)?;
         }
+
+        // 先从目录中移除再释放块，读取目录的线程不会看到指向已释放的块的目录项
+        parent_dir.files.remove(index);
+        parent_dir.files[0].length = parent_dir.files[0].length.saturating_sub(fcb.length);
+        self.save_dir_to_disk(session, &parent_dir)?;
+        self.delete_series(fcb.first_cluster)?;
+        self.locks.forget(&FileHandle::new(parent, name));
+        Ok(())
     }
 
-    pub fn delete_file_by_name(&mut self, name: &str) -> Result<(), String> {
-        // print_debug();
-        // println!("Deleting file by name: {}, cur_dir: {}", name,self.cur_dir.name);
-        let fcb = match self.cur_dir.get_fcb(name) {
-            Some((_, fcb)) => fcb.clone(),
-            None => return Err("File not found!".to_string()),
+    // 按路径获取FCB，路径指向目录本身（如/、.、..）时返回该目录的.项并以目录名命名
+    pub fn get_fcb_by_path(&self, session: &Session, path: &str) -> FsResult<Fcb> {
+        let (dir_path, name) = split_path(path);
+        if name.is_empty() || name == "." || name == ".." {
+            return Ok(self.get_directory_by_path(session, path)?.own_fcb());
+        }
+        match self.get_directory_by_path(session, dir_path)?.get_entry(name) {
+            Some(fcb) => Ok(fcb.clone()),
+            None => Err(FsError::NotFound(path.to_string())),
+        }
+    }
+
+    // 文件的大小；目录的大小为其自身数据所占的块的字节数，不含其下的文件
+    pub fn get_size(&self, fcb: &Fcb) -> usize {
+        match fcb.file_type {
+            FileType::File => fcb.length,
+            FileType::Directory => self.get_series(fcb.first_cluster).map_or(0, |series| series.len() * BLOCK_SIZE),
+        }
+    }
+
+    // 按路径读取文件
+    pub fn read_file_by_path(&self, session: &Session, path: &str) -> FsResult<Vec<u8>> {
+        let (dir_path, name) = split_path(path);
+        let dir = self.get_directory_by_path(session, dir_path)?;
+        match dir.get_fcb(name) {
+            Some((_, fcb)) => self.get_file_by_fcb(fcb),
+            None => Err(FsError::NotFound(path.to_string())),
+        }
+    }
+
+    // 按路径写入文件，文件不存在时创建，已存在时替换其内容
+    pub fn write_file_by_path(&self, session: &Session, path: &str, data: &[u8]) -> FsResult<()> {
+        let (dir_path, name) = split_path(path);
+        let parent = self.get_directory_by_path(session, dir_path)?.cluster();
+        self.replace_file_at(session, parent, name, data).map_err(|err| match err {
+            FsError::IsADirectory(_) => FsError::IsADirectory(path.to_string()),
+            err => err,
+        })
+    }
+
+    // 在首块号为parent的文件夹下写入文件，不存在时创建，已存在时替换其内容
+    pub fn replace_file_at(&self, session: &Session, parent: usize, name: &str, data: &[u8]) -> FsResult<()> {
+        let mut parent_dir = self.get_directory_by_cluster(parent)?;
+        let (index, fcb) = match parent_dir.get_fcb(name) {
+            Some((index, fcb)) => (index, fcb.clone()),
+            None => return self.new_file_at(session, parent, name, data),
         };
-        let decrease_length = fcb.clone().length;
-        self.cur_dir.files[0].length -= decrease_length;
-        let _ = self.delete_file_by_fcb(&fcb);
+        if fcb.file_type == FileType::Directory {
+            return Err(FsError::IsADirectory(name.to_string()));
+        }
+        self.check_write(session, &parent_dir, &fcb, 0, fcb.length.max(data.len()))?;
+
+        // 先写入新数据，失败时原文件保持不变
+        let compressed = fcb.compression.compress(data)?;
+        let first_cluster = self.charged(parent, |vd| vd.write_file_to_disk(session, &compressed))?;
+        parent_dir.files[index].first_cluster = first_cluster;
+        parent_dir.files[index].length = data.len();
+        parent_dir.files[index].modified = now();
+        parent_dir.files[0].length = parent_dir.files[0].length.saturating_sub(fcb.length) + data.len();
+        if let Err(err) = self.save_dir_to_disk(session, &parent_dir) {
+            self.delete_series(first_cluster)?;
+            return Err(err);
+        }
+        self.delete_series(fcb.first_cluster)?;
+        Ok(())
+    }
 
+    // 设置首块号为parent的文件夹下的文件或文件夹的修改时间
+    pub fn set_modified_at(&self, session: &Session, parent: usize, name: &str, modified: i64) -> FsResult<()> {
+        let mut parent_dir = self.get_directory_by_cluster(parent)?;
+        match parent_dir.get_fcb(name) {
+            Some((index, _)) if name != "." && name != ".." => parent_dir.files[index].modified = modified,
+            Some(_) => return Err(FsError::InvalidName(name.to_string())),
+            None => return Err(FsError::NotFound(name.to_string())),
+        }
+        self.save_dir_to_disk(session, &parent_dir)?;
         Ok(())
     }
 
-    // 将文件夹保存至磁盘，返回初始块号
-    fn save_dir_to_disk(&mut self, dir: &Directory) -> usize {
+    // 按路径在文件末尾追加数据，文件不存在时创建
+    pub fn append_file_by_path(&self, session: &Session, path: &str, data: &[u8]) -> FsResult<()> {
+        match self.lookup_file(session, path) {
+            Ok((_, _, fcb)) => self.write_at(session, path, fcb.length, data),
+            Err(FsError::NotFound(_)) => self.write_file_by_path(session, path, data),
+            Err(err) => Err(err),
+        }
+    }
+
+    // 按路径复制文件，dst为已存在的目录时复制到该目录下并保留文件名
+    pub fn copy_file_by_path(&self, session: &Session, src: &str, dst: &str) -> FsResult<()> {
+        let data = self.read_file_by_path(session, src)?;
+        let (_, src_name) = split_path(src);
+
+        let (parent, name) = match self.get_directory_by_path(session, dst) {
+            Ok(dir) => (dir, src_name),
+            Err(_) => {
+                let (dir_path, name) = split_path(dst);
+                (self.get_directory_by_path(session, dir_path)?, name)
+            }
+        };
+        self.new_file_at(session, parent.cluster(), name, data.as_slice())
+    }
+
+    // 以文件名读取文件
+    pub fn read_file_by_name(&self, session: &Session, name: &str) -> FsResult<Vec<u8>> {
+        match self.current_dir(session)?.get_fcb(name) {
+            Some((_, fcb)) => self.get_file_by_fcb(fcb),
+            None => Err(FsError::NotFound(name.to_string())),
+        }
+    }
+
+    pub fn delete_file_by_name(&self, session: &Session, name: &str) -> FsResult<()> {
         // print_debug();
-        // println!("Saving directory to disk...");
+        let cluster = session.cwd();
+        self.delete_file_at(session, cluster, name)
+    }
 
-        let data = bincode::serialize(dir).unwrap();
-        let (eof, blocks_number) = DiskOperator::calculate_blocks_with_eof(data.len());
-        // 重新分配块
-        let _ = self.delete_series(self.cur_dir.files[0].first_cluster);
+    // 按路径删除文件或空目录
+    pub fn delete_file_by_path(&self, session: &Session, path: &str) -> FsResult<()> {
+        let (dir_path, name) = split_path(path);
+        let cluster = self.get_directory_by_path(session, dir_path)?.cluster();
+        self.delete_file_at(session, cluster, name)
+    }
 
-        let clusters = self.allocate_block(blocks_number).unwrap();
-        self.disk
-            .write_in_clusters(data.as_slice(), clusters.as_slice(), eof);
+    // 将文件夹原地保存至磁盘，返回初始块号
+    pub fn save_dir_to_disk(&self, session: &Session, dir: &Directory) -> FsResult<usize> {
+        // print_debug();
+        // println!("Saving directory to disk...");
 
-        clusters[0]
+        let data = serialize_dir(dir)?;
+        let cluster = dir.cluster();
+        self.rewrite_series(session, cluster, data.as_slice())?;
+        Ok(cluster)
     }
 
-    // 保存当前文件夹至磁盘,并以文件夹名称切换当前文件夹
-    pub fn set_current_dir(&mut self, name: &str) {
-        let dir = self.cur_dir.clone();
-        self.save_dir_to_disk(&dir);
+    // 当前会话所在的目录
+    pub fn current_dir(&self, session: &Session) -> FsResult<Directory> {
+        self.get_directory_by_cluster(session.cwd())
+    }
 
-        if name == ".." {
-            let size = self.cur_dir.files[0].length.clone();
-            let cur_name = self.cur_dir.name.clone();
-            let (_, fcb) = self.cur_dir.get_fcb(name).unwrap();
-            self.cur_dir = self.get_directory_by_fcb(fcb);
-            
-            let (index, _) = self.cur_dir.get_fcb(&cur_name).unwrap();
-            self.cur_dir.files[index].length = size;
-        }
-        else {
-            let (_, fcb) = self.cur_dir.get_fcb(name).unwrap();
-            self.cur_dir = self.get_directory_by_fcb(fcb);
-        }
+    // 以文件夹名称或路径切换当前会话的当前文件夹
+    pub fn set_current_dir(&self, session: &mut Session, path: &str) -> FsResult<()> {
+        let cluster = self.get_directory_by_path(session, path)?.cluster();
+        session.set_cwd(cluster);
+        Ok(())
+    }
 
+    // 切换到根目录
+    pub fn set_current_dir_to_root(&self, session: &mut Session) -> FsResult<()> {
+        session.set_cwd(0);
+        Ok(())
     }
 
     // 更改文件名
-    pub fn rename_file(&mut self, old: &str, new: &str) {
-        if let Some((index, fcb)) = self.cur_dir.get_fcb(old) {
-            let new_fcb = Fcb {
-                name: String::from(new),
-                ..fcb.to_owned()
-            };
-            self.cur_dir.files[index] = new_fcb;
-        } else {
-            println!("FCB not found for name: {}", old);
-        }
+    pub fn rename_file(&self, session: &Session, old: &str, new: &str) -> FsResult<()> {
+        let cluster = session.cwd();
+        self.move_entry(session, cluster, old, cluster, new)
     }
 
     // 获取磁盘大小，已分配，未分配数量
     pub fn get_disk_info(&self) -> (usize, usize, usize) {
-        let disk_size = self.disk.fat.len();
+        let disk_size = self.disk.cluster_count();
         let mut used = 0;
         let mut unused = 0;
-        for item in &self.disk.fat {
+        for item in &self.disk.fat()[..disk_size] {
             match item {
                 FATItem::UnUsed => unused += 1,
                 FATItem::BadCluster => continue,
@@ -479,111 +1435,621 @@ impl DiskOperator {
         (disk_size, used, unused)
     }
 
-    // 复制文件
-    pub fn copy_file_by_name(&mut self, name: &str, path: &str) {
-        let (fcb, _) = match self.cur_dir.get_fcb(name) {
-            Some((index, fcb)) => (fcb.clone(), index),
-            None => {
-                println!("File not found!");
-                return;
+    // 当前目录树引用的块数（被多次引用的块计算多次）与其中不同的块数，两者之比为去重比
+    pub fn block_usage(&self) -> FsResult<(usize, usize)> {
+        let mut references = vec![0; self.disk.cluster_count()];
+        self.count_references(0, &mut references)?;
+        let referenced = references.iter().sum();
+        let distinct = references.iter().filter(|&&count| count > 0).count();
+        Ok((referenced, distinct))
+    }
+
+    fn count_references(&self, cluster: usize, references: &mut [usize]) -> FsResult<()> {
+        for block in self.get_series(cluster)? {
+            references[block] += 1;
+        }
+        for fcb in self.get_directory_by_cluster(cluster)?.entries() {
+            match fcb.file_type {
+                FileType::Directory => self.count_references(fcb.first_cluster, references)?,
+                FileType::File => {
+                    for block in self.get_series(fcb.first_cluster)? {
+                        references[block] += 1;
+                    }
+                }
             }
+        }
+        Ok(())
+    }
+
+    // 当前目录树中文件的原始大小之和与占用的块的字节数之和
+    pub fn file_usage(&self) -> FsResult<(usize, usize)> {
+        let mut usage = (0, 0);
+        self.add_file_usage(0, &mut usage)?;
+        Ok(usage)
+    }
+
+    fn add_file_usage(&self, cluster: usize, usage: &mut (usize, usize)) -> FsResult<()> {
+        for fcb in self.get_directory_by_cluster(cluster)?.entries() {
+            match fcb.file_type {
+                FileType::Directory => self.add_file_usage(fcb.first_cluster, usage)?,
+                FileType::File => {
+                    usage.0 += fcb.length;
+                    usage.1 += self.allocated_size(fcb);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 复制文件
+    pub fn copy_file_by_name(&self, session: &Session, name: &str, path: &str) -> FsResult<()> {
+        let fcb = match self.current_dir(session)?.get_fcb(name) {
+            Some((_, fcb)) => fcb.clone(),
+            None => return Err(FsError::NotFound(name.to_string())),
         };
+        if fcb.file_type == FileType::Directory {
+            return Err(FsError::IsADirectory(name.to_string()));
+        }
 
+        // 通过路径找到目标文件夹，在目标文件夹新建文件并写入数据
+        let target = self.get_directory_by_path(session, path)?;
+        let data = self.get_file_by_fcb(&fcb)?;
+        self.new_file_at(session, target.cluster(), name, data.as_slice())
+    }
+
+    pub fn move_file_by_name(&self, session: &Session, name: &str, path: &str) -> FsResult<()> {
         // 通过路径找到目标文件夹
-        let path: Vec<&str> = path.split('/').collect();
-        let mut cur_dir = self.cur_dir.clone();
-        for dir in path {
-            if dir == "" {
-                continue;
-            }
-            else {
-                let (_, fcb1) = cur_dir.get_fcb(dir).unwrap();
-                cur_dir = self.get_directory_by_fcb(fcb1);
+        let from = session.cwd();
+        let to = self.get_directory_by_path(session, path)?.cluster();
+        if from == to {
+            return Ok(());
+        }
+        self.move_entry(session, from, name, to, name)
+    }
+
+    // 按路径移动或重命名，dst为已存在的目录时移动到该目录下并保留文件名
+    pub fn move_file_by_path(&self, session: &Session, src: &str, dst: &str) -> FsResult<()> {
+        let (src_dir_path, name) = split_path(src);
+        let from = self.get_directory_by_path(session, src_dir_path)?.cluster();
+
+        let (to, new_name) = match self.get_directory_by_path(session, dst) {
+            Ok(dir) => (dir.cluster(), name),
+            // 以/结尾的目标必须是已存在的目录
+            Err(FsError::NotFound(_)) | Err(FsError::NotADirectory(_)) if !dst.ends_with('/') => {
+                let (dir_path, new_name) = split_path(dst);
+                (self.get_directory_by_path(session, dir_path)?.cluster(), new_name)
             }
+            Err(err) => return Err(err),
+        };
+        self.move_entry(session, from, name, to, new_name)
+    }
+
+    // 将首块号为from的目录下的name移动到首块号为to的目录下，并命名为new_name
+    pub fn move_entry(&self, session: &Session, from: usize, name: &str, to: usize, new_name: &str) -> FsResult<()> {
+        check_name(new_name)?;
+        let mut src_dir = self.get_directory_by_cluster(from)?;
+        let (index, fcb) = match src_dir.get_fcb(name) {
+            Some((index, fcb)) if name != "." && name != ".." => (index, fcb.clone()),
+            Some(_) => return Err(FsError::InvalidName(name.to_string())),
+            None => return Err(FsError::NotFound(name.to_string())),
+        };
+        if from == to && name == new_name {
+            return Ok(());
         }
-        
-        // 在目标文件夹新建文件并写入数据
-        let data = self.get_file_by_fcb(&fcb);
-        let file_name = fcb.name.clone();
-        if cur_dir.get_fcb(name).is_some() {
-            println!("File already exists!");
-            return;
+
+        let mut dst_dir = self.get_directory_by_cluster(to)?;
+        if dst_dir.get_fcb(new_name).is_some() {
+            return Err(FsError::AlreadyExists(new_name.to_string()));
         }
-        // 写入数据
-        let first_cluster = self.write_to_disk(data.as_slice());
-        let new_file_fcb = Fcb {
-            name: String::from(file_name),
-            file_type: FileType::File,
-            first_cluster,
-            length: data.len(),
-        };
-        cur_dir.files.push(new_file_fcb);
-        
-        // 将写入新数据的文件夹重新写入磁盘
-        let data = bincode::serialize(&cur_dir).unwrap();
-        let (eof, blocks_number) = DiskOperator::calculate_blocks_with_eof(data.len());
-        self.delete_series(cur_dir.files[0].first_cluster).unwrap();
-        let clusters = self.allocate_block(blocks_number).unwrap();
-        self.disk.write_in_clusters(data.as_slice(), clusters.as_slice(), eof);
+        if fcb.file_type == FileType::Directory && self.is_ancestor(fcb.first_cluster, &dst_dir)? {
+            return Err(FsError::InvalidArgument(format!(
+                "can not move {} into itself",
+                name
+            )));
+        }
+        if fcb.file_type == FileType::File {
+            self.check_write(session, &src_dir, &fcb, 0, usize::MAX)?;
+        }
+        if from != to {
+            quota::check_move(self, from, to, &fcb)?;
+        }
+
+        // 目录移动或改名后同步其自身记录的名称与..
+        if fcb.file_type == FileType::Directory {
+            let mut moved = self.get_directory_by_fcb(&fcb)?;
+            moved.name = String::from(new_name);
+            moved.files[1].first_cluster = to;
+            self.save_dir_to_disk(session, &moved)?;
+        }
+
+        self.locks.rename(&FileHandle::new(from, name), &FileHandle::new(to, new_name));
+        if from == to {
+            src_dir.files[index].name = String::from(new_name);
+            self.save_dir_to_disk(session, &src_dir)?;
+            return Ok(());
+        }
+
+        // 将文件FCB添加至目标文件夹
+        dst_dir.files[0].length += fcb.length;
+        dst_dir.files.push(Fcb {
+            name: String::from(new_name),
+            ..fcb.clone()
+        });
+        self.save_dir_to_disk(session, &dst_dir)?;
 
+        src_dir.files.remove(index);
+        src_dir.files[0].length = src_dir.files[0].length.saturating_sub(fcb.length);
+        self.save_dir_to_disk(session, &src_dir)?;
+        Ok(())
     }
 
-    pub fn move_file_by_name(&mut self, name: &str, path: &str) {
-        let (fcb, index) = match self.cur_dir.get_fcb(name) {
-            Some((index, fcb)) => (fcb.clone(), index),
-            None => {
-                println!("File not found!");
-                return;
+    // 判断首块号为cluster的目录是否为dir本身或其祖先
+    fn is_ancestor(&self, cluster: usize, dir: &Directory) -> FsResult<bool> {
+        let mut cur_dir = dir.clone();
+        loop {
+            if cur_dir.cluster() == cluster {
+                return Ok(true);
             }
+            if cur_dir.cluster() == 0 {
+                return Ok(false);
+            }
+            cur_dir = self.get_directory_by_cluster(cur_dir.files[1].first_cluster)?;
+        }
+    }
+
+    // 输出当前绝对路径
+    pub fn get_abs_path(&self, session: &Session) -> String {
+        let mut names: Vec<String> = Vec::new();
+        let mut cur_dir = match self.current_dir(session) {
+            Ok(dir) => dir,
+            Err(_) => return String::from("?"),
         };
-        self.cur_dir.files.remove(index);
-        self.save_dir_to_disk(&self.cur_dir.clone());
+        while cur_dir.cluster() != 0 {
+            names.push(cur_dir.name.clone());
+            cur_dir = match self.get_directory_by_cluster(cur_dir.files[1].first_cluster) {
+                Ok(dir) => dir,
+                Err(_) => {
+                    names.push(String::from("?"));
+                    break;
+                }
+            };
+        }
+        names.push(String::from("root"));
+        names.reverse();
+        format!("/{}", names.join("/"))
+    }
 
-        // 通过路径找到目标文件夹
-        let path: Vec<&str> = path.split('/').collect();
-        let mut cur_dir = self.cur_dir.clone();
-        for dir in path {
-            if dir == "" {
-                continue;
-            }
-            else {
-                let (_, fcb1) = cur_dir.get_fcb(dir).unwrap();
-                cur_dir = self.get_directory_by_fcb(fcb1);
+    // 首块号为cluster的目录的绝对路径，根目录为/
+    pub fn get_path_by_cluster(&self, cluster: usize) -> FsResult<String> {
+        let mut names: Vec<String> = Vec::new();
+        let mut dir = self.get_directory_by_cluster(cluster)?;
+        while dir.cluster() != 0 {
+            if names.len() >= self.disk.cluster_count() {
+                return Err(FsError::Corrupted(format!("directory loop at cluster {}", cluster)));
             }
+            names.push(dir.name.clone());
+            dir = self.get_directory_by_cluster(dir.parent())?;
         }
+        names.reverse();
+        Ok(format!("/{}", names.join("/")))
+    }
+}
+
+// 在content的offset处写入data，不足的部分补0
+pub fn write_into(content: &mut Vec<u8>, offset: usize, data: &[u8]) -> FsResult<()> {
+    let end = sparse::check_range(offset, data.len())?;
+    if content.len() < end {
+        content.resize(end, 0);
+    }
+    content[offset..end].copy_from_slice(data);
+    Ok(())
+}
+
+// 当前时间的Unix时间戳
+fn now() -> i64 {
+    chrono::Local::now().timestamp()
+}
 
-        if cur_dir.get_fcb(name).is_some() {
-            self.cur_dir.files.push(fcb.clone());
-            self.save_dir_to_disk(&self.cur_dir.clone());
-            println!("File already exists!");
-            return;
+// 目录序列化
+pub fn serialize_dir(dir: &Directory) -> FsResult<Vec<u8>> {
+    bincode::serialize(dir).map_err(|err| FsError::Corrupted(err.to_string()))
+}
+
+// 文件名不能为空，不能包含/，也不能是.或..
+pub fn check_name(name: &str) -> FsResult<()> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(FsError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+// 将路径拆分为所在目录与最后一级名称
+pub fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(0) => ("/", &path[1..]),
+        Some(index) => (&path[..index], &path[index + 1..]),
+        None => ("", path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lock::{Lock, LockKind, LockOwner};
+
+    #[test]
+    fn set_current_dir_to_missing_dir_fails() {
+        let mut session = Session::default();
+        let vd = DiskOperator::new(None);
+        assert_eq!(vd.set_current_dir(&mut session, "missing"), Err(FsError::NotFound("missing".to_string())));
+        assert_eq!(vd.get_abs_path(&session), "/root");
+    }
+
+    #[test]
+    fn set_current_dir_to_file_fails() {
+        let mut session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_file(&session, "a", b"data").unwrap();
+        assert_eq!(vd.set_current_dir(&mut session, "a"), Err(FsError::NotADirectory("a".to_string())));
+    }
+
+    #[test]
+    fn read_directory_as_file_fails() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&session, "dir").unwrap();
+        assert_eq!(vd.read_file_by_name(&session, "dir"), Err(FsError::IsADirectory("dir".to_string())));
+        assert_eq!(vd.read_file_by_name(&session, "missing"), Err(FsError::NotFound("missing".to_string())));
+    }
+
+    #[test]
+    fn create_existing_name_fails() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&session, "dir").unwrap();
+        vd.new_file(&session, "a", b"data").unwrap();
+        assert_eq!(vd.new_directory(&session, "dir"), Err(FsError::AlreadyExists("dir".to_string())));
+        assert_eq!(vd.new_directory(&session, "a"), Err(FsError::AlreadyExists("a".to_string())));
+        assert_eq!(vd.new_file(&session, "a", b"other"), Err(FsError::AlreadyExists("a".to_string())));
+        assert_eq!(vd.read_file_by_name(&session, "a").unwrap(), b"data");
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        for name in ["", ".", "..", "a/b"] {
+            assert_eq!(vd.new_file(&session, name, b""), Err(FsError::InvalidName(name.to_string())));
         }
+        assert_eq!(vd.delete_file_by_name(&session, ".."), Err(FsError::InvalidName("..".to_string())));
+    }
 
-        let add_length = fcb.length;
-        cur_dir.files[0].length += add_length;
-        // 将文件FCB添加至目标文件夹
-        cur_dir.files.push(fcb.clone());
-        let data = bincode::serialize(&cur_dir).unwrap();
-        let (eof, blocks_number) = DiskOperator::calculate_blocks_with_eof(data.len());
-        self.delete_series(cur_dir.files[0].first_cluster).unwrap();
-        let clusters = self.allocate_block(blocks_number).unwrap();
-        self.disk.write_in_clusters(data.as_slice(), clusters.as_slice(), eof);
+    #[test]
+    fn delete_non_empty_directory_fails() {
+        let mut session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&session, "dir").unwrap();
+        vd.set_current_dir(&mut session, "dir").unwrap();
+        vd.new_file(&session, "a", b"data").unwrap();
+        vd.set_current_dir(&mut session, "..").unwrap();
+        assert_eq!(vd.delete_file_by_name(&session, "dir"), Err(FsError::DirectoryNotEmpty("dir".to_string())));
+        assert_eq!(vd.delete_file_by_name(&session, "missing"), Err(FsError::NotFound("missing".to_string())));
+    }
 
+    #[test]
+    fn sessions_keep_their_own_current_dir() {
+        let (mut first, mut second) = (Session::default(), Session::new("other"));
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&first, "a").unwrap();
+        vd.new_directory(&first, "b").unwrap();
+
+        // 两个会话使用同一个磁盘
+        vd.set_current_dir(&mut first, "a").unwrap();
+        vd.set_current_dir(&mut second, "/b").unwrap();
+        vd.new_file(&second, "f", b"b").unwrap();
+        vd.new_file(&first, "f", b"a").unwrap();
+        assert_eq!(vd.get_abs_path(&first), "/root/a");
+        assert_eq!(vd.read_file_by_path(&first, "../b/f").unwrap(), b"b");
+        assert_eq!((second.user(), vd.get_abs_path(&second).as_str()), ("other", "/root/b"));
+
+        // 当前目录不写入镜像，新会话从根目录开始
+        let data = bincode::serialize(&vd).unwrap();
+        let loaded: DiskOperator = bincode::deserialize(&data).unwrap();
+        assert_eq!(loaded.read_file_by_path(&Session::default(), "a/f").unwrap(), b"a");
     }
 
-    // 输出当前绝对路径
-    pub fn get_abs_path(&self) -> String {
-        let mut path = String::from("");
-        let mut cur_dir = self.cur_dir.clone();
-        while cur_dir.name != "root" {
-            path = format!("/{}/{}", cur_dir.name, path);
-            let (_, fcb) = cur_dir.get_fcb("..").unwrap();
-            cur_dir = self.get_directory_by_fcb(fcb);
-        }
-        path = format!("/root{}", path);
-        if path.chars().last() == Some('/') {
-            path.pop();
-        }
-        path
+    #[test]
+    fn mandatory_locks_follow_the_file() {
+        let (owner_session, other) = (Session::default(), Session::new("other"));
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&owner_session, "d").unwrap();
+        vd.write_file_by_path(&owner_session, "/d/f", b"0123456789").unwrap();
+        let file = FileHandle::for_path(&vd, &owner_session, "/d/f").unwrap();
+        let owner = LockOwner { session: owner_session.id(), handle: 1 };
+        let lock = Lock { owner, kind: LockKind::Shared, start: 4, end: 8, mandatory: true };
+        vd.locks.lock_range(&file, lock, None).unwrap();
+
+        // 持有锁的会话可以写入，其他会话只能写入锁定范围之外
+        vd.write_at(&owner_session, "/d/f", 4, b"x").unwrap();
+        vd.write_at(&other, "/d/f", 0, b"abcd").unwrap();
+        assert!(matches!(vd.write_at(&other, "/d/f", 7, b"yy"), Err(FsError::WouldBlock(_))));
+        assert!(matches!(vd.truncate(&other, "/d/f", 6), Err(FsError::WouldBlock(_))));
+        assert!(matches!(vd.write_file_by_path(&other, "/d/f", b""), Err(FsError::WouldBlock(_))));
+
+        // 其他会话不能移动或删除文件；锁随文件移动，文件删除后丢弃
+        assert!(matches!(vd.move_file_by_path(&other, "/d/f", "/g"), Err(FsError::WouldBlock(_))));
+        assert!(matches!(vd.delete_file_by_path(&other, "/d/f"), Err(FsError::WouldBlock(_))));
+        vd.move_file_by_path(&owner_session, "/d/f", "/g").unwrap();
+        assert!(vd.write_at(&other, "/g", 7, b"yy").is_err());
+        vd.delete_file_by_path(&owner_session, "/g").unwrap();
+        assert!(vd.locks.locks().is_empty());
+    }
+
+    #[test]
+    fn load_missing_image_fails() {
+        assert!(matches!(DiskOperator::load("./missing-image.vd"), Err(FsError::Io(_))));
+    }
+
+    #[test]
+    fn load_corrupted_image_fails() {
+        let name = std::env::temp_dir().join("file-system-corrupted.vd");
+        fs::write(&name, b"not an image").unwrap();
+        assert!(matches!(DiskOperator::load(name.to_str().unwrap()), Err(FsError::Corrupted(_))));
+        fs::remove_file(&name).unwrap();
+    }
+
+    #[test]
+    fn encrypted_images_need_the_passphrase() {
+        let session = Session::default();
+        let name = std::env::temp_dir().join("file-system-encrypted.vd");
+        let name = name.to_str().unwrap();
+        let mut vd = DiskOperator::new(None);
+        vd.write_file_by_path(&session, "/credentials", b"password=hunter2").unwrap();
+        vd.encryption = Some(Encryption::with_cost("secret", 64, 1).unwrap());
+        vd.save(name).unwrap();
+
+        // 文件内容与目录都不以明文出现在镜像中
+        let data = fs::read(name).unwrap();
+        assert!(!data.windows(7).any(|window| window == b"hunter2" || window == b"credent"));
+        assert_eq!(DiskOperator::load(name).err(), Some(FsError::Encrypted(name.to_string())));
+        assert_eq!(DiskOperator::load_encrypted(name, "wrong").err(), Some(FsError::WrongPassphrase));
+        let loaded = DiskOperator::load_encrypted(name, "secret").unwrap();
+        assert_eq!(loaded.read_file_by_path(&session, "/credentials").unwrap(), b"password=hunter2");
+
+        // 去掉加密后按普通镜像保存
+        let mut loaded = loaded;
+        loaded.encryption = None;
+        loaded.save(name).unwrap();
+        assert!(DiskOperator::load(name).is_ok());
+        fs::remove_file(name).unwrap();
+    }
+
+    #[test]
+    fn out_of_space_fails_without_leaking_clusters() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        let (_, used, unused) = vd.get_disk_info();
+        let data = vec![1u8; (unused + 1) * BLOCK_SIZE];
+        assert_eq!(vd.new_file(&session, "big", &data), Err(FsError::NoSpace));
+        assert_eq!(vd.get_disk_info().1, used);
+        assert!(vd.current_dir(&session).unwrap().get_file_type("big").is_none());
+
+        // 填满磁盘后创建目录同样失败
+        let data = vec![1u8; unused * BLOCK_SIZE];
+        vd.new_file(&session, "fill", &data).unwrap();
+        assert_eq!(vd.new_directory(&session, "dir"), Err(FsError::NoSpace));
+        assert_eq!(vd.read_file_by_name(&session, "fill").unwrap().len(), data.len());
+    }
+
+    #[test]
+    fn move_and_copy_to_missing_dir_keep_file() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_file(&session, "a", b"data").unwrap();
+        assert_eq!(vd.move_file_by_name(&session, "a", "missing"), Err(FsError::NotFound("missing".to_string())));
+        assert_eq!(vd.copy_file_by_name(&session, "a", "missing"), Err(FsError::NotFound("missing".to_string())));
+        assert_eq!(vd.read_file_by_name(&session, "a").unwrap(), b"data");
+    }
+
+    #[test]
+    fn move_directory_into_itself_fails() {
+        let mut session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&session, "dir").unwrap();
+        vd.set_current_dir(&mut session, "dir").unwrap();
+        vd.new_directory(&session, "sub").unwrap();
+        vd.set_current_dir(&mut session, "..").unwrap();
+        assert!(matches!(vd.move_file_by_name(&session, "dir", "dir/sub"), Err(FsError::InvalidArgument(_))));
+        assert!(vd.current_dir(&session).unwrap().get_file_type("dir").is_some());
+    }
+
+    #[test]
+    fn rename_to_existing_name_fails() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_file(&session, "a", b"1").unwrap();
+        vd.new_file(&session, "b", b"2").unwrap();
+        assert_eq!(vd.rename_file(&session, "a", "b"), Err(FsError::AlreadyExists("b".to_string())));
+        assert_eq!(vd.rename_file(&session, "c", "d"), Err(FsError::NotFound("c".to_string())));
+    }
+
+    #[test]
+    fn corrupted_fat_chain_is_reported() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_file(&session, "a", &vec![1u8; BLOCK_SIZE * 2]).unwrap();
+        let first = vd.get_fcb_by_path(&session, "a").unwrap().first_cluster;
+        vd.disk.set_fat(first, FATItem::Cluster(usize::MAX));
+        assert!(matches!(vd.read_file_by_name(&session, "a"), Err(FsError::Corrupted(_))));
+    }
+
+    #[test]
+    fn partial_writes_only_touch_needed_clusters() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        let mut expected = vec![0xffu8; BLOCK_SIZE + 10];
+        vd.new_file(&session, "a", &expected).unwrap();
+        let used = vd.get_disk_info().1;
+
+        // 跨块覆盖写入，文件长度与块数不变
+        vd.write_at(&session, "a", BLOCK_SIZE - 2, b"xyzw").unwrap();
+        expected[BLOCK_SIZE - 2..BLOCK_SIZE + 2].copy_from_slice(b"xyzw");
+        assert_eq!(vd.read_file_by_name(&session, "a").unwrap(), expected);
+        assert_eq!(vd.get_disk_info().1, used);
+
+        // 超出末尾写入时中间补0并追加块
+        vd.write_at(&session, "a", 2 * BLOCK_SIZE + 5, b"end").unwrap();
+        expected.resize(2 * BLOCK_SIZE + 5, 0);
+        expected.extend_from_slice(b"end");
+        assert_eq!(vd.read_file_by_name(&session, "a").unwrap(), expected);
+        assert_eq!(vd.get_disk_info().1, used + 1);
+        let fcb = vd.get_fcb_by_path(&session, "a").unwrap();
+        assert_eq!(vd.read_at(&fcb, BLOCK_SIZE - 1, 4).unwrap(), b"yzw\xff");
+
+        // 截断释放多余的块，再扩展时补0
+        vd.truncate(&session, "a", 3).unwrap();
+        assert_eq!(vd.read_file_by_name(&session, "a").unwrap(), b"\xff\xff\xff");
+        assert_eq!(vd.get_disk_info().1, used - 1);
+        vd.truncate(&session, "a", 5).unwrap();
+        assert_eq!(vd.read_file_by_name(&session, "a").unwrap(), b"\xff\xff\xff\0\0");
+        vd.append_file_by_path(&session, "a", b"!").unwrap();
+        assert_eq!(vd.read_file_by_name(&session, "a").unwrap(), b"\xff\xff\xff\0\0!");
+        assert!(crate::fsck::check(&vd).is_empty());
+
+        vd.new_directory(&session, "dir").unwrap();
+        assert_eq!(vd.truncate(&session, "dir", 0), Err(FsError::IsADirectory("dir".to_string())));
+        assert_eq!(vd.write_at(&session, "missing", 0, b"x"), Err(FsError::NotFound("missing".to_string())));
+    }
+
+    #[test]
+    fn compressed_files_read_and_write_transparently() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        let root = session.cwd();
+        let text = "compressible text ".repeat(1000).into_bytes();
+        let cluster = vd.new_directory_with(&session, root, "z", Some(Compression::Zstd)).unwrap();
+        vd.new_file_at(&session, cluster, "a", &text).unwrap();
+        vd.new_file_with(&session, root, "b", &text, Some(Compression::Lz4)).unwrap();
+
+        // 新文件沿用目录的算法，子目录也继承
+        let fcb = vd.get_fcb_by_path(&session, "/z/a").unwrap();
+        assert_eq!(fcb.compression(), Compression::Zstd);
+        assert_eq!((vd.get_size(&fcb), vd.allocated_size(&fcb)), (text.len(), BLOCK_SIZE));
+        vd.new_directory_with(&session, cluster, "sub", None).unwrap();
+        assert_eq!(vd.get_fcb_by_path(&session, "/z/sub").unwrap().compression(), Compression::Zstd);
+
+        vd.write_at(&session, "/z/a", 17990, b"tail!").unwrap();
+        vd.truncate(&session, "/b", 10).unwrap();
+        vd.write_file_by_path(&session, "/z/c", b"replaced").unwrap();
+        let mut expected = text.clone();
+        expected[17990..17995].copy_from_slice(b"tail!");
+        assert_eq!(vd.read_file_by_path(&session, "/z/a").unwrap(), expected);
+        assert_eq!(vd.read_at(&vd.get_fcb_by_path(&session, "/z/a").unwrap(), 17988, 100).unwrap(), &expected[17988..]);
+        assert_eq!(vd.read_file_by_path(&session, "/b").unwrap(), &text[..10]);
+        assert_eq!(vd.get_fcb_by_path(&session, "/z/c").unwrap().compression(), Compression::Zstd);
+        assert_eq!(vd.read_file_by_path(&session, "/z/c").unwrap(), b"replaced");
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+        let usage = vd.file_usage().unwrap();
+        assert_eq!(usage, (text.len() + 10 + 8, 3 * BLOCK_SIZE));
+    }
+
+    #[test]
+    fn dedup_shares_identical_chains() {
+        let session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        vd.options.dedup = true;
+        let data: Vec<u8> = (0..BLOCK_SIZE * 5 + 100).map(|i| (i % 251) as u8).collect();
+        vd.write_file_by_path(&session, "/a", &data).unwrap();
+        let used = vd.get_disk_info().1;
+
+        // 复制不占用新的数据块，只是目录项
+        vd.copy_file_by_path(&session, "/a", "/b").unwrap();
+        assert_eq!(vd.get_disk_info().1, used);
+        assert_eq!(vd.block_usage().unwrap(), (1 + 6 * 2, 1 + 6));
+
+        // 开头不同的文件共享相同的尾部
+        let mut other = data.clone();
+        other[0] = 1;
+        vd.write_file_by_path(&session, "/c", &other).unwrap();
+        assert_eq!(vd.get_disk_info().1, used + 1);
+
+        // 写入共享的文件前先复制，其他文件不受影响
+        vd.write_at(&session, "/b", 10, b"changed").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "/a").unwrap(), data);
+        assert_eq!(vd.read_file_by_path(&session, "/c").unwrap(), other);
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+
+        // 重新读取镜像后由目录树重建索引
+        let loaded: DiskOperator = bincode::deserialize(&bincode::serialize(&vd).unwrap()).unwrap();
+        assert!(loaded.options.dedup);
+        let used = loaded.get_disk_info().1;
+        loaded.write_file_by_path(&session, "/d", &data).unwrap();
+        loaded.delete_file_by_path(&session, "/a").unwrap();
+        assert_eq!(loaded.get_disk_info().1, used);
+        assert_eq!(loaded.read_file_by_path(&session, "/d").unwrap(), data);
+        assert!(crate::fsck::check(&loaded).is_empty(), "{:?}", crate::fsck::check(&loaded));
+    }
+
+    #[test]
+    fn sparse_files_only_allocate_written_blocks() {
+        let session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        vd.write_file_by_path(&session, "/s", b"head").unwrap();
+        let used = vd.get_disk_info().1;
+
+        // 扩展的部分是空洞，写入空洞时只分配涉及的块
+        vd.truncate(&session, "/s", 64 * BLOCK_SIZE).unwrap();
+        assert_eq!(vd.get_disk_info().1, used);
+        vd.write_at(&session, "/s", 40 * BLOCK_SIZE + 10, b"middle").unwrap();
+        let fcb = vd.get_fcb_by_path(&session, "/s").unwrap();
+        assert_eq!((fcb.length(), vd.allocated_size(&fcb)), (64 * BLOCK_SIZE, 2 * BLOCK_SIZE));
+        assert_eq!(vd.read_at(&fcb, 2, 4).unwrap(), b"ad\0\0");
+        assert_eq!(vd.read_at(&fcb, 40 * BLOCK_SIZE + 8, 10).unwrap(), b"\0\0middle\0\0");
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+
+        // 打洞保持长度，写时复制的副本保留空洞，快照中的内容不变
+        crate::snapshot::create(&mut vd, &session, "before").unwrap();
+        vd.punch_hole(&session, "/s", 0, 64 * BLOCK_SIZE).unwrap();
+        let fcb = vd.get_fcb_by_path(&session, "/s").unwrap();
+        assert_eq!((fcb.length(), vd.allocated_size(&fcb)), (64 * BLOCK_SIZE, BLOCK_SIZE));
+        assert_eq!(vd.read_file_by_path(&session, "/s").unwrap(), vec![0; 64 * BLOCK_SIZE]);
+        let old = crate::snapshot::lookup(&vd, "before", "/s").unwrap();
+        assert_eq!(vd.read_at(&old, 40 * BLOCK_SIZE + 10, 6).unwrap(), b"middle");
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+
+        // 只覆盖块的一部分时以0填充，截断释放新末尾之后的块
+        vd.write_at(&session, "/s", 3 * BLOCK_SIZE, b"abcdef").unwrap();
+        vd.punch_hole(&session, "/s", 3 * BLOCK_SIZE + 1, 2).unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "/s").unwrap()[3 * BLOCK_SIZE..3 * BLOCK_SIZE + 6], *b"a\0\0def");
+        vd.truncate(&session, "/s", 2 * BLOCK_SIZE).unwrap();
+        let fcb = vd.get_fcb_by_path(&session, "/s").unwrap();
+        assert_eq!(vd.allocated_size(&fcb), BLOCK_SIZE);
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+        assert!(vd.truncate(&session, "/s", sparse::MAX_FILE_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn write_offsets_are_checked() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.write_file_by_path(&session, "/f", b"data").unwrap();
+        vd.new_file_with(&session, 0, "z", b"data", Some(Compression::Deflate)).unwrap();
+        let used = vd.get_disk_info().1;
+
+        // 其他会话的强制锁覆盖整个文件，溢出的偏移先于锁被拒绝
+        let file = FileHandle::for_path(&vd, &session, "/f").unwrap();
+        let owner = LockOwner { session: Session::new("other").id(), handle: 1 };
+        let lock = Lock { owner, kind: LockKind::Exclusive, start: 0, end: crate::lock::TO_EOF, mandatory: true };
+        vd.locks.flock(&file, lock, None).unwrap();
+        for path in ["/f", "/z"] {
+            for offset in [usize::MAX - 1, usize::MAX, sparse::MAX_FILE_SIZE] {
+                let result = vd.write_at(&session, path, offset, b"xy");
+                assert!(matches!(result, Err(FsError::InvalidArgument(_))), "{} {}", path, offset);
+            }
+        }
+        assert!(matches!(vd.truncate(&session, "/z", usize::MAX), Err(FsError::InvalidArgument(_))));
+        assert_eq!(vd.get_disk_info().1, used);
+        assert_eq!(vd.read_file_by_path(&session, "/z").unwrap(), b"data");
     }
 }
@@ -0,0 +1,208 @@
+use crate::disk_operator::{DiskOperator, FileType};
+use crate::parser;
+use crate::session::Session;
+use crate::ui::COMMANDS;
+
+use ansi_rgb::Foreground;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 交互界面的行编辑辅助，负责命令名与虚拟磁盘路径的补全
+// 与交互界面共享磁盘与会话，补全时只读取正在补全的目录
+pub struct ShellHelper {
+    vd: Rc<RefCell<DiskOperator>>,
+    session: Rc<RefCell<Session>>,
+}
+
+impl ShellHelper {
+    pub fn new(vd: Rc<RefCell<DiskOperator>>, session: Rc<RefCell<Session>>) -> ShellHelper {
+        ShellHelper { vd, session }
+    }
+
+    fn complete_command(&self, prefix: &str) -> Vec<Pair> {
+        COMMANDS
+            .iter()
+            .filter(|command| command.name.starts_with(prefix))
+            .map(|command| Pair {
+                display: command.name.to_string(),
+                replacement: format!("{} ", command.name),
+            })
+            .collect()
+    }
+
+    fn complete_path(&self, word: &str) -> Vec<Pair> {
+        let (dir_path, prefix) = match word.rfind('/') {
+            Some(index) => (&word[..index + 1], &word[index + 1..]),
+            None => ("", word),
+        };
+        // 目录部分为空时补全当前目录
+        let path = if dir_path.is_empty() { "." } else { dir_path };
+        let Ok(dir) = self.vd.borrow().get_directory_by_path(&self.session.borrow(), path) else {
+            return Vec::new();
+        };
+
+        let mut candidates: Vec<Pair> = dir
+            .entries()
+            .filter(|fcb| fcb.name().starts_with(prefix))
+            .map(|fcb| {
+                let name = fcb.name();
+                let suffix = match fcb.file_type() {
+                    FileType::Directory => "/",
+                    FileType::File => " ",
+                };
+                Pair {
+                    display: format!("{}{}", name, suffix.trim()),
+                    replacement: format!("{}{}{}", escape(dir_path), escape(name), suffix),
+                }
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        candidates
+    }
+}
+
+// 为补全结果中的特殊字符添加反斜杠，使其能被命令解析器还原
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if parser::is_special_char(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// 去掉已输入部分中的反斜杠转义
+fn unescape(word: &str) -> String {
+    let mut unescaped = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        // 向前找到当前单词的开头，跳过被转义的空白
+        let bytes = &line.as_bytes()[..pos];
+        let mut start = pos;
+        while start > 0 {
+            let c = bytes[start - 1];
+            if c.is_ascii_whitespace() && !(start >= 2 && bytes[start - 2] == b'\\') {
+                break;
+            }
+            start -= 1;
+        }
+
+        let word = &line[start..pos];
+        // 行首或管道符之后为命令名
+        let before = line[..start].trim_end();
+        if before.is_empty() || before.ends_with('|') {
+            Ok((start, self.complete_command(word)))
+        } else {
+            Ok((start, self.complete_path(&unescape(word))))
+        }
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        Cow::Owned(prompt.fg(ansi_rgb::red()).to_string())
+    }
+}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+
+    // 补全的起始位置与各候选的替换文本
+    fn complete(helper: &ShellHelper, line: &str) -> (usize, Vec<String>) {
+        let history = DefaultHistory::new();
+        let (start, pairs) = helper.complete(line, line.len(), &Context::new(&history)).unwrap();
+        (start, pairs.into_iter().map(|pair| pair.replacement).collect())
+    }
+
+    // 行编辑辅助与交互界面共享磁盘与会话
+    fn helper() -> (Rc<RefCell<DiskOperator>>, Rc<RefCell<Session>>, ShellHelper) {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&session, "docs").unwrap();
+        vd.new_directory(&session, "data").unwrap();
+        vd.write_file_by_path(&session, "/docs/notes.txt", b"").unwrap();
+        vd.write_file_by_path(&session, "/docs/my file", b"").unwrap();
+        vd.new_directory_at(&session, vd.get_directory_by_path(&session, "/docs").unwrap().cluster(), "old drafts").unwrap();
+        vd.write_file_by_path(&session, "/docs/old drafts/v1", b"").unwrap();
+        let vd = Rc::new(RefCell::new(vd));
+        let session = Rc::new(RefCell::new(session));
+        let helper = ShellHelper::new(vd.clone(), session.clone());
+        (vd, session, helper)
+    }
+
+    #[test]
+    fn completes_command_names() {
+        let (_, _, helper) = helper();
+        assert_eq!(complete(&helper, "mk"), (0, vec!["mkdir ".to_string()]));
+        assert_eq!(complete(&helper, "exp"), (0, vec!["export ".to_string(), "exportfat ".to_string()]));
+        assert_eq!(complete(&helper, "ls | gr"), (5, vec!["grep ".to_string()]));
+        assert_eq!(complete(&helper, "zz"), (0, Vec::new()));
+    }
+
+    #[test]
+    fn completes_nested_paths() {
+        let (vd, session, helper) = helper();
+        assert_eq!(complete(&helper, "cd d"), (3, vec!["data/".to_string(), "docs/".to_string()]));
+        assert_eq!(complete(&helper, "cat /docs/n"), (4, vec!["/docs/notes.txt ".to_string()]));
+        assert_eq!(complete(&helper, "cat docs/../da"), (4, vec!["docs/../data/".to_string()]));
+        assert_eq!(complete(&helper, "cat /missing/"), (4, Vec::new()));
+
+        // 相对路径从当前目录开始，补全时读取磁盘的最新内容
+        vd.borrow().set_current_dir(&mut session.borrow_mut(), "/docs").unwrap();
+        assert_eq!(complete(&helper, "cat no"), (4, vec!["notes.txt ".to_string()]));
+        vd.borrow_mut().write_file_by_path(&session.borrow(), "/docs/now", b"").unwrap();
+        assert_eq!(complete(&helper, "cat no"), (4, vec!["notes.txt ".to_string(), "now ".to_string()]));
+    }
+
+    #[test]
+    fn escapes_names_with_spaces() {
+        let (_, _, helper) = helper();
+        assert_eq!(complete(&helper, "cat /docs/m"), (4, vec!["/docs/my\\ file ".to_string()]));
+        assert_eq!(complete(&helper, "cd /docs/old"), (3, vec!["/docs/old\\ drafts/".to_string()]));
+        // 已输入的转义空白属于同一个单词
+        assert_eq!(complete(&helper, "cat /docs/old\\ drafts/v"), (4, vec!["/docs/old\\ drafts/v1 ".to_string()]));
+    }
+
+    #[test]
+    fn escapes_every_special_character() {
+        let (vd, session, helper) = helper();
+        let name = "a*b?c[d|e>f'g\"h\\i\tj";
+        vd.borrow_mut().write_file_by_path(&session.borrow(), &format!("/data/{}", name), b"").unwrap();
+        let (_, replacements) = complete(&helper, "cat /data/a");
+        assert_eq!(replacements, vec!["/data/a\\*b\\?c\\[d\\|e\\>f\\'g\\\"h\\\\i\\\tj ".to_string()]);
+
+        // 补全的结果经解析后还原为原来的名字，且不作为通配符展开
+        let tokens = parser::tokenize(replacements[0].trim_end()).unwrap();
+        assert_eq!(tokens, vec![parser::Token::Word(parser::Word { text: format!("/data/{}", name), pattern: None })]);
+    }
+}
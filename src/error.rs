@@ -0,0 +1,70 @@
+use std::fmt;
+
+// 文件系统操作的错误类型
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsError {
+    NotFound(String),          // 文件或目录不存在
+    AlreadyExists(String),     // 同名文件已存在
+    NotADirectory(String),     // 需要目录但给出的是文件
+    IsADirectory(String),      // 需要文件但给出的是目录
+    DirectoryNotEmpty(String), // 删除非空目录
+    InvalidName(String),       // 非法的文件名
+    InvalidArgument(String),   // 非法的操作，如将目录移动到其子目录下
+    NoSpace,                   // 磁盘空间不足
+    QuotaExceeded(String),     // 超出用户或目录的配额
+    WouldBlock(String),        // 文件被其他持有者锁定
+    Encrypted(String),         // 镜像已加密，需要口令
+    WrongPassphrase,           // 口令错误
+    Corrupted(String),         // 磁盘数据损坏，如FAT链断裂或目录无法反序列化
+    Io(String),                // 读写本地文件失败
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsError::NotFound(name) => write!(f, "{}: No such file or directory!", name),
+            FsError::AlreadyExists(name) => write!(f, "{}: File already exists!", name),
+            FsError::NotADirectory(name) => write!(f, "{}: Not a directory!", name),
+            FsError::IsADirectory(name) => write!(f, "{}: Is a directory!", name),
+            FsError::DirectoryNotEmpty(name) => write!(f, "{}: Directory is not empty!", name),
+            FsError::InvalidName(name) => write!(f, "{}: Invalid file name!", name),
+            FsError::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
+            FsError::NoSpace => write!(f, "No enough space!"),
+            FsError::QuotaExceeded(name) => write!(f, "{}: Disk quota exceeded!", name),
+            FsError::WouldBlock(name) => write!(f, "{}: Resource temporarily unavailable (locked)!", name),
+            FsError::Encrypted(name) => write!(f, "{}: Image is encrypted, passphrase required!", name),
+            FsError::WrongPassphrase => write!(f, "Wrong passphrase!"),
+            FsError::Corrupted(msg) => write!(f, "Disk corrupted: {}", msg),
+            FsError::Io(msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl FsError {
+    // 对应的errno，供FUSE等需要系统错误码的接口使用
+    pub fn errno(&self) -> i32 {
+        match self {
+            FsError::NotFound(_) => libc::ENOENT,
+            FsError::AlreadyExists(_) => libc::EEXIST,
+            FsError::NotADirectory(_) => libc::ENOTDIR,
+            FsError::IsADirectory(_) => libc::EISDIR,
+            FsError::DirectoryNotEmpty(_) => libc::ENOTEMPTY,
+            FsError::InvalidName(_) | FsError::InvalidArgument(_) => libc::EINVAL,
+            FsError::NoSpace => libc::ENOSPC,
+            FsError::QuotaExceeded(_) => libc::EDQUOT,
+            FsError::WouldBlock(_) => libc::EAGAIN,
+            FsError::Encrypted(_) | FsError::WrongPassphrase => libc::EACCES,
+            FsError::Corrupted(_) | FsError::Io(_) => libc::EIO,
+        }
+    }
+}
+
+impl std::error::Error for FsError {}
+
+impl From<FsError> for String {
+    fn from(err: FsError) -> String {
+        err.to_string()
+    }
+}
+
+pub type FsResult<T> = Result<T, FsError>;
@@ -0,0 +1,805 @@
+use crate::archive::make_directories;
+use crate::disk::BLOCK_SIZE;
+use crate::disk_operator::{Directory, DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::session::Session;
+
+use chrono::{Datelike, TimeZone, Timelike};
+use std::collections::HashSet;
+use std::fs;
+
+const SECTOR_SIZE: usize = 512;
+const ENTRY_SIZE: usize = 32;
+const ROOT_ENTRIES: usize = 512;
+const MEDIA: u8 = 0xF8;
+const MAX_FAT12_CLUSTERS: usize = 4084;
+const MAX_FAT16_CLUSTERS: usize = 65524;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+const LAST_LONG_ENTRY: u8 = 0x40;
+const DELETED: u8 = 0xE5;
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXT: u8 = 0x10;
+
+// FAT类型，FAT12以4096字节为一簇，FAT16以512字节为一簇，簇数都与虚拟磁盘的容量对应
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+}
+
+impl FatType {
+    pub fn parse(text: &str) -> FsResult<FatType> {
+        match text {
+            "12" => Ok(FatType::Fat12),
+            "16" => Ok(FatType::Fat16),
+            _ => Err(FsError::InvalidArgument(format!("unsupported FAT type {}", text))),
+        }
+    }
+
+    // 按簇数判断FAT类型，与fsck.fat和Linux内核的规则一致
+    fn from_cluster_count(count: usize) -> FsResult<FatType> {
+        match count {
+            0 => Err(FsError::Corrupted("FAT image has no data clusters".to_string())),
+            1..=MAX_FAT12_CLUSTERS => Ok(FatType::Fat12),
+            _ if count <= MAX_FAT16_CLUSTERS => Ok(FatType::Fat16),
+            _ => Err(FsError::InvalidArgument("FAT32 images are not supported".to_string())),
+        }
+    }
+
+    fn sectors_per_cluster(&self) -> usize {
+        match self {
+            FatType::Fat12 => BLOCK_SIZE / SECTOR_SIZE,
+            FatType::Fat16 => 1,
+        }
+    }
+
+    fn eof(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+        }
+    }
+
+    // 表项大小，FAT12为1.5字节
+    fn fat_bytes(&self, entries: usize) -> usize {
+        match self {
+            FatType::Fat12 => (entries * 3).div_ceil(2),
+            FatType::Fat16 => entries * 2,
+        }
+    }
+
+    fn label(&self) -> &'static [u8; 8] {
+        match self {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+        }
+    }
+}
+
+// 读写FAT表
+struct FatTable {
+    fat_type: FatType,
+    data: Vec<u8>,
+}
+
+impl FatTable {
+    fn get(&self, cluster: usize) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster * 3 / 2;
+                let value = u16::from_le_bytes([self.data[offset], self.data[offset + 1]]) as u32;
+                if cluster.is_multiple_of(2) {
+                    value & 0xFFF
+                } else {
+                    value >> 4
+                }
+            }
+            FatType::Fat16 => u16::from_le_bytes([self.data[cluster * 2], self.data[cluster * 2 + 1]]) as u32,
+        }
+    }
+
+    fn set(&mut self, cluster: usize, value: u32) {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster * 3 / 2;
+                if cluster.is_multiple_of(2) {
+                    self.data[offset] = value as u8;
+                    self.data[offset + 1] = (self.data[offset + 1] & 0xF0) | ((value >> 8) as u8 & 0x0F);
+                } else {
+                    self.data[offset] = (self.data[offset] & 0x0F) | ((value as u8 & 0x0F) << 4);
+                    self.data[offset + 1] = (value >> 4) as u8;
+                }
+            }
+            FatType::Fat16 => self.data[cluster * 2..cluster * 2 + 2].copy_from_slice(&(value as u16).to_le_bytes()),
+        }
+    }
+}
+
+// 生成8.3短文件名，返回11字节的名称以及是否需要长文件名
+fn short_name(name: &str, used: &HashSet<[u8; 11]>) -> FsResult<([u8; 11], bool)> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c);
+    let (base, ext) = match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index + 1..]),
+        _ => (name, ""),
+    };
+    let convert = |part: &str| -> String {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| if valid(c) { c.to_ascii_uppercase() } else { '_' })
+            .collect()
+    };
+    let (short_base, short_ext) = (convert(base), convert(ext));
+    // 只改变大小写即可得到的8.3名称不需要数字后缀
+    let lossless = short_base.len() == base.len()
+        && short_ext.len() == ext.len()
+        && !short_base.is_empty()
+        && short_base.len() <= 8
+        && short_ext.len() <= 3
+        && base.chars().chain(ext.chars()).all(valid);
+
+    let pack = |base: &str, ext: &str| -> [u8; 11] {
+        let mut packed = [b' '; 11];
+        packed[..base.len()].copy_from_slice(base.as_bytes());
+        packed[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        packed
+    };
+    let short_ext: String = short_ext.chars().take(3).collect();
+    if lossless {
+        let packed = pack(&short_base, &short_ext);
+        if !used.contains(&packed) {
+            return Ok((packed, name.as_bytes() != short_name_text(&packed).as_bytes()));
+        }
+    }
+    let short_base = if short_base.is_empty() { "_".to_string() } else { short_base };
+    for number in 1..1_000_000 {
+        let tail = format!("~{}", number);
+        let base: String = short_base.chars().take(8 - tail.len()).collect();
+        let packed = pack(&format!("{}{}", base, tail), &short_ext);
+        if !used.contains(&packed) {
+            return Ok((packed, true));
+        }
+    }
+    Err(FsError::InvalidName(name.to_string()))
+}
+
+// 11字节的短文件名转换为BASE.EXT形式，flags为NT保留字节中的小写标志
+fn short_name_text(packed: &[u8; 11]) -> String {
+    short_name_with_case(packed, 0)
+}
+
+fn short_name_with_case(packed: &[u8; 11], flags: u8) -> String {
+    let mut name = packed.to_vec();
+    if name[0] == 0x05 {
+        name[0] = DELETED;
+    }
+    let text = |bytes: &[u8], lower: bool| -> String {
+        let text: String = bytes.iter().map(|&byte| byte as char).collect::<String>().trim_end().to_string();
+        if lower {
+            text.to_ascii_lowercase()
+        } else {
+            text
+        }
+    };
+    let base = text(&name[..8], flags & LOWERCASE_BASE != 0);
+    let ext = text(&name[8..], flags & LOWERCASE_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+fn checksum(packed: &[u8; 11]) -> u8 {
+    packed
+        .iter()
+        .fold(0u8, |sum, &byte| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte))
+}
+
+// 一个目录项：长文件名项在前，短文件名项在后
+struct DirEntry {
+    short: [u8; 11],
+    long: Option<Vec<u16>>,
+}
+
+impl DirEntry {
+    fn slots(&self) -> usize {
+        1 + self.long.as_ref().map_or(0, |name| name.len().div_ceil(13))
+    }
+}
+
+fn dos_time(timestamp: i64) -> (u16, u16) {
+    match chrono::Local.timestamp_opt(timestamp, 0).single() {
+        Some(time) if time.year() >= 1980 && time.year() < 2108 => {
+            let date = ((time.year() as u16 - 1980) << 9) | ((time.month() as u16) << 5) | time.day() as u16;
+            let clock = ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | (time.second() as u16 / 2);
+            (date, clock)
+        }
+        // 超出FAT能表示的范围时使用1980-01-01
+        _ => ((1 << 5) | 1, 0),
+    }
+}
+
+// 将DOS日期与时间还原为时间戳，无效的日期返回None
+fn from_dos_time(date: u16, clock: u16) -> Option<i64> {
+    let year = 1980 + (date >> 9) as i32;
+    let (month, day) = ((date >> 5) as u32 & 0x0F, date as u32 & 0x1F);
+    let (hour, minute, second) = ((clock >> 11) as u32, (clock >> 5) as u32 & 0x3F, (clock as u32 & 0x1F) * 2);
+    let time = chrono::Local.with_ymd_and_hms(year, month, day, hour, minute, second);
+    time.earliest().map(|time| time.timestamp())
+}
+
+// 导出时在内存中依次分配簇，文件与目录都连续存放
+struct Builder<'a> {
+    vd: &'a DiskOperator,
+    fat_type: FatType,
+    cluster_size: usize,
+    data: Vec<u8>, // 从2号簇开始的数据区
+    next: Vec<u32>, // 各簇在FAT中的下一簇，下标为簇号减2
+    count: usize,
+}
+
+impl Builder<'_> {
+    fn allocate(&mut self, size: usize) -> u32 {
+        if size == 0 {
+            return 0;
+        }
+        let clusters = size.div_ceil(self.cluster_size);
+        let first = self.next.len() + 2;
+        for index in 0..clusters {
+            let next = if index + 1 == clusters { self.fat_type.eof() } else { (first + index + 1) as u32 };
+            self.next.push(next);
+        }
+        self.data.resize(self.next.len() * self.cluster_size, 0);
+        first as u32
+    }
+
+    fn write(&mut self, cluster: u32, data: &[u8]) {
+        let offset = (cluster as usize - 2) * self.cluster_size;
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    // 为目录中的文件生成短文件名与长文件名
+    fn names(&self, dir: &Directory) -> FsResult<Vec<(Fcb, DirEntry)>> {
+        let mut used = HashSet::from([*b".          ", *b"..         "]);
+        let mut entries = Vec::new();
+        for fcb in dir.entries() {
+            let (short, needs_long) = short_name(fcb.name(), &used)?;
+            used.insert(short);
+            let long: Vec<u16> = fcb.name().encode_utf16().collect();
+            if long.len() > 255 {
+                return Err(FsError::InvalidName(fcb.name().to_string()));
+            }
+            let long = if needs_long { Some(long) } else { None };
+            entries.push((fcb.clone(), DirEntry { short, long }));
+        }
+        Ok(entries)
+    }
+
+    // 写出目录中的全部内容，返回目录项数据；cluster为目录自身的首簇，根目录为0
+    fn directory(&mut self, dir: &Directory, cluster: u32, parent: u32) -> FsResult<Vec<u8>> {
+        let mut table = Vec::new();
+        if cluster != 0 {
+            let (date, time) = dos_time(dir.get_entry(".").map_or(0, |fcb| fcb.modified()));
+            table.extend(raw_entry(b".          ", ATTR_DIRECTORY, cluster, 0, date, time));
+            table.extend(raw_entry(b"..         ", ATTR_DIRECTORY, parent, 0, date, time));
+        }
+
+        for (fcb, entry) in self.names(dir)? {
+            let (attr, first, size) = match fcb.file_type() {
+                FileType::File => {
+                    let data = self.vd.get_file_by_fcb(&fcb)?;
+                    let first = self.allocate(data.len());
+                    if first != 0 {
+                        self.write(first, &data);
+                    }
+                    (ATTR_ARCHIVE, first, data.len() as u32)
+                }
+                FileType::Directory => {
+                    let sub_dir = self.vd.get_directory_by_fcb(&fcb)?;
+                    let slots: usize = 2 + self.names(&sub_dir)?.iter().map(|(_, entry)| entry.slots()).sum::<usize>();
+                    let first = self.allocate(slots * ENTRY_SIZE);
+                    let content = self.directory(&sub_dir, first, cluster)?;
+                    self.write(first, &content);
+                    (ATTR_DIRECTORY, first, 0)
+                }
+            };
+            if let Some(long) = &entry.long {
+                table.extend(long_entries(long, checksum(&entry.short)));
+            }
+            let (date, time) = dos_time(fcb.modified());
+            table.extend(raw_entry(&entry.short, attr, first, size, date, time));
+            self.count += 1;
+        }
+        Ok(table)
+    }
+}
+
+fn raw_entry(name: &[u8; 11], attr: u8, cluster: u32, size: u32, date: u16, time: u16) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].copy_from_slice(name);
+    if entry[0] == DELETED {
+        entry[0] = 0x05;
+    }
+    entry[11] = attr;
+    for offset in [14, 22] {
+        entry[offset..offset + 2].copy_from_slice(&time.to_le_bytes());
+    }
+    for offset in [16, 18, 24] {
+        entry[offset..offset + 2].copy_from_slice(&date.to_le_bytes());
+    }
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+// 长文件名项按序号倒序存放，每项保存13个UTF-16字符
+fn long_entries(name: &[u16], checksum: u8) -> Vec<u8> {
+    let count = name.len().div_ceil(13);
+    let mut table = Vec::new();
+    for index in (0..count).rev() {
+        let mut chars = [0xFFFFu16; 13];
+        for (slot, char) in chars.iter_mut().enumerate() {
+            let position = index * 13 + slot;
+            if position < name.len() {
+                *char = name[position];
+            } else if position == name.len() {
+                *char = 0;
+            }
+        }
+        let mut entry = [0u8; ENTRY_SIZE];
+        entry[0] = (index + 1) as u8 | if index + 1 == count { LAST_LONG_ENTRY } else { 0 };
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = checksum;
+        let offsets = (0..5).map(|i| 1 + i * 2).chain((0..6).map(|i| 14 + i * 2)).chain((0..2).map(|i| 28 + i * 2));
+        for (char, offset) in chars.iter().zip(offsets) {
+            entry[offset..offset + 2].copy_from_slice(&char.to_le_bytes());
+        }
+        table.extend(entry);
+    }
+    table
+}
+
+// 将虚拟磁盘中path目录下的全部内容导出为FAT12/16镜像，返回导出的条目数
+pub fn export_fat(vd: &DiskOperator, session: &Session, path: &str, image_name: &str, fat_type: FatType) -> FsResult<usize> {
+    let dir = vd.get_directory_by_path(session, path)?;
+    let spc = fat_type.sectors_per_cluster();
+    let mut builder = Builder {
+        vd,
+        fat_type,
+        cluster_size: spc * SECTOR_SIZE,
+        data: Vec::new(),
+        next: Vec::new(),
+        count: 0,
+    };
+    let root = builder.directory(&dir, 0, 0)?;
+
+    // 根目录区的大小固定，项数须为每扇区项数的整数倍
+    let root_entries = (root.len() / ENTRY_SIZE).max(ROOT_ENTRIES).next_multiple_of(SECTOR_SIZE / ENTRY_SIZE);
+    if root_entries > u16::MAX as usize {
+        return Err(FsError::NoSpace);
+    }
+    // 簇数与虚拟磁盘的容量相同，内容更多时扩大镜像
+    let max = match fat_type {
+        FatType::Fat12 => MAX_FAT12_CLUSTERS,
+        FatType::Fat16 => MAX_FAT16_CLUSTERS,
+    };
+    let min = match fat_type {
+        FatType::Fat12 => 1,
+        FatType::Fat16 => MAX_FAT12_CLUSTERS + 1,
+    };
+    let clusters = (vd.disk.cluster_count() * BLOCK_SIZE / builder.cluster_size)
+        .max(builder.next.len())
+        .clamp(min, max);
+    if builder.next.len() > clusters {
+        return Err(FsError::NoSpace);
+    }
+
+    let fat_sectors = fat_type.fat_bytes(clusters + 2).div_ceil(SECTOR_SIZE);
+    let root_sectors = root_entries * ENTRY_SIZE / SECTOR_SIZE;
+    let data_start = 1 + 2 * fat_sectors + root_sectors;
+    let total_sectors = data_start + clusters * spc;
+
+    let mut fat = FatTable { fat_type, data: vec![0; fat_sectors * SECTOR_SIZE] };
+    fat.set(0, fat_type.eof() & !0xFF | MEDIA as u32);
+    fat.set(1, fat_type.eof());
+    for (index, &next) in builder.next.iter().enumerate() {
+        fat.set(index + 2, next);
+    }
+
+    let mut image = vec![0u8; total_sectors * SECTOR_SIZE];
+    image[..SECTOR_SIZE].copy_from_slice(&boot_sector(fat_type, root_entries, total_sectors, fat_sectors));
+    for copy in 0..2 {
+        let offset = (1 + copy * fat_sectors) * SECTOR_SIZE;
+        image[offset..offset + fat.data.len()].copy_from_slice(&fat.data);
+    }
+    let root_offset = (1 + 2 * fat_sectors) * SECTOR_SIZE;
+    image[root_offset..root_offset + root.len()].copy_from_slice(&root);
+    let data_offset = data_start * SECTOR_SIZE;
+    image[data_offset..data_offset + builder.data.len()].copy_from_slice(&builder.data);
+
+    fs::write(image_name, image).map_err(|err| FsError::Io(format!("{}: {}", image_name, err)))?;
+    Ok(builder.count)
+}
+
+fn boot_sector(fat_type: FatType, root_entries: usize, total_sectors: usize, fat_sectors: usize) -> [u8; SECTOR_SIZE] {
+    let mut sector = [0u8; SECTOR_SIZE];
+    sector[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    sector[3..11].copy_from_slice(b"FILESYS ");
+    sector[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    sector[13] = fat_type.sectors_per_cluster() as u8;
+    sector[14..16].copy_from_slice(&1u16.to_le_bytes()); // 保留扇区数
+    sector[16] = 2; // FAT份数
+    sector[17..19].copy_from_slice(&(root_entries as u16).to_le_bytes());
+    if total_sectors <= u16::MAX as usize {
+        sector[19..21].copy_from_slice(&(total_sectors as u16).to_le_bytes());
+    } else {
+        sector[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+    }
+    sector[21] = MEDIA;
+    sector[22..24].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+    sector[24..26].copy_from_slice(&32u16.to_le_bytes()); // 每磁道扇区数
+    sector[26..28].copy_from_slice(&64u16.to_le_bytes()); // 磁头数
+    sector[36] = 0x80; // 驱动器号
+    sector[38] = 0x29; // 扩展引导标记
+    sector[39..43].copy_from_slice(&(chrono::Local::now().timestamp() as u32).to_le_bytes());
+    sector[43..54].copy_from_slice(b"NO NAME    ");
+    sector[54..62].copy_from_slice(fat_type.label());
+    // 引导代码：int 18h，从其他设备启动
+    sector[62..64].copy_from_slice(&[0xCD, 0x18]);
+    sector[510..].copy_from_slice(&[0x55, 0xAA]);
+    sector
+}
+
+// 解析后的FAT镜像
+struct FatImage {
+    data: Vec<u8>,
+    fat: FatTable,
+    cluster_size: usize,
+    clusters: usize,
+    root_offset: usize,
+    root_entries: usize,
+    data_offset: usize,
+}
+
+impl FatImage {
+    fn parse(data: Vec<u8>) -> FsResult<FatImage> {
+        let corrupted = |message: &str| FsError::Corrupted(message.to_string());
+        if data.len() < SECTOR_SIZE || data[510..512] != [0x55, 0xAA] {
+            return Err(corrupted("not a FAT image: missing boot signature"));
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]) as usize;
+        let sector_size = u16_at(11);
+        let spc = data[13] as usize;
+        let reserved = u16_at(14);
+        let fats = data[16] as usize;
+        let root_entries = u16_at(17);
+        let total_sectors = match u16_at(19) {
+            0 => u32::from_le_bytes([data[32], data[33], data[34], data[35]]) as usize,
+            total => total,
+        };
+        let fat_sectors = u16_at(22);
+
+        if ![512, 1024, 2048, 4096].contains(&sector_size) || !spc.is_power_of_two() || reserved == 0 || fats == 0 {
+            return Err(corrupted("not a FAT image: invalid BIOS parameter block"));
+        }
+        if root_entries == 0 || fat_sectors == 0 {
+            return Err(FsError::InvalidArgument("FAT32 images are not supported".to_string()));
+        }
+        let root_sectors = (root_entries * ENTRY_SIZE).div_ceil(sector_size);
+        let data_start = reserved + fats * fat_sectors + root_sectors;
+        if total_sectors <= data_start || total_sectors * sector_size > data.len() {
+            return Err(corrupted("FAT image is truncated"));
+        }
+        let clusters = (total_sectors - data_start) / spc;
+        let fat_type = FatType::from_cluster_count(clusters)?;
+        if fat_type.fat_bytes(clusters + 2) > fat_sectors * sector_size {
+            return Err(corrupted("FAT is too small for the data area"));
+        }
+
+        let fat_offset = reserved * sector_size;
+        let fat = FatTable {
+            fat_type,
+            data: data[fat_offset..fat_offset + fat_sectors * sector_size].to_vec(),
+        };
+        Ok(FatImage {
+            fat,
+            cluster_size: spc * sector_size,
+            clusters,
+            root_offset: (reserved + fats * fat_sectors) * sector_size,
+            root_entries,
+            data_offset: data_start * sector_size,
+            data,
+        })
+    }
+
+    // 沿FAT读出簇链，遇到越界、空闲或成环的表项时报错
+    fn chain(&self, first: usize) -> FsResult<Vec<usize>> {
+        let eof = self.fat.fat_type.eof() as usize;
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        loop {
+            if cluster < 2 || cluster >= self.clusters + 2 {
+                return Err(FsError::Corrupted(format!("FAT chain from {} reaches cluster {}", first, cluster)));
+            }
+            if chain.len() > self.clusters {
+                return Err(FsError::Corrupted(format!("FAT chain from {} has a loop", first)));
+            }
+            chain.push(cluster);
+            let next = self.fat.get(cluster) as usize;
+            if next >= eof - 7 {
+                return Ok(chain);
+            }
+            cluster = next;
+        }
+    }
+
+    fn read_chain(&self, first: usize) -> FsResult<Vec<u8>> {
+        let mut content = Vec::new();
+        for cluster in self.chain(first)? {
+            let offset = self.data_offset + (cluster - 2) * self.cluster_size;
+            content.extend_from_slice(&self.data[offset..offset + self.cluster_size]);
+        }
+        Ok(content)
+    }
+
+    fn read_file(&self, first: usize, size: usize) -> FsResult<Vec<u8>> {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let mut content = self.read_chain(first)?;
+        if content.len() < size {
+            return Err(FsError::Corrupted(format!("file at cluster {} is shorter than its size", first)));
+        }
+        content.truncate(size);
+        Ok(content)
+    }
+
+    // 目录的原始数据，first为0表示根目录
+    fn directory(&self, first: usize) -> FsResult<Vec<u8>> {
+        if first == 0 {
+            Ok(self.data[self.root_offset..self.root_offset + self.root_entries * ENTRY_SIZE].to_vec())
+        } else {
+            self.read_chain(first)
+        }
+    }
+}
+
+// 解析目录项，返回名称、是否为目录、首簇、大小与修改时间
+fn parse_directory(table: &[u8]) -> Vec<(String, bool, usize, usize, Option<i64>)> {
+    let mut entries = Vec::new();
+    let mut long: Vec<(u8, [u16; 13])> = Vec::new();
+    for raw in table.chunks_exact(ENTRY_SIZE) {
+        match raw[0] {
+            0 => break,
+            DELETED => {
+                long.clear();
+                continue;
+            }
+            _ => {}
+        }
+        let attr = raw[11];
+        if attr & 0x3F == ATTR_LONG_NAME {
+            let mut chars = [0u16; 13];
+            let offsets = (0..5).map(|i| 1 + i * 2).chain((0..6).map(|i| 14 + i * 2)).chain((0..2).map(|i| 28 + i * 2));
+            for (char, offset) in chars.iter_mut().zip(offsets) {
+                *char = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+            }
+            if raw[0] & LAST_LONG_ENTRY != 0 {
+                long.clear();
+            }
+            long.push((raw[13], chars));
+            continue;
+        }
+        if attr & ATTR_VOLUME_ID != 0 {
+            long.clear();
+            continue;
+        }
+
+        let mut short = [0u8; 11];
+        short.copy_from_slice(&raw[..11]);
+        let sum = checksum(&short);
+        // 长文件名项的校验和与数量都匹配时才使用长文件名
+        let long_name = if !long.is_empty() && long.iter().all(|(check, _)| *check == sum) {
+            let units: Vec<u16> = long
+                .iter()
+                .rev()
+                .flat_map(|(_, chars)| chars.iter().copied())
+                .take_while(|&char| char != 0)
+                .collect();
+            String::from_utf16(&units).ok()
+        } else {
+            None
+        };
+        long.clear();
+        let name = long_name.unwrap_or_else(|| short_name_with_case(&short, raw[12]));
+        if name == "." || name == ".." {
+            continue;
+        }
+        let cluster = u16::from_le_bytes([raw[26], raw[27]]) as usize;
+        let size = u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]) as usize;
+        let modified = from_dos_time(u16::from_le_bytes([raw[24], raw[25]]), u16::from_le_bytes([raw[22], raw[23]]));
+        entries.push((name, attr & ATTR_DIRECTORY != 0, cluster, size, modified));
+    }
+    entries
+}
+
+// 将FAT12/16镜像中的内容导入虚拟磁盘的path目录，已存在的同名文件会被覆盖，返回导入的条目数
+pub fn import_fat(vd: &mut DiskOperator, session: &Session, image_name: &str, path: &str) -> FsResult<usize> {
+    let target = vd.get_directory_by_path(session, path)?.cluster();
+    let data = fs::read(image_name).map_err(|err| FsError::Io(format!("{}: {}", image_name, err)))?;
+    let image = FatImage::parse(data)?;
+    let mut visited = HashSet::new();
+    import_directory(vd, session, &image, 0, target, &mut visited)
+}
+
+fn import_directory(
+    vd: &mut DiskOperator,
+    session: &Session,
+    image: &FatImage,
+    first: usize,
+    target: usize,
+    visited: &mut HashSet<usize>,
+) -> FsResult<usize> {
+    if !visited.insert(first) {
+        return Err(FsError::Corrupted(format!("directory at cluster {} appears twice", first)));
+    }
+    let mut count = 0;
+    for (name, is_dir, cluster, size, modified) in parse_directory(&image.directory(first)?) {
+        if is_dir {
+            let sub_dir = make_directories(vd, session, target, std::slice::from_ref(&name))?;
+            count += import_directory(vd, session, image, cluster, sub_dir, visited)?;
+        } else {
+            // 先写入新数据再替换，写入失败时已有的同名文件保持不变
+            let content = image.read_file(cluster, size)?;
+            vd.replace_file_at(session, target, &name, &content)?;
+        }
+        // 目录的修改时间在导入其内容之后设置
+        if let Some(modified) = modified {
+            vd.set_modified_at(session, target, &name, modified)?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_image(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("file-system-{}-{}.img", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn short_names_follow_the_8_3_rules() {
+        let mut used = HashSet::new();
+        assert_eq!(short_name("README.TXT", &used).unwrap(), (*b"README  TXT", false));
+        assert_eq!(short_name("x.txt", &used).unwrap(), (*b"X       TXT", true));
+        let (long, _) = short_name("very long name.text", &used).unwrap();
+        assert_eq!(&long, b"VERYLO~1TEX");
+        used.insert(long);
+        assert_eq!(&short_name("very long name2.text", &used).unwrap().0, b"VERYLO~2TEX");
+        assert_eq!(&short_name(".hidden", &used).unwrap().0, b"HIDDEN~1   ");
+        assert_eq!(short_name_with_case(b"README  TXT", LOWERCASE_BASE), "readme.TXT");
+    }
+
+    #[test]
+    fn fat_images_round_trip() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        let big: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        vd.new_file(&session, "big.bin", &big).unwrap();
+        vd.new_file(&session, "empty", b"").unwrap();
+        let dir = vd.new_directory_at(&session, 0, "A Long Directory Name").unwrap();
+        vd.new_file_at(&session, dir, "README.TXT", b"read me").unwrap();
+        vd.new_directory_at(&session, dir, "sub").unwrap();
+        // DOS时间精确到2秒
+        vd.set_modified_at(&session, 0, "big.bin", 1_000_000_000).unwrap();
+        vd.set_modified_at(&session, 0, "A Long Directory Name", 1_200_000_000).unwrap();
+
+        for fat_type in [FatType::Fat12, FatType::Fat16] {
+            let image = temp_image(&format!("{:?}", fat_type));
+            assert_eq!(export_fat(&vd, &session, "/", &image, fat_type).unwrap(), 5);
+            let mut copy = DiskOperator::new(None);
+            assert_eq!(import_fat(&mut copy, &session, &image, "/").unwrap(), 5);
+            let _ = fs::remove_file(&image);
+
+            assert_eq!(copy.read_file_by_path(&session, "/big.bin").unwrap(), big);
+            assert_eq!(copy.read_file_by_path(&session, "/empty").unwrap(), b"");
+            assert_eq!(copy.read_file_by_path(&session, "/A Long Directory Name/README.TXT").unwrap(), b"read me");
+            assert!(copy.get_directory_by_path(&session, "/A Long Directory Name/sub").is_ok());
+            assert_eq!(copy.get_fcb_by_path(&session, "/big.bin").unwrap().modified(), 1_000_000_000);
+            assert_eq!(copy.get_fcb_by_path(&session, "/A Long Directory Name").unwrap().modified(), 1_200_000_000);
+        }
+    }
+
+    #[test]
+    fn exported_images_are_well_formed() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_file(&session, "big.bin", &vec![7; 10000]).unwrap();
+        let dir = vd.new_directory_at(&session, 0, "dir").unwrap();
+        vd.new_directory_at(&session, dir, "sub").unwrap();
+
+        for fat_type in [FatType::Fat12, FatType::Fat16] {
+            let name = temp_image(&format!("well-formed-{:?}", fat_type));
+            export_fat(&vd, &session, "/", &name, fat_type).unwrap();
+            let image = FatImage::parse(fs::read(&name).unwrap()).unwrap();
+            let _ = fs::remove_file(&name);
+            assert_eq!(image.fat.fat_type, fat_type);
+
+            // FAT[0]低字节为介质描述符，其余位与FAT[1]都为结束标记
+            let eof = fat_type.eof();
+            assert_eq!(image.fat.get(0), eof & !0xFF | MEDIA as u32);
+            assert_eq!(image.fat.get(1), eof);
+
+            // 簇链以结束标记终止，长度与文件大小一致
+            let root = parse_directory(&image.directory(0).unwrap());
+            let (_, _, first, size, _) = root.iter().find(|entry| entry.0 == "big.bin").unwrap();
+            let chain = image.chain(*first).unwrap();
+            assert_eq!(chain.len(), size.div_ceil(image.cluster_size));
+            assert_eq!(image.fat.get(*chain.last().unwrap()), eof);
+
+            // 子目录以.与..开头，分别指向自身与父目录，父目录为根目录时为0
+            let (_, _, dir, _, _) = root.iter().find(|entry| entry.0 == "dir").unwrap();
+            let (_, _, sub, _, _) = parse_directory(&image.directory(*dir).unwrap())[0];
+            for (cluster, parent) in [(*dir, 0), (sub, *dir)] {
+                let table = image.directory(cluster).unwrap();
+                assert_eq!(&table[..11], b".          ");
+                assert_eq!(&table[ENTRY_SIZE..ENTRY_SIZE + 11], b"..         ");
+                for (offset, expected) in [(0, cluster), (ENTRY_SIZE, parent)] {
+                    assert_eq!(table[offset + 11], ATTR_DIRECTORY);
+                    assert_eq!(u16::from_le_bytes([table[offset + 26], table[offset + 27]]) as usize, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn failed_imports_keep_existing_files() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_file(&session, "big.bin", &vec![7; 10000]).unwrap();
+        let image = temp_image("overwrite");
+        export_fat(&vd, &session, "/", &image, FatType::Fat12).unwrap();
+
+        let mut copy = DiskOperator::new(None);
+        let small = copy.new_directory_at(&session, 0, "small").unwrap();
+        copy.new_file_at(&session, small, "big.bin", b"old").unwrap();
+        copy.quotas().set_dir(small, crate::quota::Limit { blocks: 2, inodes: 0 });
+        let result = import_fat(&mut copy, &session, &image, "/small");
+        let _ = fs::remove_file(&image);
+        assert!(matches!(result, Err(FsError::QuotaExceeded(_))), "{:?}", result);
+        assert_eq!(copy.read_file_by_path(&session, "/small/big.bin").unwrap(), b"old");
+        assert!(crate::fsck::check(&copy).is_empty(), "{:?}", crate::fsck::check(&copy));
+    }
+
+    #[test]
+    fn broken_images_are_rejected() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_file(&session, "a", b"data").unwrap();
+        let image = temp_image("broken");
+        export_fat(&vd, &session, "/", &image, FatType::Fat12).unwrap();
+        let mut data = fs::read(&image).unwrap();
+
+        // 清空FAT后文件的簇链无效
+        let mut copy = data.clone();
+        copy[SECTOR_SIZE + 3..SECTOR_SIZE + 6].fill(0);
+        fs::write(&image, &copy).unwrap();
+        assert!(matches!(import_fat(&mut DiskOperator::new(None), &Session::default(), &image, "/"), Err(FsError::Corrupted(_))));
+
+        data[510] = 0;
+        fs::write(&image, &data).unwrap();
+        assert!(matches!(import_fat(&mut DiskOperator::new(None), &Session::default(), &image, "/"), Err(FsError::Corrupted(_))));
+        let _ = fs::remove_file(&image);
+    }
+}
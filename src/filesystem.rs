@@ -0,0 +1,412 @@
+use crate::disk_operator::{split_path, DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::session::Session;
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// 可在多个线程间共享的文件系统，通过Arc<FileSystem>并发读写
+// 每次调用传入调用者的会话：相对路径从会话的当前目录开始，写入检查其他会话持有的强制锁，分配的块计入会话用户的配额
+// 内部是一个DiskOperator，块的分配、写入、快照的写时复制、压缩、去重、配额与文件锁
+// 都与交互界面、FUSE和9P使用相同的代码；FAT、空闲块与数据块各自加锁，
+// 目录的元数据按目录加锁：修改同一目录中的目录项或文件互斥，读取其中的文件共享，不同目录中的修改同时进行；
+// 删除或移动目录会改变目录树的结构，期间独占整棵树
+pub struct FileSystem {
+    vd: DiskOperator,
+    tree: RwLock<()>,
+    dirs: DirLocks,
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+// 独占的目录在持有数中的记录
+const EXCLUSIVE: usize = usize::MAX;
+
+// 以目录首块号区分的目录锁，一次操作涉及的目录一起获得，不会互相等待
+#[derive(Default)]
+struct DirLocks {
+    held: Mutex<HashMap<usize, usize>>, // 目录首块号 -> 共享的持有数，独占时为EXCLUSIVE
+    released: Condvar,
+}
+
+impl DirLocks {
+    fn lock(&self, clusters: &[usize], exclusive: bool) -> DirGuard<'_> {
+        let mut clusters = clusters.to_vec();
+        clusters.sort_unstable();
+        clusters.dedup();
+        let held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        let busy = |held: &mut HashMap<usize, usize>| {
+            clusters.iter().any(|cluster| match held.get(cluster) {
+                Some(&count) => exclusive || count == EXCLUSIVE,
+                None => false,
+            })
+        };
+        let mut held = self.released.wait_while(held, busy).unwrap_or_else(PoisonError::into_inner);
+        for &cluster in &clusters {
+            let count = held.entry(cluster).or_insert(0);
+            *count = if exclusive { EXCLUSIVE } else { *count + 1 };
+        }
+        DirGuard { locks: self, clusters, exclusive }
+    }
+}
+
+// 离开作用域时释放目录锁并唤醒等待者
+struct DirGuard<'a> {
+    locks: &'a DirLocks,
+    clusters: Vec<usize>,
+    exclusive: bool,
+}
+
+impl Drop for DirGuard<'_> {
+    fn drop(&mut self) {
+        let mut held = self.locks.held.lock().unwrap_or_else(PoisonError::into_inner);
+        for cluster in &self.clusters {
+            match held.get(cluster) {
+                Some(&count) if !self.exclusive && count > 1 => held.insert(*cluster, count - 1),
+                _ => held.remove(cluster),
+            };
+        }
+        self.locks.released.notify_all();
+    }
+}
+
+// 路径中的最后一级不能是.或..
+fn check_entry(path: &str) -> FsResult<(&str, &str)> {
+    let (dir_path, name) = split_path(path);
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidName(name.to_string()));
+    }
+    Ok((dir_path, name))
+}
+
+impl FileSystem {
+    pub fn new() -> FileSystem {
+        FileSystem::from_operator(DiskOperator::new(None))
+    }
+
+    pub fn from_operator(vd: DiskOperator) -> FileSystem {
+        FileSystem {
+            vd,
+            tree: RwLock::default(),
+            dirs: DirLocks::default(),
+        }
+    }
+
+    // 等待进行中的操作完成后复制整个磁盘，复制期间其他操作等待；副本与这里共用锁表
+    pub fn to_operator(&self) -> DiskOperator {
+        let _tree = write(&self.tree);
+        self.vd.clone()
+    }
+
+    // 复制时阻塞其他操作，之后写入本地文件时不阻塞
+    pub fn save(&self, name: &str) -> FsResult<()> {
+        self.to_operator().save(name)
+    }
+
+    // 获取磁盘大小，已分配，未分配数量
+    pub fn disk_info(&self) -> (usize, usize, usize) {
+        self.vd.get_disk_info()
+    }
+
+    // 路径所在目录的首块号
+    fn parent(&self, session: &Session, path: &str) -> FsResult<usize> {
+        let (dir_path, _) = split_path(path);
+        Ok(self.vd.get_directory_by_path(session, dir_path)?.cluster())
+    }
+
+    // 按路径获取FCB，路径指向目录本身时返回目录自身的FCB
+    pub fn stat(&self, session: &Session, path: &str) -> FsResult<Fcb> {
+        let _tree = read(&self.tree);
+        self.vd.get_fcb_by_path(session, path)
+    }
+
+    // 文件的大小；目录的大小为其自身数据所占的块的字节数
+    pub fn size(&self, fcb: &Fcb) -> usize {
+        self.vd.get_size(fcb)
+    }
+
+    // 目录中除.和..以外的目录项
+    pub fn list_dir(&self, session: &Session, path: &str) -> FsResult<Vec<Fcb>> {
+        let _tree = read(&self.tree);
+        Ok(self.vd.get_directory_by_path(session, path)?.entries().cloned().collect())
+    }
+
+    pub fn read_file(&self, session: &Session, path: &str) -> FsResult<Vec<u8>> {
+        self.read_at(session, path, 0, usize::MAX)
+    }
+
+    // 从文件的offset处读取至多len字节，读取期间同一目录中的修改等待
+    pub fn read_at(&self, session: &Session, path: &str, offset: usize, len: usize) -> FsResult<Vec<u8>> {
+        let _tree = read(&self.tree);
+        let _dir = self.dirs.lock(&[self.parent(session, path)?], false);
+        let fcb = self.vd.get_fcb_by_path(session, path)?;
+        if fcb.file_type() == FileType::Directory {
+            return Err(FsError::IsADirectory(path.to_string()));
+        }
+        self.vd.read_at(&fcb, offset, len)
+    }
+
+    // 写入文件，文件不存在时创建，已存在时替换其内容
+    pub fn write_file(&self, session: &Session, path: &str, data: &[u8]) -> FsResult<()> {
+        let _tree = read(&self.tree);
+        let _dir = self.dirs.lock(&[self.parent(session, path)?], true);
+        self.vd.write_file_by_path(session, path, data)
+    }
+
+    // 从文件的offset处写入数据，offset超过原长度时中间是空洞
+    pub fn write_at(&self, session: &Session, path: &str, offset: usize, data: &[u8]) -> FsResult<()> {
+        let _tree = read(&self.tree);
+        let _dir = self.dirs.lock(&[self.parent(session, path)?], true);
+        self.vd.write_at(session, path, offset, data)
+    }
+
+    pub fn create_dir(&self, session: &Session, path: &str) -> FsResult<()> {
+        let _tree = read(&self.tree);
+        let parent = self.parent(session, path)?;
+        let _dir = self.dirs.lock(&[parent], true);
+        self.vd.new_directory_at(session, parent, split_path(path).1).map(|_| ())
+    }
+
+    // 删除文件或空目录
+    pub fn remove(&self, session: &Session, path: &str) -> FsResult<()> {
+        let (_, name) = check_entry(path)?;
+        {
+            let _tree = read(&self.tree);
+            let parent = self.parent(session, path)?;
+            let _dir = self.dirs.lock(&[parent], true);
+            if !self.is_directory(parent, name)? {
+                return self.vd.delete_file_at(session, parent, name);
+            }
+        }
+        let _tree = write(&self.tree);
+        let parent = self.parent(session, path)?;
+        self.vd.delete_file_at(session, parent, name)
+    }
+
+    // 移动或重命名，目标已存在时报错
+    pub fn rename(&self, session: &Session, src: &str, dst: &str) -> FsResult<()> {
+        let (_, name) = check_entry(src)?;
+        let new_name = split_path(dst).1;
+        {
+            let _tree = read(&self.tree);
+            let (from, to) = (self.parent(session, src)?, self.parent(session, dst)?);
+            let _dirs = self.dirs.lock(&[from, to], true);
+            if !self.is_directory(from, name)? {
+                return self.vd.move_entry(session, from, name, to, new_name);
+            }
+        }
+        let _tree = write(&self.tree);
+        let (from, to) = (self.parent(session, src)?, self.parent(session, dst)?);
+        self.vd.move_entry(session, from, name, to, new_name)
+    }
+
+    // 首块号为parent的目录下的name是否为目录，不存在时不是
+    fn is_directory(&self, parent: usize, name: &str) -> FsResult<bool> {
+        let dir = self.vd.get_directory_by_cluster(parent)?;
+        Ok(dir.get_file_type(name) == Some(FileType::Directory))
+    }
+}
+
+impl Default for FileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::BLOCK_SIZE;
+    use std::sync::Arc;
+    use std::thread;
+
+    const THREADS: usize = 8;
+    const FILES: usize = 12;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    // 第thread个线程的第file个文件的内容，长度跨越若干块
+    fn content(thread: usize, file: usize) -> Vec<u8> {
+        let length = (thread * 997 + file * 1733) % (3 * BLOCK_SIZE) + 1;
+        (0..length).map(|i| (i * 31 + thread * 7 + file) as u8).collect()
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers_keep_disk_consistent() {
+        let session = Session::default();
+        assert_send_sync::<FileSystem>();
+        let fs = Arc::new(FileSystem::new());
+        let (_, used, _) = fs.disk_info();
+        fs.create_dir(&session, "/shared").unwrap();
+        fs.write_file(&session, "/shared/common", &[]).unwrap();
+
+        let workers: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let fs = Arc::clone(&fs);
+                thread::spawn(move || {
+                    // 每个线程使用各自的会话
+                    let session = Session::new(&format!("user{}", thread));
+                    let dir = format!("/t{}", thread);
+                    fs.create_dir(&session, &dir).unwrap();
+                    for file in 0..FILES {
+                        let path = format!("{}/f{}", dir, file);
+                        let data = content(thread, file);
+                        fs.write_file(&session, &path, &data[..data.len() / 2]).unwrap();
+                        fs.write_at(&session, &path, data.len() / 2, &data[data.len() / 2..]).unwrap();
+                        assert_eq!(fs.read_file(&session, &path).unwrap(), data);
+                        assert_eq!(fs.read_file(&session, "/shared/common").unwrap().len() % 8, 0);
+
+                        // 各线程改写同一文件中互不重叠的部分
+                        let slot = (file * THREADS + thread) * 8;
+                        fs.write_at(&session, "/shared/common", slot, &[thread as u8 + 1; 8]).unwrap();
+
+                        // 在共享目录中创建、改名与删除
+                        let shared = format!("/shared/t{}-f{}", thread, file);
+                        fs.rename(&session, &path, &shared).unwrap();
+                        if file % 3 == 0 {
+                            fs.remove(&session, &shared).unwrap();
+                        } else {
+                            assert_eq!(fs.read_at(&session, &shared, 1, 4).unwrap(), data[1..5.min(data.len())]);
+                        }
+                    }
+                    // 目录改名与删除
+                    fs.create_dir(&session, &format!("{}/sub", dir)).unwrap();
+                    fs.rename(&session, &format!("{}/sub", dir), &format!("/shared/sub{}", thread)).unwrap();
+                    fs.remove(&session, &format!("/shared/sub{}", thread)).unwrap();
+                    fs.remove(&session, &dir).unwrap();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let common = fs.read_file(&session, "/shared/common").unwrap();
+        assert_eq!(common.len(), FILES * THREADS * 8);
+        for (index, chunk) in common.chunks(8).enumerate() {
+            assert_eq!(chunk, [(index % THREADS) as u8 + 1; 8]);
+        }
+        for thread in 0..THREADS {
+            assert!(matches!(fs.stat(&session, &format!("/t{}", thread)), Err(FsError::NotFound(_))));
+            for file in (0..FILES).filter(|file| file % 3 != 0) {
+                assert_eq!(fs.read_file(&session, &format!("/shared/t{}-f{}", thread, file)).unwrap(), content(thread, file));
+            }
+        }
+        let expected = THREADS * (FILES - FILES.div_ceil(3)) + 1;
+        assert_eq!(fs.list_dir(&session, "/shared").unwrap().len(), expected);
+
+        let (total, now_used, unused) = fs.disk_info();
+        assert_eq!(now_used + unused, total);
+        let vd = fs.to_operator();
+        assert!(crate::fsck::check(&vd).is_empty());
+        assert_eq!(vd.get_disk_info(), fs.disk_info());
+
+        // 删除全部文件后回到初始的占用
+        for fcb in fs.list_dir(&session, "/shared").unwrap() {
+            fs.remove(&session, &format!("/shared/{}", fcb.name())).unwrap();
+        }
+        fs.remove(&session, "/shared").unwrap();
+        assert_eq!(fs.disk_info().1, used);
+    }
+
+    #[test]
+    fn rename_keeps_tree_well_formed() {
+        let session = Session::default();
+        let fs = FileSystem::new();
+        fs.create_dir(&session, "/a").unwrap();
+        fs.create_dir(&session, "/a/b").unwrap();
+        fs.write_file(&session, "/a/b/file", b"data").unwrap();
+        assert!(matches!(fs.rename(&session, "/a", "/a/b/a"), Err(FsError::InvalidArgument(_))));
+        fs.write_file(&session, "/c", b"").unwrap();
+        assert_eq!(fs.rename(&session, "/a", "/c"), Err(FsError::AlreadyExists("c".to_string())));
+        assert_eq!(fs.remove(&session, "/a"), Err(FsError::DirectoryNotEmpty("a".to_string())));
+
+        fs.rename(&session, "/a/b", "/b").unwrap();
+        fs.rename(&session, "/b", "/a/renamed").unwrap();
+        assert_eq!(fs.read_file(&session, "/a/renamed/../renamed/file").unwrap(), b"data");
+        assert_eq!(fs.stat(&session, "/a/renamed").unwrap().name(), "renamed");
+        assert_eq!(fs.stat(&session, "/a/renamed/.").unwrap().name(), "renamed");
+        assert!(crate::fsck::check(&fs.to_operator()).is_empty());
+    }
+
+    #[test]
+    fn writes_do_not_change_snapshots() {
+        let session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        vd.write_file_by_path(&session, "/a", b"old a").unwrap();
+        vd.write_file_by_path(&session, "/b", b"old b").unwrap();
+        crate::snapshot::create(&mut vd, &session, "s").unwrap();
+
+        let fs = FileSystem::from_operator(vd);
+        fs.write_at(&session, "/a", 0, b"new").unwrap();
+        fs.write_file(&session, "/b", b"new b").unwrap();
+        fs.remove(&session, "/a").unwrap();
+
+        let vd = fs.to_operator();
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+        let old = crate::snapshot::lookup(&vd, "s", "/a").unwrap();
+        assert_eq!(vd.get_file_by_fcb(&old).unwrap(), b"old a");
+        assert_eq!(vd.read_file_by_path(&session, "/b").unwrap(), b"new b");
+        assert_eq!(vd.snapshots().shared_count(), 0);
+    }
+
+    #[test]
+    fn writes_past_the_end_leave_holes() {
+        let session = Session::default();
+        let fs = FileSystem::new();
+        fs.write_file(&session, "/a", b"start").unwrap();
+        let (_, used, _) = fs.disk_info();
+        fs.write_at(&session, "/a", 10 * BLOCK_SIZE, b"end").unwrap();
+        assert_eq!(fs.disk_info().1, used + 1);
+        let data = fs.read_file(&session, "/a").unwrap();
+        assert_eq!((data.len(), &data[..6], &data[10 * BLOCK_SIZE..]), (10 * BLOCK_SIZE + 3, &b"start\0"[..], &b"end"[..]));
+        fs.write_at(&session, "/a", 5 * BLOCK_SIZE, b"x").unwrap();
+        assert_eq!(fs.read_at(&session, "/a", 5 * BLOCK_SIZE - 1, 3).unwrap(), b"\0x\0");
+        assert!(crate::fsck::check(&fs.to_operator()).is_empty());
+    }
+
+    #[test]
+    fn compressed_directories_compress_new_files() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_directory_with(&session, 0, "z", Some(crate::compression::Compression::Deflate)).unwrap();
+        let fs = FileSystem::from_operator(vd);
+        fs.create_dir(&session, "/z/sub").unwrap();
+        fs.write_file(&session, "/z/sub/a", &[b'a'; 10000]).unwrap();
+        fs.write_at(&session, "/z/sub/a", 9999, b"bc").unwrap();
+        assert_eq!(fs.read_at(&session, "/z/sub/a", 9998, 10).unwrap(), b"abc");
+
+        let vd = fs.to_operator();
+        let fcb = vd.get_fcb_by_path(&session, "/z/sub/a").unwrap();
+        assert_eq!((fcb.compression(), fcb.length(), vd.allocated_size(&fcb)), (crate::compression::Compression::Deflate, 10001, BLOCK_SIZE));
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+    }
+
+    #[test]
+    fn writes_share_identical_blocks_when_dedup_is_on() {
+        let session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        vd.options.dedup = true;
+        let fs = FileSystem::from_operator(vd);
+        let data: Vec<u8> = (0..4 * BLOCK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        fs.write_file(&session, "/a", &data).unwrap();
+        let (_, used, _) = fs.disk_info();
+        fs.write_file(&session, "/b", &data).unwrap();
+        assert_eq!(fs.disk_info().1, used);
+        assert_eq!(fs.read_file(&session, "/b").unwrap(), data);
+        assert_eq!(fs.to_operator().block_usage().unwrap(), (11, 6));
+
+        // 写入被共享的文件时先复制，另一个文件不变
+        fs.write_at(&session, "/b", 0, b"changed").unwrap();
+        assert_eq!(fs.read_file(&session, "/a").unwrap(), data);
+        let vd = fs.to_operator();
+        assert_eq!(vd.block_usage().unwrap(), (11, 11));
+        assert!(crate::fsck::check(&vd).is_empty(), "{:?}", crate::fsck::check(&vd));
+    }
+}
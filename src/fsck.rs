@@ -0,0 +1,220 @@
+use crate::disk::{FATItem, BLOCK_SIZE, HOLE_END};
+use crate::disk_operator::{DiskOperator, FileType};
+
+// 检查磁盘一致性：遍历目录树与各个快照，校验FAT链、目录的.与..、文件长度，
+// 查找交叉链接与丢失的块，并核对被快照或去重共享的块的引用计数
+// 返回发现的问题列表，为空表示磁盘完好
+pub fn check(vd: &DiskOperator) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut owner = Owners {
+        paths: vec![None; vd.disk.fat_len()],
+        counts: vec![0; vd.disk.fat_len()],
+    };
+
+    check_directory(vd, 0, 0, "/", &mut owner, &mut errors);
+    let snapshots = vd.snapshots().list().to_vec();
+    for snapshot in snapshots {
+        let path = format!("@{}:/", snapshot.name());
+        check_directory(vd, snapshot.root(), snapshot.root(), &path, &mut owner, &mut errors);
+    }
+
+    for (cluster, item) in vd.disk.fat().iter().enumerate() {
+        if let FATItem::Cluster(_) | FATItem::EOF | FATItem::Hole(..) = item {
+            if owner.paths[cluster].is_none() {
+                errors.push(format!("Lost cluster {}: allocated but not referenced.", cluster));
+            }
+        }
+        let references = vd.snapshots().references(cluster);
+        if references > 1 && owner.counts[cluster] != references {
+            errors.push(format!(
+                "Cluster {} is referenced {} times but its reference count is {}.",
+                cluster, owner.counts[cluster], references
+            ));
+        }
+    }
+    errors
+}
+
+// 每个块首个引用者的路径与被引用的次数
+struct Owners {
+    paths: Vec<Option<String>>,
+    counts: Vec<usize>,
+}
+
+// 沿FAT链收集块号与空洞的块数，遇到越界、未分配或成环时记录错误并返回None
+fn collect_chain(vd: &DiskOperator, start: usize, path: &str, errors: &mut Vec<String>) -> Option<(Vec<usize>, usize)> {
+    let mut chain = Vec::new();
+    let mut holes = 0;
+    let mut cluster = start;
+
+    loop {
+        if cluster >= vd.disk.fat_len() {
+            errors.push(format!("{}: cluster {} out of range.", path, cluster));
+            return None;
+        }
+        if chain.contains(&cluster) {
+            errors.push(format!("{}: cluster chain loops at {}.", path, cluster));
+            return None;
+        }
+        chain.push(cluster);
+        match vd.disk.fat_item(cluster) {
+            FATItem::Cluster(next) => cluster = next,
+            FATItem::EOF => return Some((chain, holes)),
+            FATItem::Hole(count, next) => {
+                holes += count as usize;
+                if next == HOLE_END {
+                    return Some((chain, holes));
+                }
+                cluster = next as usize;
+            }
+            FATItem::UnUsed => {
+                errors.push(format!("{}: chain runs into unused cluster {}.", path, cluster));
+                return None;
+            }
+            FATItem::BadCluster => {
+                errors.push(format!("{}: chain runs into bad cluster {}.", path, cluster));
+                return None;
+            }
+        }
+    }
+}
+
+// 标记块的归属，已被其它文件占用且不是被快照共享的块时记录交叉链接
+fn claim_chain(vd: &DiskOperator, chain: &[usize], path: &str, owner: &mut Owners, errors: &mut Vec<String>) {
+    for &cluster in chain {
+        owner.counts[cluster] += 1;
+        match &owner.paths[cluster] {
+            Some(_) if vd.snapshots().is_shared(cluster) => {}
+            Some(other) => errors.push(format!(
+                "{}: cluster {} is cross-linked with {}.",
+                path, cluster, other
+            )),
+            None => owner.paths[cluster] = Some(path.to_string()),
+        }
+    }
+}
+
+fn check_directory(
+    vd: &DiskOperator,
+    cluster: usize,
+    parent: usize,
+    path: &str,
+    owner: &mut Owners,
+    errors: &mut Vec<String>,
+) {
+    let Some((chain, _)) = collect_chain(vd, cluster, path, errors) else {
+        return;
+    };
+    claim_chain(vd, &chain, path, owner, errors);
+
+    let dir = match vd.get_directory_by_cluster(cluster) {
+        Ok(dir) => dir,
+        Err(err) => {
+            errors.push(format!("{}: unreadable directory: {}", path, err));
+            return;
+        }
+    };
+
+    match dir.get_entry(".") {
+        Some(fcb) if fcb.first_cluster() == cluster => {}
+        _ => errors.push(format!("{}: entry . does not point to itself.", path)),
+    }
+    match dir.get_entry("..") {
+        Some(fcb) if fcb.first_cluster() == parent => {}
+        _ => errors.push(format!("{}: entry .. does not point to its parent.", path)),
+    }
+
+    for fcb in dir.entries() {
+        let sub_path = format!("{}{}", path, fcb.name());
+        // 已被认领且未被共享的首块说明目录树中存在重复引用，不再深入以免死循环
+        let first = fcb.first_cluster();
+        if first < owner.paths.len() && !vd.snapshots().is_shared(first) && owner.paths[first].is_some() {
+            errors.push(format!(
+                "{}: cluster {} is cross-linked with {}.",
+                sub_path,
+                first,
+                owner.paths[first].clone().unwrap()
+            ));
+            continue;
+        }
+
+        match fcb.file_type() {
+            FileType::Directory => {
+                check_directory(vd, fcb.first_cluster(), cluster, &format!("{}/", sub_path), owner, errors);
+            }
+            FileType::File => {
+                let Some((chain, holes)) = collect_chain(vd, fcb.first_cluster(), &sub_path, errors) else {
+                    continue;
+                };
+                claim_chain(vd, &chain, &sub_path, owner, errors);
+
+                // 压缩文件按压缩后的大小计算块数，并检查能否解压
+                let stored = match vd.stored_size(fcb) {
+                    Ok(size) if fcb.compression().is_none() => size,
+                    Ok(size) => match vd.read_content(fcb) {
+                        Ok(_) => size,
+                        Err(err) => {
+                            errors.push(format!("{}: unreadable {} data: {}", sub_path, fcb.compression(), err));
+                            continue;
+                        }
+                    },
+                    Err(err) => {
+                        errors.push(format!("{}: unreadable {} data: {}", sub_path, fcb.compression(), err));
+                        continue;
+                    }
+                };
+                // 空文件也占用一块，稀疏文件的空洞计入块数
+                let expected = if stored == 0 { 1 } else { stored.div_ceil(BLOCK_SIZE) };
+                if chain.len() + holes != expected {
+                    errors.push(format!(
+                        "{}: length {} needs {} clusters but chain has {}.",
+                        sub_path,
+                        stored,
+                        expected,
+                        chain.len() + holes
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+
+    fn disk_with_files() -> DiskOperator {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.write_file_by_path(&session, "/a", &vec![1; 2 * BLOCK_SIZE]).unwrap();
+        vd.write_file_by_path(&session, "/b", &vec![2; 2 * BLOCK_SIZE]).unwrap();
+        assert!(check(&vd).is_empty());
+        vd
+    }
+
+    #[test]
+    fn finds_lost_clusters() {
+        let vd = disk_with_files();
+        let lost = vd.disk.fat().iter().position(|item| matches!(item, FATItem::UnUsed)).unwrap();
+        vd.disk.set_fat(lost, FATItem::EOF);
+        assert_eq!(check(&vd), [format!("Lost cluster {}: allocated but not referenced.", lost)]);
+    }
+
+    #[test]
+    fn finds_cross_linked_clusters() {
+        let session = Session::default();
+        let vd = disk_with_files();
+        let a = vd.get_series(vd.get_fcb_by_path(&session, "/a").unwrap().first_cluster()).unwrap();
+        let b = vd.get_series(vd.get_fcb_by_path(&session, "/b").unwrap().first_cluster()).unwrap();
+        // b的第二块改为a的第二块，b原来的第二块随之丢失
+        vd.disk.set_fat(b[0], FATItem::Cluster(a[1]));
+        assert_eq!(
+            check(&vd),
+            [
+                format!("/b: cluster {} is cross-linked with /a.", a[1]),
+                format!("Lost cluster {}: allocated but not referenced.", b[1]),
+            ]
+        );
+    }
+}
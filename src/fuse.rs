@@ -0,0 +1,589 @@
+use crate::disk::BLOCK_SIZE;
+use crate::disk_operator::{DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::handle::{FileHandle, HandleIds};
+use crate::session::Session;
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::OnceLock;
+
+// FUSE内核协议的操作码，见linux/fuse.h
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_SETATTR: u32 = 4;
+const FUSE_MKDIR: u32 = 9;
+const FUSE_UNLINK: u32 = 10;
+const FUSE_RMDIR: u32 = 11;
+const FUSE_RENAME: u32 = 12;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_WRITE: u32 = 16;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FSYNC: u32 = 20;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_FSYNCDIR: u32 = 30;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_CREATE: u32 = 35;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+const FUSE_RENAME2: u32 = 45;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+const FUSE_BIG_WRITES: u32 = 1 << 5;
+const FATTR_SIZE: u32 = 1 << 3;
+const RENAME_NOREPLACE: u32 = 1;
+
+const ROOT_ID: u64 = HandleIds::ROOT_ID;
+const IN_HEADER_SIZE: usize = 40;
+const MAX_WRITE: usize = 128 * 1024;
+const TTL: u64 = 1; // 内核缓存目录项与属性的秒数
+
+// 已挂载的FUSE连接，释放时卸载
+pub struct FuseMount {
+    device: File,
+    mountpoint: CString,
+}
+
+impl FuseMount {
+    // 打开/dev/fuse并挂载到mountpoint，需要root权限
+    pub fn new(mountpoint: &str) -> FsResult<FuseMount> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/fuse")
+            .map_err(|err| FsError::Io(format!("/dev/fuse: {}", err)))?;
+        let target = CString::new(mountpoint).map_err(|_| FsError::InvalidName(mountpoint.to_string()))?;
+        let options = format!(
+            "fd={},rootmode=40000,user_id={},group_id={}",
+            device.as_raw_fd(),
+            unsafe { libc::getuid() },
+            unsafe { libc::getgid() }
+        );
+        let options = CString::new(options).unwrap_or_default();
+        let result = unsafe {
+            libc::mount(
+                c"file-system".as_ptr(),
+                target.as_ptr(),
+                c"fuse.file-system".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                options.as_ptr() as *const libc::c_void,
+            )
+        };
+        if result != 0 {
+            return Err(FsError::Io(format!("mount {}: {}", mountpoint, std::io::Error::last_os_error())));
+        }
+        Ok(FuseMount { device, mountpoint: target })
+    }
+
+    // 收到Ctrl-C时卸载，使serve正常返回并保存镜像
+    pub fn unmount_on_interrupt(&self) {
+        extern "C" fn handler(_: libc::c_int) {
+            if let Some(mountpoint) = MOUNTPOINT.get() {
+                unsafe { libc::umount2(mountpoint.as_ptr(), libc::MNT_DETACH) };
+            }
+        }
+        if MOUNTPOINT.set(self.mountpoint.clone()).is_ok() {
+            unsafe {
+                libc::signal(libc::SIGINT, handler as *const () as libc::sighandler_t);
+                libc::signal(libc::SIGTERM, handler as *const () as libc::sighandler_t);
+            }
+        }
+    }
+
+    pub fn unmount(&self) {
+        unsafe { libc::umount2(self.mountpoint.as_ptr(), libc::MNT_DETACH) };
+    }
+}
+
+impl Drop for FuseMount {
+    fn drop(&mut self) {
+        self.unmount();
+    }
+}
+
+static MOUNTPOINT: OnceLock<CString> = OnceLock::new();
+
+// 处理内核发来的请求，直到文件系统被卸载；save在数据有改动时被调用
+pub fn serve<F>(vd: &mut DiskOperator, mount: &FuseMount, mut save: F) -> FsResult<()>
+where
+    F: FnMut(&DiskOperator) -> FsResult<()>,
+{
+    let mut fs = FuseFs::new(vd);
+    let mut device = &mount.device;
+    let mut buffer = vec![0u8; MAX_WRITE + 4096];
+
+    loop {
+        let len = match device.read(&mut buffer) {
+            Ok(len) => len,
+            Err(err) => match err.raw_os_error() {
+                // 请求被中断，继续读取下一个
+                Some(libc::ENOENT) | Some(libc::EINTR) | Some(libc::EAGAIN) => continue,
+                // 已卸载
+                Some(libc::ENODEV) => break,
+                _ => return Err(FsError::Io(err.to_string())),
+            },
+        };
+        if len < IN_HEADER_SIZE {
+            continue;
+        }
+        let mut header = Reader::new(&buffer[..len]);
+        let _ = header.u32();
+        let opcode = header.u32();
+        let unique = header.u64();
+        let node = header.u64();
+        let body = &buffer[IN_HEADER_SIZE..len];
+
+        let reply = fs.handle(opcode, node, body);
+        if let Some(reply) = reply {
+            let (error, payload) = match reply {
+                Ok(payload) => (0, payload),
+                Err(errno) => (-errno, Vec::new()),
+            };
+            let mut message = Vec::with_capacity(16 + payload.len());
+            message.extend_from_slice(&((16 + payload.len()) as u32).to_ne_bytes());
+            message.extend_from_slice(&error.to_ne_bytes());
+            message.extend_from_slice(&unique.to_ne_bytes());
+            message.extend_from_slice(&payload);
+            // 请求已被中断时写入会失败，忽略即可
+            let _ = device.write(&message);
+        }
+
+        if fs.dirty && matches!(opcode, FUSE_FLUSH | FUSE_FSYNC | FUSE_RELEASE | FUSE_MKDIR | FUSE_UNLINK | FUSE_RMDIR | FUSE_RENAME | FUSE_RENAME2) {
+            save(fs.vd)?;
+            fs.dirty = false;
+        }
+        if opcode == FUSE_DESTROY {
+            break;
+        }
+    }
+
+    if fs.dirty {
+        save(fs.vd)?;
+    }
+    Ok(())
+}
+
+// 按内核的字节序读取请求中的字段
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        let len = len.min(self.data.len());
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        head
+    }
+
+    fn u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes[..].copy_from_slice(&[self.take(4), &[0; 4]].concat()[..4]);
+        u32::from_ne_bytes(bytes)
+    }
+
+    fn u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes[..].copy_from_slice(&[self.take(8), &[0; 8]].concat()[..8]);
+        u64::from_ne_bytes(bytes)
+    }
+
+    // 内核给出的偏移或大小，超出usize时报错
+    fn offset(&mut self) -> Result<usize, i32> {
+        usize::try_from(self.u64()).map_err(|_| libc::EINVAL)
+    }
+
+    // 以\0结尾的名称
+    fn name(&mut self) -> Result<String, i32> {
+        let end = self.data.iter().position(|&byte| byte == 0).ok_or(libc::EINVAL)?;
+        let name = String::from_utf8(self.data[..end].to_vec()).map_err(|_| libc::EINVAL)?;
+        self.data = &self.data[end + 1..];
+        Ok(name)
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+// 构造回复
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.data.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.data.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+}
+
+// 内核的节点号即句柄的编号；整个挂载使用同一个会话
+struct FuseFs<'a> {
+    vd: &'a mut DiskOperator,
+    session: Session,
+    handles: HandleIds,
+    dirty: bool,
+    uid: u32,
+    gid: u32,
+}
+
+impl<'a> FuseFs<'a> {
+    fn new(vd: &'a mut DiskOperator) -> FuseFs<'a> {
+        FuseFs {
+            vd,
+            session: Session::default(),
+            handles: HandleIds::new(),
+            dirty: false,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    fn node_id(&mut self, parent: usize, name: &str) -> u64 {
+        self.handles.id(&FileHandle::new(parent, name))
+    }
+
+    fn fcb(&self, id: u64) -> FsResult<Fcb> {
+        self.handles.get(id)?.fcb(self.vd)
+    }
+
+    fn location(&self, id: u64) -> Result<FileHandle, i32> {
+        self.handles.get(id).cloned().map_err(|err| err.errno())
+    }
+
+    // 目录节点自身的首块号
+    fn dir_cluster(&self, id: u64) -> FsResult<usize> {
+        self.handles.get(id)?.dir_cluster(self.vd)
+    }
+
+    fn attr(&self, id: u64, fcb: &Fcb, out: &mut Writer) {
+        let size = self.vd.get_size(fcb) as u64;
+        let time = fcb.modified().max(0) as u64;
+        let (mode, nlink) = match fcb.file_type() {
+            FileType::Directory => (libc::S_IFDIR | 0o755, 2),
+            FileType::File => (libc::S_IFREG | 0o644, 1),
+        };
+        // 块数按实际分配的块计算，稀疏文件的空洞不占用块
+        out.u64(id).u64(size).u64(self.vd.allocated_size(fcb) as u64 / 512);
+        out.u64(time).u64(time).u64(time).u32(0).u32(0).u32(0);
+        out.u32(mode).u32(nlink).u32(self.uid).u32(self.gid).u32(0);
+        out.u32(BLOCK_SIZE as u32).u32(0);
+    }
+
+    fn entry(&self, id: u64, fcb: &Fcb) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.u64(id).u64(0).u64(TTL).u64(TTL).u32(0).u32(0);
+        self.attr(id, fcb, &mut out);
+        out.data
+    }
+
+    fn lookup(&mut self, parent: usize, name: &str) -> FsResult<Vec<u8>> {
+        let fcb = match self.vd.get_directory_by_cluster(parent)?.get_entry(name) {
+            Some(fcb) => fcb.clone(),
+            None => return Err(FsError::NotFound(name.to_string())),
+        };
+        let id = self.node_id(parent, name);
+        Ok(self.entry(id, &fcb))
+    }
+
+    fn handle(&mut self, opcode: u32, node: u64, body: &[u8]) -> Option<Result<Vec<u8>, i32>> {
+        let mut input = Reader::new(body);
+        let result = match opcode {
+            FUSE_INIT => Ok(self.init(&mut input)),
+            // 不需要回复的请求
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+            FUSE_DESTROY => Ok(Vec::new()),
+            FUSE_LOOKUP => self.request(|fs| {
+                let parent = fs.dir_cluster(node)?;
+                fs.lookup(parent, &input.name().map_err(|_| FsError::InvalidName(String::new()))?)
+            }),
+            FUSE_GETATTR => self.request(|fs| {
+                let fcb = fs.fcb(node)?;
+                Ok(fs.attr_out(node, &fcb))
+            }),
+            FUSE_SETATTR => self.setattr(node, &mut input),
+            FUSE_OPEN | FUSE_OPENDIR => {
+                let mut out = Writer::default();
+                out.u64(0).u32(0).u32(0);
+                self.request(|fs| fs.fcb(node).map(|_| out.data))
+            }
+            FUSE_READ => self.read(node, &mut input),
+            FUSE_WRITE => self.write(node, &mut input),
+            FUSE_READDIR => self.readdir(node, &mut input),
+            FUSE_MKDIR => {
+                let _mode = input.u32();
+                let _umask = input.u32();
+                self.create(node, &mut input, FileType::Directory)
+            }
+            FUSE_CREATE => {
+                let _flags = input.u32();
+                let _mode = input.u32();
+                let _umask = input.u32();
+                let _open_flags = input.u32();
+                self.create(node, &mut input, FileType::File)
+            }
+            FUSE_UNLINK => self.remove(node, &mut input, FileType::File),
+            FUSE_RMDIR => self.remove(node, &mut input, FileType::Directory),
+            FUSE_RENAME => {
+                let new_parent = input.u64();
+                self.rename(node, new_parent, 0, &mut input)
+            }
+            FUSE_RENAME2 => {
+                let new_parent = input.u64();
+                let flags = input.u32();
+                let _ = input.u32();
+                self.rename(node, new_parent, flags, &mut input)
+            }
+            FUSE_STATFS => Ok(self.statfs()),
+            FUSE_RELEASE | FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_FSYNC | FUSE_FSYNCDIR | FUSE_ACCESS => Ok(Vec::new()),
+            _ => Err(libc::ENOSYS),
+        };
+        Some(result)
+    }
+
+    // 执行操作并把FsError转换为errno
+    fn request<T, F>(&mut self, op: F) -> Result<T, i32>
+    where
+        F: FnOnce(&mut Self) -> FsResult<T>,
+    {
+        op(self).map_err(|err| err.errno())
+    }
+
+    fn init(&mut self, input: &mut Reader) -> Vec<u8> {
+        let _major = input.u32();
+        let _minor = input.u32();
+        let max_readahead = input.u32();
+        let mut out = Writer::default();
+        out.u32(FUSE_KERNEL_VERSION).u32(FUSE_KERNEL_MINOR_VERSION);
+        out.u32(max_readahead).u32(FUSE_BIG_WRITES);
+        out.u16(16).u16(12).u32(MAX_WRITE as u32).u32(1);
+        out.u16((MAX_WRITE / 4096) as u16).u16(0);
+        for _ in 0..8 {
+            out.u32(0);
+        }
+        out.data
+    }
+
+    fn attr_out(&self, id: u64, fcb: &Fcb) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.u64(TTL).u32(0).u32(0);
+        self.attr(id, fcb, &mut out);
+        out.data
+    }
+
+    // 只支持修改大小，其余属性的修改直接忽略
+    fn setattr(&mut self, node: u64, input: &mut Reader) -> Result<Vec<u8>, i32> {
+        let valid = input.u32();
+        let _ = input.u32();
+        let _fh = input.u64();
+        let size = input.offset()?;
+        if valid & FATTR_SIZE != 0 {
+            let handle = self.location(node)?;
+            self.request(|fs| handle.truncate(fs.vd, &fs.session, size))?;
+            self.dirty = true;
+        }
+        self.request(|fs| {
+            let fcb = fs.fcb(node)?;
+            Ok(fs.attr_out(node, &fcb))
+        })
+    }
+
+    fn read(&mut self, node: u64, input: &mut Reader) -> Result<Vec<u8>, i32> {
+        let _fh = input.u64();
+        let offset = input.offset()?;
+        let size = input.u32() as usize;
+        self.request(|fs| {
+            let fcb = fs.fcb(node)?;
+            fs.vd.read_at(&fcb, offset, size)
+        })
+    }
+
+    fn write(&mut self, node: u64, input: &mut Reader) -> Result<Vec<u8>, i32> {
+        let _fh = input.u64();
+        let offset = input.offset()?;
+        let size = input.u32() as usize;
+        let _ = input.take(40 - 20);
+        let data = &input.rest()[..size.min(input.rest().len())];
+        let handle = self.location(node)?;
+        self.request(|fs| handle.write(fs.vd, &fs.session, offset, data))?;
+        self.dirty = true;
+        let mut out = Writer::default();
+        out.u32(data.len() as u32).u32(0);
+        Ok(out.data)
+    }
+
+    fn readdir(&mut self, node: u64, input: &mut Reader) -> Result<Vec<u8>, i32> {
+        let _fh = input.u64();
+        let offset = input.offset()?;
+        let size = input.u32() as usize;
+        let dir_cluster = self.request(|fs| fs.dir_cluster(node))?;
+        let dir = self.vd.get_directory_by_cluster(dir_cluster).map_err(|err| err.errno())?;
+
+        let mut entries = vec![(node, ".".to_string(), FileType::Directory), (ROOT_ID, "..".to_string(), FileType::Directory)];
+        for fcb in dir.entries() {
+            let id = self.node_id(dir_cluster, fcb.name());
+            entries.push((id, fcb.name().to_string(), fcb.file_type()));
+        }
+
+        let mut out = Writer::default();
+        for (index, (id, name, file_type)) in entries.into_iter().enumerate().skip(offset) {
+            let entry_size = (24 + name.len()).div_ceil(8) * 8;
+            if out.data.len() + entry_size > size {
+                break;
+            }
+            let kind = match file_type {
+                FileType::Directory => libc::DT_DIR,
+                FileType::File => libc::DT_REG,
+            };
+            out.u64(id).u64(index as u64 + 1).u32(name.len() as u32).u32(kind as u32);
+            out.data.extend_from_slice(name.as_bytes());
+            out.data.resize(out.data.len() + entry_size - 24 - name.len(), 0);
+        }
+        Ok(out.data)
+    }
+
+    fn create(&mut self, node: u64, input: &mut Reader, file_type: FileType) -> Result<Vec<u8>, i32> {
+        let name = input.name()?;
+        let reply = self.request(|fs| {
+            let parent = fs.dir_cluster(node)?;
+            match file_type {
+                FileType::Directory => fs.vd.new_directory_at(&fs.session, parent, &name).map(|_| ())?,
+                FileType::File => fs.vd.new_file_at(&fs.session, parent, &name, &[])?,
+            }
+            fs.lookup(parent, &name)
+        })?;
+        self.dirty = true;
+        match file_type {
+            FileType::Directory => Ok(reply),
+            // create同时打开文件
+            FileType::File => {
+                let mut out = Writer { data: reply };
+                out.u64(0).u32(0).u32(0);
+                Ok(out.data)
+            }
+        }
+    }
+
+    fn remove(&mut self, node: u64, input: &mut Reader, file_type: FileType) -> Result<Vec<u8>, i32> {
+        let name = input.name()?;
+        self.request(|fs| {
+            let parent = fs.dir_cluster(node)?;
+            fs.handles.remove(fs.vd, &fs.session, parent, &name, file_type)?;
+            fs.dirty = true;
+            Ok(Vec::new())
+        })
+    }
+
+    fn rename(&mut self, node: u64, new_node: u64, flags: u32, input: &mut Reader) -> Result<Vec<u8>, i32> {
+        if flags & !RENAME_NOREPLACE != 0 {
+            return Err(libc::EINVAL);
+        }
+        let name = input.name()?;
+        let new_name = input.name()?;
+        self.request(|fs| {
+            let from = FileHandle::new(fs.dir_cluster(node)?, &name);
+            let to = FileHandle::new(fs.dir_cluster(new_node)?, &new_name);
+            fs.handles.rename(fs.vd, &fs.session, &from, &to, flags & RENAME_NOREPLACE == 0)?;
+            fs.dirty = true;
+            Ok(Vec::new())
+        })
+    }
+
+    fn statfs(&self) -> Vec<u8> {
+        let (total, _, unused) = self.vd.get_disk_info();
+        let mut out = Writer::default();
+        out.u64(total as u64).u64(unused as u64).u64(unused as u64);
+        out.u64(total as u64).u64(unused as u64);
+        out.u32(BLOCK_SIZE as u32).u32(255).u32(BLOCK_SIZE as u32).u32(0);
+        for _ in 0..6 {
+            out.u32(0);
+        }
+        out.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn mounted_image_works_with_std_fs() {
+        let session = Session::default();
+        let mountpoint = std::env::temp_dir().join(format!("file-system-fuse-{}", std::process::id()));
+        fs::create_dir_all(&mountpoint).unwrap();
+        let mut vd = DiskOperator::new(None);
+        vd.new_file(&session, "old.txt", b"old").unwrap();
+
+        // 没有/dev/fuse或挂载权限时跳过
+        let mount = match FuseMount::new(mountpoint.to_str().unwrap()) {
+            Ok(mount) => mount,
+            Err(err) => {
+                eprintln!("skipping FUSE test: {}", err);
+                return;
+            }
+        };
+        let server = thread::spawn(move || {
+            let mut saves = 0;
+            serve(&mut vd, &mount, |_| {
+                saves += 1;
+                Ok(())
+            })
+            .unwrap();
+            (vd, saves)
+        });
+
+        let path = |name: &str| mountpoint.join(name);
+        assert_eq!(fs::read(path("old.txt")).unwrap(), b"old");
+        fs::write(path("new.txt"), b"hello").unwrap();
+        fs::create_dir(path("dir")).unwrap();
+        fs::rename(path("new.txt"), path("dir/moved.txt")).unwrap();
+        fs::remove_file(path("old.txt")).unwrap();
+        assert_eq!(fs::read(path("dir/moved.txt")).unwrap(), b"hello");
+        let mut names: Vec<String> = fs::read_dir(&mountpoint)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["dir"]);
+        assert_eq!(
+            fs::remove_dir(path("dir")).unwrap_err().raw_os_error(),
+            Some(libc::ENOTEMPTY)
+        );
+
+        unsafe { libc::umount2(CString::new(mountpoint.to_str().unwrap()).unwrap().as_ptr(), libc::MNT_DETACH) };
+        let (vd, saves) = server.join().unwrap();
+        assert!(saves > 0);
+        assert_eq!(vd.read_file_by_path(&session, "/dir/moved.txt").unwrap(), b"hello");
+        assert!(vd.read_file_by_path(&session, "/old.txt").is_err());
+        let _ = fs::remove_dir(&mountpoint);
+    }
+}
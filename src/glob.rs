@@ -0,0 +1,249 @@
+use crate::disk_operator::{Directory, DiskOperator, FileType};
+use crate::error::FsResult;
+use crate::parser::is_glob_char;
+use crate::session::Session;
+
+// 判断名称是否匹配通配模式：*匹配任意个字符，?匹配一个字符，
+// [abc]、[a-z]匹配集合中的一个字符，[!abc]匹配集合外的字符，反斜杠转义下一个字符
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_from(&pattern, &name)
+}
+
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| match_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && match_from(&pattern[1..], &name[1..]),
+        Some('[') => match (name.first(), match_class(&pattern[1..])) {
+            (Some(&c), Some((set, negate, rest))) => {
+                set.contains(c) != negate && match_from(&pattern[1 + rest..], &name[1..])
+            }
+            // 没有闭合的[按普通字符处理
+            (Some(&c), None) => c == '[' && match_from(&pattern[1..], &name[1..]),
+            (None, _) => false,
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && match_from(&pattern[2..], &name[1..])
+        }
+        Some(&c) => name.first() == Some(&c) && match_from(&pattern[1..], &name[1..]),
+    }
+}
+
+// 字符集合
+struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(low, high)| low <= c && c <= high)
+    }
+}
+
+// 解析[之后的字符集合，返回集合、是否取反以及]之后的位置
+fn match_class(pattern: &[char]) -> Option<(CharClass, bool, usize)> {
+    let mut index = 0;
+    let negate = matches!(pattern.first(), Some('!') | Some('^'));
+    if negate {
+        index += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    while index < pattern.len() {
+        let mut c = pattern[index];
+        // 紧跟在[后的]视为普通字符
+        if c == ']' && !first {
+            return Some((CharClass { ranges }, negate, index + 1));
+        }
+        if c == '\\' && index + 1 < pattern.len() {
+            index += 1;
+            c = pattern[index];
+        }
+        first = false;
+
+        if index + 2 < pattern.len() && pattern[index + 1] == '-' && pattern[index + 2] != ']' {
+            ranges.push((c, pattern[index + 2]));
+            index += 3;
+        } else {
+            ranges.push((c, c));
+            index += 1;
+        }
+    }
+    None
+}
+
+fn has_glob(component: &str) -> bool {
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            c if is_glob_char(c) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+// 去掉模式中的转义，得到字面名称
+fn literal(component: &str) -> String {
+    let mut name = String::with_capacity(component.len());
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => name.extend(chars.next()),
+            c => name.push(c),
+        }
+    }
+    name
+}
+
+// 按目录树展开通配路径，**匹配零或多级目录；结果按字典序排列
+// 没有匹配项时返回空列表
+pub fn expand(vd: &DiskOperator, session: &Session, pattern: &str) -> FsResult<Vec<String>> {
+    let (start, prefix) = if pattern.starts_with('/') {
+        (vd.get_directory_by_cluster(0)?, String::from("/"))
+    } else {
+        (vd.current_dir(session)?, String::new())
+    };
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+
+    let mut paths = Vec::new();
+    expand_components(vd, &start, &prefix, &components, &mut paths)?;
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+fn expand_components(
+    vd: &DiskOperator,
+    dir: &Directory,
+    prefix: &str,
+    components: &[&str],
+    paths: &mut Vec<String>,
+) -> FsResult<()> {
+    let Some((&component, rest)) = components.split_first() else {
+        return Ok(());
+    };
+
+    if component == "**" {
+        // 末尾的**匹配其下的全部文件与目录
+        if rest.is_empty() {
+            return collect_all(vd, dir, prefix, paths);
+        }
+        expand_components(vd, dir, prefix, rest, paths)?;
+        for fcb in dir.entries() {
+            if fcb.file_type() == FileType::Directory && !fcb.name().starts_with('.') {
+                let sub_dir = vd.get_directory_by_fcb(fcb)?;
+                expand_components(vd, &sub_dir, &format!("{}{}/", prefix, fcb.name()), components, paths)?;
+            }
+        }
+        return Ok(());
+    }
+
+    if !has_glob(component) {
+        let name = literal(component);
+        let Some(fcb) = dir.get_entry(&name) else {
+            return Ok(());
+        };
+        let path = format!("{}{}", prefix, name);
+        if rest.is_empty() {
+            paths.push(path);
+        } else if fcb.file_type() == FileType::Directory {
+            let sub_dir = vd.get_directory_by_fcb(fcb)?;
+            expand_components(vd, &sub_dir, &format!("{}/", path), rest, paths)?;
+        }
+        return Ok(());
+    }
+
+    for fcb in dir.entries() {
+        // 以.开头的隐藏文件只有模式也以.开头时才匹配
+        if fcb.name().starts_with('.') && !component.starts_with('.') {
+            continue;
+        }
+        if !matches(component, fcb.name()) {
+            continue;
+        }
+        let path = format!("{}{}", prefix, fcb.name());
+        if rest.is_empty() {
+            paths.push(path);
+        } else if fcb.file_type() == FileType::Directory {
+            let sub_dir = vd.get_directory_by_fcb(fcb)?;
+            expand_components(vd, &sub_dir, &format!("{}/", path), rest, paths)?;
+        }
+    }
+    Ok(())
+}
+
+fn collect_all(vd: &DiskOperator, dir: &Directory, prefix: &str, paths: &mut Vec<String>) -> FsResult<()> {
+    for fcb in dir.entries() {
+        if fcb.name().starts_with('.') {
+            continue;
+        }
+        let path = format!("{}{}", prefix, fcb.name());
+        if fcb.file_type() == FileType::Directory {
+            let sub_dir = vd.get_directory_by_fcb(fcb)?;
+            collect_all(vd, &sub_dir, &format!("{}/", path), paths)?;
+        }
+        paths.push(path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_match_names() {
+        assert!(matches("*.log", "a.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(!matches("*.log", "a.log.txt"));
+        assert!(matches("?.txt", "a.txt"));
+        assert!(!matches("?.txt", "ab.txt"));
+        assert!(matches("[abc]1", "b1"));
+        assert!(!matches("[abc]1", "d1"));
+        assert!(matches("[a-z]x", "qx"));
+        assert!(!matches("[!a-z]x", "qx"));
+        assert!(matches("[!a-z]x", "Qx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        // 没有闭合的[是普通字符
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn expand_walks_the_tree() {
+        let mut session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&session, "data").unwrap();
+        vd.set_current_dir(&mut session, "/data").unwrap();
+        vd.new_directory(&session, "sub").unwrap();
+        vd.set_current_dir(&mut session, "/").unwrap();
+        for path in ["/data/a.csv", "/data/b.csv", "/data/c.txt", "/data/sub/d.csv", "/x.log", "/.hidden.log", "/y*.log"] {
+            vd.write_file_by_path(&session, path, b"").unwrap();
+        }
+
+        assert_eq!(expand(&vd, &session, "*.log").unwrap(), ["x.log", "y*.log"]);
+        assert_eq!(expand(&vd, &session, ".*.log").unwrap(), [".hidden.log"]);
+        assert_eq!(expand(&vd, &session, "y\\*.log").unwrap(), ["y*.log"]);
+        assert_eq!(expand(&vd, &session, "/data/*.csv").unwrap(), ["/data/a.csv", "/data/b.csv"]);
+        assert_eq!(expand(&vd, &session, "data/**/*.csv").unwrap(), ["data/a.csv", "data/b.csv", "data/sub/d.csv"]);
+        assert_eq!(
+            expand(&vd, &session, "data/**").unwrap(),
+            ["data/a.csv", "data/b.csv", "data/c.txt", "data/sub", "data/sub/d.csv"]
+        );
+        assert!(expand(&vd, &session, "*.png").unwrap().is_empty());
+        assert!(expand(&vd, &session, "missing/*").unwrap().is_empty());
+
+        // 相对路径从当前目录开始
+        vd.set_current_dir(&mut session, "/data").unwrap();
+        assert_eq!(expand(&vd, &session, "[!a]*").unwrap(), ["b.csv", "c.txt", "sub"]);
+        assert_eq!(expand(&vd, &session, "?.c[st][vx]").unwrap(), ["a.csv", "b.csv"]);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::disk_operator::{split_path, DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::session::Session;
+
+use std::collections::HashMap;
+
+// 文件句柄：以所在目录的首块号与名称标识一个文件或目录，根目录没有所在目录
+// 目录原地改写，首块号在其生命周期内不变，因此句柄在文件被移动或删除前一直有效
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FileHandle {
+    parent: Option<usize>,
+    name: String,
+}
+
+impl FileHandle {
+    pub fn root() -> FileHandle {
+        FileHandle { parent: None, name: String::from("/") }
+    }
+
+    pub fn new(parent: usize, name: &str) -> FileHandle {
+        FileHandle { parent: Some(parent), name: name.to_string() }
+    }
+
+    // 首块号为cluster的目录的句柄，通过..找到其所在目录
+    pub fn for_directory(vd: &DiskOperator, cluster: usize) -> FsResult<FileHandle> {
+        if cluster == 0 {
+            return Ok(FileHandle::root());
+        }
+        let parent = match vd.get_directory_by_cluster(cluster)?.get_entry("..") {
+            Some(fcb) => fcb.first_cluster(),
+            None => return Err(FsError::Corrupted(format!("directory at cluster {} has no ..", cluster))),
+        };
+        let dir = vd.get_directory_by_cluster(parent)?;
+        let found = dir.entries().find(|fcb| fcb.file_type() == FileType::Directory && fcb.first_cluster() == cluster);
+        match found {
+            Some(fcb) => Ok(FileHandle::new(parent, fcb.name())),
+            None => Err(FsError::Corrupted(format!("directory at cluster {} is not in its parent", cluster))),
+        }
+    }
+
+    // 路径指向的文件或目录的句柄，相对路径从当前目录开始
+    pub fn for_path(vd: &DiskOperator, session: &Session, path: &str) -> FsResult<FileHandle> {
+        let (dir_path, name) = split_path(path);
+        if name.is_empty() || name == "." || name == ".." {
+            return FileHandle::for_directory(vd, vd.get_directory_by_path(session, path)?.cluster());
+        }
+        let handle = FileHandle::new(vd.get_directory_by_path(session, dir_path)?.cluster(), name);
+        handle.fcb(vd)?;
+        Ok(handle)
+    }
+
+    // 句柄指向的项的绝对路径
+    pub fn path(&self, vd: &DiskOperator) -> FsResult<String> {
+        match self.parent {
+            Some(parent) => Ok(format!("{}/{}", vd.get_path_by_cluster(parent)?.trim_end_matches('/'), self.name)),
+            None => Ok(String::from("/")),
+        }
+    }
+
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fcb(&self, vd: &DiskOperator) -> FsResult<Fcb> {
+        let Some(parent) = self.parent else {
+            return Ok(vd.get_directory_by_cluster(0)?.own_fcb());
+        };
+        match vd.get_directory_by_cluster(parent)?.get_entry(&self.name) {
+            Some(fcb) => Ok(fcb.clone()),
+            None => Err(FsError::NotFound(self.name.clone())),
+        }
+    }
+
+    // 目录句柄指向的目录的首块号
+    pub fn dir_cluster(&self, vd: &DiskOperator) -> FsResult<usize> {
+        let fcb = self.fcb(vd)?;
+        match fcb.file_type() {
+            FileType::Directory => Ok(fcb.first_cluster()),
+            FileType::File => Err(FsError::NotADirectory(self.name.clone())),
+        }
+    }
+
+    // 目录中名为name的项，.与..分别为目录自身与其所在目录
+    pub fn child(&self, vd: &DiskOperator, name: &str) -> FsResult<FileHandle> {
+        let cluster = self.dir_cluster(vd)?;
+        match name {
+            "." => Ok(self.clone()),
+            ".." => match self.parent {
+                Some(parent) => FileHandle::for_directory(vd, parent),
+                None => Ok(FileHandle::root()),
+            },
+            _ => match vd.get_directory_by_cluster(cluster)?.get_entry(name) {
+                Some(_) => Ok(FileHandle::new(cluster, name)),
+                None => Err(FsError::NotFound(name.to_string())),
+            },
+        }
+    }
+
+    // 文件的句柄才能读写，根目录没有所在目录
+    fn location(&self) -> FsResult<usize> {
+        self.parent.ok_or(FsError::IsADirectory(self.name.clone()))
+    }
+
+    pub fn read(&self, vd: &DiskOperator, offset: usize, len: usize) -> FsResult<Vec<u8>> {
+        vd.read_at(&self.fcb(vd)?, offset, len)
+    }
+
+    pub fn write(&self, vd: &mut DiskOperator, session: &Session, offset: usize, data: &[u8]) -> FsResult<()> {
+        vd.write_file_at(session, self.location()?, &self.name, offset, data)
+    }
+
+    pub fn truncate(&self, vd: &mut DiskOperator, session: &Session, length: usize) -> FsResult<()> {
+        vd.truncate_at(session, self.location()?, &self.name, length)
+    }
+}
+
+// 为句柄分配稳定的编号，用作FUSE的节点号与9P的qid.path，根目录的编号为1
+pub struct HandleIds {
+    ids: HashMap<FileHandle, u64>,
+    handles: HashMap<u64, FileHandle>,
+    next_id: u64,
+}
+
+impl HandleIds {
+    pub const ROOT_ID: u64 = 1;
+
+    pub fn new() -> HandleIds {
+        let mut ids = HandleIds { ids: HashMap::new(), handles: HashMap::new(), next_id: Self::ROOT_ID };
+        ids.id(&FileHandle::root());
+        ids
+    }
+
+    pub fn id(&mut self, handle: &FileHandle) -> u64 {
+        if let Some(&id) = self.ids.get(handle) {
+            return id;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids.insert(handle.clone(), id);
+        self.handles.insert(id, handle.clone());
+        id
+    }
+
+    pub fn get(&self, id: u64) -> FsResult<&FileHandle> {
+        self.handles.get(&id).ok_or(FsError::NotFound(format!("handle {}", id)))
+    }
+
+    pub fn forget(&mut self, handle: &FileHandle) {
+        if let Some(id) = self.ids.remove(handle) {
+            self.handles.remove(&id);
+        }
+    }
+
+    // 删除dir目录中的文件或空目录，file_type为要求的类型
+    pub fn remove(&mut self, vd: &mut DiskOperator, session: &Session, dir: usize, name: &str, file_type: FileType) -> FsResult<()> {
+        match (vd.get_directory_by_cluster(dir)?.get_file_type(name), file_type) {
+            (None, _) => return Err(FsError::NotFound(name.to_string())),
+            (Some(FileType::Directory), FileType::File) => return Err(FsError::IsADirectory(name.to_string())),
+            (Some(FileType::File), FileType::Directory) => return Err(FsError::NotADirectory(name.to_string())),
+            _ => {}
+        }
+        vd.delete_file_at(session, dir, name)?;
+        self.forget(&FileHandle::new(dir, name));
+        Ok(())
+    }
+
+    // 移动目录项，编号跟随文件；目标已存在时按POSIX语义替换：文件替换文件，目录替换空目录
+    // replace为false时目标已存在即报错
+    pub fn rename(
+        &mut self,
+        vd: &mut DiskOperator,
+        session: &Session,
+        from: &FileHandle,
+        to: &FileHandle,
+        replace: bool,
+    ) -> FsResult<()> {
+        let (Some(from_dir), Some(to_dir)) = (from.parent, to.parent) else {
+            return Err(FsError::InvalidArgument("cannot rename the root directory".to_string()));
+        };
+        let source = vd.get_directory_by_cluster(from_dir)?.get_file_type(&from.name);
+        let target = vd.get_directory_by_cluster(to_dir)?.get_file_type(&to.name);
+        match (source, target) {
+            (None, _) => return Err(FsError::NotFound(from.name.clone())),
+            _ if from == to => return Ok(()),
+            (Some(_), Some(_)) if !replace => return Err(FsError::AlreadyExists(to.name.clone())),
+            (Some(FileType::File), Some(FileType::Directory)) => {
+                return Err(FsError::IsADirectory(to.name.clone()))
+            }
+            (Some(FileType::Directory), Some(FileType::File)) => {
+                return Err(FsError::NotADirectory(to.name.clone()))
+            }
+            (Some(_), Some(file_type)) => self.remove(vd, session, to_dir, &to.name, file_type)?,
+            (Some(_), None) => {}
+        }
+        vd.move_entry(session, from_dir, &from.name, to_dir, &to.name)?;
+
+        if let Some(id) = self.ids.remove(from) {
+            self.ids.insert(to.clone(), id);
+            self.handles.insert(id, to.clone());
+        }
+        Ok(())
+    }
+}
+
+impl Default for HandleIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
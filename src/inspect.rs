@@ -0,0 +1,167 @@
+use crate::disk::{FATItem, BLOCK_SIZE, HOLE_END};
+use crate::disk_operator::{DiskOperator, Fcb};
+use crate::error::{FsError, FsResult};
+
+const LINE: usize = 16;
+
+// 按块输入数据并输出十六进制转储，相同的连续行以*省略
+// canonical为true时输出十六进制字节与ASCII（hexdump -C），否则输出小端序的16位字
+pub struct HexDump {
+    canonical: bool,
+    offset: usize,
+    pending: Vec<u8>,
+    last: Option<Vec<u8>>,
+    squeezing: bool,
+}
+
+impl HexDump {
+    pub fn new(canonical: bool) -> HexDump {
+        HexDump {
+            canonical,
+            offset: 0,
+            pending: Vec::new(),
+            last: None,
+            squeezing: false,
+        }
+    }
+
+    // 从offset开始计算地址，用于转储块等不从0开始的数据
+    pub fn with_offset(canonical: bool, offset: usize) -> HexDump {
+        HexDump { offset, ..HexDump::new(canonical) }
+    }
+
+    pub fn write(&mut self, data: &[u8], output: &mut Vec<u8>) {
+        self.pending.extend_from_slice(data);
+        let full = self.pending.len() / LINE * LINE;
+        let lines: Vec<u8> = self.pending.drain(..full).collect();
+        for line in lines.chunks(LINE) {
+            self.line(line, output);
+        }
+    }
+
+    // 输出剩余不足一行的数据与结尾的地址
+    pub fn finish(mut self, output: &mut Vec<u8>) {
+        let rest = std::mem::take(&mut self.pending);
+        if !rest.is_empty() {
+            self.line(&rest, output);
+        }
+        if self.canonical {
+            output.extend_from_slice(format!("{:08x}\n", self.offset).as_bytes());
+        } else {
+            output.extend_from_slice(format!("{:07x}\n", self.offset).as_bytes());
+        }
+    }
+
+    fn line(&mut self, line: &[u8], output: &mut Vec<u8>) {
+        if line.len() == LINE && self.last.as_deref() == Some(line) {
+            if !self.squeezing {
+                output.extend_from_slice(b"*\n");
+                self.squeezing = true;
+            }
+            self.offset += line.len();
+            return;
+        }
+        self.squeezing = false;
+        self.last = Some(line.to_vec());
+
+        let text = if self.canonical {
+            let mut hex = String::new();
+            for index in 0..LINE {
+                match line.get(index) {
+                    Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+                    None => hex.push_str("   "),
+                }
+                if index == LINE / 2 - 1 {
+                    hex.push(' ');
+                }
+            }
+            let ascii: String = line
+                .iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            format!("{:08x}  {} |{}|\n", self.offset, hex, ascii)
+        } else {
+            let words: Vec<String> = line
+                .chunks(2)
+                .map(|word| format!("{:04x}", u16::from_le_bytes([word[0], *word.get(1).unwrap_or(&0)])))
+                .collect();
+            format!("{:07x} {}\n", self.offset, words.join(" "))
+        };
+        output.extend_from_slice(text.as_bytes());
+        self.offset += line.len();
+    }
+}
+
+// 按块读取文件并转储
+pub fn hexdump_file(vd: &DiskOperator, fcb: &Fcb, canonical: bool, output: &mut Vec<u8>) -> FsResult<()> {
+    let mut dump = HexDump::new(canonical);
+    let mut offset = 0;
+    while offset < fcb.length() {
+        let chunk = vd.read_at(fcb, offset, BLOCK_SIZE)?;
+        dump.write(&chunk, output);
+        offset += chunk.len();
+    }
+    dump.finish(output);
+    Ok(())
+}
+
+fn fat_item(item: &FATItem) -> String {
+    match item {
+        FATItem::UnUsed => "unused".to_string(),
+        FATItem::Cluster(next) => format!("next {}", next),
+        FATItem::BadCluster => "bad".to_string(),
+        FATItem::EOF => "EOF".to_string(),
+        FATItem::Hole(holes, HOLE_END) => format!("{} holes, EOF", holes),
+        FATItem::Hole(holes, next) => format!("{} holes, next {}", holes, next),
+    }
+}
+
+// 转储一个块的原始数据及其FAT表项
+pub fn dump_cluster(vd: &DiskOperator, cluster: usize, output: &mut Vec<u8>) -> FsResult<()> {
+    if cluster >= vd.disk.cluster_count() {
+        return Err(FsError::InvalidArgument(format!(
+            "cluster {} out of range 0..{}",
+            cluster,
+            vd.disk.cluster_count()
+        )));
+    }
+    let header = format!(
+        "Cluster {}: offset {:#x}, FAT entry: {}\n",
+        cluster,
+        cluster * BLOCK_SIZE,
+        fat_item(&vd.disk.fat_item(cluster))
+    );
+    output.extend_from_slice(header.as_bytes());
+    let mut dump = HexDump::with_offset(true, cluster * BLOCK_SIZE);
+    dump.write(&vd.disk.read_in_cluster(cluster), output);
+    dump.finish(output);
+    Ok(())
+}
+
+// 沿FAT输出从first开始的块链与其中空洞的块数，遇到越界、成环或异常的表项时停止并说明原因
+pub fn fat_chain(vd: &DiskOperator, first: usize) -> (Vec<usize>, usize, Option<String>) {
+    let mut chain = Vec::new();
+    let mut holes = 0;
+    let mut cluster = first;
+    loop {
+        if cluster >= vd.disk.cluster_count() {
+            return (chain, holes, Some(format!("cluster {} out of range", cluster)));
+        }
+        if chain.contains(&cluster) {
+            return (chain, holes, Some(format!("loop back to cluster {}", cluster)));
+        }
+        chain.push(cluster);
+        match vd.disk.fat_item(cluster) {
+            FATItem::Cluster(next) => cluster = next,
+            FATItem::EOF => return (chain, holes, None),
+            FATItem::Hole(count, next) => {
+                holes += count as usize;
+                if next == HOLE_END {
+                    return (chain, holes, None);
+                }
+                cluster = next as usize;
+            }
+            ref item => return (chain, holes, Some(format!("cluster {} is {}", cluster, fat_item(item)))),
+        }
+    }
+}
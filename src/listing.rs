@@ -0,0 +1,416 @@
+use crate::compression::Compression;
+use crate::disk::BLOCK_SIZE;
+use crate::disk_operator::{DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::session::Session;
+
+use chrono::TimeZone;
+use serde::Serialize;
+use std::cmp::Ordering;
+
+// 排序方式
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    #[default]
+    Name,
+    Time, // 修改时间，新的在前
+    Size, // 大小，大的在前
+    None, // 保持目录中的顺序
+}
+
+// ls与tree的选项
+#[derive(Default)]
+pub struct ListOptions {
+    pub long: bool,      // -l，显示类型、大小、占用空间、压缩算法与修改时间
+    pub all: bool,       // -a，显示.、..与以.开头的文件
+    pub recursive: bool, // -R，递归列出子目录
+    pub human: bool,     // -h，以K、M为单位显示大小
+    pub reverse: bool,   // -r，倒序
+    pub sort: SortBy,
+    pub json: bool,             // --json，以JSON格式输出
+    pub depth: Option<usize>,   // tree -L，最大深度
+}
+
+impl ListOptions {
+    // 解析选项，选项可以出现在路径前后，--之后的参数都视为路径；返回选项与路径参数
+    pub fn parse(args: &[String]) -> FsResult<(ListOptions, Vec<String>)> {
+        let mut options = ListOptions::default();
+        let mut paths = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') || arg.len() == 1 {
+                paths.push(arg.clone());
+                continue;
+            }
+            match arg.as_str() {
+                "--" => {
+                    paths.extend(args.by_ref().cloned());
+                    break;
+                }
+                "--json" => options.json = true,
+                _ if arg.starts_with("--") => {
+                    return Err(FsError::InvalidArgument(format!("unknown option {}", arg)))
+                }
+                "-L" => match args.next().and_then(|depth| depth.parse().ok()) {
+                    Some(depth) => options.depth = Some(depth),
+                    None => return Err(FsError::InvalidArgument("-L requires a depth".to_string())),
+                },
+                _ => {
+                    for flag in arg.chars().skip(1) {
+                        match flag {
+                            'l' => options.long = true,
+                            'a' => options.all = true,
+                            'R' => options.recursive = true,
+                            'h' => options.human = true,
+                            'r' => options.reverse = true,
+                            't' => options.sort = SortBy::Time,
+                            'S' => options.sort = SortBy::Size,
+                            'U' => options.sort = SortBy::None,
+                            _ => return Err(FsError::InvalidArgument(format!("unknown option -{}", flag))),
+                        }
+                    }
+                }
+            }
+        }
+        Ok((options, paths))
+    }
+
+    fn size(&self, size: usize) -> String {
+        if self.human {
+            human_size(size)
+        } else {
+            size.to_string()
+        }
+    }
+
+    // 按选项筛选并排序目录项
+    fn entries(&self, vd: &DiskOperator, fcb: &Fcb) -> FsResult<Vec<Fcb>> {
+        let dir = vd.get_directory_by_fcb(fcb)?;
+        let mut entries: Vec<Fcb> = Vec::new();
+        if self.all {
+            entries.extend(dir.get_entry(".").cloned());
+            entries.extend(dir.get_entry("..").cloned());
+        }
+        entries.extend(
+            dir.entries()
+                .filter(|entry| self.all || !entry.name().starts_with('.'))
+                .cloned(),
+        );
+
+        let compare = |a: &Fcb, b: &Fcb| -> Ordering {
+            match self.sort {
+                SortBy::Name => a.name().cmp(b.name()),
+                SortBy::Time => b.modified().cmp(&a.modified()).then(a.name().cmp(b.name())),
+                SortBy::Size => vd.get_size(b).cmp(&vd.get_size(a)).then(a.name().cmp(b.name())),
+                SortBy::None => Ordering::Equal,
+            }
+        };
+        entries.sort_by(compare);
+        if self.reverse {
+            entries.reverse();
+        }
+        Ok(entries)
+    }
+
+    // 一个目录项的文本输出，目录名后加/
+    fn format(&self, vd: &DiskOperator, fcb: &Fcb) -> String {
+        self.format_as(vd, fcb, fcb.name())
+    }
+
+    fn format_as(&self, vd: &DiskOperator, fcb: &Fcb, name: &str) -> String {
+        let name = match fcb.file_type() {
+            FileType::Directory => format!("{}/", name),
+            FileType::File => name.to_string(),
+        };
+        if !self.long {
+            return name;
+        }
+        let kind = match fcb.file_type() {
+            FileType::Directory => 'd',
+            FileType::File => '-',
+        };
+        let compression = match fcb.compression() {
+            Compression::None => "-".to_string(),
+            compression => compression.to_string(),
+        };
+        format!(
+            "{} {:>8} {:>8} {:<7} {} {}",
+            kind,
+            self.size(vd.get_size(fcb)),
+            self.size(vd.allocated_size(fcb)),
+            compression,
+            format_time(fcb.modified()),
+            name
+        )
+    }
+}
+
+// 以K、M、G为单位的大小
+pub fn human_size(size: usize) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+pub fn format_time(timestamp: i64) -> String {
+    match chrono::Local.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn plural<'a>(count: usize, one: &'a str, many: &'a str) -> &'a str {
+    if count == 1 {
+        one
+    } else {
+        many
+    }
+}
+
+// JSON输出中的一项
+#[derive(Serialize)]
+pub struct EntryInfo {
+    path: String,
+    name: String,
+    #[serde(rename = "type")]
+    file_type: &'static str,
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allocated: Option<usize>, // 占用的块的字节数
+    #[serde(skip_serializing_if = "Compression::is_none")]
+    compression: Compression,
+    modified: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<EntryInfo>>,
+}
+
+impl EntryInfo {
+    fn new(vd: &DiskOperator, path: &str, fcb: &Fcb) -> EntryInfo {
+        EntryInfo {
+            allocated: Some(vd.allocated_size(fcb)),
+            ..EntryInfo::with_size(path, fcb, vd.get_size(fcb))
+        }
+    }
+
+    // 大小由调用者给出，供不经过DiskOperator的接口使用
+    pub fn with_size(path: &str, fcb: &Fcb, size: usize) -> EntryInfo {
+        EntryInfo {
+            path: path.to_string(),
+            name: fcb.name().to_string(),
+            file_type: match fcb.file_type() {
+                FileType::Directory => "directory",
+                FileType::File => "file",
+            },
+            size,
+            allocated: None,
+            compression: fcb.compression(),
+            modified: fcb.modified(),
+            children: None,
+        }
+    }
+}
+
+pub fn to_json<T: Serialize>(value: &T) -> FsResult<String> {
+    serde_json::to_string_pretty(value)
+        .map(|json| json + "\n")
+        .map_err(|err| FsError::InvalidArgument(err.to_string()))
+}
+
+// 连接目录路径与文件名
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() || dir == "." {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+// 列出paths中的文件与目录，没有路径时列出当前目录
+pub fn list(vd: &DiskOperator, session: &Session, paths: &[String], options: &ListOptions) -> FsResult<String> {
+    let default = [String::from(".")];
+    let paths = if paths.is_empty() { &default[..] } else { paths };
+
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for path in paths {
+        let fcb = vd.get_fcb_by_path(session, path)?;
+        match fcb.file_type() {
+            FileType::File => files.push((path.clone(), fcb)),
+            FileType::Directory => dirs.push((path.clone(), fcb)),
+        }
+    }
+
+    if options.json {
+        let mut infos: Vec<EntryInfo> = files.iter().map(|(path, fcb)| EntryInfo::new(vd, path, fcb)).collect();
+        for (path, fcb) in &dirs {
+            collect_json(vd, path, fcb, options, &mut infos)?;
+        }
+        return to_json(&infos);
+    }
+
+    let mut text = String::new();
+    // 文件参数显示给出的路径
+    for (path, fcb) in &files {
+        text.push_str(&options.format_as(vd, fcb, path));
+        text.push('\n');
+    }
+    let with_header = paths.len() > 1 || options.recursive;
+    for (path, fcb) in &dirs {
+        list_dir(vd, path, fcb, options, with_header, &mut text)?;
+    }
+    Ok(text)
+}
+
+fn list_dir(
+    vd: &DiskOperator,
+    path: &str,
+    fcb: &Fcb,
+    options: &ListOptions,
+    with_header: bool,
+    text: &mut String,
+) -> FsResult<()> {
+    if with_header {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!("{}:\n", path));
+    }
+    let entries = options.entries(vd, fcb)?;
+    for entry in &entries {
+        text.push_str(&options.format(vd, entry));
+        text.push('\n');
+    }
+    if options.recursive {
+        for entry in &entries {
+            if entry.file_type() == FileType::Directory && entry.name() != "." && entry.name() != ".." {
+                list_dir(vd, &join(path, entry.name()), entry, options, true, text)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn collect_json(
+    vd: &DiskOperator,
+    path: &str,
+    fcb: &Fcb,
+    options: &ListOptions,
+    infos: &mut Vec<EntryInfo>,
+) -> FsResult<()> {
+    for entry in options.entries(vd, fcb)? {
+        let entry_path = join(path, entry.name());
+        infos.push(EntryInfo::new(vd, &entry_path, &entry));
+        if options.recursive && entry.file_type() == FileType::Directory && entry.name() != "." && entry.name() != ".." {
+            collect_json(vd, &entry_path, &entry, options, infos)?;
+        }
+    }
+    Ok(())
+}
+
+// stat显示的文件信息：逻辑大小与实际占用的块数，稀疏文件占用的块少于其大小
+pub fn stat(vd: &DiskOperator, session: &Session, path: &str) -> FsResult<String> {
+    let fcb = vd.get_fcb_by_path(session, path)?;
+    let kind = match fcb.file_type() {
+        FileType::Directory => "directory",
+        FileType::File => "regular file",
+    };
+    let mut text = format!(
+        "  File: {}
+  Size: {:<12} Blocks: {:<8} Block size: {}  {}
+",
+        path,
+        vd.get_size(&fcb),
+        vd.allocated_size(&fcb) / BLOCK_SIZE,
+        BLOCK_SIZE,
+        kind
+    );
+    if !fcb.compression().is_none() {
+        text.push_str(&format!("Compression: {}
+", fcb.compression()));
+    }
+    text.push_str(&format!("Modified: {}
+", format_time(fcb.modified())));
+    Ok(text)
+}
+
+// 以树状图显示path下的目录结构
+pub fn tree(vd: &DiskOperator, session: &Session, path: &str, options: &ListOptions) -> FsResult<String> {
+    let fcb = vd.get_fcb_by_path(session, path)?;
+    if options.json {
+        let mut info = EntryInfo::new(vd, path, &fcb);
+        if fcb.file_type() == FileType::Directory {
+            info.children = Some(tree_json(vd, path, &fcb, options, 1)?);
+        }
+        return to_json(&info);
+    }
+
+    let mut text = format!("{}\n", path);
+    let mut counts = (0, 0);
+    if fcb.file_type() == FileType::Directory {
+        tree_text(vd, &fcb, options, "", 1, &mut text, &mut counts)?;
+    }
+    text.push_str(&format!("\n{} {}, {} {}\n", counts.0, plural(counts.0, "directory", "directories"), counts.1, plural(counts.1, "file", "files")));
+    Ok(text)
+}
+
+fn tree_text(
+    vd: &DiskOperator,
+    fcb: &Fcb,
+    options: &ListOptions,
+    prefix: &str,
+    depth: usize,
+    text: &mut String,
+    counts: &mut (usize, usize),
+) -> FsResult<()> {
+    if options.depth.is_some_and(|max| depth > max) {
+        return Ok(());
+    }
+    // 树中不显示.与..
+    let entries: Vec<Fcb> = options
+        .entries(vd, fcb)?
+        .into_iter()
+        .filter(|entry| entry.name() != "." && entry.name() != "..")
+        .collect();
+    for (index, entry) in entries.iter().enumerate() {
+        let last = index + 1 == entries.len();
+        let (branch, indent) = if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        text.push_str(&format!("{}{}{}\n", prefix, branch, options.format(vd, entry)));
+        match entry.file_type() {
+            FileType::Directory => {
+                counts.0 += 1;
+                tree_text(vd, entry, options, &format!("{}{}", prefix, indent), depth + 1, text, counts)?;
+            }
+            FileType::File => counts.1 += 1,
+        }
+    }
+    Ok(())
+}
+
+fn tree_json(vd: &DiskOperator, path: &str, fcb: &Fcb, options: &ListOptions, depth: usize) -> FsResult<Vec<EntryInfo>> {
+    let mut children = Vec::new();
+    if options.depth.is_some_and(|max| depth > max) {
+        return Ok(children);
+    }
+    for entry in options.entries(vd, fcb)? {
+        if entry.name() == "." || entry.name() == ".." {
+            continue;
+        }
+        let entry_path = join(path, entry.name());
+        let mut info = EntryInfo::new(vd, &entry_path, &entry);
+        if entry.file_type() == FileType::Directory {
+            info.children = Some(tree_json(vd, &entry_path, &entry, options, depth + 1)?);
+        }
+        children.push(info);
+    }
+    Ok(children)
+}
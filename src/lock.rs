@@ -0,0 +1,341 @@
+use crate::error::{FsError, FsResult};
+use crate::handle::FileHandle;
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+// 到文件末尾的范围的结束位置
+pub const TO_EOF: u64 = u64::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,    // 共享锁（读锁）
+    Exclusive, // 排他锁（写锁）
+}
+
+// 锁的持有者：会话中打开的一个句柄
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LockOwner {
+    pub session: u64,
+    pub handle: u64,
+}
+
+// 一把锁，覆盖[start, end)；flock锁覆盖整个文件
+// mandatory为true时其他会话对该范围的写入会被拒绝，否则只是建议性的
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lock {
+    pub owner: LockOwner,
+    pub kind: LockKind,
+    pub start: u64,
+    pub end: u64,
+    pub mandatory: bool,
+}
+
+impl Lock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start < end && start < self.end
+    }
+
+    // 不同持有者的重叠的锁中有排他锁时冲突
+    fn conflicts(&self, other: &Lock) -> bool {
+        self.owner != other.owner
+            && self.overlaps(other.start, other.end)
+            && (self.kind == LockKind::Exclusive || other.kind == LockKind::Exclusive)
+    }
+}
+
+// 一个文件上的锁，flock锁与字节范围锁互不影响
+#[derive(Default)]
+struct FileLocks {
+    flocks: Vec<Lock>,
+    ranges: Vec<Lock>,
+}
+
+impl FileLocks {
+    fn is_empty(&self) -> bool {
+        self.flocks.is_empty() && self.ranges.is_empty()
+    }
+}
+
+// 内存中的锁表，由共享同一个文件系统的所有会话共用
+// 获取锁时可以不等待、等待一段时间或一直等待，释放锁时唤醒等待者
+#[derive(Default)]
+pub struct LockTable {
+    files: Mutex<HashMap<FileHandle, FileLocks>>,
+    released: Condvar,
+}
+
+impl LockTable {
+    pub fn new() -> LockTable {
+        LockTable::default()
+    }
+
+    fn files(&self) -> MutexGuard<'_, HashMap<FileHandle, FileLocks>> {
+        self.files.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // 等待直到conflict返回None；timeout为None时一直等待，为0时不等待
+    fn wait_for<F>(&self, file: &FileHandle, timeout: Option<Duration>, conflict: F) -> FsResult<MutexGuard<'_, HashMap<FileHandle, FileLocks>>>
+    where
+        F: Fn(&FileLocks) -> bool,
+    {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut files = self.files();
+        while files.get(file).is_some_and(&conflict) {
+            files = match deadline {
+                None => self.released.wait(files).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(FsError::WouldBlock(file.name().to_string()));
+                    }
+                    self.released.wait_timeout(files, deadline - now).unwrap_or_else(PoisonError::into_inner).0
+                }
+            };
+        }
+        Ok(files)
+    }
+
+    // 对整个文件加flock锁，已持有时转换锁的类型
+    pub fn flock(&self, file: &FileHandle, lock: Lock, timeout: Option<Duration>) -> FsResult<()> {
+        let lock = Lock { start: 0, end: TO_EOF, ..lock };
+        let mut files = self.wait_for(file, timeout, |locks| locks.flocks.iter().any(|held| held.conflicts(&lock)))?;
+        let locks = files.entry(file.clone()).or_default();
+        locks.flocks.retain(|held| held.owner != lock.owner);
+        locks.flocks.push(lock);
+        drop(files);
+        // 由排他锁转为共享锁时其他共享锁可以获取
+        self.released.notify_all();
+        Ok(())
+    }
+
+    pub fn funlock(&self, file: &FileHandle, owner: LockOwner) {
+        self.remove(file, |locks| locks.flocks.retain(|held| held.owner != owner));
+    }
+
+    // 对[lock.start, lock.end)加字节范围锁，同一持有者已锁定的重叠部分改为新的类型
+    pub fn lock_range(&self, file: &FileHandle, lock: Lock, timeout: Option<Duration>) -> FsResult<()> {
+        if lock.start >= lock.end {
+            return Err(FsError::InvalidArgument(format!("empty lock range {}-{}", lock.start, lock.end)));
+        }
+        let mut files = self.wait_for(file, timeout, |locks| locks.ranges.iter().any(|held| held.conflicts(&lock)))?;
+        let locks = files.entry(file.clone()).or_default();
+        locks.ranges = split_out(&locks.ranges, lock.owner, lock.start, lock.end);
+        locks.ranges.push(lock);
+        locks.ranges.sort_by_key(|held| held.start);
+        drop(files);
+        self.released.notify_all();
+        Ok(())
+    }
+
+    // 释放持有者在[start, end)内的字节范围锁，部分重叠的锁被拆分
+    pub fn unlock_range(&self, file: &FileHandle, owner: LockOwner, start: u64, end: u64) {
+        self.remove(file, |locks| locks.ranges = split_out(&locks.ranges, owner, start, end));
+    }
+
+    // 与lock冲突的第一把字节范围锁
+    pub fn conflicting_range(&self, file: &FileHandle, lock: &Lock) -> Option<Lock> {
+        self.files().get(file)?.ranges.iter().find(|held| held.conflicts(lock)).copied()
+    }
+
+    // 关闭句柄时释放其持有的全部锁
+    pub fn release(&self, owner: LockOwner) {
+        self.release_where(|held| held.owner == owner);
+    }
+
+    // 会话结束时释放其持有的全部锁
+    pub fn release_session(&self, session: u64) {
+        self.release_where(|held| held.owner.session == session);
+    }
+
+    // 其他会话在[start, end)上的强制锁会拒绝写入
+    pub fn check_write(&self, file: &FileHandle, session: u64, start: u64, end: u64) -> FsResult<()> {
+        let files = self.files();
+        let Some(locks) = files.get(file) else {
+            return Ok(());
+        };
+        let blocked = locks
+            .flocks
+            .iter()
+            .chain(&locks.ranges)
+            .any(|held| held.mandatory && held.owner.session != session && held.overlaps(start, end.max(start + 1)));
+        if blocked {
+            return Err(FsError::WouldBlock(file.name().to_string()));
+        }
+        Ok(())
+    }
+
+    // 文件被移动或改名时锁随之移动
+    pub fn rename(&self, from: &FileHandle, to: &FileHandle) {
+        let mut files = self.files();
+        if let Some(locks) = files.remove(from) {
+            files.insert(to.clone(), locks);
+        }
+    }
+
+    // 文件被删除时丢弃其上的锁
+    pub fn forget(&self, file: &FileHandle) {
+        if self.files().remove(file).is_some() {
+            self.released.notify_all();
+        }
+    }
+
+    // 全部的锁，flock锁在前
+    pub fn locks(&self) -> Vec<(FileHandle, Lock)> {
+        let files = self.files();
+        let mut all: Vec<(FileHandle, Lock)> = files
+            .iter()
+            .flat_map(|(file, locks)| locks.flocks.iter().chain(&locks.ranges).map(move |lock| (file.clone(), *lock)))
+            .collect();
+        all.sort_by(|(a, x), (b, y)| {
+            (a.parent(), a.name(), x.start != 0 || x.end != TO_EOF, x.start).cmp(&(b.parent(), b.name(), y.start != 0 || y.end != TO_EOF, y.start))
+        });
+        all
+    }
+
+    fn remove<F>(&self, file: &FileHandle, update: F)
+    where
+        F: FnOnce(&mut FileLocks),
+    {
+        let mut files = self.files();
+        if let Some(locks) = files.get_mut(file) {
+            update(locks);
+            if locks.is_empty() {
+                files.remove(file);
+            }
+        }
+        drop(files);
+        self.released.notify_all();
+    }
+
+    fn release_where<F>(&self, matches: F)
+    where
+        F: Fn(&Lock) -> bool,
+    {
+        let mut files = self.files();
+        for locks in files.values_mut() {
+            locks.flocks.retain(|held| !matches(held));
+            locks.ranges.retain(|held| !matches(held));
+        }
+        files.retain(|_, locks| !locks.is_empty());
+        drop(files);
+        self.released.notify_all();
+    }
+}
+
+// 从owner的锁中去掉[start, end)，其他持有者的锁不变
+fn split_out(ranges: &[Lock], owner: LockOwner, start: u64, end: u64) -> Vec<Lock> {
+    let mut result = Vec::with_capacity(ranges.len() + 1);
+    for &held in ranges {
+        if held.owner != owner || !held.overlaps(start, end) {
+            result.push(held);
+            continue;
+        }
+        if held.start < start {
+            result.push(Lock { end: start, ..held });
+        }
+        if end < held.end {
+            result.push(Lock { start: end, ..held });
+        }
+    }
+    result
+}
+
+// 由起始位置与长度得到范围的结束位置，长度为0表示到文件末尾
+pub fn range_end(start: u64, length: u64) -> u64 {
+    if length == 0 {
+        TO_EOF
+    } else {
+        start.saturating_add(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn owner(session: u64, handle: u64) -> LockOwner {
+        LockOwner { session, handle }
+    }
+
+    fn lock(owner: LockOwner, kind: LockKind, start: u64, end: u64) -> Lock {
+        Lock { owner, kind, start, end, mandatory: false }
+    }
+
+    #[test]
+    fn flock_shared_and_exclusive() {
+        let table = LockTable::new();
+        let file = FileHandle::new(0, "a");
+        let (a, b) = (owner(1, 1), owner(1, 2));
+        let now = Some(Duration::ZERO);
+        table.flock(&file, lock(a, LockKind::Shared, 0, 0), now).unwrap();
+        table.flock(&file, lock(b, LockKind::Shared, 0, 0), now).unwrap();
+        assert_eq!(table.flock(&file, lock(b, LockKind::Exclusive, 0, 0), now), Err(FsError::WouldBlock("a".to_string())));
+        table.funlock(&file, a);
+        table.flock(&file, lock(b, LockKind::Exclusive, 0, 0), now).unwrap();
+        assert!(table.flock(&file, lock(a, LockKind::Shared, 0, 0), now).is_err());
+
+        // 字节范围锁与flock锁互不影响
+        table.lock_range(&file, lock(a, LockKind::Exclusive, 0, 10), now).unwrap();
+        table.release(b);
+        table.flock(&file, lock(a, LockKind::Exclusive, 0, 0), now).unwrap();
+        assert_eq!(table.locks().len(), 2);
+    }
+
+    #[test]
+    fn byte_ranges_split_and_conflict() {
+        let table = LockTable::new();
+        let file = FileHandle::new(0, "a");
+        let (a, b) = (owner(1, 1), owner(2, 1));
+        let now = Some(Duration::ZERO);
+        table.lock_range(&file, lock(a, LockKind::Exclusive, 0, 100), now).unwrap();
+        table.unlock_range(&file, a, 40, 60);
+        table.lock_range(&file, lock(b, LockKind::Exclusive, 40, 60), now).unwrap();
+        assert!(table.lock_range(&file, lock(b, LockKind::Shared, 59, 61), now).is_err());
+        assert_eq!(table.conflicting_range(&file, &lock(b, LockKind::Shared, 90, TO_EOF)), Some(lock(a, LockKind::Exclusive, 60, 100)));
+
+        // 同一持有者的重叠部分改为新的类型
+        table.lock_range(&file, lock(a, LockKind::Shared, 50, TO_EOF), Some(Duration::ZERO)).unwrap_err();
+        table.lock_range(&file, lock(a, LockKind::Shared, 80, TO_EOF), now).unwrap();
+        table.lock_range(&file, lock(b, LockKind::Shared, 90, 200), now).unwrap();
+        let ranges: Vec<(u64, u64)> = table.locks().iter().filter(|(_, held)| held.owner == a).map(|(_, held)| (held.start, held.end)).collect();
+        assert_eq!(ranges, [(0, 40), (60, 80), (80, TO_EOF)]);
+
+        table.release_session(1);
+        table.lock_range(&file, lock(b, LockKind::Exclusive, 0, 40), now).unwrap();
+    }
+
+    #[test]
+    fn mandatory_locks_reject_writes_from_other_sessions() {
+        let table = LockTable::new();
+        let file = FileHandle::new(0, "a");
+        let held = Lock { mandatory: true, ..lock(owner(1, 1), LockKind::Shared, 10, 20) };
+        table.lock_range(&file, held, None).unwrap();
+        assert!(table.check_write(&file, 1, 0, 100).is_ok());
+        assert!(table.check_write(&file, 2, 0, 10).is_ok());
+        assert!(table.check_write(&file, 2, 19, 30).is_err());
+        // 截断到范围内同样被拒绝
+        assert!(table.check_write(&file, 2, 15, 15).is_err());
+    }
+
+    #[test]
+    fn blocking_lock_waits_for_release() {
+        let table = Arc::new(LockTable::new());
+        let file = FileHandle::new(0, "a");
+        table.flock(&file, lock(owner(1, 1), LockKind::Exclusive, 0, 0), None).unwrap();
+
+        let waiter = {
+            let (table, file) = (Arc::clone(&table), file.clone());
+            thread::spawn(move || table.flock(&file, lock(owner(2, 1), LockKind::Exclusive, 0, 0), None))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        assert!(table.flock(&file, lock(owner(3, 1), LockKind::Shared, 0, 0), Some(Duration::from_millis(20))).is_err());
+        table.release_session(1);
+        waiter.join().unwrap().unwrap();
+        assert_eq!(table.locks()[0].1.owner, owner(2, 1));
+    }
+}
@@ -2,15 +2,40 @@ mod ui;
 use ui::*;
 pub mod disk;
 pub mod disk_operator;
-extern crate lazy_static;
+pub mod archive;
+pub mod fsck;
+pub mod error;
+mod cli;
+mod parser;
+mod editor;
+mod glob;
+mod search;
+mod listing;
+mod text_editor;
+mod inspect;
+mod fat_image;
+mod webdav;
+mod handle;
+mod ninep;
+mod filesystem;
+mod session;
+mod lock;
+mod snapshot;
+mod compression;
+mod crypto;
+mod dedup;
+mod sparse;
+mod resize;
+mod quota;
+#[cfg(target_os = "linux")]
+mod fuse;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
 
-    // unsafe {
-    //     let mut vd = load_ui(VIRTUAL_DISK_NAME);
-    //     interact_with_user(&mut vd);
-    // }
-    let mut vd = load_ui();
-    interact_with_user(&mut vd);
+    interact_with_user(load_ui());
     
-}
\ No newline at end of file
+}
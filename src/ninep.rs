@@ -0,0 +1,796 @@
+use crate::disk::BLOCK_SIZE;
+use crate::disk_operator::{DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::handle::{FileHandle, HandleIds};
+use crate::lock::{range_end, Lock, LockKind, LockOwner, TO_EOF};
+use crate::session::Session;
+
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::time::Duration;
+
+// 9P2000.L的消息类型，见Linux的include/net/9p/9p.h
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TRENAME: u8 = 20;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TLOCK: u8 = 52;
+const TGETLOCK: u8 = 54;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+
+const VERSION: &str = "9P2000.L";
+const MAX_MSIZE: u32 = 128 * 1024 + 24;
+const HEADER_SIZE: usize = 7; // size[4] type[1] tag[2]
+const IO_HEADER_SIZE: u32 = 24; // Rread、Twrite中数据前的字节数上限
+const QID_DIR: u8 = 0x80;
+const GETATTR_BASIC: u64 = 0x7ff;
+const SETATTR_SIZE: u32 = 1 << 3;
+const AT_REMOVEDIR: u32 = 0x200;
+const O_TRUNC: u32 = 0o1000;
+const LOCK_TYPE_RDLCK: u8 = 0;
+const LOCK_TYPE_WRLCK: u8 = 1;
+const LOCK_TYPE_UNLCK: u8 = 2;
+const LOCK_SUCCESS: u8 = 0;
+const LOCK_BLOCKED: u8 = 1;
+const V9FS_MAGIC: u32 = 0x01021997;
+
+// 监听TCP地址或unix:开头的Unix套接字路径，依次处理每个连接；修改过磁盘时调用save
+pub fn serve<F>(vd: &mut DiskOperator, listen: &str, mut save: F) -> FsResult<()>
+where
+    F: FnMut(&DiskOperator) -> FsResult<()>,
+{
+    let io_error = |err: std::io::Error| FsError::Io(format!("{}: {}", listen, err));
+    #[cfg(unix)]
+    if let Some(path) = listen.strip_prefix("unix:") {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).map_err(io_error)?;
+        for stream in listener.incoming() {
+            serve_connection(vd, stream.map_err(io_error)?, &mut save)?;
+        }
+        return Ok(());
+    }
+    let listener = TcpListener::bind(listen).map_err(io_error)?;
+    for stream in listener.incoming() {
+        serve_connection(vd, stream.map_err(io_error)?, &mut save)?;
+    }
+    Ok(())
+}
+
+// 处理一个连接上的全部请求，直到对方关闭连接
+pub fn serve_connection<S, F>(vd: &mut DiskOperator, mut stream: S, save: &mut F) -> FsResult<()>
+where
+    S: Read + Write,
+    F: FnMut(&DiskOperator) -> FsResult<()>,
+{
+    // 每个连接是一个会话，断开时释放其持有的锁
+    let mut connection = Connection::new(vd);
+    let result = loop {
+        let mut size = [0u8; 4];
+        match stream.read_exact(&mut size) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break Ok(()),
+            Err(err) => break Err(FsError::Io(err.to_string())),
+        }
+        let size = u32::from_le_bytes(size);
+        if (size as usize) < HEADER_SIZE || size > connection.msize {
+            break Err(FsError::Io(format!("invalid 9P message size {}", size)));
+        }
+        let mut message = vec![0u8; size as usize - 4];
+        if let Err(err) = stream.read_exact(&mut message) {
+            break Err(FsError::Io(err.to_string()));
+        }
+
+        let mut input = Reader::new(&message);
+        let kind = input.u8();
+        let tag = input.u16();
+        let (kind, body) = match connection.handle(kind, &mut input) {
+            Ok(body) => (kind + 1, body),
+            Err(errno) => {
+                let mut out = Writer::default();
+                out.u32(errno as u32);
+                (RLERROR, out.data)
+            }
+        };
+        let mut reply = Writer::default();
+        reply.u32((HEADER_SIZE + body.len()) as u32).u8(kind).u16(tag);
+        reply.data.extend_from_slice(&body);
+        if let Err(err) = stream.write_all(&reply.data) {
+            break Err(FsError::Io(err.to_string()));
+        }
+
+        if connection.save_pending {
+            save(connection.vd)?;
+            connection.save_pending = false;
+            connection.dirty = false;
+        }
+    };
+    // 连接断开时保存尚未保存的写入
+    let saved = if connection.dirty { save(connection.vd) } else { Ok(()) };
+    connection.vd.locks.release_session(connection.session.id());
+    saved.and(result)
+}
+
+// 按小端序读取消息中的字段，数据不足时返回0
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        let len = len.min(self.data.len());
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        head
+    }
+
+    fn number<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes = [0u8; N];
+        let data = self.take(N);
+        bytes[..data.len()].copy_from_slice(data);
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.number::<1>()[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.number())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.number())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.number())
+    }
+
+    // 客户端给出的偏移或大小，超出usize时报错
+    fn offset(&mut self) -> Result<usize, i32> {
+        usize::try_from(self.u64()).map_err(|_| libc::EINVAL)
+    }
+
+    fn string(&mut self) -> Result<String, i32> {
+        let len = self.u16() as usize;
+        String::from_utf8(self.take(len).to_vec()).map_err(|_| libc::EINVAL)
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.data.push(value);
+        self
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn string(&mut self, value: &str) -> &mut Self {
+        self.u16(value.len() as u16);
+        self.data.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    fn qid(&mut self, id: u64, fcb: &Fcb) -> &mut Self {
+        let kind = match fcb.file_type() {
+            FileType::Directory => QID_DIR,
+            FileType::File => 0,
+        };
+        self.u8(kind).u32(fcb.modified() as u32).u64(id)
+    }
+}
+
+// 客户端的fid，指向一个句柄的编号，文件移动后仍然有效
+struct Fid {
+    id: u64,
+    open: bool,
+}
+
+// 一个连接的状态：会话、协商的消息大小与fid表
+struct Connection<'a> {
+    vd: &'a mut DiskOperator,
+    session: Session,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+    handles: HandleIds,
+    dirty: bool,        // 有尚未保存的修改
+    save_pending: bool, // 回复后立即保存
+}
+
+impl<'a> Connection<'a> {
+    fn new(vd: &'a mut DiskOperator) -> Connection<'a> {
+        Connection {
+            vd,
+            session: Session::default(),
+            msize: MAX_MSIZE,
+            fids: HashMap::new(),
+            handles: HandleIds::new(),
+            dirty: false,
+            save_pending: false,
+        }
+    }
+
+    fn handle(&mut self, kind: u8, input: &mut Reader) -> Result<Vec<u8>, i32> {
+        let mut out = Writer::default();
+        match kind {
+            TVERSION => {
+                let msize = input.u32();
+                let version = input.string()?;
+                self.msize = msize.clamp(4096, MAX_MSIZE);
+                self.fids.clear();
+                let version = if version.starts_with(VERSION) { VERSION } else { "unknown" };
+                out.u32(self.msize).string(version);
+            }
+            TATTACH => {
+                let fid = input.u32();
+                let _afid = input.u32();
+                let _uname = input.string()?;
+                let _aname = input.string()?;
+                self.new_fid(fid, HandleIds::ROOT_ID)?;
+                let fcb = self.fcb(fid)?;
+                out.qid(HandleIds::ROOT_ID, &fcb);
+            }
+            TFLUSH => {}
+            TWALK => self.walk(input, &mut out)?,
+            TGETATTR => {
+                let fid = input.u32();
+                self.getattr(fid, &mut out)?;
+            }
+            TSETATTR => {
+                let fid = input.u32();
+                let valid = input.u32();
+                let _mode = input.u32();
+                let _uid = input.u32();
+                let _gid = input.u32();
+                let size = input.offset()?;
+                // 只支持修改大小，其余属性的修改直接忽略
+                if valid & SETATTR_SIZE != 0 {
+                    let handle = self.handle_of(fid)?;
+                    self.request(|connection| handle.truncate(connection.vd, &connection.session, size))?;
+                    self.modified();
+                }
+            }
+            TLOPEN => {
+                let fid = input.u32();
+                let flags = input.u32();
+                let fcb = self.fcb(fid)?;
+                if fcb.file_type() == FileType::File && flags & O_TRUNC != 0 {
+                    let handle = self.handle_of(fid)?;
+                    self.request(|connection| handle.truncate(connection.vd, &connection.session, 0))?;
+                    self.modified();
+                }
+                self.fid(fid)?.open = true;
+                out.qid(self.fid(fid)?.id, &fcb).u32(0);
+            }
+            TLCREATE => {
+                let fid = input.u32();
+                let name = input.string()?;
+                let dir = self.dir_cluster(fid)?;
+                self.request(|connection| connection.vd.new_file_at(&connection.session, dir, &name, &[]))?;
+                self.metadata_changed();
+                // fid改为指向新建并打开的文件
+                let id = self.handles.id(&FileHandle::new(dir, &name));
+                *self.fid(fid)? = Fid { id, open: true };
+                let fcb = self.fcb(fid)?;
+                out.qid(id, &fcb).u32(0);
+            }
+            TMKDIR => {
+                let fid = input.u32();
+                let name = input.string()?;
+                let dir = self.dir_cluster(fid)?;
+                self.request(|connection| connection.vd.new_directory_at(&connection.session, dir, &name))?;
+                self.metadata_changed();
+                let handle = FileHandle::new(dir, &name);
+                let fcb = self.request(|connection| handle.fcb(connection.vd))?;
+                out.qid(self.handles.id(&handle), &fcb);
+            }
+            TREAD => {
+                let fid = input.u32();
+                let offset = input.offset()?;
+                let count = input.u32().min(self.msize - IO_HEADER_SIZE) as usize;
+                let handle = self.handle_of(fid)?;
+                if self.fcb(fid)?.file_type() == FileType::Directory {
+                    return Err(libc::EISDIR);
+                }
+                let data = self.request(|connection| handle.read(connection.vd, offset, count))?;
+                out.u32(data.len() as u32);
+                out.data.extend_from_slice(&data);
+            }
+            TWRITE => {
+                let fid = input.u32();
+                let offset = input.offset()?;
+                let count = input.u32() as usize;
+                let data = input.take(count);
+                let handle = self.handle_of(fid)?;
+                self.request(|connection| handle.write(connection.vd, &connection.session, offset, data))?;
+                self.modified();
+                out.u32(data.len() as u32);
+            }
+            TREADDIR => self.readdir(input, &mut out)?,
+            TUNLINKAT => {
+                let fid = input.u32();
+                let name = input.string()?;
+                let flags = input.u32();
+                let dir = self.dir_cluster(fid)?;
+                let file_type = if flags & AT_REMOVEDIR != 0 { FileType::Directory } else { FileType::File };
+                self.request(|connection| {
+                    connection.handles.remove(connection.vd, &connection.session, dir, &name, file_type)
+                })?;
+                self.metadata_changed();
+            }
+            TRENAMEAT => {
+                let old_dir = self.dir_cluster(input.u32())?;
+                let old_name = input.string()?;
+                let new_dir = self.dir_cluster(input.u32())?;
+                let new_name = input.string()?;
+                let (from, to) = (FileHandle::new(old_dir, &old_name), FileHandle::new(new_dir, &new_name));
+                self.request(|connection| connection.handles.rename(connection.vd, &connection.session, &from, &to, true))?;
+                self.metadata_changed();
+            }
+            TRENAME => {
+                let handle = self.handle_of(input.u32())?;
+                let new_dir = self.dir_cluster(input.u32())?;
+                let new_name = input.string()?;
+                if handle.parent().is_none() {
+                    return Err(libc::EBUSY);
+                }
+                let to = FileHandle::new(new_dir, &new_name);
+                self.request(|connection| connection.handles.rename(connection.vd, &connection.session, &handle, &to, true))?;
+                self.metadata_changed();
+            }
+            TREMOVE => {
+                let fid = input.u32();
+                let handle = self.handle_of(fid)?;
+                self.fids.remove(&fid);
+                let dir = handle.parent().ok_or(libc::EBUSY)?;
+                let file_type = self.request(|connection| handle.fcb(connection.vd))?.file_type();
+                self.request(|connection| {
+                    connection.handles.remove(connection.vd, &connection.session, dir, handle.name(), file_type)
+                })?;
+                self.metadata_changed();
+            }
+            TCLUNK => {
+                let fid = input.u32();
+                let closed = self.fids.remove(&fid).ok_or(libc::EBADF)?;
+                if closed.open && self.dirty {
+                    self.save_pending = true;
+                }
+            }
+            TFSYNC => {
+                self.fid(input.u32())?;
+                self.save_pending = self.dirty;
+            }
+            TSTATFS => {
+                self.fid(input.u32())?;
+                let (total, _, unused) = self.vd.get_disk_info();
+                out.u32(V9FS_MAGIC).u32(BLOCK_SIZE as u32);
+                out.u64(total as u64).u64(unused as u64).u64(unused as u64);
+                out.u64(0).u64(0).u64(0).u32(255);
+            }
+            // POSIX字节范围锁，持有者为本连接中的进程；同一时间只处理一个连接，
+            // 等待会使持有锁的连接无法释放，因此冲突时总是返回BLOCKED由客户端重试
+            TLOCK => {
+                let handle = self.handle_of(input.u32())?;
+                let kind = input.u8();
+                let _flags = input.u32();
+                let lock = self.read_lock(input, kind)?;
+                let locks = &self.vd.locks;
+                let status = match kind {
+                    LOCK_TYPE_UNLCK => {
+                        locks.unlock_range(&handle, lock.owner, lock.start, lock.end);
+                        LOCK_SUCCESS
+                    }
+                    _ => match locks.lock_range(&handle, lock, Some(Duration::ZERO)) {
+                        Ok(()) => LOCK_SUCCESS,
+                        Err(FsError::WouldBlock(_)) => LOCK_BLOCKED,
+                        Err(err) => return Err(err.errno()),
+                    },
+                };
+                out.u8(status);
+            }
+            // 返回与请求冲突的第一把锁，没有冲突时返回UNLCK
+            TGETLOCK => {
+                let handle = self.handle_of(input.u32())?;
+                let kind = input.u8();
+                let lock = self.read_lock(input, kind)?;
+                match self.vd.locks.conflicting_range(&handle, &lock) {
+                    Some(held) => {
+                        let kind = if held.kind == LockKind::Shared { LOCK_TYPE_RDLCK } else { LOCK_TYPE_WRLCK };
+                        let length = if held.end == TO_EOF { 0 } else { held.end - held.start };
+                        out.u8(kind).u64(held.start).u64(length).u32(held.owner.handle as u32).string("");
+                    }
+                    None => {
+                        let length = if lock.end == TO_EOF { 0 } else { lock.end - lock.start };
+                        out.u8(LOCK_TYPE_UNLCK).u64(lock.start).u64(length).u32(lock.owner.handle as u32).string("");
+                    }
+                }
+            }
+            _ => return Err(libc::EOPNOTSUPP),
+        }
+        Ok(out.data)
+    }
+
+    // 读取Tlock、Tgetlock中的start length proc_id client_id，长度为0表示到文件末尾
+    fn read_lock(&self, input: &mut Reader, kind: u8) -> Result<Lock, i32> {
+        let start = input.u64();
+        let length = input.u64();
+        let proc_id = input.u32();
+        let _client_id = input.string()?;
+        let kind = match kind {
+            LOCK_TYPE_RDLCK => LockKind::Shared,
+            LOCK_TYPE_WRLCK | LOCK_TYPE_UNLCK => LockKind::Exclusive,
+            _ => return Err(libc::EINVAL),
+        };
+        let owner = LockOwner { session: self.session.id(), handle: proc_id as u64 };
+        Ok(Lock { owner, kind, start, end: range_end(start, length), mandatory: false })
+    }
+
+    // 执行操作并把FsError转换为errno
+    fn request<T, F>(&mut self, op: F) -> Result<T, i32>
+    where
+        F: FnOnce(&mut Self) -> FsResult<T>,
+    {
+        op(self).map_err(|err| err.errno())
+    }
+
+    // 写入数据，在clunk或fsync时保存
+    fn modified(&mut self) {
+        self.dirty = true;
+    }
+
+    // 目录结构的修改立即保存
+    fn metadata_changed(&mut self) {
+        self.dirty = true;
+        self.save_pending = true;
+    }
+
+    fn new_fid(&mut self, fid: u32, id: u64) -> Result<(), i32> {
+        if self.fids.contains_key(&fid) {
+            return Err(libc::EBADF);
+        }
+        self.fids.insert(fid, Fid { id, open: false });
+        Ok(())
+    }
+
+    fn fid(&mut self, fid: u32) -> Result<&mut Fid, i32> {
+        self.fids.get_mut(&fid).ok_or(libc::EBADF)
+    }
+
+    fn handle_of(&mut self, fid: u32) -> Result<FileHandle, i32> {
+        let id = self.fid(fid)?.id;
+        self.handles.get(id).cloned().map_err(|_| libc::ENOENT)
+    }
+
+    fn fcb(&mut self, fid: u32) -> Result<Fcb, i32> {
+        let handle = self.handle_of(fid)?;
+        self.request(|connection| handle.fcb(connection.vd))
+    }
+
+    fn dir_cluster(&mut self, fid: u32) -> Result<usize, i32> {
+        let handle = self.handle_of(fid)?;
+        self.request(|connection| handle.dir_cluster(connection.vd))
+    }
+
+    // 从fid开始逐级查找，全部成功时newfid指向最后一级；第一级就失败时返回错误，否则返回已找到的部分
+    fn walk(&mut self, input: &mut Reader, out: &mut Writer) -> Result<(), i32> {
+        let fid = input.u32();
+        let new_fid = input.u32();
+        let count = input.u16();
+        let mut handle = self.handle_of(fid)?;
+        if new_fid != fid && self.fids.contains_key(&new_fid) {
+            return Err(libc::EBADF);
+        }
+
+        let mut qids = Vec::new();
+        for index in 0..count {
+            let name = input.string()?;
+            let next = match self.request(|connection| handle.child(connection.vd, &name)) {
+                Ok(next) => next,
+                Err(errno) if index == 0 => return Err(errno),
+                Err(_) => break,
+            };
+            let fcb = self.request(|connection| next.fcb(connection.vd))?;
+            qids.push((self.handles.id(&next), fcb));
+            handle = next;
+        }
+
+        out.u16(qids.len() as u16);
+        for (id, fcb) in &qids {
+            out.qid(*id, fcb);
+        }
+        if qids.len() == count as usize {
+            let id = self.handles.id(&handle);
+            self.fids.insert(new_fid, Fid { id, open: false });
+        }
+        Ok(())
+    }
+
+    fn getattr(&mut self, fid: u32, out: &mut Writer) -> Result<(), i32> {
+        let id = self.fid(fid)?.id;
+        let fcb = self.fcb(fid)?;
+        let size = self.vd.get_size(&fcb) as u64;
+        let time = fcb.modified().max(0) as u64;
+        let (mode, nlink) = match fcb.file_type() {
+            FileType::Directory => (libc::S_IFDIR | 0o755, 2),
+            FileType::File => (libc::S_IFREG | 0o644, 1),
+        };
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        out.u64(GETATTR_BASIC).qid(id, &fcb);
+        out.u32(mode).u32(uid).u32(gid).u64(nlink).u64(0);
+        out.u64(size).u64(BLOCK_SIZE as u64).u64(size.div_ceil(512));
+        // atime、mtime、ctime都取修改时间，btime、gen与data_version为0
+        for _ in 0..3 {
+            out.u64(time).u64(0);
+        }
+        out.u64(0).u64(0).u64(0).u64(0);
+        Ok(())
+    }
+
+    // 目录项依次为.、..与目录中的文件，offset为已读取的项数
+    fn readdir(&mut self, input: &mut Reader, out: &mut Writer) -> Result<(), i32> {
+        let fid = input.u32();
+        let offset = input.offset()?;
+        let count = input.u32().min(self.msize - IO_HEADER_SIZE) as usize;
+        let handle = self.handle_of(fid)?;
+        let cluster = self.request(|connection| handle.dir_cluster(connection.vd))?;
+        let dir = self.request(|connection| connection.vd.get_directory_by_cluster(cluster))?;
+
+        let mut entries = Vec::new();
+        for name in [".", ".."] {
+            let child = self.request(|connection| handle.child(connection.vd, name))?;
+            let fcb = self.request(|connection| child.fcb(connection.vd))?;
+            entries.push((name.to_string(), self.handles.id(&child), fcb));
+        }
+        for fcb in dir.entries() {
+            let id = self.handles.id(&FileHandle::new(cluster, fcb.name()));
+            entries.push((fcb.name().to_string(), id, fcb.clone()));
+        }
+
+        let mut data = Writer::default();
+        for (index, (name, id, fcb)) in entries.iter().enumerate().skip(offset) {
+            if data.data.len() + 13 + 8 + 1 + 2 + name.len() > count {
+                break;
+            }
+            let kind = match fcb.file_type() {
+                FileType::Directory => libc::DT_DIR,
+                FileType::File => libc::DT_REG,
+            };
+            data.qid(*id, fcb).u64(index as u64 + 1).u8(kind).string(name);
+        }
+        out.u32(data.data.len() as u32);
+        out.data.extend_from_slice(&data.data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    const RWALK: u8 = TWALK + 1;
+
+    // 测试用的9P客户端
+    struct Client {
+        stream: UnixStream,
+        tag: u16,
+    }
+
+    impl Client {
+        fn call(&mut self, kind: u8, body: &Writer) -> (u8, Vec<u8>) {
+            self.tag += 1;
+            let mut message = Writer::default();
+            message.u32((HEADER_SIZE + body.data.len()) as u32).u8(kind).u16(self.tag);
+            message.data.extend_from_slice(&body.data);
+            self.stream.write_all(&message.data).unwrap();
+
+            let mut size = [0u8; 4];
+            self.stream.read_exact(&mut size).unwrap();
+            let mut reply = vec![0u8; u32::from_le_bytes(size) as usize - 4];
+            self.stream.read_exact(&mut reply).unwrap();
+            assert_eq!(u16::from_le_bytes([reply[1], reply[2]]), self.tag);
+            (reply[0], reply[3..].to_vec())
+        }
+
+        // 成功时返回回复的内容，失败时返回errno
+        fn ok(&mut self, kind: u8, build: impl FnOnce(&mut Writer)) -> Result<Vec<u8>, u32> {
+            let mut body = Writer::default();
+            build(&mut body);
+            match self.call(kind, &body) {
+                (RLERROR, body) => Err(Reader::new(&body).u32()),
+                (reply, body) => {
+                    assert_eq!(reply, kind + 1);
+                    Ok(body)
+                }
+            }
+        }
+
+        fn walk(&mut self, fid: u32, new_fid: u32, names: &[&str]) -> Result<Vec<u8>, u32> {
+            self.ok(TWALK, |body| {
+                body.u32(fid).u32(new_fid).u16(names.len() as u16);
+                for name in names {
+                    body.string(name);
+                }
+            })
+        }
+
+        fn read(&mut self, fid: u32, offset: u64) -> Vec<u8> {
+            let reply = self.ok(TREAD, |body| {
+                body.u32(fid).u64(offset).u32(4096);
+            });
+            reply.unwrap()[4..].to_vec()
+        }
+    }
+
+    #[test]
+    fn nine_p_client_session() {
+        let session = Session::default();
+        let (server_end, client_end) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut vd = DiskOperator::new(None);
+            let mut saves = 0;
+            serve_connection(&mut vd, server_end, &mut |_| {
+                saves += 1;
+                Ok(())
+            })
+            .unwrap();
+            (vd, saves)
+        });
+        let mut client = Client { stream: client_end, tag: 0 };
+
+        let reply = client.ok(TVERSION, |body| {
+            body.u32(8192).string("9P2000.L");
+        });
+        let mut reply = Reader::new(&reply.as_ref().unwrap()[..]);
+        assert_eq!((reply.u32(), reply.string().unwrap().as_str()), (8192, "9P2000.L"));
+        client.ok(TATTACH, |body| {
+            body.u32(0).u32(!0).string("user").string("").u32(0);
+        }).unwrap();
+
+        // 新建并写入文件
+        client.walk(0, 1, &[]).unwrap();
+        client.ok(TLCREATE, |body| {
+            body.u32(1).string("a.txt").u32(2).u32(0o644).u32(0);
+        }).unwrap();
+        client.ok(TWRITE, |body| {
+            body.u32(1).u64(0).u32(11);
+            body.data.extend_from_slice(b"hello world");
+        }).unwrap();
+        client.ok(TCLUNK, |body| {
+            body.u32(1);
+        }).unwrap();
+        client.ok(TMKDIR, |body| {
+            body.u32(0).string("dir").u32(0o755).u32(0);
+        }).unwrap();
+
+        // fid在文件移动后仍然指向该文件
+        client.walk(0, 2, &["a.txt"]).unwrap();
+        assert_eq!(client.read(2, 6), b"world");
+        client.walk(0, 3, &["dir"]).unwrap();
+        client.ok(TRENAMEAT, |body| {
+            body.u32(0).string("a.txt").u32(3).string("b.txt");
+        }).unwrap();
+        assert_eq!(client.read(2, 0), b"hello world");
+        client.ok(TSETATTR, |body| {
+            body.u32(2).u32(SETATTR_SIZE).u32(0).u32(0).u32(0).u64(5).u64(0).u64(0).u64(0).u64(0);
+        }).unwrap();
+        let attr = client.ok(TGETATTR, |body| {
+            body.u32(2).u64(GETATTR_BASIC);
+        }).unwrap();
+        assert_eq!(u64::from_le_bytes(attr[8 + 13 + 28..8 + 13 + 36].try_into().unwrap()), 5);
+
+        // 部分查找成功时只返回找到的qid且不建立newfid
+        assert_eq!(client.walk(0, 4, &["missing"]), Err(libc::ENOENT as u32));
+        let reply = client.walk(0, 4, &["dir", "missing"]).unwrap();
+        assert_eq!(u16::from_le_bytes([reply[0], reply[1]]), 1);
+        assert_eq!(client.walk(4, 5, &[]), Err(libc::EBADF as u32));
+        let reply = client.walk(3, 4, &["..", "dir", "b.txt"]).unwrap();
+        assert_eq!(u16::from_le_bytes([reply[0], reply[1]]), 3);
+        assert_eq!(client.read(4, 0), b"hello");
+
+        let reply = client.ok(TREADDIR, |body| {
+            body.u32(3).u64(0).u32(4096);
+        }).unwrap();
+        let mut entries = Reader::new(&reply[4..]);
+        let mut names = Vec::new();
+        while !entries.data.is_empty() {
+            entries.take(13 + 8 + 1);
+            names.push(entries.string().unwrap());
+        }
+        assert_eq!(names, [".", "..", "b.txt"]);
+
+        assert_eq!(
+            client.ok(TUNLINKAT, |body| {
+                body.u32(0).string("dir").u32(AT_REMOVEDIR);
+            }),
+            Err(libc::ENOTEMPTY as u32)
+        );
+        client.ok(TREMOVE, |body| {
+            body.u32(2);
+        }).unwrap();
+        client.ok(TUNLINKAT, |body| {
+            body.u32(0).string("dir").u32(AT_REMOVEDIR);
+        }).unwrap();
+        client.ok(TLCREATE, |body| {
+            body.u32(0).string("c.txt").u32(2).u32(0o644).u32(0);
+        }).unwrap();
+        assert_eq!(client.call(RWALK, &Writer::default()).0, RLERROR);
+
+        // 不同进程的字节范围锁冲突时返回BLOCKED
+        let lock = |client: &mut Client, kind: u8, start: u64, length: u64, proc_id: u32| {
+            client.ok(TLOCK, |body| {
+                body.u32(0).u8(kind).u32(0).u64(start).u64(length).u32(proc_id).string("host");
+            }).unwrap()[0]
+        };
+        assert_eq!(lock(&mut client, LOCK_TYPE_WRLCK, 10, 0, 1), LOCK_SUCCESS);
+        assert_eq!(lock(&mut client, LOCK_TYPE_RDLCK, 0, 10, 2), LOCK_SUCCESS);
+        assert_eq!(lock(&mut client, LOCK_TYPE_RDLCK, 5, 10, 2), LOCK_BLOCKED);
+        let reply = client.ok(TGETLOCK, |body| {
+            body.u32(0).u8(LOCK_TYPE_RDLCK).u64(0).u64(0).u32(2).string("host");
+        }).unwrap();
+        let mut reply = Reader::new(&reply);
+        assert_eq!((reply.u8(), reply.u64(), reply.u64(), reply.u32()), (LOCK_TYPE_WRLCK, 10, 0, 1));
+        assert_eq!(lock(&mut client, LOCK_TYPE_UNLCK, 0, 0, 1), LOCK_SUCCESS);
+        assert_eq!(lock(&mut client, LOCK_TYPE_RDLCK, 5, 10, 2), LOCK_SUCCESS);
+
+        // 溢出的偏移返回EINVAL，连接仍然可用
+        for offset in [u64::MAX - 2, u64::MAX] {
+            let write = client.ok(TWRITE, |body| {
+                body.u32(0).u64(offset).u32(5);
+                body.data.extend_from_slice(b"hello");
+            });
+            assert_eq!(write, Err(libc::EINVAL as u32));
+        }
+        client.ok(TWRITE, |body| {
+            body.u32(0).u64(0).u32(2);
+            body.data.extend_from_slice(b"hi");
+        }).unwrap();
+
+        drop(client);
+        let (vd, saves) = server.join().unwrap();
+        assert!(saves > 0);
+        assert_eq!(vd.read_file_by_path(&session, "/c.txt").unwrap(), b"hi");
+        assert!(vd.get_directory_by_path(&session, "/dir").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::cmp::Ordering;
+
+// 命令中的一个参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub text: String,
+    // 含有未加引号的通配符时为通配模式，其中被引号或反斜杠保护的字符以反斜杠转义
+    pub pattern: Option<String>,
+}
+
+// 命令行中的记号：参数、管道符或输出重定向
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    Pipe,                      // |
+    Redirect { append: bool }, // >与>>
+}
+
+// 以管道连接的命令，最后一条命令的输出可重定向到文件
+#[derive(Debug, Default, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<Vec<Word>>,
+    pub redirect: Option<(Word, bool)>, // 目标文件，是否追加
+}
+
+// 解析带符号与单位的大小，如+10k、-2M、512c，符号+、-与没有符号分别为Greater、Less与Equal；
+// 单位为k、M、G（1024的幂）或c（字节），格式错误或溢出时返回None
+pub fn parse_size(text: &str) -> Option<(Ordering, usize)> {
+    let (sign, number) = match text.as_bytes().first() {
+        Some(b'+') => (Ordering::Greater, &text[1..]),
+        Some(b'-') => (Ordering::Less, &text[1..]),
+        _ => (Ordering::Equal, text),
+    };
+    let (number, unit) = match number.chars().last() {
+        Some('k' | 'K') => (&number[..number.len() - 1], 1 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('c') => (&number[..number.len() - 1], 1),
+        _ => (number, 1),
+    };
+    // usize的解析接受+号，这里只允许数字
+    if !number.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let size = number.parse::<usize>().ok()?.checked_mul(unit)?;
+    Some((sign, size))
+}
+
+// 通配符
+pub fn is_glob_char(c: char) -> bool {
+    matches!(c, '*' | '?' | '[')
+}
+
+// 引号外有特殊含义的字符，作为普通字符时需要转义
+pub fn is_special_char(c: char) -> bool {
+    c.is_whitespace() || is_glob_char(c) || matches!(c, '\'' | '"' | '\\' | '|' | '>')
+}
+
+#[derive(Default)]
+struct WordBuilder {
+    text: String,
+    pattern: String,
+    has_glob: bool,
+    // 区分空参数""与没有参数
+    started: bool,
+}
+
+impl WordBuilder {
+    // 加入被引号或反斜杠保护的字符
+    fn push_quoted(&mut self, c: char) {
+        self.started = true;
+        self.text.push(c);
+        if is_glob_char(c) || c == '\\' {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+    }
+
+    fn push(&mut self, c: char) {
+        self.started = true;
+        self.text.push(c);
+        self.pattern.push(c);
+        self.has_glob |= is_glob_char(c);
+    }
+
+    fn finish(&mut self, tokens: &mut Vec<Token>) {
+        if self.started {
+            let builder = std::mem::take(self);
+            tokens.push(Token::Word(Word {
+                text: builder.text,
+                pattern: if builder.has_glob { Some(builder.pattern) } else { None },
+            }));
+        }
+    }
+}
+
+// 将一行命令拆分为参数，支持单引号、双引号与反斜杠转义
+// 单引号内的内容原样保留；双引号内与引号外可用反斜杠转义下一个字符
+// 引号外的|、>、>>作为单独的记号
+pub fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut word = WordBuilder::default();
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                word.started = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push_quoted(c),
+                        None => return Err("Unterminated single quote.".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                word.started = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push_quoted(c),
+                            None => return Err("Unterminated double quote.".to_string()),
+                        },
+                        Some(c) => word.push_quoted(c),
+                        None => return Err("Unterminated double quote.".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.push_quoted(c),
+                None => return Err("Trailing backslash.".to_string()),
+            },
+            '|' => {
+                word.finish(&mut tokens);
+                tokens.push(Token::Pipe);
+            }
+            '>' => {
+                word.finish(&mut tokens);
+                let append = chars.next_if_eq(&'>').is_some();
+                tokens.push(Token::Redirect { append });
+            }
+            c if c.is_whitespace() => word.finish(&mut tokens),
+            c => word.push(c),
+        }
+    }
+
+    word.finish(&mut tokens);
+    Ok(tokens)
+}
+
+// 将记号按管道符分为多条命令，重定向只能出现在末尾
+pub fn parse_pipeline(tokens: Vec<Token>) -> Result<Pipeline, String> {
+    let mut pipeline = Pipeline::default();
+    if tokens.is_empty() {
+        return Ok(pipeline);
+    }
+
+    let mut command = Vec::new();
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => command.push(word),
+            Token::Pipe => {
+                if command.is_empty() {
+                    return Err("Missing command before |.".to_string());
+                }
+                pipeline.commands.push(std::mem::take(&mut command));
+            }
+            Token::Redirect { append } => {
+                let target = match tokens.next() {
+                    Some(Token::Word(word)) => word,
+                    _ => return Err("Missing file name after >.".to_string()),
+                };
+                if tokens.next().is_some() {
+                    return Err("Redirection must be at the end of the command.".to_string());
+                }
+                pipeline.redirect = Some((target, append));
+            }
+        }
+    }
+
+    if command.is_empty() {
+        return Err(match pipeline.redirect {
+            Some(_) => "Missing command before >.".to_string(),
+            None => "Missing command after |.".to_string(),
+        });
+    }
+    pipeline.commands.push(command);
+    Ok(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 一行中各参数的文本
+    fn words(line: &str) -> Vec<String> {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .map(|token| match token {
+                Token::Word(word) => word.text,
+                other => panic!("unexpected token {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quotes_and_escapes_form_words() {
+        assert_eq!(words("touch  a \t b"), ["touch", "a", "b"]);
+        assert_eq!(words("touch 'my file' \"hello world\""), ["touch", "my file", "hello world"]);
+        assert_eq!(words("cd my\\ dir"), ["cd", "my dir"]);
+        // 单引号内原样保留，双引号内可以转义
+        assert_eq!(words(r#"echo 'a \ "b"'"#), ["echo", r#"a \ "b""#]);
+        assert_eq!(words(r#"echo "a \"b\" \\ 'c'""#), ["echo", r#"a "b" \ 'c'"#]);
+        assert_eq!(words(r#"touch "" ''"#), ["touch", "", ""]);
+        assert_eq!(words(r#"touch a"b c"'d'"#), ["touch", "ab cd"]);
+        assert!(words("   ").is_empty());
+    }
+
+    #[test]
+    fn unterminated_input_is_rejected() {
+        assert_eq!(tokenize("echo 'abc"), Err("Unterminated single quote.".to_string()));
+        assert_eq!(tokenize("echo \"abc"), Err("Unterminated double quote.".to_string()));
+        assert_eq!(tokenize("echo \"abc\\"), Err("Unterminated double quote.".to_string()));
+        assert_eq!(tokenize("echo abc\\"), Err("Trailing backslash.".to_string()));
+    }
+
+    #[test]
+    fn sizes_are_checked() {
+        assert_eq!(parse_size("512"), Some((Ordering::Equal, 512)));
+        assert_eq!(parse_size("+3k"), Some((Ordering::Greater, 3 * 1024)));
+        assert_eq!(parse_size("-2M"), Some((Ordering::Less, 2 * 1024 * 1024)));
+        assert_eq!(parse_size("1G"), Some((Ordering::Equal, 1 << 30)));
+        assert_eq!(parse_size("7c"), Some((Ordering::Equal, 7)));
+        for text in ["", "+", "k", "1x", "-+1", "18014398509481984M", "18446744073709551616"] {
+            assert_eq!(parse_size(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn pipelines_split_on_unquoted_operators() {
+        let pipeline = parse_pipeline(tokenize("cat 'a | b' | grep x >> out").unwrap()).unwrap();
+        let commands: Vec<Vec<&str>> = pipeline
+            .commands
+            .iter()
+            .map(|command| command.iter().map(|word| word.text.as_str()).collect())
+            .collect();
+        assert_eq!(commands, [vec!["cat", "a | b"], vec!["grep", "x"]]);
+        let (target, append) = pipeline.redirect.unwrap();
+        assert_eq!((target.text.as_str(), append), ("out", true));
+
+        for line in ["| cat", "cat |", "> out", "cat >", "cat > a b"] {
+            assert!(parse_pipeline(tokenize(line).unwrap()).is_err(), "{} should fail", line);
+        }
+    }
+}
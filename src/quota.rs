@@ -0,0 +1,247 @@
+use crate::disk_operator::{DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+// 块数与文件数的上限，0表示不限制
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limit {
+    pub blocks: usize,
+    pub inodes: usize,
+}
+
+// 已使用的块数与文件数（含目录）
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub blocks: usize,
+    pub inodes: usize,
+}
+
+impl Limit {
+    pub fn is_unlimited(&self) -> bool {
+        self.blocks == 0 && self.inodes == 0
+    }
+
+    // 在usage之外再使用blocks块与inodes个文件是否不超过上限，不增加的一项不检查
+    pub fn allows(&self, usage: Usage, blocks: usize, inodes: usize) -> bool {
+        let fits = |limit: usize, used: usize, extra: usize| limit == 0 || extra == 0 || used + extra <= limit;
+        fits(self.blocks, usage.blocks, blocks) && fits(self.inodes, usage.inodes, inodes)
+    }
+}
+
+// 配额随镜像保存：用户的配额按分配块的用户计算，目录的配额计算整棵子树；
+// 每个块记录分配它的用户，文件与目录属于其首块的用户，旧镜像中的块不属于任何用户
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Quotas {
+    users: BTreeMap<String, Limit>,
+    dirs: BTreeMap<usize, Limit>,    // 目录首块号 -> 上限
+    owners: BTreeMap<usize, String>, // 块号 -> 分配它的用户
+}
+
+impl Quotas {
+    pub fn user_limit(&self, user: &str) -> Option<Limit> {
+        self.users.get(user).copied()
+    }
+
+    pub fn dir_limit(&self, cluster: usize) -> Option<Limit> {
+        self.dirs.get(&cluster).copied()
+    }
+
+    // 两项都为0时删除配额
+    pub fn set_user(&mut self, user: &str, limit: Limit) {
+        if limit.is_unlimited() {
+            self.users.remove(user);
+        } else {
+            self.users.insert(user.to_string(), limit);
+        }
+    }
+
+    pub fn set_dir(&mut self, cluster: usize, limit: Limit) {
+        if limit.is_unlimited() {
+            self.dirs.remove(&cluster);
+        } else {
+            self.dirs.insert(cluster, limit);
+        }
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = (&str, Limit)> {
+        self.users.iter().map(|(user, &limit)| (user.as_str(), limit))
+    }
+
+    pub fn dirs(&self) -> impl Iterator<Item = (usize, Limit)> + '_ {
+        self.dirs.iter().map(|(&cluster, &limit)| (cluster, limit))
+    }
+
+    // 新分配的块记入user名下
+    pub fn charge(&mut self, clusters: &[usize], user: &str) {
+        for &cluster in clusters {
+            self.owners.insert(cluster, user.to_string());
+        }
+    }
+
+    // 块被释放后不再属于任何用户；目录的首块被释放说明目录已删除，其配额一并删除
+    pub fn forget(&mut self, cluster: usize) {
+        self.owners.remove(&cluster);
+        self.dirs.remove(&cluster);
+    }
+
+    pub fn owner(&self, cluster: usize) -> Option<&str> {
+        self.owners.get(&cluster).map(String::as_str)
+    }
+
+    // 块号改变后更新记录的块号，map给出每块的新块号
+    pub fn relocate<F: Fn(usize) -> usize>(&mut self, map: F) {
+        self.dirs = self.dirs.iter().map(|(&cluster, &limit)| (map(cluster), limit)).collect();
+        self.owners = std::mem::take(&mut self.owners).into_iter().map(|(cluster, user)| (map(cluster), user)).collect();
+    }
+}
+
+// 遍历目录下的全部文件与子目录
+fn visit<F: FnMut(&Fcb) -> FsResult<()>>(vd: &DiskOperator, cluster: usize, f: &mut F) -> FsResult<()> {
+    for fcb in vd.get_directory_by_cluster(cluster)?.entries() {
+        f(fcb)?;
+        if fcb.file_type() == FileType::Directory {
+            visit(vd, fcb.first_cluster(), f)?;
+        }
+    }
+    Ok(())
+}
+
+// 用户分配的块数，以及首块由其分配的文件与目录数
+pub fn user_usage(vd: &DiskOperator, user: &str) -> FsResult<Usage> {
+    let blocks = vd.quotas().owners.values().filter(|&owner| owner == user).count();
+    let mut inodes = 0;
+    visit(vd, 0, &mut |fcb| {
+        if vd.quotas().owner(fcb.first_cluster()) == Some(user) {
+            inodes += 1;
+        }
+        Ok(())
+    })?;
+    Ok(Usage { blocks, inodes })
+}
+
+// 目录子树使用的块（含目录自身，被多个文件共享的块只计一次）与其中的文件和目录数
+pub fn dir_usage(vd: &DiskOperator, cluster: usize) -> FsResult<Usage> {
+    let mut clusters: BTreeSet<usize> = vd.get_series(cluster)?.into_iter().collect();
+    let mut inodes = 0;
+    visit(vd, cluster, &mut |fcb| {
+        inodes += 1;
+        clusters.extend(vd.get_series(fcb.first_cluster())?);
+        Ok(())
+    })?;
+    Ok(Usage { blocks: clusters.len(), inodes })
+}
+
+// 目录自身及其各级祖先的首块号，直到根目录
+fn ancestors(vd: &DiskOperator, cluster: usize) -> FsResult<Vec<usize>> {
+    let mut clusters = vec![cluster];
+    let mut cluster = cluster;
+    while cluster != 0 {
+        if clusters.len() > vd.disk.cluster_count() {
+            return Err(FsError::Corrupted(format!("directory loop at cluster {}", cluster)));
+        }
+        cluster = vd.get_directory_by_cluster(cluster)?.parent();
+        clusters.push(cluster);
+    }
+    Ok(clusters)
+}
+
+fn check_dirs(vd: &DiskOperator, dirs: &[usize], blocks: usize, inodes: usize) -> FsResult<()> {
+    for &cluster in dirs {
+        let Some(limit) = vd.quotas().dir_limit(cluster) else {
+            continue;
+        };
+        if !limit.allows(dir_usage(vd, cluster)?, blocks, inodes) {
+            return Err(FsError::QuotaExceeded(vd.get_path_by_cluster(cluster)?));
+        }
+    }
+    Ok(())
+}
+
+// 用户user在首块号为dir的目录下再分配blocks块、创建inodes个文件前，
+// 检查用户的配额以及dir和其各级祖先目录的配额，dir为None时只检查用户
+pub fn check(vd: &DiskOperator, user: &str, dir: Option<usize>, blocks: usize, inodes: usize) -> FsResult<()> {
+    let limit = vd.quotas().user_limit(user);
+    if let Some(limit) = limit {
+        if !limit.allows(user_usage(vd, user)?, blocks, inodes) {
+            return Err(FsError::QuotaExceeded(format!("user {}", user)));
+        }
+    }
+    let has_dirs = !vd.quotas().dirs.is_empty();
+    match dir {
+        Some(dir) if has_dirs => check_dirs(vd, &ancestors(vd, dir)?, blocks, inodes),
+        _ => Ok(()),
+    }
+}
+
+// 将fcb从首块号为from的目录移动到to下，检查只包含to而不包含from的目录的配额
+pub fn check_move(vd: &DiskOperator, from: usize, to: usize, fcb: &Fcb) -> FsResult<()> {
+    if vd.quotas().dirs.is_empty() {
+        return Ok(());
+    }
+    let usage = match fcb.file_type() {
+        FileType::Directory => dir_usage(vd, fcb.first_cluster())?,
+        FileType::File => Usage { blocks: vd.get_series(fcb.first_cluster())?.len(), inodes: 0 },
+    };
+    let kept = ancestors(vd, from)?;
+    let dirs: Vec<usize> = ancestors(vd, to)?.into_iter().filter(|cluster| !kept.contains(cluster)).collect();
+    check_dirs(vd, &dirs, usage.blocks, usage.inodes + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::BLOCK_SIZE;
+    use crate::fsck;
+    use crate::session::Session;
+
+    #[test]
+    fn quotas_limit_users_and_directory_subtrees() {
+        let session = Session::new("alice");
+        let vd = DiskOperator::new(None);
+        vd.quotas().set_user("alice", Limit { blocks: 10, inodes: 4 });
+        vd.new_directory(&session, "d").unwrap();
+        vd.write_file_by_path(&session, "/d/a", &vec![1; 3 * BLOCK_SIZE]).unwrap();
+        assert_eq!(user_usage(&vd, "alice").unwrap(), Usage { blocks: 4, inodes: 2 });
+
+        // 超出用户的块数时不分配任何块，文件保持不变
+        let err = vd.write_at(&session, "/d/a", 3 * BLOCK_SIZE, &vec![2; 7 * BLOCK_SIZE]).unwrap_err();
+        assert_eq!(err, FsError::QuotaExceeded("user alice".to_string()));
+        assert_eq!(vd.get_fcb_by_path(&session, "/d/a").unwrap().length(), 3 * BLOCK_SIZE);
+        vd.write_file_by_path(&session, "/d/b", b"b").unwrap();
+        vd.write_file_by_path(&session, "/c", b"c").unwrap();
+        assert!(matches!(vd.write_file_by_path(&session, "/d/e", b"e"), Err(FsError::QuotaExceeded(_))));
+
+        // 其他用户不受alice的配额限制，但受目录配额限制
+        let session = Session::new("bob");
+        let d = vd.get_directory_by_path(&session, "/d").unwrap().cluster();
+        vd.quotas().set_dir(d, Limit { blocks: 7, inodes: 0 });
+        assert_eq!(dir_usage(&vd, d).unwrap(), Usage { blocks: 5, inodes: 2 });
+        vd.write_file_by_path(&session, "/d/e", &vec![3; 2 * BLOCK_SIZE]).unwrap();
+        assert_eq!(
+            vd.write_at(&session, "/d/e", 2 * BLOCK_SIZE, b"x").unwrap_err(),
+            FsError::QuotaExceeded("/d".to_string())
+        );
+        vd.write_file_by_path(&session, "/big", &vec![4; 2 * BLOCK_SIZE]).unwrap();
+        assert!(matches!(vd.move_file_by_path(&session, "/big", "/d"), Err(FsError::QuotaExceeded(_))));
+        vd.delete_file_by_path(&session, "/d/e").unwrap();
+        vd.move_file_by_path(&session, "/big", "/d").unwrap();
+        assert_eq!(user_usage(&vd, "alice").unwrap(), Usage { blocks: 6, inodes: 4 });
+
+        // 配额随镜像保存
+        let data = bincode::serialize(&vd).unwrap();
+        let loaded: DiskOperator = bincode::deserialize(&data).unwrap();
+        assert_eq!(loaded.quotas().dir_limit(d), Some(Limit { blocks: 7, inodes: 0 }));
+        assert_eq!(loaded.quotas().owner(d), Some("alice"));
+        assert!(fsck::check(&loaded).is_empty());
+
+        // 删除目录时其配额一并删除
+        vd.delete_file_by_path(&session, "/d/a").unwrap();
+        vd.delete_file_by_path(&session, "/d/b").unwrap();
+        vd.delete_file_by_path(&session, "/d/big").unwrap();
+        vd.delete_file_by_path(&session, "/d").unwrap();
+        assert_eq!(vd.quotas().dirs().count(), 0);
+        assert_eq!(user_usage(&vd, "alice").unwrap(), Usage { blocks: 1, inodes: 1 });
+    }
+}
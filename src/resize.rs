@@ -0,0 +1,132 @@
+use crate::disk::{Disk, FATItem, HOLE_END};
+use crate::disk_operator::{DiskOperator, FileType};
+use crate::error::{FsError, FsResult};
+use crate::session::Session;
+
+use std::collections::HashMap;
+
+// 镜像整体读入内存，总块数最多为4GiB
+pub const MAX_BLOCK_COUNT: usize = 1 << 20;
+
+// 调整磁盘的总块数（含FAT所占的块），扩大时增加FAT表项与数据区；
+// 缩小时先将新末尾之后已分配的块移到前面的空闲块，更新FAT链、目录项、快照与会话的当前目录中的块号，放不下时拒绝
+pub fn resize(vd: &mut DiskOperator, session: &mut Session, block_count: usize) -> FsResult<()> {
+    let clusters = Disk::data_clusters(block_count);
+    if clusters == 0 || block_count > MAX_BLOCK_COUNT {
+        return Err(FsError::InvalidArgument(format!(
+            "block count {} out of range, expected 2..={}",
+            block_count, MAX_BLOCK_COUNT
+        )));
+    }
+    // 打开的句柄与锁记录着目录的首块号
+    if session.handles().next().is_some() {
+        return Err(FsError::InvalidArgument("close the open files before resizing".to_string()));
+    }
+    if clusters < vd.disk.cluster_count() {
+        relocate(vd, session, clusters)?;
+    }
+    vd.disk.resize(block_count);
+    Ok(())
+}
+
+// 将[clusters, 数据区末尾)中已分配的块移到前面的空闲块，损坏的块直接丢弃
+fn relocate(vd: &mut DiskOperator, session: &mut Session, clusters: usize) -> FsResult<()> {
+    let fat = vd.disk.fat();
+    let moving: Vec<usize> = (clusters..vd.disk.cluster_count())
+        .filter(|&cluster| !matches!(fat[cluster], FATItem::UnUsed | FATItem::BadCluster))
+        .collect();
+    let free: Vec<usize> = (0..clusters).filter(|&cluster| matches!(fat[cluster], FATItem::UnUsed)).collect();
+    if free.len() < moving.len() {
+        let used = clusters - free.len() + moving.len();
+        return Err(FsError::InvalidArgument(format!(
+            "{} clusters in use do not fit in {} clusters",
+            used, clusters
+        )));
+    }
+    if moving.is_empty() {
+        return Ok(());
+    }
+
+    let map: HashMap<usize, usize> = moving.into_iter().zip(free).collect();
+    for (&from, &to) in &map {
+        vd.disk.copy_cluster(from, to);
+        vd.disk.set_fat(to, vd.disk.fat_item(from));
+        vd.disk.free(from);
+    }
+    let remap = |cluster: usize| map.get(&cluster).copied().unwrap_or(cluster);
+    for (cluster, item) in vd.disk.fat().into_iter().enumerate() {
+        match item {
+            FATItem::Cluster(next) => vd.disk.set_fat(cluster, FATItem::Cluster(remap(next))),
+            FATItem::Hole(holes, next) if next != HOLE_END => {
+                vd.disk.set_fat(cluster, FATItem::Hole(holes, remap(next as usize) as u32))
+            }
+            _ => {}
+        }
+    }
+
+    // FAT更新后块链完整，再沿目录树更新目录项
+    vd.snapshots().relocate(remap);
+    vd.quotas().relocate(remap);
+    let roots: Vec<usize> = vd.snapshots().list().iter().map(|snapshot| snapshot.root()).collect();
+    for root in std::iter::once(0).chain(roots) {
+        relocate_directory(vd, session, root, &remap)?;
+    }
+    session.set_cwd(remap(session.cwd()));
+    vd.clear_dedup_index();
+    Ok(())
+}
+
+fn relocate_directory<F: Fn(usize) -> usize>(vd: &mut DiskOperator, session: &Session, cluster: usize, remap: &F) -> FsResult<()> {
+    let mut dir = vd.get_directory_by_cluster(cluster)?;
+    dir.relocate_clusters(remap);
+    vd.save_dir_to_disk(session, &dir)?;
+    for fcb in dir.entries() {
+        if fcb.file_type() == FileType::Directory {
+            relocate_directory(vd, session, fcb.first_cluster(), remap)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::BLOCK_SIZE;
+    use crate::{fsck, snapshot};
+
+    #[test]
+    fn shrinking_moves_clusters_past_the_new_end() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        // 先占满前面的块，之后的文件位于磁盘末尾附近
+        vd.write_file_by_path(&session, "/fill", &vec![1; 900 * BLOCK_SIZE]).unwrap();
+        vd.new_directory(&session, "d").unwrap();
+        let data: Vec<u8> = (0..5 * BLOCK_SIZE + 7).map(|i| (i % 253) as u8).collect();
+        vd.write_file_by_path(&session, "/d/a", &data).unwrap();
+        vd.write_file_by_path(&session, "/d/s", b"sparse").unwrap();
+        vd.write_at(&session, "/d/s", 30 * BLOCK_SIZE, b"tail").unwrap();
+        vd.delete_file_by_path(&session, "/fill").unwrap();
+        snapshot::create(&mut vd, &session, "snap").unwrap();
+        vd.write_at(&session, "/d/a", 0, b"new").unwrap();
+        vd.set_current_dir(&mut session, "/d").unwrap();
+
+        // 放不下时拒绝，磁盘不变
+        let used = vd.get_disk_info().1;
+        assert!(resize(&mut vd, &mut session, used).is_err());
+        assert_eq!(vd.disk.cluster_count(), 1019);
+
+        resize(&mut vd, &mut session, 64).unwrap();
+        assert_eq!((vd.disk.cluster_count(), vd.get_disk_info().1), (63, used));
+        assert!(fsck::check(&vd).is_empty(), "{:?}", fsck::check(&vd));
+        assert_eq!(vd.read_file_by_name(&session, "a").unwrap()[3..], data[3..]);
+        assert_eq!(vd.read_file_by_path(&session, "/d/s").unwrap()[30 * BLOCK_SIZE..], *b"tail");
+        let old = snapshot::lookup(&vd, "snap", "/d/a").unwrap();
+        assert_eq!(vd.get_file_by_fcb(&old).unwrap(), data);
+
+        // 扩大后新增的块可以使用
+        resize(&mut vd, &mut session, 2048).unwrap();
+        vd.write_file_by_path(&session, "/big", &vec![2; 1500 * BLOCK_SIZE]).unwrap();
+        assert!(fsck::check(&vd).is_empty(), "{:?}", fsck::check(&vd));
+        assert!(resize(&mut vd, &mut session, 1).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use crate::disk::BLOCK_SIZE;
+use crate::disk_operator::{DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::glob;
+use crate::parser;
+use crate::session::Session;
+
+use std::cmp::Ordering;
+
+// find的筛选条件，未指定的条件不参与筛选
+#[derive(Default)]
+pub struct FindFilter {
+    pub name: Option<String>,           // 文件名通配模式
+    pub file_type: Option<FileType>,    // 文件类型
+    pub size: Option<(Ordering, usize)>, // 与给定大小比较的结果
+    pub newer: Option<i64>,             // 修改时间晚于该时间
+}
+
+impl FindFilter {
+    // 解析find的选项，如-name *.txt -type f -size +10k -newer a.txt
+    pub fn parse(vd: &DiskOperator, session: &Session, args: &[String]) -> FsResult<FindFilter> {
+        let mut filter = FindFilter::default();
+        let mut args = args.iter();
+        while let Some(option) = args.next() {
+            let Some(value) = args.next() else {
+                return Err(FsError::InvalidArgument(format!("{} requires a value", option)));
+            };
+            match option.as_str() {
+                "-name" => filter.name = Some(value.clone()),
+                "-type" => {
+                    filter.file_type = match value.as_str() {
+                        "f" => Some(FileType::File),
+                        "d" => Some(FileType::Directory),
+                        _ => return Err(FsError::InvalidArgument(format!("unknown type {}", value))),
+                    }
+                }
+                "-size" => {
+                    let size = parser::parse_size(value);
+                    filter.size = Some(size.ok_or(FsError::InvalidArgument(format!("invalid size {}", value)))?);
+                }
+                "-newer" => filter.newer = Some(vd.get_fcb_by_path(session, value)?.modified()),
+                _ => return Err(FsError::InvalidArgument(format!("unknown option {}", option))),
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, fcb: &Fcb) -> bool {
+        if let Some(pattern) = &self.name {
+            if !glob::matches(pattern, fcb.name()) {
+                return false;
+            }
+        }
+        if let Some(file_type) = &self.file_type {
+            if fcb.file_type() != *file_type {
+                return false;
+            }
+        }
+        if let Some((ordering, size)) = self.size {
+            if fcb.length().cmp(&size) != ordering {
+                return false;
+            }
+        }
+        if let Some(time) = self.newer {
+            if fcb.modified() <= time {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// 连接目录路径与文件名
+fn join(dir: &str, name: &str) -> String {
+    if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+// 从path开始先序遍历目录树，对每一项调用visit，visit返回false时不进入该目录
+pub fn walk<F>(vd: &DiskOperator, session: &Session, path: &str, visit: &mut F) -> FsResult<()>
+where
+    F: FnMut(&str, &Fcb) -> FsResult<bool>,
+{
+    let fcb = vd.get_fcb_by_path(session, path)?;
+    walk_fcb(vd, path, &fcb, visit)
+}
+
+fn walk_fcb<F>(vd: &DiskOperator, path: &str, fcb: &Fcb, visit: &mut F) -> FsResult<()>
+where
+    F: FnMut(&str, &Fcb) -> FsResult<bool>,
+{
+    if !visit(path, fcb)? || fcb.file_type() != FileType::Directory {
+        return Ok(());
+    }
+    let dir = vd.get_directory_by_fcb(fcb)?;
+    for entry in dir.entries() {
+        walk_fcb(vd, &join(path, entry.name()), entry, visit)?;
+    }
+    Ok(())
+}
+
+// 查找path下满足条件的文件，每找到一个就调用output
+pub fn find<F>(vd: &DiskOperator, session: &Session, path: &str, filter: &FindFilter, output: &mut F) -> FsResult<()>
+where
+    F: FnMut(&str),
+{
+    walk(vd, session, path, &mut |path, fcb| {
+        if filter.matches(fcb) {
+            output(path);
+        }
+        Ok(true)
+    })
+}
+
+// grep的选项
+#[derive(Default)]
+pub struct GrepOptions {
+    pub recursive: bool,    // -r，递归搜索目录
+    pub ignore_case: bool,  // -i，忽略大小写
+    pub line_number: bool,  // -n，输出行号
+    pub with_name: bool,    // 在每行前输出文件名
+}
+
+// 逐行查找包含pattern的行，记录行号与匹配的行数
+struct LineMatcher<'a, F> {
+    pattern: Vec<u8>,
+    name: Option<&'a str>,
+    options: &'a GrepOptions,
+    output: &'a mut F,
+    line_number: usize,
+    count: usize,
+}
+
+impl<'a, F> LineMatcher<'a, F>
+where
+    F: FnMut(&[u8]),
+{
+    fn new(pattern: &[u8], name: Option<&'a str>, options: &'a GrepOptions, output: &'a mut F) -> Self {
+        let pattern = if options.ignore_case { pattern.to_ascii_lowercase() } else { pattern.to_vec() };
+        LineMatcher { pattern, name, options, output, line_number: 0, count: 0 }
+    }
+
+    // 处理不含换行符的一行
+    fn line(&mut self, line: &[u8]) {
+        self.line_number += 1;
+        let found = if self.options.ignore_case {
+            contains(&line.to_ascii_lowercase(), &self.pattern)
+        } else {
+            contains(line, &self.pattern)
+        };
+        if !found {
+            return;
+        }
+
+        let mut result = Vec::new();
+        if let Some(name) = self.name {
+            result.extend_from_slice(format!("{}:", name).as_bytes());
+        }
+        if self.options.line_number {
+            result.extend_from_slice(format!("{}:", self.line_number).as_bytes());
+        }
+        result.extend_from_slice(line);
+        result.push(b'\n');
+        (self.output)(&result);
+        self.count += 1;
+    }
+}
+
+// 在data中查找包含pattern的行，每找到一行就调用output，返回匹配的行数
+pub fn grep_data<F>(data: &[u8], pattern: &[u8], name: Option<&str>, options: &GrepOptions, output: &mut F) -> usize
+where
+    F: FnMut(&[u8]),
+{
+    let mut matcher = LineMatcher::new(pattern, name, options, output);
+    if data.is_empty() {
+        return 0;
+    }
+    // 最后的换行不产生新的空行
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    for line in data.split(|&byte| byte == b'\n') {
+        matcher.line(line);
+    }
+    matcher.count
+}
+
+// 按块读取文件并逐行查找，不完整的行留到读入下一块后再处理
+fn grep_file<F>(
+    vd: &DiskOperator,
+    fcb: &Fcb,
+    pattern: &[u8],
+    name: Option<&str>,
+    options: &GrepOptions,
+    output: &mut F,
+) -> FsResult<usize>
+where
+    F: FnMut(&[u8]),
+{
+    // 压缩的文件每次读取都要整体解压，因此一次读出
+    if !fcb.compression().is_none() {
+        return Ok(grep_data(&vd.get_file_by_fcb(fcb)?, pattern, name, options, output));
+    }
+    let mut matcher = LineMatcher::new(pattern, name, options, output);
+    let mut pending = Vec::new();
+    let mut offset = 0;
+    while offset < fcb.length() {
+        let chunk = vd.read_at(fcb, offset, BLOCK_SIZE)?;
+        if chunk.is_empty() {
+            break;
+        }
+        offset += chunk.len();
+        pending.extend_from_slice(&chunk);
+        if let Some(end) = pending.iter().rposition(|&byte| byte == b'\n') {
+            for line in pending[..end].split(|&byte| byte == b'\n') {
+                matcher.line(line);
+            }
+            pending.drain(..=end);
+        }
+    }
+    if !pending.is_empty() {
+        matcher.line(&pending);
+    }
+    Ok(matcher.count)
+}
+
+fn contains(line: &[u8], pattern: &[u8]) -> bool {
+    pattern.is_empty() || line.windows(pattern.len()).any(|window| window == pattern)
+}
+
+// 在path指向的文件中查找，-r时递归搜索目录下的所有文件
+pub fn grep_path<F>(
+    vd: &DiskOperator,
+    session: &Session,
+    path: &str,
+    pattern: &[u8],
+    options: &GrepOptions,
+    output: &mut F,
+) -> FsResult<usize>
+where
+    F: FnMut(&[u8]),
+{
+    let mut count = 0;
+    walk(vd, session, path, &mut |file_path, fcb| {
+        if fcb.file_type() == FileType::Directory {
+            if !options.recursive {
+                return Err(FsError::IsADirectory(file_path.to_string()));
+            }
+            return Ok(true);
+        }
+        let name = if options.with_name { Some(file_path) } else { None };
+        count += grep_file(vd, fcb, pattern, name, options, output)?;
+        Ok(false)
+    })?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+
+    #[test]
+    fn grep_reads_files_block_by_block() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        let zstd = vd.new_directory_with(&session, 0, "z", Some(Compression::Zstd)).unwrap();
+        // 行跨越块的边界，结尾没有换行符，中间有空行
+        let mut data = Vec::new();
+        for index in 0..2000 {
+            data.extend_from_slice(format!("line {}{}\n", index, if index % 7 == 0 { " match" } else { "" }).as_bytes());
+            if index % 500 == 0 {
+                data.push(b'\n');
+            }
+        }
+        data.extend_from_slice(b"last match");
+        assert!(data.len() > 3 * BLOCK_SIZE);
+        vd.new_file(&session, "plain", &data).unwrap();
+        vd.new_file_at(&session, zstd, "packed", &data).unwrap();
+
+        let options = GrepOptions { line_number: true, ..Default::default() };
+        let mut expected = Vec::new();
+        let count = grep_data(&data, b"match", None, &options, &mut |line| expected.extend_from_slice(line));
+        assert_eq!(count, 287);
+        for path in ["/plain", "/z/packed"] {
+            let mut found = Vec::new();
+            assert_eq!(grep_path(&vd, &session, path, b"match", &options, &mut |line| found.extend_from_slice(line)).unwrap(), count);
+            assert_eq!(found, expected, "{}", path);
+        }
+        // 空行也有行号
+        let mut found = Vec::new();
+        grep_path(&vd, &session, "/plain", b"", &options, &mut |line| found.extend_from_slice(line)).unwrap();
+        assert!(found.starts_with(b"1:line 0 match\n2:\n3:line 1\n"));
+    }
+}
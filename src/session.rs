@@ -0,0 +1,75 @@
+use crate::error::{FsError, FsResult};
+use crate::handle::FileHandle;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// 下一个会话的编号
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
+// 会话：一个客户端的当前目录、用户身份与打开的文件
+// 会话不写入镜像，多个会话可以轮流使用同一个DiskOperator，彼此的当前目录互不影响
+#[derive(Clone, Debug)]
+pub struct Session {
+    id: u64,    // 进程内唯一的编号，用于标识锁的持有者
+    cwd: usize, // 当前目录的首块号
+    user: String,
+    handles: BTreeMap<u64, FileHandle>,
+    next_handle: u64,
+}
+
+impl Session {
+    pub fn new(user: &str) -> Session {
+        Session {
+            id: NEXT_SESSION.fetch_add(1, Ordering::Relaxed),
+            cwd: 0,
+            user: user.to_string(),
+            handles: BTreeMap::new(),
+            next_handle: 1,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn cwd(&self) -> usize {
+        self.cwd
+    }
+
+    pub fn set_cwd(&mut self, cluster: usize) {
+        self.cwd = cluster;
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    // 记录打开的文件，返回其编号
+    pub fn open(&mut self, handle: FileHandle) -> u64 {
+        let id = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(id, handle);
+        id
+    }
+
+    pub fn handle(&self, id: u64) -> FsResult<&FileHandle> {
+        self.handles.get(&id).ok_or(FsError::InvalidArgument(format!("bad file handle {}", id)))
+    }
+
+    pub fn close(&mut self, id: u64) -> FsResult<FileHandle> {
+        self.handles.remove(&id).ok_or(FsError::InvalidArgument(format!("bad file handle {}", id)))
+    }
+
+    // 按编号排列的打开的文件
+    pub fn handles(&self) -> impl Iterator<Item = (u64, &FileHandle)> {
+        self.handles.iter().map(|(&id, handle)| (id, handle))
+    }
+}
+
+// 默认以运行程序的用户的身份
+impl Default for Session {
+    fn default() -> Self {
+        Session::new(&std::env::var("USER").unwrap_or_else(|_| String::from("root")))
+    }
+}
@@ -0,0 +1,310 @@
+use crate::disk_operator::{check_name, Directory, DiskOperator, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::session::Session;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// 快照：整个文件系统某一时刻的只读副本
+// 创建快照时复制全部目录，文件共享原来的块链并增加其引用计数；
+// 之后写入被共享的文件时先复制其块链（写时复制），快照中的内容保持不变
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    name: String,
+    created: i64, // 创建时间，Unix时间戳（秒）
+    root: usize,  // 快照根目录的首块号
+}
+
+impl Snapshot {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn created(&self) -> i64 {
+        self.created
+    }
+
+    pub fn root(&self) -> usize {
+        self.root
+    }
+}
+
+// 快照列表与块的引用计数，随镜像保存；去重共享的块也记录在引用计数中
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Snapshots {
+    list: Vec<Snapshot>,
+    refs: BTreeMap<usize, usize>, // 块的额外引用数，不在其中的已分配块只被引用一次
+}
+
+impl Snapshots {
+    pub fn list(&self) -> &[Snapshot] {
+        &self.list
+    }
+
+    pub fn get(&self, name: &str) -> FsResult<&Snapshot> {
+        self.list
+            .iter()
+            .find(|snapshot| snapshot.name == name)
+            .ok_or(FsError::NotFound(format!("snapshot {}", name)))
+    }
+
+    // 块是否被多处引用，写入前需要复制
+    pub fn is_shared(&self, cluster: usize) -> bool {
+        self.refs.contains_key(&cluster)
+    }
+
+    // 块被引用的次数
+    pub fn references(&self, cluster: usize) -> usize {
+        1 + self.refs.get(&cluster).copied().unwrap_or(0)
+    }
+
+    // 被多处引用的块数
+    pub fn shared_count(&self) -> usize {
+        self.refs.len()
+    }
+
+    // 磁盘缩小时块被移动，按映射更新快照的根目录与引用计数
+    pub fn relocate<F: Fn(usize) -> usize>(&mut self, map: F) {
+        for snapshot in &mut self.list {
+            snapshot.root = map(snapshot.root);
+        }
+        self.refs = std::mem::take(&mut self.refs).into_iter().map(|(cluster, count)| (map(cluster), count)).collect();
+    }
+
+    pub fn share(&mut self, cluster: usize) {
+        *self.refs.entry(cluster).or_insert(0) += 1;
+    }
+
+    // 去掉块的一个引用，返回块是否已不再被引用，可以释放
+    pub fn release(&mut self, cluster: usize) -> bool {
+        match self.refs.get_mut(&cluster) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                self.refs.remove(&cluster);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+// 以当前的目录树创建快照
+pub fn create(vd: &mut DiskOperator, session: &Session, name: &str) -> FsResult<()> {
+    check_name(name)?;
+    if vd.snapshots().get(name).is_ok() {
+        return Err(FsError::AlreadyExists(format!("snapshot {}", name)));
+    }
+    reserve(vd, 0)?;
+    let root = copy_tree(vd, session, 0, None, name)?;
+    vd.snapshots().list.push(Snapshot {
+        name: name.to_string(),
+        created: chrono::Local::now().timestamp(),
+        root,
+    });
+    Ok(())
+}
+
+// 删除快照，只被该快照引用的块随之释放
+pub fn delete(vd: &mut DiskOperator, name: &str) -> FsResult<()> {
+    let root = vd.snapshots().get(name)?.root;
+    let own = vd.get_directory_by_cluster(root)?.own_fcb();
+    remove_tree(vd, &own)?;
+    vd.snapshots().list.retain(|snapshot| snapshot.name != name);
+    Ok(())
+}
+
+// 将当前的目录树回滚到快照，快照本身保留；会话回到根目录
+pub fn restore(vd: &mut DiskOperator, session: &mut Session, name: &str) -> FsResult<()> {
+    let root = vd.snapshots().get(name)?.root;
+    reserve(vd, root)?;
+
+    let live = vd.get_directory_by_cluster(0)?;
+    for fcb in live.entries() {
+        remove_tree(vd, fcb)?;
+    }
+    let snapshot = vd.get_directory_by_cluster(root)?;
+    let mut restored = Directory::empty(live.name(), 0, 0);
+    restored.set_compression(snapshot.compression());
+    copy_entries(vd, session, &snapshot, &mut restored)?;
+    vd.save_dir_to_disk(session, &restored)?;
+    vd.set_current_dir_to_root(session)
+}
+
+// 快照中路径指向的文件或目录，路径从快照的根目录开始
+pub fn lookup(vd: &DiskOperator, name: &str, path: &str) -> FsResult<Fcb> {
+    let root = vd.snapshots().get(name)?.root;
+    let mut fcb = vd.get_directory_by_cluster(root)?.own_fcb();
+    for part in path.split('/').filter(|part| !part.is_empty()) {
+        let dir = vd.get_directory_by_fcb(&fcb)?;
+        fcb = match dir.get_entry(part) {
+            Some(entry) if part == "." || part == ".." => vd.get_directory_by_cluster(entry.first_cluster())?.own_fcb(),
+            Some(entry) => entry.clone(),
+            None => return Err(FsError::NotFound(path.to_string())),
+        };
+    }
+    Ok(fcb)
+}
+
+// 快照中全部文件与目录的路径，按路径排序
+pub fn files(vd: &DiskOperator, name: &str) -> FsResult<Vec<(String, Fcb)>> {
+    let mut entries = BTreeMap::new();
+    let root = vd.snapshots().get(name)?.root;
+    collect(vd, root, "", &mut entries)?;
+    Ok(entries.into_iter().collect())
+}
+
+// 快照之间或快照与当前目录树之间的差异，按路径排序：+新增，-删除，M内容改变
+pub fn diff(vd: &DiskOperator, name: &str, other: Option<&str>) -> FsResult<Vec<(char, String)>> {
+    let mut old = BTreeMap::new();
+    let root = vd.snapshots().get(name)?.root;
+    collect(vd, root, "", &mut old)?;
+    let mut new = BTreeMap::new();
+    let root = match other {
+        Some(other) => vd.snapshots().get(other)?.root,
+        None => 0,
+    };
+    collect(vd, root, "", &mut new)?;
+
+    let mut changes = Vec::new();
+    for (path, fcb) in &old {
+        match new.get(path) {
+            None => changes.push(('-', path.clone())),
+            Some(now) if changed(fcb, now) => changes.push(('M', path.clone())),
+            Some(_) => {}
+        }
+    }
+    for path in new.keys().filter(|path| !old.contains_key(*path)) {
+        changes.push(('+', path.clone()));
+    }
+    changes.sort_by(|(_, a), (_, b)| a.cmp(b));
+    Ok(changes)
+}
+
+// 共享同一块链的文件内容相同；目录在每个快照中都是副本，只比较类型
+fn changed(old: &Fcb, new: &Fcb) -> bool {
+    match (old.file_type(), new.file_type()) {
+        (FileType::File, FileType::File) => old.first_cluster() != new.first_cluster() || old.length() != new.length(),
+        (FileType::Directory, FileType::Directory) => false,
+        _ => true,
+    }
+}
+
+fn collect(vd: &DiskOperator, cluster: usize, prefix: &str, entries: &mut BTreeMap<String, Fcb>) -> FsResult<()> {
+    for fcb in vd.get_directory_by_cluster(cluster)?.entries() {
+        let path = format!("{}/{}", prefix, fcb.name());
+        if fcb.file_type() == FileType::Directory {
+            collect(vd, fcb.first_cluster(), &path, entries)?;
+        }
+        entries.insert(path, fcb.clone());
+    }
+    Ok(())
+}
+
+// 复制目录树需要的块数不超过空闲块数，避免复制到一半时空间不足
+fn reserve(vd: &DiskOperator, root: usize) -> FsResult<()> {
+    let (_, _, unused) = vd.get_disk_info();
+    if directory_clusters(vd, root)? > unused {
+        return Err(FsError::NoSpace);
+    }
+    Ok(())
+}
+
+fn directory_clusters(vd: &DiskOperator, cluster: usize) -> FsResult<usize> {
+    let mut count = vd.get_series(cluster)?.len();
+    for fcb in vd.get_directory_by_cluster(cluster)?.entries() {
+        if fcb.file_type() == FileType::Directory {
+            count += directory_clusters(vd, fcb.first_cluster())?;
+        }
+    }
+    Ok(count)
+}
+
+// 复制首块号为cluster的目录，子目录逐个复制，文件共享块链；返回副本的首块号
+fn copy_tree(vd: &mut DiskOperator, session: &Session, cluster: usize, parent: Option<usize>, name: &str) -> FsResult<usize> {
+    let source = vd.get_directory_by_cluster(cluster)?;
+    let copy_cluster = vd.allocate_block(session, 1)?[0];
+    // 快照的根目录的..指向自身
+    let mut copy = Directory::empty(name, copy_cluster, parent.unwrap_or(copy_cluster));
+    copy.set_compression(source.compression());
+    copy_entries(vd, session, &source, &mut copy)?;
+    vd.save_dir_to_disk(session, &copy)?;
+    Ok(copy_cluster)
+}
+
+fn copy_entries(vd: &mut DiskOperator, session: &Session, source: &Directory, copy: &mut Directory) -> FsResult<()> {
+    for fcb in source.entries() {
+        let first_cluster = match fcb.file_type() {
+            FileType::Directory => copy_tree(vd, session, fcb.first_cluster(), Some(copy.cluster()), fcb.name())?,
+            FileType::File => {
+                for cluster in vd.get_series(fcb.first_cluster())? {
+                    vd.snapshots().share(cluster);
+                }
+                fcb.first_cluster()
+            }
+        };
+        copy.insert(fcb.with_cluster(first_cluster));
+    }
+    Ok(())
+}
+
+// 释放目录树占用的块，被共享的块只减少引用计数
+fn remove_tree(vd: &mut DiskOperator, fcb: &Fcb) -> FsResult<()> {
+    if fcb.file_type() == FileType::Directory {
+        for entry in vd.get_directory_by_cluster(fcb.first_cluster())?.entries() {
+            remove_tree(vd, entry)?;
+        }
+    }
+    vd.delete_series(fcb.first_cluster())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsck;
+
+    #[test]
+    fn snapshots_keep_old_contents_after_writes() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        vd.new_directory(&session, "d").unwrap();
+        vd.write_file_by_path(&session, "/d/a", &vec![b'a'; 5000]).unwrap();
+        vd.write_file_by_path(&session, "/b", b"bbb").unwrap();
+        let used = vd.get_disk_info().1;
+
+        // 快照只复制目录，文件的块被共享
+        create(&mut vd, &session, "first").unwrap();
+        assert_eq!(vd.get_disk_info().1, used + 2);
+        assert_eq!(vd.snapshots().shared_count(), 3);
+        assert!(create(&mut vd, &session, "first").is_err());
+
+        vd.write_at(&session, "/d/a", 0, b"x").unwrap();
+        vd.truncate(&session, "/b", 1).unwrap();
+        vd.write_file_by_path(&session, "/c", b"new").unwrap();
+        vd.delete_file_by_path(&session, "/b").unwrap();
+        assert_eq!(vd.snapshots().shared_count(), 0);
+        assert_eq!(lookup(&vd, "first", "/d/a").unwrap().length(), 5000);
+        assert_eq!(vd.get_file_by_fcb(&lookup(&vd, "first", "d/../b").unwrap()).unwrap(), b"bbb");
+        assert_eq!(vd.read_at(&lookup(&vd, "first", "/d/a").unwrap(), 0, 2).unwrap(), b"aa");
+        assert!(fsck::check(&vd).is_empty(), "{:?}", fsck::check(&vd));
+
+        let changes = diff(&vd, "first", None).unwrap();
+        assert_eq!(changes, [('-', "/b".to_string()), ('+', "/c".to_string()), ('M', "/d/a".to_string())]);
+        create(&mut vd, &session, "second").unwrap();
+        assert!(diff(&vd, "second", None).unwrap().is_empty());
+        assert_eq!(files(&vd, "second").unwrap().len(), 3);
+
+        // 回滚后内容与快照相同，删除全部快照后块全部释放
+        restore(&mut vd, &mut session, "first").unwrap();
+        assert!(diff(&vd, "first", None).unwrap().is_empty());
+        assert_eq!(vd.read_file_by_path(&session, "/b").unwrap(), b"bbb");
+        assert!(fsck::check(&vd).is_empty(), "{:?}", fsck::check(&vd));
+        delete(&mut vd, "first").unwrap();
+        delete(&mut vd, "second").unwrap();
+        assert_eq!((vd.get_disk_info().1, vd.snapshots().shared_count()), (used, 0));
+        assert!(fsck::check(&vd).is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::disk::{FATItem, BLOCK_SIZE, HOLE_END};
+use crate::error::{FsError, FsResult};
+
+use std::ops::Range;
+
+// 文件的最大长度，空洞也需要在块布局中逐块记录
+pub const MAX_FILE_SIZE: usize = 1 << 32;
+
+// 稀疏文件：文件中没有分配块的部分是空洞，读取时为0
+// 空洞记录在其前一块的FAT表项中（FATItem::Hole），文件的第一块总是已分配的，首块号始终有效；
+// 文件的块布局按文件中的顺序列出每块的块号，空洞为None
+
+// 沿FAT读取从start开始的块布局，count为数据区的块数，越界或成环说明FAT已损坏
+pub fn layout(fat: &[FATItem], start: usize, count: usize) -> FsResult<Vec<Option<usize>>> {
+    let mut layout = Vec::new();
+    let mut allocated = 0;
+    let mut cluster = start;
+    loop {
+        if cluster >= count || allocated >= count {
+            return Err(FsError::Corrupted(format!("Invalid cluster chain from {}", start)));
+        }
+        layout.push(Some(cluster));
+        allocated += 1;
+        match fat[cluster] {
+            FATItem::Cluster(next) => cluster = next,
+            FATItem::EOF => return Ok(layout),
+            FATItem::Hole(holes, next) => {
+                if layout.len() + holes as usize > MAX_FILE_SIZE / BLOCK_SIZE {
+                    return Err(FsError::Corrupted(format!("Hole too large at cluster {}", cluster)));
+                }
+                layout.resize(layout.len() + holes as usize, None);
+                if next == HOLE_END {
+                    return Ok(layout);
+                }
+                cluster = next as usize;
+            }
+            _ => return Err(FsError::Corrupted(format!("Unexpected FATItem: {}", cluster))),
+        }
+    }
+}
+
+// 按块布局重写其中各块的FAT表项，第一块必须已分配
+pub fn link(fat: &mut [FATItem], layout: &[Option<usize>]) {
+    let mut previous = None;
+    let mut holes = 0;
+    for &block in layout {
+        let Some(cluster) = block else {
+            holes += 1;
+            continue;
+        };
+        if let Some(previous) = previous {
+            fat[previous] = entry(holes, Some(cluster));
+        }
+        previous = Some(cluster);
+        holes = 0;
+    }
+    if let Some(previous) = previous {
+        fat[previous] = entry(holes, None);
+    }
+}
+
+fn entry(holes: u32, next: Option<usize>) -> FATItem {
+    match (holes, next) {
+        (0, Some(next)) => FATItem::Cluster(next),
+        (0, None) => FATItem::EOF,
+        (holes, next) => FATItem::Hole(holes, next.map_or(HOLE_END, |next| next as u32)),
+    }
+}
+
+// 布局中已分配的块
+pub fn clusters(layout: &[Option<usize>]) -> Vec<usize> {
+    layout.iter().flatten().copied().collect()
+}
+
+// 文件中[from, to)范围涉及的各块：块在布局中的下标与块内的范围
+pub fn block_ranges(from: usize, to: usize) -> impl Iterator<Item = (usize, Range<usize>)> {
+    (from / BLOCK_SIZE..to.div_ceil(BLOCK_SIZE))
+        .map(move |index| {
+            let start = index * BLOCK_SIZE;
+            (index, from.max(start) - start..to.min(start + BLOCK_SIZE) - start)
+        })
+        .filter(|(_, range)| !range.is_empty())
+}
+
+// 写入[from, to)范围需要分配的空洞在布局中的下标
+pub fn holes(layout: &[Option<usize>], from: usize, to: usize) -> Vec<usize> {
+    block_ranges(from, to).map(|(index, _)| index).filter(|&index| layout[index].is_none()).collect()
+}
+
+pub fn check_length(length: usize) -> FsResult<()> {
+    if length > MAX_FILE_SIZE {
+        return Err(FsError::InvalidArgument(format!("file size {} exceeds the maximum {}", length, MAX_FILE_SIZE)));
+    }
+    Ok(())
+}
+
+// 在offset处写入len字节后的结束位置，溢出或超过文件的最大长度时报错
+pub fn check_range(offset: usize, len: usize) -> FsResult<usize> {
+    let end = offset
+        .checked_add(len)
+        .ok_or(FsError::InvalidArgument(format!("offset {} plus length {} overflows", offset, len)))?;
+    check_length(end)?;
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_round_trip_through_the_fat() {
+        let mut fat = vec![FATItem::UnUsed; 8];
+        let layout = [Some(2), None, None, Some(5), Some(1), None];
+        link(&mut fat, &layout);
+        assert!(matches!(fat[2], FATItem::Hole(2, 5)));
+        assert!(matches!(fat[5], FATItem::Cluster(1)));
+        assert!(matches!(fat[1], FATItem::Hole(1, HOLE_END)));
+        assert_eq!(super::layout(&fat, 2, 8).unwrap(), layout);
+        assert_eq!(clusters(&layout), [2, 5, 1]);
+
+        link(&mut fat, &[Some(2), Some(5)]);
+        assert_eq!(super::layout(&fat, 2, 8).unwrap(), [Some(2), Some(5)]);
+        fat[5] = FATItem::Hole(3, 2);
+        assert!(super::layout(&fat, 2, 8).is_err());
+
+        let ranges: Vec<_> = block_ranges(BLOCK_SIZE - 1, 2 * BLOCK_SIZE + 1).collect();
+        assert_eq!(ranges, [(0, BLOCK_SIZE - 1..BLOCK_SIZE), (1, 0..BLOCK_SIZE), (2, 0..1)]);
+        assert_eq!(block_ranges(BLOCK_SIZE, BLOCK_SIZE).count(), 0);
+        assert_eq!(holes(&layout, 0, 3 * BLOCK_SIZE), [1, 2]);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::disk_operator::DiskOperator;
+use crate::error::{FsError, FsResult};
+use crate::session::Session;
+
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+Commands (N and M are line numbers, $ is the last line):
+    p [N[,M]]   Print lines with numbers, all lines by default.
+    a [N]       Append lines after line N (end of file by default), end with a single '.'.
+    i [N]       Insert lines before line N, end with a single '.'.
+    c N[,M]     Replace lines N to M with new lines, end with a single '.'.
+    d N[,M]     Delete lines N to M.
+    w           Write the buffer to the file.
+    q           Quit, q! discards unsaved changes.
+    wq          Write and quit.
+    h           Show this help.";
+
+// 行编辑器，以行为单位修改缓冲区，写回时只改写与原文件不同的部分
+pub struct LineEditor<'a> {
+    vd: &'a mut DiskOperator,
+    session: &'a Session,
+    path: String,
+    lines: Vec<String>,
+    saved: Vec<u8>, // 文件当前在磁盘上的内容
+    exists: bool,
+    modified: bool,
+}
+
+impl<'a> LineEditor<'a> {
+    // 读取文件，文件不存在时从空缓冲区开始，写回时创建
+    pub fn open(vd: &'a mut DiskOperator, session: &'a Session, path: &str) -> FsResult<LineEditor<'a>> {
+        let (saved, exists) = match vd.read_file_by_path(session, path) {
+            Ok(data) => (data, true),
+            Err(FsError::NotFound(_)) => (Vec::new(), false),
+            Err(err) => return Err(err),
+        };
+        let text = String::from_utf8(saved.clone())
+            .map_err(|_| FsError::InvalidArgument(format!("{}: Not a UTF-8 text file!", path)))?;
+        Ok(LineEditor {
+            vd,
+            session,
+            path: path.to_string(),
+            lines: text.lines().map(String::from).collect(),
+            saved,
+            exists,
+            modified: false,
+        })
+    }
+
+    // 逐行读取编辑命令直到退出或输入结束
+    pub fn run(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> FsResult<()> {
+        let _ = writeln!(output, "{}: {} lines. Type h for help.", self.path, self.lines.len());
+        loop {
+            let _ = write!(output, "* ");
+            let _ = output.flush();
+            let Some(line) = read_line(input) else {
+                return Ok(());
+            };
+            match self.execute(line.trim(), input, output) {
+                Ok(true) => continue,
+                Ok(false) => return Ok(()),
+                Err(err) => {
+                    let _ = writeln!(output, "? {}", err);
+                }
+            }
+        }
+    }
+
+    // 执行一条编辑命令，返回是否继续
+    fn execute(&mut self, command: &str, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<bool, String> {
+        let split = command.find(|c: char| !c.is_ascii_alphabetic() && c != '!').unwrap_or(command.len());
+        let (name, range) = (&command[..split], command[split..].trim());
+        match name {
+            "" if range.is_empty() => {}
+            "p" => {
+                let (start, end) = self.range(range, (1, self.lines.len()))?;
+                for number in start..=end {
+                    let _ = writeln!(output, "{:>4}  {}", number, self.lines[number - 1]);
+                }
+            }
+            "a" | "i" => {
+                let default = if name == "a" { self.lines.len() } else { 1 };
+                let number = if range.is_empty() { default } else { self.line_number(range, 0)? };
+                // a 0表示插入到开头
+                let index = match name {
+                    "a" => number,
+                    _ => number.saturating_sub(1),
+                };
+                let new_lines = read_block(input);
+                self.modified |= !new_lines.is_empty();
+                self.lines.splice(index..index, new_lines);
+            }
+            "c" => {
+                let (start, end) = self.range(range, (0, 0))?;
+                let new_lines = read_block(input);
+                self.lines.splice(start - 1..end, new_lines);
+                self.modified = true;
+            }
+            "d" => {
+                let (start, end) = self.range(range, (0, 0))?;
+                self.lines.drain(start - 1..end);
+                self.modified = true;
+            }
+            "w" | "wq" => {
+                let written = self.write().map_err(|err| err.to_string())?;
+                let _ = writeln!(output, "{} bytes written to {}.", written, self.path);
+                return Ok(name == "w");
+            }
+            "q" if self.modified => {
+                return Err("Unsaved changes, use w to write or q! to discard.".to_string());
+            }
+            "q" | "q!" => return Ok(false),
+            "h" => {
+                let _ = writeln!(output, "{}", HELP);
+            }
+            _ => return Err(format!("Unknown command: {}", command)),
+        }
+        Ok(true)
+    }
+
+    // 解析行号，$表示最后一行，允许的最小值为min
+    fn line_number(&self, text: &str, min: usize) -> Result<usize, String> {
+        let number = match text {
+            "$" => self.lines.len(),
+            _ => text.parse().map_err(|_| format!("Invalid line number: {}", text))?,
+        };
+        if number < min || number > self.lines.len() {
+            return Err(format!("Line {} out of range.", text));
+        }
+        Ok(number)
+    }
+
+    // 解析N或N,M形式的行范围，为空时使用default；(0, 0)表示必须给出范围
+    fn range(&self, text: &str, default: (usize, usize)) -> Result<(usize, usize), String> {
+        if text.is_empty() {
+            if default.0 == 0 {
+                return Err("A line number is required.".to_string());
+            }
+            return Ok(default);
+        }
+        let (start, end) = match text.split_once(',') {
+            Some((start, end)) => (self.line_number(start.trim(), 1)?, self.line_number(end.trim(), 1)?),
+            None => {
+                let number = self.line_number(text, 1)?;
+                (number, number)
+            }
+        };
+        if start > end {
+            return Err(format!("Invalid range: {}", text));
+        }
+        Ok((start, end))
+    }
+
+    // 写回文件：从第一个不同的字节开始改写，再截断到新的长度
+    fn write(&mut self) -> FsResult<usize> {
+        let mut data = self.lines.join("\n").into_bytes();
+        if !self.lines.is_empty() {
+            data.push(b'\n');
+        }
+
+        if self.exists {
+            let same = self.saved.iter().zip(data.iter()).take_while(|(a, b)| a == b).count();
+            self.vd.write_at(self.session, &self.path, same, &data[same..])?;
+            self.vd.truncate(self.session, &self.path, data.len())?;
+        } else {
+            self.vd.write_file_by_path(self.session, &self.path, &data)?;
+            self.exists = true;
+        }
+        self.saved = data;
+        self.modified = false;
+        Ok(self.saved.len())
+    }
+}
+
+fn read_line(input: &mut dyn BufRead) -> Option<String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+    }
+}
+
+// 读取多行文本，直到单独一行的.或输入结束
+fn read_block(input: &mut dyn BufRead) -> Vec<String> {
+    let mut lines = Vec::new();
+    while let Some(line) = read_line(input) {
+        if line == "." {
+            break;
+        }
+        lines.push(line);
+    }
+    lines
+}
@@ -1,55 +1,76 @@
 use crate::disk_operator;
+use crate::archive;
+use crate::parser;
+use crate::glob;
+use crate::search;
+use crate::snapshot;
+use crate::resize;
+use crate::quota::{self, Limit, Usage};
+use crate::listing;
+use crate::inspect;
+use crate::fat_image::{self, FatType};
+use crate::text_editor::LineEditor;
+use crate::error::{FsError, FsResult};
+use crate::crypto::Encryption;
+use crate::editor::ShellHelper;
+use crate::handle::FileHandle;
+use crate::session::Session;
+use crate::lock::{self, Lock, LockKind, LockOwner};
+use crate::compression::Compression;
 use disk_operator::*;
 use crate::disk::BLOCK_SIZE;
 
-use std::{fs, io::{stdin, stdout, Write}};
-use lazy_static::lazy_static;
-use std::sync::Mutex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::env;
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::rc::Rc;
+use std::time::Duration;
 use ansi_rgb::Foreground;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
 
 
-// pub static mut VIRTUAL_DISK_NAME: &str = "./file-system.vd";
-lazy_static! {
-    pub static ref VIRTUAL_DISK_NAME: Mutex<&'static str> = Mutex::new("./file-system.vd");
-}
+// 读取口令的环境变量与口令错误时的最多尝试次数
+const PASSPHRASE_VAR: &str = "FILE_SYSTEM_PASSPHRASE";
+const NEW_PASSPHRASE_VAR: &str = "FILE_SYSTEM_NEW_PASSPHRASE";
+const PASSPHRASE_ATTEMPTS: usize = 3;
 
 pub fn load_ui() -> DiskOperator {
-    let mut buffer = String::new();
     loop {
         print_info();
         print!("Load specified file-system.vd? [y/n]: ");
-        stdout().flush().unwrap();
-        stdin().read_line(&mut buffer).unwrap();
-        let input = buffer.as_str().trim().chars().next().unwrap();
+        // 输入已结束，无法继续询问
+        let Some(input) = read_input() else {
+            println!();
+            std::process::exit(1);
+        };
 
-        match input {
-            'Y' | 'y' => {
+        match input.chars().next() {
+            Some('Y' | 'y') => {
                 print_info();
                 print!("Input the name of your virtual disk: ");
-                stdout().flush().unwrap();
-                let mut filename = String::new();
-                stdin().read_line(&mut filename).unwrap();
-                let mut disk_name = VIRTUAL_DISK_NAME.lock().unwrap();
-                *disk_name = Box::leak(filename.trim().to_string().into_boxed_str());
-                let data = fs::read(*disk_name).unwrap();
+                let filename = read_input().unwrap_or_default();
                 print_info();
-                println!("Loading {}...", *disk_name);
-                break bincode::deserialize(data.as_slice()).unwrap();
+                println!("Loading {}...", filename);
+                match load_disk(&filename) {
+                    Ok(vd) => break vd,
+                    Err(err) => {
+                        println!("Load failed. {}", err);
+                        continue;
+                    }
+                }
             },
-            'N' | 'n' => {
+            Some('N' | 'n') => {
                 print_info();
                 print!("Input the name of new virtual disk: ");
-                stdout().flush().unwrap();
-                let mut filename = String::new();
-                stdin().read_line(&mut filename).unwrap();
-                // unsafe {
-                    // VIRTUAL_DISK_NAME = Box::leak(buffer.trim().to_string().into_boxed_str());
-                // }
-                let mut disk_name = VIRTUAL_DISK_NAME.lock().unwrap();
-                *disk_name = Box::leak(filename.trim().to_string().into_boxed_str());
+                let filename = read_input().unwrap_or_default();
                 print_debug();
-                println!("Creating new {}...", *disk_name);
-                break DiskOperator::new(None);
+                println!("Creating new {}...", filename);
+                let mut vd = DiskOperator::new(None);
+                vd.image = Some(filename);
+                break vd;
             },
             _ => {
                 println!("Invalid input, please try again.");
@@ -59,133 +80,1696 @@ pub fn load_ui() -> DiskOperator {
     }
 }
 
+// 读取镜像，加密的镜像提示输入口令，口令错误时可以重试
+pub fn load_disk(name: &str) -> FsResult<DiskOperator> {
+    match DiskOperator::load(name) {
+        Err(FsError::Encrypted(_)) => {}
+        result => return result,
+    }
+    let mut attempts = 1;
+    loop {
+        let passphrase = read_passphrase(&format!("Passphrase for {}: ", name))?;
+        match DiskOperator::load_encrypted(name, &passphrase) {
+            // 口令来自环境变量时重试没有意义
+            Err(FsError::WrongPassphrase) if attempts < PASSPHRASE_ATTEMPTS && env::var_os(PASSPHRASE_VAR).is_none() => {
+                println!("Wrong passphrase, please try again.");
+                attempts += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// 读取口令，设置了环境变量FILE_SYSTEM_PASSPHRASE时使用其值，供脚本使用
+pub fn read_passphrase(prompt: &str) -> FsResult<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    prompt_password(prompt)
+}
+
+// 从终端读取口令时不回显；输入不是终端时从中读取一行
+fn prompt_password(prompt: &str) -> FsResult<String> {
+    if stdin().is_terminal() {
+        return rpassword::prompt_password(prompt).map_err(|err| FsError::Io(err.to_string()));
+    }
+    print!("{}", prompt);
+    read_input().ok_or(FsError::Io("no passphrase given".to_string()))
+}
+
+// 读取新口令，需要输入两次；设置了环境变量FILE_SYSTEM_NEW_PASSPHRASE时使用其值
+pub fn read_new_passphrase() -> FsResult<String> {
+    if let Ok(passphrase) = env::var(NEW_PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    let passphrase = prompt_password("New passphrase: ")?;
+    let repeated = prompt_password("Repeat passphrase: ")?;
+    if passphrase != repeated {
+        return Err(FsError::InvalidArgument("passphrases do not match".to_string()));
+    }
+    Ok(passphrase)
+}
+
+// 调整磁盘的总块数，返回调整后的大小；下次保存时生效
+pub fn resize_disk(vd: &mut DiskOperator, session: &mut Session, block_count: &str) -> FsResult<String> {
+    let block_count = block_count
+        .parse()
+        .map_err(|_| FsError::InvalidArgument(format!("invalid block count {}", block_count)))?;
+    resize::resize(vd, session, block_count)?;
+    Ok(format!(
+        "Disk resized to {} blocks, {} bytes of data.",
+        block_count,
+        vd.disk.cluster_count() * BLOCK_SIZE
+    ))
+}
+
+// 加密镜像、修改口令或去掉加密，已加密时先验证当前口令；下次保存时生效
+pub fn change_passphrase(vd: &mut DiskOperator, remove: bool) -> FsResult<&'static str> {
+    match &vd.encryption {
+        Some(encryption) if !encryption.verify(&read_passphrase("Current passphrase: ")?) => {
+            return Err(FsError::WrongPassphrase)
+        }
+        None if remove => return Err(FsError::InvalidArgument("the image is not encrypted".to_string())),
+        _ => {}
+    }
+    if remove {
+        vd.encryption = None;
+        return Ok("Encryption removed.");
+    }
+    let passphrase = read_new_passphrase()?;
+    match &mut vd.encryption {
+        Some(encryption) => encryption.change_passphrase(&passphrase)?,
+        None => vd.encryption = Some(Encryption::new(&passphrase)?),
+    }
+    Ok("Passphrase changed.")
+}
+
+// 读取一行输入，输入结束或读取失败时返回None
+fn read_input() -> Option<String> {
+    let _ = stdout().flush();
+    let mut buffer = String::new();
+    match stdin().read_line(&mut buffer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(buffer.trim().to_string()),
+    }
+}
+
 const UI_INIT: &str = "\
 \n**************************************************\
 \n         Simple File System in Rust\
 \n**************************************************\
-\nCommands:\
-\n\tcd <dirname>: Change current dir.\
-\n\tmkdir <dirname>: Create a new dir.\
-\n\tls : List all files and dir in current dir.\
-\n\ttouch <filename> <data>: Create a new file.\
-\n\tcat <filename>: Show the file content.\
-\n\trm <filename>: Delete a file on disk.\
-\n\tcp <src> <dst>: Copy a file.\
-\n\tmv <src> <dst>: Move a file.\
-\n\tdiskinfo : Show some info about disk.\
-\n\tsave : Save this virtual disk to file.\
-\n\texit : Exit the system. 
-\n"; // UI主菜单
-
-pub fn interact_with_user(vd: &mut DiskOperator) {
-    println!("{}", UI_INIT);
+\nCommands:"; // UI主菜单
+
+// 命令的输入与输出：管道中前一条命令的输出作为下一条命令的输入
+#[derive(Default)]
+pub struct Io {
+    pub input: Option<Vec<u8>>,
+    pub output: Vec<u8>,
+}
+
+impl Io {
+    pub fn write(&mut self, data: &[u8]) {
+        self.output.extend_from_slice(data);
+    }
+
+    pub fn println(&mut self, text: impl std::fmt::Display) {
+        self.output.extend_from_slice(format!("{}\n", text).as_bytes());
+    }
+}
+
+type Handler = fn(&mut DiskOperator, &mut Session, &[String], &mut Io) -> Result<bool, String>;
+
+// 命令表项，参数个数不在[min_args, max_args]内时输出用法
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+    min_args: usize,
+    max_args: usize,
+    handler: Handler,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "cd",
+        usage: "cd <path>",
+        description: "Change current dir.",
+        min_args: 1,
+        max_args: 1,
+        handler: cmd_cd,
+    },
+    Command {
+        name: "mkdir",
+        usage: "mkdir [-z lz4|zstd|deflate|none] <dirname>",
+        description: "Create a new dir, -z sets the default compression of files created in it.",
+        min_args: 1,
+        max_args: 3,
+        handler: cmd_mkdir,
+    },
+    Command {
+        name: "ls",
+        usage: "ls [-l] [-a] [-R] [-h] [-t|-S|-U] [-r] [--json] [path...]",
+        description: "List all files and dir in current dir or the given paths.",
+        min_args: 0,
+        max_args: usize::MAX,
+        handler: cmd_ls,
+    },
+    Command {
+        name: "tree",
+        usage: "tree [-a] [-L depth] [--json] [path]",
+        description: "Show the dir hierarchy as a tree.",
+        min_args: 0,
+        max_args: usize::MAX,
+        handler: cmd_tree,
+    },
+    Command {
+        name: "stat",
+        usage: "stat <path...>",
+        description: "Show the size and the allocated blocks of files or dirs.",
+        min_args: 1,
+        max_args: usize::MAX,
+        handler: cmd_stat,
+    },
+    Command {
+        name: "touch",
+        usage: "touch [-z lz4|zstd|deflate|none] <filename> [data]",
+        description: "Create a new file, compressed with the dir's default or the -z algorithm.",
+        min_args: 1,
+        max_args: usize::MAX,
+        handler: cmd_touch,
+    },
+    Command {
+        name: "cat",
+        usage: "cat [path...]",
+        description: "Show the file content, or the piped input without paths.",
+        min_args: 0,
+        max_args: usize::MAX,
+        handler: cmd_cat,
+    },
+    Command {
+        name: "echo",
+        usage: "echo [text...]",
+        description: "Write the text.",
+        min_args: 0,
+        max_args: usize::MAX,
+        handler: cmd_echo,
+    },
+    Command {
+        name: "grep",
+        usage: "grep [-r] [-i] [-n] <pattern> [path...]",
+        description: "Show the lines containing pattern in files or the piped input.",
+        min_args: 1,
+        max_args: usize::MAX,
+        handler: cmd_grep,
+    },
+    Command {
+        name: "find",
+        usage: "find <path> [-name pattern] [-type f|d] [-size [+|-]N[k|M|G]] [-newer path]",
+        description: "List the files under path matching all conditions.",
+        min_args: 1,
+        max_args: usize::MAX,
+        handler: cmd_find,
+    },
+    Command {
+        name: "head",
+        usage: "head [-n lines | -c bytes] [path...]",
+        description: "Show the first lines (10 by default) of files or the piped input.",
+        min_args: 0,
+        max_args: usize::MAX,
+        handler: cmd_head,
+    },
+    Command {
+        name: "tail",
+        usage: "tail [-n lines | -c bytes] [path...]",
+        description: "Show the last lines (10 by default) of files or the piped input.",
+        min_args: 0,
+        max_args: usize::MAX,
+        handler: cmd_tail,
+    },
+    Command {
+        name: "truncate",
+        usage: "truncate -s [+|-]size[k|M|G] <path...>",
+        description: "Shrink or extend files to the size.",
+        min_args: 3,
+        max_args: usize::MAX,
+        handler: cmd_truncate,
+    },
+    Command {
+        name: "fallocate",
+        usage: "fallocate --punch-hole [-o offset] -l length <path...>",
+        description: "Free the blocks in the range, keeping the file size; the range reads as zeros.",
+        min_args: 3,
+        max_args: usize::MAX,
+        handler: cmd_fallocate,
+    },
+    Command {
+        name: "edit",
+        usage: "edit <path>",
+        description: "Edit a text file line by line, creating it when missing.",
+        min_args: 1,
+        max_args: 1,
+        handler: cmd_edit,
+    },
+    Command {
+        name: "hexdump",
+        usage: "hexdump [-C] [path...]",
+        description: "Show files or the piped input in hex, -C also shows ASCII.",
+        min_args: 0,
+        max_args: usize::MAX,
+        handler: cmd_hexdump,
+    },
+    Command {
+        name: "dumpcluster",
+        usage: "dumpcluster <cluster>",
+        description: "Show the raw data and FAT entry of a cluster.",
+        min_args: 1,
+        max_args: 1,
+        handler: cmd_dumpcluster,
+    },
+    Command {
+        name: "fatchain",
+        usage: "fatchain <path...>",
+        description: "Show the cluster chain of files in the FAT.",
+        min_args: 1,
+        max_args: usize::MAX,
+        handler: cmd_fatchain,
+    },
+    Command {
+        name: "rm",
+        usage: "rm <path...>",
+        description: "Delete files or empty dirs on disk.",
+        min_args: 1,
+        max_args: usize::MAX,
+        handler: cmd_rm,
+    },
+    Command {
+        name: "cp",
+        usage: "cp <src...> <dst>",
+        description: "Copy files.",
+        min_args: 2,
+        max_args: usize::MAX,
+        handler: cmd_cp,
+    },
+    Command {
+        name: "mv",
+        usage: "mv <src...> <dst>",
+        description: "Move or rename files.",
+        min_args: 2,
+        max_args: usize::MAX,
+        handler: cmd_mv,
+    },
+    Command {
+        name: "export",
+        usage: "export <dirname> <tarfile>",
+        description: "Export a dir to a tar archive.",
+        min_args: 2,
+        max_args: 2,
+        handler: cmd_export,
+    },
+    Command {
+        name: "import",
+        usage: "import <tarfile> <dirname>",
+        description: "Import a tar archive into a dir.",
+        min_args: 2,
+        max_args: 2,
+        handler: cmd_import,
+    },
+    Command {
+        name: "exportfat",
+        usage: "exportfat [-F 12|16] <dirname> <image>",
+        description: "Export a dir to a FAT12 (default) or FAT16 image.",
+        min_args: 2,
+        max_args: 4,
+        handler: cmd_exportfat,
+    },
+    Command {
+        name: "importfat",
+        usage: "importfat <image> <dirname>",
+        description: "Import a FAT12/16 image into a dir.",
+        min_args: 2,
+        max_args: 2,
+        handler: cmd_importfat,
+    },
+    Command {
+        name: "diskinfo",
+        usage: "diskinfo",
+        description: "Show some info about disk.",
+        min_args: 0,
+        max_args: 0,
+        handler: cmd_diskinfo,
+    },
+    Command {
+        name: "resize",
+        usage: "resize <new_block_count>",
+        description: "Grow or shrink the disk, moving clusters past the new end forward.",
+        min_args: 1,
+        max_args: 1,
+        handler: cmd_resize,
+    },
+    Command {
+        name: "whoami",
+        usage: "whoami",
+        description: "Show the user of this session.",
+        min_args: 0,
+        max_args: 0,
+        handler: cmd_whoami,
+    },
+    Command {
+        name: "quota",
+        usage: "quota [-u user | -d path]",
+        description: "Show block and file usage against the user and directory quotas.",
+        min_args: 0,
+        max_args: 2,
+        handler: cmd_quota,
+    },
+    Command {
+        name: "setquota",
+        usage: "setquota (-u user | -d path) <blocks> <files>",
+        description: "Limit the blocks and files of a user or directory subtree, 0 for unlimited.",
+        min_args: 4,
+        max_args: 4,
+        handler: cmd_setquota,
+    },
+    Command {
+        name: "open",
+        usage: "open [path]",
+        description: "Open a file in this session and print its handle, or list open handles.",
+        min_args: 0,
+        max_args: 1,
+        handler: cmd_open,
+    },
+    Command {
+        name: "close",
+        usage: "close <handle>",
+        description: "Close an open handle.",
+        min_args: 1,
+        max_args: 1,
+        handler: cmd_close,
+    },
+    Command {
+        name: "lock",
+        usage: "lock [-s|-x] [-m] [-w seconds] <handle> [start length]",
+        description: "Lock an open file (whole file, or a byte range), or list all locks.",
+        min_args: 0,
+        max_args: 7,
+        handler: cmd_lock,
+    },
+    Command {
+        name: "unlock",
+        usage: "unlock <handle> [start length]",
+        description: "Release a whole-file lock or a byte range lock of an open file.",
+        min_args: 1,
+        max_args: 3,
+        handler: cmd_unlock,
+    },
+    Command {
+        name: "snapshot",
+        usage: "snapshot <create|list|show|diff|restore|delete> [name] [path|name]",
+        description: "Manage copy-on-write snapshots of the whole file system.",
+        min_args: 1,
+        max_args: 3,
+        handler: cmd_snapshot,
+    },
+    Command {
+        name: "dedup",
+        usage: "dedup [on|off]",
+        description: "Show or set whether files written later share identical clusters.",
+        min_args: 0,
+        max_args: 1,
+        handler: cmd_dedup,
+    },
+    Command {
+        name: "passphrase",
+        usage: "passphrase [--remove]",
+        description: "Encrypt this disk or change its passphrase, --remove decrypts it; takes effect on save.",
+        min_args: 0,
+        max_args: 1,
+        handler: cmd_passphrase,
+    },
+    Command {
+        name: "save",
+        usage: "save",
+        description: "Save this virtual disk to file.",
+        min_args: 0,
+        max_args: 0,
+        handler: cmd_save,
+    },
+    Command {
+        name: "help",
+        usage: "help",
+        description: "Show this help.",
+        min_args: 0,
+        max_args: 0,
+        handler: cmd_help,
+    },
+    Command {
+        name: "exit",
+        usage: "exit",
+        description: "Exit the system.",
+        min_args: 0,
+        max_args: 0,
+        handler: cmd_exit,
+    },
+];
+
+fn help_text() -> String {
+    let mut text = format!("{}\n", UI_INIT);
+    for command in COMMANDS {
+        text.push_str(&format!("\t{}: {}\n", command.usage, command.description));
+    }
+    text
+}
+
+fn print_help() {
+    println!("{}", help_text());
+}
+
+pub fn interact_with_user(vd: DiskOperator) {
+    print_help();
+
+    // 每个虚拟磁盘有各自的历史记录文件
+    let history = format!("{}.history", vd.image.as_deref().unwrap_or_default());
+
+    // 补全时需要读取目录，与行编辑辅助共享磁盘与会话
+    let vd = Rc::new(RefCell::new(vd));
+    let session = Rc::new(RefCell::new(Session::default()));
+    let mut editor: Editor<ShellHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            println!("Failed to initialize the terminal: {}", err);
+            return;
+        }
+    };
+    editor.set_helper(Some(ShellHelper::new(vd.clone(), session.clone())));
+    let _ = editor.load_history(&history);
     
-    let mut input = String::new();
     loop {
-        input.clear();
         print!("{}", "[PATH] ".fg(ansi_rgb::yellow()));
-        println!("{}", vd.get_abs_path());
-        print!("{}", "$ ".fg(ansi_rgb::red()));
-
-        stdout().flush().unwrap();
-        stdin().read_line(&mut input).unwrap();
-        let args = String::from(input.trim());
-        
-        if args.starts_with("help") {
-            println!("{}", UI_INIT);
-        }
-        else if args.starts_with("exit") {
-            print_info();
-            println!("Exiting...");
+        println!("{}", vd.borrow().get_abs_path(&session.borrow()));
+
+        let input = match editor.readline("$ ") {
+            Ok(input) => input,
+            // Ctrl-C放弃当前输入
+            Err(ReadlineError::Interrupted) => continue,
+            // 读到输入结尾时退出
+            Err(_) => break,
+        };
+        if !input.trim().is_empty() {
+            let _ = editor.add_history_entry(input.as_str());
+            let _ = editor.save_history(&history);
+        }
+
+        match run_command(&mut vd.borrow_mut(), &mut session.borrow_mut(), input.trim()) {
+            Ok(true) => continue,
+            Ok(false) => break,
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+// 执行一行命令，返回是否继续运行
+// 命令之间以|连接，最后的输出写到终端或重定向到虚拟磁盘中的文件
+pub fn run_command(vd: &mut DiskOperator, session: &mut Session, line: &str) -> Result<bool, String> {
+    // 以#开头的行为注释
+    if line.starts_with('#') {
+        return Ok(true);
+    }
+    let pipeline = parser::parse_pipeline(parser::tokenize(line)?)?;
+    if pipeline.commands.is_empty() {
+        return Ok(true);
+    }
+
+    // 某条命令出错时仍将其输出交给下一条命令，最后报告第一个错误
+    let mut input = None;
+    let mut error = None;
+    let mut running = true;
+    for words in &pipeline.commands {
+        let mut io = Io { input: input.take(), output: Vec::new() };
+        match run_single(vd, session, words, &mut io) {
+            Ok(keep_running) => running = keep_running,
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        }
+        input = Some(io.output);
+        // exit之后的命令不再执行，但其输出仍然写出
+        if !running {
             break;
         }
-        else if args.starts_with("save") {
-            print_info();
-            println!("Saving {}...", VIRTUAL_DISK_NAME.lock().unwrap().clone());
-            let data = bincode::serialize(&vd).unwrap();
-            // unsafe {
-            //     fs::write(VIRTUAL_DISK_NAME, data.as_slice()).unwrap();
-            // }
-            fs::write(VIRTUAL_DISK_NAME.lock().unwrap().clone(), data.as_slice()).unwrap();
-            print_info();
-            println!("File saved.");
-        }
-        else if let Some(name) = args.strip_prefix("cd ") {
-            // print_info();
-            // println!("Changing dir to {}...", name);
-            vd.set_current_dir(name);
-        }
-        else if let Some(name) = args.strip_prefix("mkdir ") {
-            // print_info();
-            // println!("Creating dir {}...", name);
-            vd.new_directory(name).unwrap();
-        }
-        else if args.starts_with("ls") {
-            println!("{}", vd.cur_dir);
-        }
-        else if let Some(name) = args.strip_prefix("cat ") {
-            let data = vd.read_file_by_name(name.trim()).unwrap();
-            println!("{}", String::from_utf8(data).unwrap());
-        }
-        else if let Some(name) = args.strip_prefix("rm ") {
-            vd.delete_file_by_name(name.trim()).expect("Error: Delete Failed.");
-            // print_info();
-            // println!("File {} deleted.", name);
-        }
-        else if args.starts_with("diskinfo") {
-            let (disk_size, used_size, unused_size) = vd.get_disk_info();
-            println!("Disk Size: {} bytes", disk_size * BLOCK_SIZE);
-            println!("Used Size: {} bytes", used_size * BLOCK_SIZE);
-            println!("Unused Size: {} bytes", unused_size * BLOCK_SIZE);
-        }
-        else if let Some(name) = args.strip_prefix("cp ") {
-            let name: Vec<&str> = name.split(" ").collect();
-            if name.len() != 2 {
-                println!("Invalid command, please try again.");
-                continue;
+    }
+    let output = input.unwrap_or_default();
+
+    match &pipeline.redirect {
+        Some((target, false)) => vd.write_file_by_path(session, &target.text, &output)?,
+        Some((target, true)) => vd.append_file_by_path(session, &target.text, &output)?,
+        None => {
+            let mut out = stdout();
+            let _ = out.write_all(&escape_output(&output));
+            // 保证提示符从新的一行开始
+            if !output.is_empty() && !output.ends_with(b"\n") {
+                let _ = out.write_all(b"\n");
             }
-            vd.copy_file_by_name(name[0], name[1]);
+            let _ = out.flush();
         }
-        else if let Some(name) = args.strip_prefix("mv ") {
-            let name: Vec<&str> = name.split(" ").collect();
-            if name.len() != 2 {
-                println!("Invalid command, please try again.");
-                continue;
+    }
+
+    match error {
+        Some(err) if running => Err(err),
+        _ => Ok(running),
+    }
+}
+
+// 输出到终端前转义无效的UTF-8字节与控制字符，以\xNN的形式显示
+fn escape_output(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for chunk in data.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() && !matches!(c, '\n' | '\t' | '\r') {
+                escaped.extend_from_slice(format!("\\x{:02x}", c as u32).as_bytes());
+            } else {
+                let mut buffer = [0u8; 4];
+                escaped.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
             }
-            // 移动与重命名
-            if name[1].contains("/") {
-                vd.move_file_by_name(name[0], name[1]);
+        }
+        for byte in chunk.invalid() {
+            escaped.extend_from_slice(format!("\\x{:02x}", byte).as_bytes());
+        }
+    }
+    escaped
+}
+
+// 执行管道中的一条命令
+fn run_single(vd: &mut DiskOperator, session: &mut Session, words: &[parser::Word], io: &mut Io) -> Result<bool, String> {
+    let args = expand_words(vd, session, words)?;
+    let Some(name) = args.first() else {
+        return Ok(true);
+    };
+
+    let Some(command) = COMMANDS.iter().find(|command| command.name == name) else {
+        return Err(format!("Unknown command: {}. Type help to list commands.", name));
+    };
+    let args = &args[1..];
+    if args.len() < command.min_args || args.len() > command.max_args {
+        return Err(format!("Usage: {}", command.usage));
+    }
+    (command.handler)(vd, session, args, io)
+}
+
+// 展开参数中的通配符，没有匹配项时保留原样
+fn expand_words(vd: &DiskOperator, session: &Session, words: &[parser::Word]) -> Result<Vec<String>, String> {
+    let mut args = Vec::with_capacity(words.len());
+    for word in words {
+        match &word.pattern {
+            Some(pattern) => {
+                let paths = glob::expand(vd, session, pattern)?;
+                if paths.is_empty() {
+                    args.push(word.text.clone());
+                } else {
+                    args.extend(paths);
+                }
+            }
+            None => args.push(word.text.clone()),
+        }
+    }
+    Ok(args)
+}
+
+// 对每个参数执行操作，出错时继续处理其余参数，最后汇总错误
+fn for_each_arg<F>(args: &[String], mut op: F) -> Result<bool, String>
+where
+    F: FnMut(&str) -> Result<(), String>,
+{
+    let errors: Vec<String> = args.iter().filter_map(|arg| op(arg).err()).collect();
+    if errors.is_empty() {
+        Ok(true)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn cmd_help(_vd: &mut DiskOperator, _session: &mut Session, _args: &[String], io: &mut Io) -> Result<bool, String> {
+    io.println(help_text());
+    Ok(true)
+}
+
+fn cmd_exit(_vd: &mut DiskOperator, _session: &mut Session, _args: &[String], io: &mut Io) -> Result<bool, String> {
+    io.println("Exiting...");
+    Ok(false)
+}
+
+fn cmd_dedup(vd: &mut DiskOperator, _session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("on") => vd.options.dedup = true,
+        Some("off") => vd.options.dedup = false,
+        Some(_) => return Err("Usage: dedup [on|off]".to_string()),
+    }
+    io.println(format!("Dedup: {}", if vd.options.dedup { "on" } else { "off" }));
+    Ok(true)
+}
+
+fn cmd_passphrase(vd: &mut DiskOperator, _session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let remove = match args {
+        [] => false,
+        [flag] if flag == "--remove" => true,
+        _ => return Err("Usage: passphrase [--remove]".to_string()),
+    };
+    io.println(change_passphrase(vd, remove)?);
+    Ok(true)
+}
+
+fn cmd_save(vd: &mut DiskOperator, _session: &mut Session, _args: &[String], io: &mut Io) -> Result<bool, String> {
+    // 保存到读取时的镜像，新建的磁盘保存到创建时输入的文件名
+    let Some(image) = vd.image.clone() else {
+        return Err("No image file to save to.".to_string());
+    };
+    vd.save(&image)?;
+    io.println(format!("Saved {}.", image));
+    Ok(true)
+}
+
+fn cmd_cd(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    // print_info();
+    // println!("Changing dir to {}...", name);
+    vd.set_current_dir(session, &args[0])?;
+    Ok(true)
+}
+
+fn cmd_mkdir(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    // print_info();
+    // println!("Creating dir {}...", name);
+    let (compression, args) = parse_compression(args, "mkdir [-z lz4|zstd|deflate|none] <dirname>")?;
+    if args.len() != 1 {
+        return Err("Usage: mkdir [-z lz4|zstd|deflate|none] <dirname>".to_string());
+    }
+    vd.new_directory_with(session, session.cwd(), &args[0], compression)?;
+    Ok(true)
+}
+
+// 解析开头的-z选项，返回压缩算法与其余参数
+fn parse_compression<'a>(args: &'a [String], usage: &str) -> Result<(Option<Compression>, &'a [String]), String> {
+    match args {
+        [flag, name, rest @ ..] if flag == "-z" && !rest.is_empty() => Ok((Some(Compression::parse(name)?), rest)),
+        [flag, ..] if flag == "-z" => Err(format!("Usage: {}", usage)),
+        _ => Ok((None, args)),
+    }
+}
+
+fn cmd_ls(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let (options, paths) = listing::ListOptions::parse(args)?;
+    if options.depth.is_some() {
+        return Err("ls: unknown option -L".to_string());
+    }
+    io.write(listing::list(vd, session, &paths, &options)?.as_bytes());
+    Ok(true)
+}
+
+fn cmd_tree(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let (options, paths) = listing::ListOptions::parse(args)?;
+    let path = match paths.as_slice() {
+        [] => ".",
+        [path] => path.as_str(),
+        _ => return Err("Usage: tree [-a] [-L depth] [--json] [path]".to_string()),
+    };
+    io.write(listing::tree(vd, session, path, &options)?.as_bytes());
+    Ok(true)
+}
+
+fn cmd_cat(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    // 没有参数时输出管道输入
+    if args.is_empty() {
+        let input = io.input.take().ok_or("Usage: cat [path...]")?;
+        io.write(&input);
+        return Ok(true);
+    }
+    for_each_arg(args, |path| {
+        io.write(&vd.read_file_by_path(session, path)?);
+        Ok(())
+    })
+}
+
+fn cmd_hexdump(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let canonical = args.first().is_some_and(|arg| arg == "-C");
+    let paths = if canonical { &args[1..] } else { args };
+    if paths.is_empty() {
+        let input = io.input.take().ok_or("Usage: hexdump [-C] [path...]")?;
+        let mut dump = inspect::HexDump::new(canonical);
+        dump.write(&input, &mut io.output);
+        dump.finish(&mut io.output);
+        return Ok(true);
+    }
+    for_each_arg(paths, |path| {
+        let fcb = vd.get_fcb_by_path(session, path)?;
+        if paths.len() > 1 {
+            io.println(format!("{}:", path));
+        }
+        inspect::hexdump_file(vd, &fcb, canonical, &mut io.output)?;
+        Ok(())
+    })
+}
+
+fn cmd_dumpcluster(vd: &mut DiskOperator, _session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let cluster = args[0].parse().map_err(|_| format!("{}: Invalid cluster number!", args[0]))?;
+    inspect::dump_cluster(vd, cluster, &mut io.output)?;
+    Ok(true)
+}
+
+fn cmd_fatchain(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    for_each_arg(args, |path| {
+        let fcb = vd.get_fcb_by_path(session, path)?;
+        let (chain, holes, problem) = inspect::fat_chain(vd, fcb.first_cluster());
+        let clusters: Vec<String> = chain.iter().map(|cluster| cluster.to_string()).collect();
+        // 稀疏文件另外显示空洞的块数
+        let holes = match holes {
+            0 => String::new(),
+            1 => ", 1 hole".to_string(),
+            holes => format!(", {} holes", holes),
+        };
+        io.println(format!(
+            "{}: {} -> {} ({} cluster{}{}, {} bytes)",
+            path,
+            clusters.join(" -> "),
+            problem.as_deref().unwrap_or("EOF"),
+            chain.len(),
+            if chain.len() == 1 { "" } else { "s" },
+            holes,
+            fcb.length()
+        ));
+        Ok(())
+    })
+}
+
+fn cmd_echo(_vd: &mut DiskOperator, _session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    io.println(args.join(" "));
+    Ok(true)
+}
+
+fn cmd_grep(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    // 解析开头的选项，可以合并书写，如-rn
+    let mut options = search::GrepOptions::default();
+    let mut index = 0;
+    while index < args.len() && args[index].starts_with('-') && args[index].len() > 1 {
+        if args[index] == "--" {
+            index += 1;
+            break;
+        }
+        for flag in args[index].chars().skip(1) {
+            match flag {
+                'r' => options.recursive = true,
+                'i' => options.ignore_case = true,
+                'n' => options.line_number = true,
+                _ => return Err(format!("grep: unknown option -{}", flag)),
+            }
+        }
+        index += 1;
+    }
+    let Some(pattern) = args.get(index) else {
+        return Err("Usage: grep [-r] [-i] [-n] <pattern> [path...]".to_string());
+    };
+    let pattern = pattern.as_bytes();
+    let paths = &args[index + 1..];
+
+    if paths.is_empty() {
+        let input = io.input.take().ok_or("Usage: grep [-r] [-i] [-n] <pattern> [path...]")?;
+        search::grep_data(&input, pattern, None, &options, &mut |line| io.write(line));
+        return Ok(true);
+    }
+    let mut output = |line: &[u8]| io.write(line);
+    // 多个文件或递归搜索时在每行前加上文件名
+    options.with_name = paths.len() > 1 || options.recursive;
+    for_each_arg(paths, |path| {
+        search::grep_path(vd, session, path, pattern, &options, &mut output)?;
+        Ok(())
+    })
+}
+
+fn cmd_find(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let filter = search::FindFilter::parse(vd, session, &args[1..])?;
+    search::find(vd, session, &args[0], &filter, &mut |path| io.println(path))?;
+    Ok(true)
+}
+
+// head与tail的选项：按行或按字节
+enum Count {
+    Lines(usize),
+    Bytes(usize),
+}
+
+// 解析-n N或-c N，返回数量与其余的路径参数
+fn parse_count<'a>(args: &'a [String], usage: &str) -> Result<(Count, &'a [String]), String> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some(option @ ("-n" | "-c")) => {
+            let number = args
+                .get(1)
+                .and_then(|number| number.parse().ok())
+                .ok_or(format!("Usage: {}", usage))?;
+            let count = if option == "-n" { Count::Lines(number) } else { Count::Bytes(number) };
+            Ok((count, &args[2..]))
+        }
+        _ => Ok((Count::Lines(10), args)),
+    }
+}
+
+// 对管道输入或每个文件执行op，多个文件时输出文件名
+fn for_each_input<F>(
+    vd: &DiskOperator,
+    session: &Session,
+    paths: &[String],
+    io: &mut Io,
+    usage: &str,
+    mut op: F,
+) -> Result<bool, String>
+where
+    F: FnMut(&DiskOperator, Source, &mut Io) -> FsResult<()>,
+{
+    if paths.is_empty() {
+        let input = io.input.take().ok_or(format!("Usage: {}", usage))?;
+        op(vd, Source::Data(&input), io)?;
+        return Ok(true);
+    }
+    let mut first = true;
+    for_each_arg(paths, |path| {
+        let fcb = vd.get_fcb_by_path(session, path)?;
+        if paths.len() > 1 {
+            io.println(format!("{}==> {} <==", if first { "" } else { "\n" }, path));
+            first = false;
+        }
+        op(vd, Source::File(&fcb), io)?;
+        Ok(())
+    })
+}
+
+// 读取的来源：管道输入或虚拟磁盘中的文件
+enum Source<'a> {
+    Data(&'a [u8]),
+    File(&'a Fcb),
+}
+
+impl Source<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Source::Data(data) => data.len(),
+            Source::File(fcb) => fcb.length(),
+        }
+    }
+
+    // 只读取文件中需要的部分
+    fn read(&self, vd: &DiskOperator, offset: usize, len: usize) -> FsResult<Vec<u8>> {
+        match self {
+            Source::Data(data) => Ok(data[offset.min(data.len())..offset.saturating_add(len).min(data.len())].to_vec()),
+            Source::File(fcb) => vd.read_at(fcb, offset, len),
+        }
+    }
+}
+
+fn cmd_head(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let usage = "head [-n lines | -c bytes] [path...]";
+    let (count, paths) = parse_count(args, usage)?;
+    for_each_input(vd, session, paths, io, usage, |vd, source, io| {
+        let end = match count {
+            Count::Bytes(bytes) => bytes.min(source.len()),
+            Count::Lines(0) => 0,
+            Count::Lines(lines) => {
+                // 按块向后查找第lines个换行
+                let mut offset = 0;
+                let mut found = 0;
+                'search: loop {
+                    let chunk = source.read(vd, offset, BLOCK_SIZE)?;
+                    if chunk.is_empty() {
+                        break offset;
+                    }
+                    for (index, &byte) in chunk.iter().enumerate() {
+                        if byte == b'\n' {
+                            found += 1;
+                            if found == lines {
+                                break 'search offset + index + 1;
+                            }
+                        }
+                    }
+                    offset += chunk.len();
+                }
             }
-            else {
-                if vd.cur_dir.get_file_type(name[1]).is_some() {
-                    vd.move_file_by_name(name[0], name[1]);
+        };
+        io.write(&source.read(vd, 0, end)?);
+        Ok(())
+    })
+}
+
+fn cmd_tail(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let usage = "tail [-n lines | -c bytes] [path...]";
+    let (count, paths) = parse_count(args, usage)?;
+    for_each_input(vd, session, paths, io, usage, |vd, source, io| {
+        let len = source.len();
+        let start = match count {
+            Count::Bytes(bytes) => len.saturating_sub(bytes),
+            Count::Lines(0) => len,
+            Count::Lines(lines) => {
+                // 从末尾按块向前查找换行，文件末尾的换行不计入
+                let mut end = if source.read(vd, len.saturating_sub(1), 1)? == b"\n" { len - 1 } else { len };
+                let mut found = 0;
+                let mut start = 0;
+                'search: while end > 0 {
+                    let offset = end.saturating_sub(BLOCK_SIZE);
+                    let chunk = source.read(vd, offset, end - offset)?;
+                    for (index, &byte) in chunk.iter().enumerate().rev() {
+                        if byte == b'\n' {
+                            found += 1;
+                            if found == lines {
+                                start = offset + index + 1;
+                                break 'search;
+                            }
+                        }
+                    }
+                    end = offset;
                 }
-                else {
-                    vd.rename_file(name[0], name[1]);
+                start
+            }
+        };
+        io.write(&source.read(vd, start, len - start)?);
+        Ok(())
+    })
+}
+
+fn cmd_truncate(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    let usage = "Usage: truncate -s [+|-]size[k|M|G] <path...>";
+    if args[0] != "-s" {
+        return Err(usage.to_string());
+    }
+    let (sign, size) = parser::parse_size(&args[1]).ok_or(usage)?;
+    for_each_arg(&args[2..], |path| {
+        let length = vd.get_fcb_by_path(session, path)?.length();
+        let new_length = match sign {
+            Ordering::Greater => length.checked_add(size).ok_or(usage)?,
+            Ordering::Less => length.saturating_sub(size),
+            Ordering::Equal => size,
+        };
+        Ok(vd.truncate(session, path, new_length)?)
+    })
+}
+
+// 只支持打洞，-o与-l可以带k、M、G单位
+fn cmd_fallocate(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    let usage = "Usage: fallocate --punch-hole [-o offset] -l length <path...>";
+    let (mut punch, mut offset, mut length) = (false, 0, None);
+    let mut rest = args;
+    loop {
+        rest = match rest {
+            [flag, rest @ ..] if flag == "--punch-hole" || flag == "-p" => {
+                punch = true;
+                rest
+            }
+            [flag, value, rest @ ..] if flag == "-o" || flag == "-l" => {
+                let size = match parser::parse_size(value) {
+                    Some((Ordering::Equal, size)) => size,
+                    _ => return Err(usage.to_string()),
+                };
+                if flag == "-o" {
+                    offset = size;
+                } else {
+                    length = Some(size);
                 }
+                rest
+            }
+            _ => break,
+        };
+    }
+    let Some(length) = length.filter(|_| punch && !rest.is_empty()) else {
+        return Err(usage.to_string());
+    };
+    for_each_arg(rest, |path| Ok(vd.punch_hole(session, path, offset, length)?))
+}
+
+fn cmd_stat(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    for_each_arg(args, |path| {
+        io.write(listing::stat(vd, session, path)?.as_bytes());
+        Ok(())
+    })
+}
+
+fn cmd_edit(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    let mut editor = LineEditor::open(vd, session, &args[0])?;
+    editor.run(&mut stdin().lock(), &mut stdout())?;
+    Ok(true)
+}
+
+fn cmd_rm(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    // print_info();
+    // println!("File {} deleted.", name);
+    for_each_arg(args, |path| {
+        vd.delete_file_by_path(session, path)
+            .map_err(|err| format!("Error: Delete Failed. {}", err))
+    })
+}
+
+fn cmd_diskinfo(vd: &mut DiskOperator, _session: &mut Session, _args: &[String], io: &mut Io) -> Result<bool, String> {
+    io.write(disk_info(vd).as_bytes());
+    Ok(true)
+}
+
+fn cmd_resize(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    io.println(resize_disk(vd, session, &args[0])?);
+    Ok(true)
+}
+
+fn cmd_whoami(_vd: &mut DiskOperator, session: &mut Session, _args: &[String], io: &mut Io) -> Result<bool, String> {
+    io.println(session.user());
+    Ok(true)
+}
+
+// 配额的一行：名称、已用/上限的块数与文件数，没有上限时为-
+fn quota_line(name: &str, usage: Usage, limit: Option<Limit>) -> String {
+    let show = |limit: usize| if limit == 0 { String::from("-") } else { limit.to_string() };
+    let limit = limit.unwrap_or_default();
+    format!(
+        "{}\t{}/{} blocks\t{}/{} files",
+        name,
+        usage.blocks,
+        show(limit.blocks),
+        usage.inodes,
+        show(limit.inodes)
+    )
+}
+
+fn user_quota(vd: &DiskOperator, user: &str) -> FsResult<String> {
+    let usage = quota::user_usage(vd, user)?;
+    Ok(quota_line(&format!("user {}", user), usage, vd.quotas().user_limit(user)))
+}
+
+fn dir_quota(vd: &DiskOperator, cluster: usize) -> FsResult<String> {
+    let usage = quota::dir_usage(vd, cluster)?;
+    let name = format!("dir {}", vd.get_path_by_cluster(cluster)?);
+    Ok(quota_line(&name, usage, vd.quotas().dir_limit(cluster)))
+}
+
+fn cmd_quota(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["-u", user] => io.println(user_quota(vd, user)?),
+        ["-d", path] => io.println(dir_quota(vd, vd.get_directory_by_path(session, path)?.cluster())?),
+        // 当前用户，以及设置了配额的用户与目录
+        [] => {
+            let user = session.user();
+            io.println(user_quota(vd, user)?);
+            let others: Vec<String> = vd.quotas().users().filter(|&(other, _)| other != user).map(|(other, _)| other.to_string()).collect();
+            for other in others {
+                io.println(user_quota(vd, &other)?);
+            }
+            let dirs: Vec<usize> = vd.quotas().dirs().map(|(cluster, _)| cluster).collect();
+            for cluster in dirs {
+                io.println(dir_quota(vd, cluster)?);
             }
         }
-        else if let Some(name) = args.strip_prefix("cat ") {
-            let data = vd.read_file_by_name(name.trim()).unwrap();
-            println!("{}", String::from_utf8(data).unwrap());
+        _ => return Err("Usage: quota [-u user | -d path]".to_string()),
+    }
+    Ok(true)
+}
+
+fn cmd_setquota(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let usage = "Usage: setquota (-u user | -d path) <blocks> <files>";
+    let (Ok(blocks), Ok(inodes)) = (args[2].parse(), args[3].parse()) else {
+        return Err(usage.to_string());
+    };
+    let limit = Limit { blocks, inodes };
+    let line = match args[0].as_str() {
+        "-u" => {
+            vd.quotas().set_user(&args[1], limit);
+            user_quota(vd, &args[1])?
         }
-        else if let Some(name) = args.strip_prefix("touch ") {
-            let mut index = 0;
-            for i in name.trim().chars() {
-                if i == ' ' {
-                    break;
+        "-d" => {
+            let cluster = vd.get_directory_by_path(session, &args[1])?.cluster();
+            vd.quotas().set_dir(cluster, limit);
+            dir_quota(vd, cluster)?
+        }
+        _ => return Err(usage.to_string()),
+    };
+    io.println(line);
+    Ok(true)
+}
+
+fn cmd_open(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let Some(path) = args.first() else {
+        for (id, handle) in session.handles() {
+            // 文件已被删除或移走时句柄失效
+            let path = handle.fcb(vd).and_then(|_| handle.path(vd)).unwrap_or_else(|_| format!("{} (deleted)", handle.name()));
+            io.println(format!("{}\t{}", id, path));
+        }
+        return Ok(true);
+    };
+    let handle = FileHandle::for_path(vd, session, path)?;
+    io.println(session.open(handle));
+    Ok(true)
+}
+
+fn cmd_close(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    let id = parse_handle(&args[0])?;
+    session.close(id)?;
+    // 关闭句柄时释放其持有的锁
+    vd.locks.release(LockOwner { session: session.id(), handle: id });
+    Ok(true)
+}
+
+fn cmd_snapshot(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    const USAGE: &str = "Usage: snapshot <create|list|show|diff|restore|delete> [name] [path|name]";
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["create", name] => snapshot::create(vd, session, name)?,
+        ["list"] => {
+            for snapshot in vd.snapshots().list() {
+                io.println(format!("{}\t{}", listing::format_time(snapshot.created()), snapshot.name()));
+            }
+        }
+        // 浏览快照中的全部文件
+        ["list", name] => {
+            for (path, fcb) in snapshot::files(vd, name)? {
+                let suffix = if fcb.file_type() == FileType::Directory { "/" } else { "" };
+                io.println(format!("{}{}", path, suffix));
+            }
+        }
+        // 输出快照中的文件，或列出快照中的目录
+        ["show", name, path] => {
+            let fcb = snapshot::lookup(vd, name, path)?;
+            match fcb.file_type() {
+                FileType::File => io.write(&vd.get_file_by_fcb(&fcb)?),
+                FileType::Directory => {
+                    for entry in vd.get_directory_by_fcb(&fcb)?.entries() {
+                        io.println(entry.name());
+                    }
                 }
+            }
+        }
+        ["diff", name] | ["diff", name, _] => {
+            for (change, path) in snapshot::diff(vd, name, args.get(2).copied())? {
+                io.println(format!("{}\t{}", change, path));
+            }
+        }
+        ["restore", name] => snapshot::restore(vd, session, name)?,
+        ["delete", name] => snapshot::delete(vd, name)?,
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(true)
+}
+
+fn parse_handle(arg: &str) -> Result<u64, String> {
+    arg.parse().map_err(|_| format!("Invalid handle: {}", arg))
+}
+
+// 解析可选的start length，省略时返回None表示整个文件的flock锁
+fn parse_lock_range(args: &[String], usage: &str) -> Result<Option<(u64, u64)>, String> {
+    match args {
+        [] => Ok(None),
+        [start, length] => match (start.parse(), length.parse()) {
+            (Ok(start), Ok(length)) => Ok(Some((start, lock::range_end(start, length)))),
+            _ => Err(format!("Usage: {}", usage)),
+        },
+        _ => Err(format!("Usage: {}", usage)),
+    }
+}
+
+fn cmd_lock(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    const USAGE: &str = "lock [-s|-x] [-m] [-w seconds] <handle> [start length]";
+    if args.is_empty() {
+        for (file, lock) in vd.locks.locks() {
+            let path = file.path(vd).unwrap_or_else(|_| file.name().to_string());
+            let kind = if lock.kind == LockKind::Shared { "shared" } else { "exclusive" };
+            let mode = if lock.mandatory { "mandatory" } else { "advisory" };
+            let range = match (lock.start, lock.end) {
+                (0, lock::TO_EOF) => String::from("whole"),
+                (start, lock::TO_EOF) => format!("{}-EOF", start),
+                (start, end) => format!("{}-{}", start, end),
+            };
+            io.println(format!("{}:{}\t{}\t{}\t{}\t{}", lock.owner.session, lock.owner.handle, kind, mode, range, path));
+        }
+        return Ok(true);
+    }
+
+    // 默认不等待，-w给出最长等待的秒数
+    let (mut kind, mut mandatory, mut timeout) = (LockKind::Exclusive, false, Some(Duration::ZERO));
+    let mut index = 0;
+    while index < args.len() && args[index].starts_with('-') {
+        match args[index].as_str() {
+            "-s" => kind = LockKind::Shared,
+            "-x" => kind = LockKind::Exclusive,
+            "-m" => mandatory = true,
+            "-w" => {
                 index += 1;
+                // 负数、inf、NaN与过大的值都无法表示为Duration
+                let seconds = args.get(index).and_then(|arg| arg.parse().ok());
+                timeout = Some(seconds.and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()).ok_or(format!("Usage: {}", USAGE))?);
             }
-            let file_name = &name.trim()[..index];
-            let mut data = String::from(&name.trim()[index+1..]);
-            let time: String = format!("\nGnerated at {:?}.", chrono::Local::now());
-            data.push_str(&time);
-            vd.new_file(file_name, data.as_bytes()).unwrap();
+            option => return Err(format!("lock: unknown option {}", option)),
         }
-        else {
-            println!("Invalid command, please try again.");
+        index += 1;
+    }
+    let Some(id) = args.get(index) else {
+        return Err(format!("Usage: {}", USAGE));
+    };
+    let id = parse_handle(id)?;
+    let range = parse_lock_range(&args[index + 1..], USAGE)?;
+    let file = session.handle(id)?.clone();
+    file.fcb(vd)?;
+    let owner = LockOwner { session: session.id(), handle: id };
+    let held = Lock { owner, kind, start: 0, end: lock::TO_EOF, mandatory };
+    match range {
+        None => vd.locks.flock(&file, held, timeout)?,
+        Some((start, end)) => vd.locks.lock_range(&file, Lock { start, end, ..held }, timeout)?,
+    }
+    Ok(true)
+}
+
+fn cmd_unlock(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    let id = parse_handle(&args[0])?;
+    let file = session.handle(id)?.clone();
+    let owner = LockOwner { session: session.id(), handle: id };
+    match parse_lock_range(&args[1..], "unlock <handle> [start length]")? {
+        None => vd.locks.funlock(&file, owner),
+        Some((start, end)) => vd.locks.unlock_range(&file, owner, start, end),
+    }
+    Ok(true)
+}
+
+// 多个源时目标必须是目录
+fn check_target_dir(vd: &DiskOperator, session: &Session, args: &[String]) -> Result<(), String> {
+    let dst = &args[args.len() - 1];
+    if args.len() > 2 && vd.get_directory_by_path(session, dst).is_err() {
+        return Err(format!("{}: Not a directory!", dst));
+    }
+    Ok(())
+}
+
+fn cmd_cp(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    check_target_dir(vd, session, args)?;
+    let (dst, srcs) = args.split_last().unwrap_or_else(|| unreachable!());
+    for_each_arg(srcs, |src| Ok(vd.copy_file_by_path(session, src, dst)?))
+}
+
+fn cmd_mv(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    // 移动与重命名
+    check_target_dir(vd, session, args)?;
+    let (dst, srcs) = args.split_last().unwrap_or_else(|| unreachable!());
+    for_each_arg(srcs, |src| Ok(vd.move_file_by_path(session, src, dst)?))
+}
+
+fn cmd_export(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let count = archive::export_tar(vd, session, &args[0], &args[1]).map_err(|err| format!("Error: Export Failed. {}", err))?;
+    io.println(format!("{} entries exported to {}.", count, args[1]));
+    Ok(true)
+}
+
+fn cmd_import(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let count = archive::import_tar(vd, session, &args[0], &args[1]).map_err(|err| format!("Error: Import Failed. {}", err))?;
+    io.println(format!("{} entries imported from {}.", count, args[0]));
+    Ok(true)
+}
+
+// 解析exportfat的参数，返回FAT类型、目录与镜像文件名
+pub fn parse_exportfat_args(args: &[String]) -> Result<(FatType, &str, &str), String> {
+    match args {
+        [option, fat_type, dir, image] if option == "-F" => {
+            Ok((FatType::parse(fat_type).map_err(|err| err.to_string())?, dir, image))
+        }
+        [dir, image] => Ok((FatType::Fat12, dir, image)),
+        _ => Err("Usage: exportfat [-F 12|16] <dirname> <image>".to_string()),
+    }
+}
+
+fn cmd_exportfat(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let (fat_type, dir, image) = parse_exportfat_args(args)?;
+    let count = fat_image::export_fat(vd, session, dir, image, fat_type).map_err(|err| format!("Error: Export Failed. {}", err))?;
+    io.println(format!("{} entries exported to {}.", count, image));
+    Ok(true)
+}
+
+fn cmd_importfat(vd: &mut DiskOperator, session: &mut Session, args: &[String], io: &mut Io) -> Result<bool, String> {
+    let count = fat_image::import_fat(vd, session, &args[0], &args[1]).map_err(|err| format!("Error: Import Failed. {}", err))?;
+    io.println(format!("{} entries imported from {}.", count, args[0]));
+    Ok(true)
+}
+
+fn cmd_touch(vd: &mut DiskOperator, session: &mut Session, args: &[String], _io: &mut Io) -> Result<bool, String> {
+    let (compression, args) = parse_compression(args, "touch [-z lz4|zstd|deflate|none] <filename> [data]")?;
+    let mut data = args[1..].join(" ");
+    let time: String = format!("\nGnerated at {:?}.", chrono::Local::now());
+    data.push_str(&time);
+    vd.new_file_with(session, session.cwd(), &args[0], data.as_bytes(), compression)?;
+    Ok(true)
+}
+
+fn disk_info(vd: &DiskOperator) -> String {
+    let (disk_size, used_size, unused_size) = vd.get_disk_info();
+    // 目录树损坏时不影响其余信息的显示
+    let (logical, physical) = vd.file_usage().unwrap_or_default();
+    let (referenced, distinct) = vd.block_usage().unwrap_or_default();
+    let dedup = format!(
+        "{}, ratio {:.2} ({} clusters referenced, {} stored)",
+        if vd.options.dedup { "on" } else { "off" },
+        referenced as f64 / distinct.max(1) as f64,
+        referenced,
+        distinct
+    );
+    let encryption = match vd.encryption {
+        Some(_) => "AES-256-GCM, Argon2id",
+        None => "none",
+    };
+    let snapshots = vd.snapshots();
+    format!(
+        "Disk Size: {} bytes\nUsed Size: {} bytes\nUnused Size: {} bytes\nFile Size: {} bytes ({} bytes allocated)\nDedup: {}\nSnapshots: {} ({} shared clusters)\nEncryption: {}\n",
+        disk_size * BLOCK_SIZE,
+        used_size * BLOCK_SIZE,
+        unused_size * BLOCK_SIZE,
+        logical,
+        physical,
+        dedup,
+        snapshots.list().len(),
+        snapshots.shared_count(),
+        encryption
+    )
+}
+
+pub fn print_disk_info(vd: &DiskOperator) {
+    print!("{}", disk_info(vd));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_commands_keep_the_shell_running() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        run_command(&mut vd, &mut session, "mkdir dir").unwrap();
+        run_command(&mut vd, &mut session, "touch a text").unwrap();
+        vd.new_file(&session, "bin", &[0xff, 0xfe, 0x00]).unwrap();
+
+        for line in [
+            "cd missing",
+            "cd a",
+            "cat dir",
+            "cat missing",
+            "mkdir dir",
+            "touch a again",
+            "rm missing",
+            "cp missing dir",
+            "mv a missing/",
+            "mv missing b",
+            "import missing.tar dir",
+            "export missing out.tar",
+        ] {
+            assert!(run_command(&mut vd, &mut session, line).is_err(), "{} should fail", line);
+        }
+
+        // 二进制文件原样重定向，输出到终端前转义
+        assert_eq!(run_command(&mut vd, &mut session, "cat bin > out"), Ok(true));
+        let output = vd.read_file_by_path(&session, "out").unwrap();
+        assert_eq!(output, [0xff, 0xfe, 0x00]);
+        assert_eq!(escape_output(&output), b"\\xff\\xfe\\x00");
+
+        // 失败后磁盘仍然可用
+        assert_eq!(run_command(&mut vd, &mut session, "cd dir"), Ok(true));
+        assert_eq!(vd.get_abs_path(&session), "/root/dir");
+    }
+
+    #[test]
+    fn pipes_and_redirection_write_files() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        run_command(&mut vd, &mut session, "echo foo bar > a").unwrap();
+        run_command(&mut vd, &mut session, "echo baz >> a").unwrap();
+        run_command(&mut vd, &mut session, "echo food >> a").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "a").unwrap(), b"foo bar\nbaz\nfood\n");
+
+        run_command(&mut vd, &mut session, "cat a | grep foo > b").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "b").unwrap(), b"foo bar\nfood\n");
+        run_command(&mut vd, &mut session, "cat b | grep d | cat > b").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "b").unwrap(), b"food\n");
+
+        // 引号中的符号不是管道或重定向
+        run_command(&mut vd, &mut session, "echo 'a | b' \\> > c").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "c").unwrap(), b"a | b >\n");
+
+        run_command(&mut vd, &mut session, "mkdir dir").unwrap();
+        assert!(run_command(&mut vd, &mut session, "echo x > dir").is_err());
+        assert!(run_command(&mut vd, &mut session, "echo x > missing/a").is_err());
+
+        // 导出、导入与退出的提示同样可以重定向
+        let tar = std::env::temp_dir().join(format!("file-system-redirect-{}.tar", std::process::id()));
+        let tar = tar.to_str().unwrap();
+        run_command(&mut vd, &mut session, &format!("export dir {} > log", tar)).unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "log").unwrap(), format!("0 entries exported to {}.\n", tar).as_bytes());
+        run_command(&mut vd, &mut session, &format!("import {} dir >> log", tar)).unwrap();
+        std::fs::remove_file(tar).unwrap();
+        assert!(vd.read_file_by_path(&session, "log").unwrap().ends_with(format!("0 entries imported from {}.\n", tar).as_bytes()));
+        assert_eq!(run_command(&mut vd, &mut session, "exit > log"), Ok(false));
+        assert_eq!(vd.read_file_by_path(&session, "log").unwrap(), b"Exiting...\n");
+    }
+
+    #[test]
+    fn globs_expand_arguments() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        for line in [
+            "mkdir data",
+            "mkdir backup",
+            "echo 1 > data/a.csv",
+            "echo 2 > data/b.csv",
+            "echo 3 > data/c.txt",
+            "echo x > a.log",
+            "echo y > b.log",
+            "echo z > '*.log'",
+            "cp data/*.csv backup/",
+            // 加引号的通配符按普通字符处理
+            "rm '*.log'",
+        ] {
+            run_command(&mut vd, &mut session, line).unwrap();
+        }
+        let names = |vd: &DiskOperator, session: &Session, path: &str| -> Vec<String> {
+            let dir = vd.get_directory_by_path(session, path).unwrap();
+            dir.entries().map(|fcb| fcb.name().to_string()).collect()
+        };
+        assert_eq!(names(&vd, &session, "/backup"), ["a.csv", "b.csv"]);
+        assert_eq!(vd.read_file_by_path(&session, "/backup/b.csv").unwrap(), b"2\n");
+        assert_eq!(names(&vd, &session, "/"), ["data", "backup", "a.log", "b.log"]);
+
+        run_command(&mut vd, &mut session, "rm *.log").unwrap();
+        assert_eq!(names(&vd, &session, "/"), ["data", "backup"]);
+        // 没有匹配项时参数原样保留
+        assert_eq!(run_command(&mut vd, &mut session, "rm *.log"), Err("Error: Delete Failed. *.log: No such file or directory!".to_string()));
+    }
+
+    #[test]
+    fn find_and_grep_walk_the_tree() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        for line in [
+            "mkdir src",
+            "cd src",
+            "mkdir sub",
+            "cd /",
+            "echo Hello > a.txt",
+            "echo hello world > src/b.txt",
+            "echo nothing > src/sub/c.log",
+            "echo hello again >> src/sub/c.log",
+            "find / -name '*.txt' > found",
+            "find src -type d >> found",
+            "find / -type f -size +12 >> found",
+            "grep -rn hello / > matches",
+            "grep -i HELLO a.txt src/b.txt >> matches",
+        ] {
+            run_command(&mut vd, &mut session, line).unwrap();
         }
+        assert_eq!(
+            String::from_utf8(vd.read_file_by_path(&session, "found").unwrap()).unwrap(),
+            "/src/b.txt\n/a.txt\nsrc\nsrc/sub\n/src/sub/c.log\n/found\n"
+        );
+        assert_eq!(
+            String::from_utf8(vd.read_file_by_path(&session, "matches").unwrap()).unwrap(),
+            "/src/sub/c.log:2:hello again\n/src/b.txt:1:hello world\na.txt:Hello\nsrc/b.txt:hello world\n"
+        );
 
+        assert!(run_command(&mut vd, &mut session, "grep hello src").is_err());
+        assert!(run_command(&mut vd, &mut session, "find missing").is_err());
+        assert!(run_command(&mut vd, &mut session, "find / -type x").is_err());
+        assert!(run_command(&mut vd, &mut session, "find / -size").is_err());
+        assert_eq!(
+            run_command(&mut vd, &mut session, "find / -size 18014398509481984M"),
+            Err("Invalid argument: invalid size 18014398509481984M".to_string())
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ls_and_tree_options() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        for line in [
+            "mkdir b",
+            "cd b",
+            "mkdir c",
+            "echo nested > c/d",
+            "cd /",
+            "echo some text > a",
+            "echo hidden > .h",
+            "ls > out1",
+            "ls -a -r >> out1",
+            "ls -R b >> out1",
+            "tree -L 1 > out2",
+            "tree b >> out2",
+            "ls --json -S > out3",
+        ] {
+            run_command(&mut vd, &mut session, line).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(vd.read_file_by_path(&session, "out1").unwrap()).unwrap(),
+            "a\nb/\n\
+             out1\nb/\na\n.h\n../\n./\n\
+             b:\nc/\n\nb/c:\nd\n"
+        );
+        assert_eq!(
+            String::from_utf8(vd.read_file_by_path(&session, "out2").unwrap()).unwrap(),
+            ".\n├── a\n├── b/\n└── out1\n\n1 directory, 2 files\n\
+             b\n└── c/\n    └── d\n\n1 directory, 1 file\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_slice(&vd.read_file_by_path(&session, "out3").unwrap()).unwrap();
+        let names: Vec<&str> = json.as_array().unwrap().iter().map(|entry| entry["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["b", "out2", "out1", "a"]);
+        assert_eq!(json[3]["type"], "file");
+        assert_eq!(json[3]["size"], 10);
+
+        assert!(run_command(&mut vd, &mut session, "ls -x").is_err());
+        assert!(run_command(&mut vd, &mut session, "ls --bogus").is_err());
+        assert!(run_command(&mut vd, &mut session, "tree -L").is_err());
+        assert!(run_command(&mut vd, &mut session, "tree a b").is_err());
+    }
+
+    #[test]
+    fn head_tail_and_truncate() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        for number in 1..=2000 {
+            vd.append_file_by_path(&session, "lines", format!("line {}\n", number).as_bytes()).unwrap();
+        }
+        for line in [
+            "head -n 2 lines > out",
+            "tail -n 3 lines >> out",
+            "head -c 3 lines >> out",
+            "tail lines | head -n 1 >> out",
+            "echo abc > short",
+            "truncate -s +2 short",
+            "truncate -s 2 lines",
+        ] {
+            run_command(&mut vd, &mut session, line).unwrap();
+        }
+        assert_eq!(
+            vd.read_file_by_path(&session, "out").unwrap(),
+            b"line 1\nline 2\nline 1998\nline 1999\nline 2000\nlinline 1991\n"
+        );
+        assert_eq!(vd.read_file_by_path(&session, "short").unwrap(), b"abc\n\0\0");
+        assert_eq!(vd.read_file_by_path(&session, "lines").unwrap(), b"li");
+
+        assert!(run_command(&mut vd, &mut session, "truncate 2 lines").is_err());
+        assert!(run_command(&mut vd, &mut session, "truncate -s x lines").is_err());
+        assert!(run_command(&mut vd, &mut session, "head -n x lines").is_err());
+        assert!(run_command(&mut vd, &mut session, "tail missing").is_err());
+    }
+
+    #[test]
+    fn truncate_sizes_are_checked() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        run_command(&mut vd, &mut session, "echo 0123456789 > f").unwrap();
+        run_command(&mut vd, &mut session, "truncate -s -4 f").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "f").unwrap(), b"0123456");
+        run_command(&mut vd, &mut session, "truncate -s -1k f").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "f").unwrap(), b"");
+        run_command(&mut vd, &mut session, "truncate -s +1k f").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "f").unwrap(), [0; 1024]);
+
+        // 溢出时报告用法，文件不变
+        let usage = Err("Usage: truncate -s [+|-]size[k|M|G] <path...>".to_string());
+        assert_eq!(run_command(&mut vd, &mut session, "truncate -s 18014398509481984M f"), usage);
+        assert_eq!(run_command(&mut vd, &mut session, "truncate -s +18446744073709551615 f"), usage);
+        assert_eq!(vd.get_fcb_by_path(&session, "f").unwrap().length(), 1024);
+    }
+
+    #[test]
+    fn lock_timeouts_are_checked() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        run_command(&mut vd, &mut session, "touch f").unwrap();
+        run_command(&mut vd, &mut session, "open f > handle").unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "handle").unwrap(), b"1\n");
+
+        // 无法表示为等待时间的值报告用法，不会退出
+        let usage = Err("Usage: lock [-s|-x] [-m] [-w seconds] <handle> [start length]".to_string());
+        for seconds in ["inf", "NaN", "-1", "1e300", "x"] {
+            assert_eq!(run_command(&mut vd, &mut session, &format!("lock -w {} 1", seconds)), usage, "{}", seconds);
+        }
+        assert_eq!(run_command(&mut vd, &mut session, "lock -w 0.5 1"), Ok(true));
+        assert_eq!(run_command(&mut vd, &mut session, "unlock 1"), Ok(true));
+    }
+
+    #[test]
+    fn line_editor_rewrites_the_file() {
+        let session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        vd.new_file(&session, "a", b"one\ntwo\nthree\n").unwrap();
+        let script = "p\nd 2\na 0\nzero\n.\nc $\nTHREE\nfour\n.\nq\nwq\n";
+        let mut output = Vec::new();
+        LineEditor::open(&mut vd, &session, "a")
+            .unwrap()
+            .run(&mut script.as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "a").unwrap(), b"zero\none\nTHREE\nfour\n");
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("   2  two"));
+        assert!(output.contains("Unsaved changes"));
+
+        // 不存在的文件在写回时创建
+        let mut editor = LineEditor::open(&mut vd, &session, "new").unwrap();
+        editor.run(&mut "a\nhello\n.\nw\nq\n".as_bytes(), &mut Vec::new()).unwrap();
+        assert_eq!(vd.read_file_by_path(&session, "new").unwrap(), b"hello\n");
+    }
+
+    #[test]
+    fn binary_inspection() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        vd.new_file(&session, "bin", &[0u8; 40]).unwrap();
+        vd.write_at(&session, "bin", 32, b"AB\xff\x1b").unwrap();
+        run_command(&mut vd, &mut session, "hexdump -C bin > out").unwrap();
+        run_command(&mut vd, &mut session, "echo hi | hexdump >> out").unwrap();
+        run_command(&mut vd, &mut session, "fatchain bin >> out").unwrap();
+        run_command(&mut vd, &mut session, "dumpcluster 0 | head -n 1 >> out").unwrap();
+        let first = vd.get_fcb_by_path(&session, "bin").unwrap().first_cluster();
+        assert_eq!(
+            String::from_utf8(vd.read_file_by_path(&session, "out").unwrap()).unwrap(),
+            format!(
+                "00000000  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+                 *\n\
+                 00000020  41 42 ff 1b 00 00 00 00                           |AB......|\n\
+                 00000028\n\
+                 0000000 6968 000a\n\
+                 0000003\n\
+                 bin: {} -> EOF (1 cluster, 40 bytes)\n\
+                 Cluster 0: offset 0x0, FAT entry: EOF\n",
+                first
+            )
+        );
+        assert_eq!(escape_output(b"ok\xff\x1b[2J\n"), b"ok\\xff\\x1b[2J\n");
+
+        assert!(run_command(&mut vd, &mut session, "dumpcluster 100000").is_err());
+        assert!(run_command(&mut vd, &mut session, "dumpcluster x").is_err());
+        assert!(run_command(&mut vd, &mut session, "fatchain missing").is_err());
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        let mut session = Session::default();
+        let mut vd = DiskOperator::new(None);
+        for line in ["lsfoo", "touch", "cp a", "cd", "cat \"unterminated", "rm a\\", "| cat", "ls |", "> a", "ls >", "ls > a b"] {
+            assert!(run_command(&mut vd, &mut session, line).is_err(), "{} should fail", line);
+        }
+        assert_eq!(run_command(&mut vd, &mut session, ""), Ok(true));
+        assert_eq!(run_command(&mut vd, &mut session, "exit"), Ok(false));
+    }
+}
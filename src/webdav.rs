@@ -0,0 +1,635 @@
+use crate::disk::BLOCK_SIZE;
+use crate::disk_operator::{split_path, Fcb, FileType};
+use crate::error::{FsError, FsResult};
+use crate::filesystem::FileSystem;
+use crate::listing::{self, EntryInfo};
+use crate::session::Session;
+
+use chrono::TimeZone;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use tiny_http::{Header, Request, Response, Server};
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, MOVE, PROPFIND";
+
+// 同时处理请求的线程数
+const WORKERS: usize = 4;
+
+// URL路径中一段需要转义的字符
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/');
+
+// 以HTTP/WebDAV协议提供虚拟磁盘中的文件，可在其他线程中调用stop结束
+#[derive(Clone)]
+pub struct WebServer {
+    server: Arc<Server>,
+}
+
+impl WebServer {
+    pub fn bind(listen: &str) -> FsResult<WebServer> {
+        let server = Server::http(listen).map_err(|err| FsError::Io(format!("{}: {}", listen, err)))?;
+        Ok(WebServer { server: Arc::new(server) })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    // 测试中用于结束serve
+    #[cfg(test)]
+    pub fn stop(&self) {
+        self.server.unblock();
+    }
+
+    // 由多个线程并发处理请求直到stop被调用；修改了磁盘的请求完成后调用save，save不会并发执行
+    // 每个请求使用以user为身份的新会话，写入计入user的配额
+    pub fn serve<F>(&self, fs: &FileSystem, user: &str, save: F) -> FsResult<()>
+    where
+        F: FnMut(&FileSystem) -> FsResult<()> + Send,
+    {
+        let save = Mutex::new(save);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..WORKERS).map(|_| scope.spawn(|| self.work(fs, user, &save))).collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().unwrap_or_else(|_| Err(FsError::Io("worker panicked".to_string()))))
+        })
+    }
+
+    fn work<F>(&self, fs: &FileSystem, user: &str, save: &Mutex<F>) -> FsResult<()>
+    where
+        F: FnMut(&FileSystem) -> FsResult<()>,
+    {
+        // 被唤醒退出时唤醒下一个线程，使所有线程都能退出
+        let result = loop {
+            let Ok(mut request) = self.server.recv() else {
+                break Ok(());
+            };
+            let (response, modified) = handle(fs, &Session::new(user), &mut request);
+            if modified {
+                let mut save = save.lock().unwrap_or_else(PoisonError::into_inner);
+                if let Err(err) = save(fs) {
+                    break Err(err);
+                }
+            }
+            // 客户端断开时写入失败，忽略即可
+            let _ = request.respond(response);
+        };
+        self.server.unblock();
+        result
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    // 名称与值都由本模块生成，只含ASCII字符
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap_or_else(|_| unreachable!())
+}
+
+fn text(status: u16, message: &str) -> HttpResponse {
+    Response::from_data(format!("{}\n", message))
+        .with_status_code(status)
+        .with_header(header("Content-Type", "text/plain; charset=utf-8"))
+}
+
+fn no_content() -> HttpResponse {
+    Response::from_data(Vec::new()).with_status_code(204)
+}
+
+// FsError对应的HTTP状态码
+fn error_response(err: &FsError) -> HttpResponse {
+    let status = match err {
+        FsError::NotFound(_) => 404,
+        FsError::AlreadyExists(_) | FsError::IsADirectory(_) => 405,
+        FsError::NotADirectory(_) | FsError::DirectoryNotEmpty(_) => 409,
+        FsError::InvalidName(_) | FsError::InvalidArgument(_) => 400,
+        FsError::NoSpace | FsError::QuotaExceeded(_) => 507,
+        FsError::WouldBlock(_) => 423,
+        FsError::Encrypted(_) | FsError::WrongPassphrase => 403,
+        FsError::Corrupted(_) | FsError::Io(_) => 500,
+    };
+    text(status, &err.to_string())
+}
+
+fn http_date(timestamp: i64) -> String {
+    match chrono::Utc.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        None => String::new(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// 逐段转义路径，目录以/结尾
+fn href(path: &str, file_type: FileType) -> String {
+    let mut href: String = path
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    if file_type == FileType::Directory && !href.ends_with('/') {
+        href.push('/');
+    }
+    href
+}
+
+fn join(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).as_deref() {
+        Some("txt" | "md" | "log" | "rs" | "toml") => "text/plain; charset=utf-8",
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+fn request_header(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str().to_string())
+}
+
+// 处理一个请求，返回响应以及磁盘是否被修改
+fn handle(fs: &FileSystem, session: &Session, request: &mut Request) -> (HttpResponse, bool) {
+    let url = request.url().to_string();
+    let (raw_path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let path = match percent_decode_str(raw_path).decode_utf8() {
+        Ok(path) if path.starts_with('/') => path.into_owned(),
+        _ => return (text(400, "Invalid path."), false),
+    };
+    let range = request_header(request, "Range");
+    let content_range = request_header(request, "Content-Range");
+    let destination = request_header(request, "Destination");
+    let overwrite = request_header(request, "Overwrite").is_none_or(|value| value.trim() != "F");
+    let depth = request_header(request, "Depth");
+    let json = query.split('&').any(|pair| pair == "format=json")
+        || request_header(request, "Accept").is_some_and(|accept| accept.contains("application/json"));
+
+    let method = request.method().as_str().to_string();
+    let result = match method.as_str() {
+        "GET" | "HEAD" => get(fs, session, &path, range.as_deref(), json).map(|response| (response, false)),
+        "PUT" => put(fs, session, &path, content_range.as_deref(), request).map(|response| (response, true)),
+        "DELETE" => delete(fs, session, &path).map(|response| (response, true)),
+        "MKCOL" => mkcol(fs, session, &path, request).map(|response| (response, true)),
+        "MOVE" => move_to(fs, session, &path, destination.as_deref(), overwrite).map(|response| (response, true)),
+        "PROPFIND" => propfind(fs, session, &path, depth.as_deref()).map(|response| (response, false)),
+        "OPTIONS" => Ok((
+            Response::from_data(Vec::new())
+                .with_header(header("Allow", ALLOW))
+                .with_header(header("DAV", "1")),
+            false,
+        )),
+        _ => Ok((text(405, "Method not allowed.").with_header(header("Allow", ALLOW)), false)),
+    };
+    result.unwrap_or_else(|err| (error_response(&err), false))
+}
+
+fn get(fs: &FileSystem, session: &Session, path: &str, range: Option<&str>, json: bool) -> FsResult<HttpResponse> {
+    let fcb = fs.stat(session, path)?;
+    if fcb.file_type() == FileType::Directory {
+        return list(fs, session, path, json);
+    }
+
+    let length = fcb.length();
+    let modified = header("Last-Modified", &http_date(fcb.modified()));
+    let kind = header("Content-Type", content_type(fcb.name()));
+    let Some(range) = range else {
+        return Ok(Response::from_data(fs.read_file(session, path)?)
+            .with_header(kind)
+            .with_header(modified)
+            .with_header(header("Accept-Ranges", "bytes")));
+    };
+    let Some((start, end)) = parse_range(range, length) else {
+        return Ok(text(416, "Range not satisfiable.")
+            .with_header(header("Content-Range", &format!("bytes */{}", length))));
+    };
+    Ok(Response::from_data(fs.read_at(session, path, start, end + 1 - start)?)
+        .with_status_code(206)
+        .with_header(kind)
+        .with_header(modified)
+        .with_header(header("Content-Range", &format!("bytes {}-{}/{}", start, end, length))))
+}
+
+// 解析单个字节范围bytes=a-b、bytes=a-或bytes=-n，返回闭区间；无法满足时返回None
+fn parse_range(range: &str, length: usize) -> Option<(usize, usize)> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: usize = suffix.parse().ok()?;
+            if suffix == 0 {
+                return None;
+            }
+            (length.saturating_sub(suffix), length.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, length.checked_sub(1)?),
+        (start, end) => (start.parse().ok()?, end.parse::<usize>().ok()?.min(length.checked_sub(1)?)),
+    };
+    if start > end || start >= length {
+        return None;
+    }
+    Some((start, end))
+}
+
+// 解析bytes a-b/total，长度需与请求体一致，返回起始位置
+fn parse_content_range(content_range: &str, length: usize) -> Option<usize> {
+    let spec = content_range.trim().strip_prefix("bytes ")?;
+    let (range, _) = spec.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end): (usize, usize) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    if end < start || end - start + 1 != length {
+        return None;
+    }
+    Some(start)
+}
+
+// 目录的HTML或JSON列表
+fn list(fs: &FileSystem, session: &Session, path: &str, json: bool) -> FsResult<HttpResponse> {
+    // 与ls一样不显示以.开头的文件
+    let mut entries: Vec<Fcb> = fs.list_dir(session, path)?.into_iter().filter(|fcb| !fcb.name().starts_with('.')).collect();
+    entries.sort_by(|a, b| a.name().cmp(b.name()));
+    if json {
+        let infos: Vec<EntryInfo> = entries
+            .iter()
+            .map(|fcb| EntryInfo::with_size(&join(path, fcb.name()), fcb, fs.size(fcb)))
+            .collect();
+        let text = listing::to_json(&infos)?;
+        return Ok(Response::from_data(text).with_header(header("Content-Type", "application/json")));
+    }
+
+    let title = escape(path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<table>\n",
+        title
+    );
+    if path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for fcb in &entries {
+        let suffix = if fcb.file_type() == FileType::Directory { "/" } else { "" };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            href(&join(path, fcb.name()), fcb.file_type()),
+            escape(fcb.name()),
+            suffix,
+            listing::human_size(fs.size(fcb)),
+            http_date(fcb.modified())
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    Ok(Response::from_data(html).with_header(header("Content-Type", "text/html; charset=utf-8")))
+}
+
+fn put(fs: &FileSystem, session: &Session, path: &str, content_range: Option<&str>, request: &mut Request) -> FsResult<HttpResponse> {
+    if path.ends_with('/') {
+        return Err(FsError::IsADirectory(path.to_string()));
+    }
+    // 请求体不会超过磁盘容量
+    let (total, _, _) = fs.disk_info();
+    let limit = (total * BLOCK_SIZE) as u64;
+    let mut data = Vec::new();
+    request
+        .as_reader()
+        .take(limit + 1)
+        .read_to_end(&mut data)
+        .map_err(|err| FsError::Io(err.to_string()))?;
+    if data.len() as u64 > limit {
+        return Err(FsError::NoSpace);
+    }
+
+    // 带Content-Range的PUT只改写文件中的一段
+    if let Some(content_range) = content_range {
+        let Some(start) = parse_content_range(content_range, data.len()) else {
+            return Ok(text(400, "Invalid Content-Range."));
+        };
+        fs.write_at(session, path, start, &data)?;
+        return Ok(no_content());
+    }
+
+    let exists = match fs.stat(session, path) {
+        Ok(fcb) if fcb.file_type() == FileType::Directory => return Err(FsError::IsADirectory(path.to_string())),
+        Ok(_) => true,
+        Err(FsError::NotFound(_)) => false,
+        Err(err) => return Err(err),
+    };
+    match fs.write_file(session, path, &data) {
+        // 父目录不存在
+        Err(FsError::NotFound(_)) => return Ok(text(409, "Parent collection does not exist.")),
+        result => result?,
+    }
+    Ok(if exists { no_content() } else { text(201, "Created.") })
+}
+
+// 删除文件，或递归删除目录及其中的全部内容
+fn delete(fs: &FileSystem, session: &Session, path: &str) -> FsResult<HttpResponse> {
+    let fcb = fs.stat(session, path)?;
+    if path.trim_end_matches('/').is_empty() {
+        return Ok(text(403, "The root collection can not be deleted."));
+    }
+    let mut entries = Vec::new();
+    walk(fs, session, path.trim_end_matches('/'), fcb, &mut entries)?;
+    // 先删除深层的文件
+    for (entry_path, _) in entries.iter().rev() {
+        fs.remove(session, entry_path)?;
+    }
+    Ok(no_content())
+}
+
+// 先序遍历path下的全部文件与目录，包括path本身
+fn walk(fs: &FileSystem, session: &Session, path: &str, fcb: Fcb, entries: &mut Vec<(String, Fcb)>) -> FsResult<()> {
+    let is_dir = fcb.file_type() == FileType::Directory;
+    entries.push((path.to_string(), fcb));
+    if is_dir {
+        for entry in fs.list_dir(session, path)? {
+            walk(fs, session, &join(path, entry.name()), entry, entries)?;
+        }
+    }
+    Ok(())
+}
+
+fn mkcol(fs: &FileSystem, session: &Session, path: &str, request: &mut Request) -> FsResult<HttpResponse> {
+    if request.body_length().is_some_and(|length| length > 0) {
+        return Ok(text(415, "MKCOL with a body is not supported."));
+    }
+    let (dir_path, _) = split_path(path.trim_end_matches('/'));
+    match fs.stat(session, dir_path) {
+        Ok(parent) if parent.file_type() == FileType::Directory => {}
+        Ok(_) | Err(FsError::NotFound(_) | FsError::NotADirectory(_)) => {
+            return Ok(text(409, "Parent collection does not exist."))
+        }
+        Err(err) => return Err(err),
+    }
+    fs.create_dir(session, path.trim_end_matches('/'))?;
+    Ok(text(201, "Created."))
+}
+
+// 移动或重命名到Destination，目标已存在时按Overwrite决定是否先删除目标
+fn move_to(fs: &FileSystem, session: &Session, path: &str, destination: Option<&str>, overwrite: bool) -> FsResult<HttpResponse> {
+    let Some(destination) = destination else {
+        return Ok(text(400, "Missing Destination."));
+    };
+    // Destination可以是完整的URL，只取其中的路径
+    let raw = match destination.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |index| &rest[index..]),
+        None => destination,
+    };
+    let target = match percent_decode_str(raw).decode_utf8() {
+        Ok(target) if target.starts_with('/') => target.trim_end_matches('/').to_string(),
+        _ => return Ok(text(400, "Invalid Destination.")),
+    };
+    let source = path.trim_end_matches('/');
+    fs.stat(session, source)?;
+    if source.is_empty() || target.is_empty() || source == target {
+        return Ok(text(403, "Can not move to the same or the root collection."));
+    }
+
+    let exists = match fs.stat(session, &target) {
+        Ok(_) if !overwrite => return Ok(text(412, "Destination already exists.")),
+        Ok(fcb) => {
+            let mut entries = Vec::new();
+            walk(fs, session, &target, fcb, &mut entries)?;
+            for (entry_path, _) in entries.iter().rev() {
+                fs.remove(session, entry_path)?;
+            }
+            true
+        }
+        Err(FsError::NotFound(_)) => false,
+        Err(err) => return Err(err),
+    };
+    match fs.rename(session, source, &target) {
+        // 目标所在目录不存在
+        Err(FsError::NotFound(_) | FsError::NotADirectory(_)) if fs.stat(session, source).is_ok() => {
+            Ok(text(409, "Parent collection does not exist."))
+        }
+        result => result.map(|_| if exists { no_content() } else { text(201, "Created.") }),
+    }
+}
+
+// 返回207多状态响应，Depth为0时只包含请求的资源本身
+fn propfind(fs: &FileSystem, session: &Session, path: &str, depth: Option<&str>) -> FsResult<HttpResponse> {
+    let fcb = fs.stat(session, path)?;
+    let mut entries = Vec::new();
+    match (depth.unwrap_or("infinity"), fcb.file_type()) {
+        (_, FileType::File) | ("0", _) => entries.push((path.to_string(), fcb)),
+        ("1", _) => {
+            entries.push((path.to_string(), fcb));
+            entries.extend(fs.list_dir(session, path)?.into_iter().map(|entry| (join(path, entry.name()), entry)));
+        }
+        _ => walk(fs, session, path, fcb, &mut entries)?,
+    }
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for (entry_path, entry) in &entries {
+        let (resource_type, length) = match entry.file_type() {
+            FileType::Directory => ("<D:collection/>".to_string(), String::new()),
+            FileType::File => (
+                String::new(),
+                format!("<D:getcontentlength>{}</D:getcontentlength>", entry.length()),
+            ),
+        };
+        let name = if entry_path.trim_end_matches('/').is_empty() { "" } else { entry.name() };
+        xml.push_str(&format!(
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname><D:resourcetype>{}</D:resourcetype>{}<D:getlastmodified>{}</D:getlastmodified></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+            escape(&href(entry_path, entry.file_type())),
+            escape(name),
+            resource_type,
+            length,
+            http_date(entry.modified())
+        ));
+    }
+    xml.push_str("</D:multistatus>\n");
+    Ok(Response::from_data(xml)
+        .with_status_code(207)
+        .with_header(header("Content-Type", "application/xml; charset=utf-8")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk_operator::DiskOperator;
+    use crate::handle::FileHandle;
+    use crate::lock::{Lock, LockKind, LockOwner, TO_EOF};
+    use crate::quota::Limit;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    // 发送一个HTTP请求，返回状态码、响应头与响应体
+    fn request(addr: SocketAddr, method: &str, path: &str, headers: &[&str], body: &[u8]) -> (u16, String, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n", method, path, body.len());
+        for header in headers {
+            head.push_str(header);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(body).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..split].to_vec()).unwrap();
+        let status = head[9..12].parse().unwrap();
+        (status, head, response[split + 4..].to_vec())
+    }
+
+    // 在本机的任意端口上以用户web的身份提供fs，服务线程返回保存的次数
+    fn start(fs: &Arc<FileSystem>) -> (WebServer, SocketAddr, thread::JoinHandle<usize>) {
+        let server = WebServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.clone();
+        let shared = Arc::clone(fs);
+        let thread = thread::spawn(move || {
+            let mut saves = 0;
+            handle
+                .serve(&shared, "web", |_| {
+                    saves += 1;
+                    Ok(())
+                })
+                .unwrap();
+            saves
+        });
+        (server, addr, thread)
+    }
+
+    #[test]
+    fn webdav_server_over_localhost() {
+        let session = Session::default();
+        let fs = Arc::new(FileSystem::new());
+        let (server, addr, thread) = start(&fs);
+
+        assert_eq!(request(addr, "MKCOL", "/my%20dir", &[], b"").0, 201);
+        assert_eq!(request(addr, "MKCOL", "/my%20dir", &[], b"").0, 405);
+        assert_eq!(request(addr, "MKCOL", "/missing/dir", &[], b"").0, 409);
+        assert_eq!(request(addr, "PUT", "/my%20dir/a.txt", &[], b"0123456789").0, 201);
+        assert_eq!(request(addr, "PUT", "/my%20dir/a.txt", &[], b"hello world").0, 204);
+        assert_eq!(request(addr, "PUT", "/missing/a.txt", &[], b"x").0, 409);
+
+        let (status, _, body) = request(addr, "GET", "/my%20dir/a.txt", &[], b"");
+        assert_eq!((status, body.as_slice()), (200, &b"hello world"[..]));
+        let (status, head, body) = request(addr, "GET", "/my%20dir/a.txt", &["Range: bytes=6-"], b"");
+        assert_eq!((status, body.as_slice()), (206, &b"world"[..]));
+        assert!(head.contains("Content-Range: bytes 6-10/11"));
+        assert_eq!(request(addr, "GET", "/my%20dir/a.txt", &["Range: bytes=-3"], b"").2, b"rld");
+        assert_eq!(request(addr, "GET", "/my%20dir/a.txt", &["Range: bytes=20-"], b"").0, 416);
+        assert_eq!(request(addr, "GET", "/nothing", &[], b"").0, 404);
+
+        let (status, _, body) = request(addr, "GET", "/", &[], b"");
+        assert_eq!(status, 200);
+        assert!(String::from_utf8(body).unwrap().contains("<a href=\"/my%20dir/\">my dir/</a>"));
+        let (_, head, body) = request(addr, "GET", "/my%20dir?format=json", &[], b"");
+        assert!(head.contains("application/json"));
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json[0]["path"], "/my dir/a.txt");
+        assert_eq!(json[0]["size"], 11);
+
+        let (status, _, body) = request(addr, "PROPFIND", "/", &["Depth: 1"], b"");
+        let body = String::from_utf8(body).unwrap();
+        assert_eq!(status, 207);
+        assert!(body.contains("<D:href>/my%20dir/</D:href>"));
+        assert!(!body.contains("a.txt"));
+        let (_, _, body) = request(addr, "PROPFIND", "/", &["Depth: infinity"], b"");
+        assert!(String::from_utf8(body).unwrap().contains("<D:getcontentlength>11</D:getcontentlength>"));
+
+        assert_eq!(request(addr, "PUT", "/my%20dir/a.txt", &["Content-Range: bytes 6-10/11"], b"WORLD").0, 204);
+        assert_eq!(request(addr, "PUT", "/my%20dir/a.txt", &["Content-Range: bytes 0-9/*"], b"x").0, 400);
+        assert_eq!(request(addr, "PUT", "/my%20dir/b.txt", &[], b"b").0, 201);
+        let to_b = format!("Destination: http://{}/my%20dir/b.txt", addr);
+        assert_eq!(request(addr, "MOVE", "/my%20dir/a.txt", &[&to_b, "Overwrite: F"], b"").0, 412);
+        assert_eq!(request(addr, "MOVE", "/my%20dir/a.txt", &[&to_b], b"").0, 204);
+        assert_eq!(request(addr, "MOVE", "/my%20dir/b.txt", &["Destination: /missing/b.txt"], b"").0, 409);
+        assert_eq!(request(addr, "GET", "/my%20dir/b.txt", &[], b"").2, b"hello WORLD");
+        assert_eq!(request(addr, "GET", "/my%20dir/a.txt", &[], b"").0, 404);
+
+        assert_eq!(request(addr, "DELETE", "/my%20dir", &[], b"").0, 204);
+        assert_eq!(request(addr, "DELETE", "/my%20dir", &[], b"").0, 404);
+        assert_eq!(request(addr, "PUT", "/b.bin", &[], &[0, 1, 2]).0, 201);
+
+        server.stop();
+        let saves = thread.join().unwrap();
+        assert!(saves > 0);
+        assert_eq!(fs.read_file(&session, "/b.bin").unwrap(), [0, 1, 2]);
+        assert!(fs.stat(&session, "/my dir").is_err());
+        assert!(crate::fsck::check(&fs.to_operator()).is_empty());
+    }
+
+    #[test]
+    fn mandatory_locks_block_webdav_writes() {
+        let session = Session::default();
+        let vd = DiskOperator::new(None);
+        vd.write_file_by_path(&session, "/a", b"locked").unwrap();
+        // 另一个会话（如交互界面或9P的连接）持有强制锁，锁表与服务共用
+        let locks = Arc::clone(&vd.locks);
+        let owner = LockOwner { session: Session::new("other").id(), handle: 1 };
+        let held = Lock { owner, kind: LockKind::Exclusive, start: 0, end: TO_EOF, mandatory: true };
+        locks.flock(&FileHandle::new(0, "a"), held, None).unwrap();
+        let fs = Arc::new(FileSystem::from_operator(vd));
+        let (server, addr, thread) = start(&fs);
+
+        assert_eq!(request(addr, "PUT", "/a", &[], b"changed").0, 423);
+        assert_eq!(request(addr, "PUT", "/a", &["Content-Range: bytes 0-0/6"], b"L").0, 423);
+        assert_eq!(request(addr, "MOVE", "/a", &["Destination: /b"], b"").0, 423);
+        assert_eq!(request(addr, "DELETE", "/a", &[], b"").0, 423);
+        assert_eq!(request(addr, "GET", "/a", &[], b"").2, b"locked");
+
+        locks.release(owner);
+        assert_eq!(request(addr, "PUT", "/a", &[], b"changed").0, 204);
+        server.stop();
+        thread.join().unwrap();
+        assert_eq!(fs.read_file(&session, "/a").unwrap(), b"changed");
+    }
+
+    #[test]
+    fn quotas_limit_webdav_writes() {
+        let session = Session::new("web");
+        let vd = DiskOperator::new(None);
+        vd.new_directory(&session, "d").unwrap();
+        let d = vd.get_directory_by_path(&session, "/d").unwrap().cluster();
+        vd.quotas().set_dir(d, Limit { blocks: 3, inodes: 0 });
+        vd.quotas().set_user("web", Limit { blocks: 0, inodes: 3 });
+        let fs = Arc::new(FileSystem::from_operator(vd));
+        assert_eq!(fs.write_file(&session, "/d/a", &[1; 3 * BLOCK_SIZE]), Err(FsError::QuotaExceeded("/d".to_string())));
+        let (server, addr, thread) = start(&fs);
+
+        // 目录的块数与用户的文件数
+        assert_eq!(request(addr, "PUT", "/d/a", &[], &[1; 2 * BLOCK_SIZE]).0, 201);
+        assert_eq!(request(addr, "PUT", "/d/b", &[], b"b").0, 507);
+        assert_eq!(request(addr, "PUT", "/d/a", &["Content-Range: bytes 8192-8192/*"], b"x").0, 507);
+        assert_eq!(request(addr, "MKCOL", "/e", &[], b"").0, 201);
+        assert_eq!(request(addr, "MKCOL", "/f", &[], b"").0, 507);
+
+        // 删除目录时其配额一并删除
+        assert_eq!(request(addr, "DELETE", "/d", &[], b"").0, 204);
+        server.stop();
+        thread.join().unwrap();
+        let vd = fs.to_operator();
+        assert_eq!(vd.quotas().dirs().count(), 0);
+        assert!(crate::fsck::check(&vd).is_empty());
+    }
+}